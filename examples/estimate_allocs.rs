@@ -0,0 +1,89 @@
+use clap::Parser;
+use ndarray::Array2;
+use spatial_maker::{model, OnnxDepthEstimator};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+		ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+		System.alloc(layout)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		System.dealloc(ptr, layout)
+	}
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+#[derive(Parser)]
+#[command(name = "estimate-allocs")]
+#[command(about = "Compare per-frame allocations of estimate_raw and estimate_raw_into on one frame size")]
+struct Args {
+	input: String,
+
+	#[arg(long, default_value = "20")]
+	frames: u32,
+
+	#[arg(long, default_value = "s")]
+	encoder_size: String,
+}
+
+fn per_frame<F: FnMut() -> Result<(), Box<dyn std::error::Error>>>(
+	frames: u32,
+	mut estimate: F,
+) -> Result<(u64, u64, std::time::Duration), Box<dyn std::error::Error>> {
+	// The first call sizes the reusable buffers.
+	estimate()?;
+	let (allocations, bytes) = (ALLOCATIONS.load(Ordering::Relaxed), ALLOCATED_BYTES.load(Ordering::Relaxed));
+	let start = std::time::Instant::now();
+	for _ in 0..frames {
+		estimate()?;
+	}
+	let elapsed = start.elapsed() / frames;
+	Ok((
+		(ALLOCATIONS.load(Ordering::Relaxed) - allocations) / frames as u64,
+		(ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes) / frames as u64,
+		elapsed,
+	))
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+	let args = Args::parse();
+
+	let frame = image::open(&args.input)?;
+	let model_path = model::find_model(&args.encoder_size)?;
+	let mut estimator = OnnxDepthEstimator::new(model_path.to_str().unwrap())?;
+
+	let allocating = per_frame(args.frames, || {
+		estimator.estimate_raw(&frame)?;
+		Ok(())
+	})?;
+
+	let mut depth = Array2::zeros((0, 0));
+	let reusing = per_frame(args.frames, || {
+		estimator.estimate_raw_into(&frame, &mut depth)?;
+		Ok(())
+	})?;
+
+	eprintln!("{}x{}, {} frames", frame.width(), frame.height(), args.frames);
+	for (name, (allocations, bytes, elapsed)) in [("estimate_raw", allocating), ("estimate_raw_into", reusing)] {
+		eprintln!(
+			"{:<18} {:>6} allocations/frame {:>10.1} MB/frame {:>8.1?}/frame",
+			name,
+			allocations,
+			bytes as f64 / 1e6,
+			elapsed
+		);
+	}
+
+	Ok(())
+}
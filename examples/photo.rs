@@ -1,5 +1,7 @@
 use clap::Parser;
-use spatial_maker::{generate_stereo_pair, model, output, OnnxDepthEstimator};
+use spatial_maker::{
+	generate_stereo_pair_with, model, output, DisocclusionFill, OnnxDepthEstimator, StereoMode, StereoParams,
+};
 
 #[derive(Parser)]
 #[command(name = "spatial-maker-onnx")]
@@ -13,6 +15,22 @@ struct Args {
 	#[arg(long, default_value = "30")]
 	max_disparity: u32,
 
+	/// Which eyes to synthesize: right or symmetric
+	#[arg(long, default_value = "right")]
+	mode: StereoMode,
+
+	/// Disocclusion fill: background, nearest, or black
+	#[arg(long, default_value = "background")]
+	fill: DisocclusionFill,
+
+	/// Normalized depth (0-1) placed on the screen plane
+	#[arg(long, default_value = "0.0")]
+	convergence: f32,
+
+	/// Exponent applied to depth before it becomes disparity
+	#[arg(long, default_value = "1.0")]
+	gamma: f32,
+
 	#[arg(long, default_value = "s")]
 	encoder_size: String,
 }
@@ -24,14 +42,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 	let model_path = model::find_model(&args.encoder_size)?;
 
-	let estimator = OnnxDepthEstimator::new(model_path.to_str().unwrap())?;
+	let mut estimator = OnnxDepthEstimator::new(model_path.to_str().unwrap())?;
 
 	let start = std::time::Instant::now();
 	let depth_map = estimator.estimate(&input_image)?;
 	let depth_time = start.elapsed();
 	eprintln!("Depth estimation: {:?}", depth_time);
 
-	let (left, right) = generate_stereo_pair(&input_image, &depth_map, args.max_disparity)?;
+	let params = StereoParams {
+		mode: args.mode,
+		fill: args.fill,
+		convergence: args.convergence,
+		gamma: args.gamma,
+		..StereoParams::new(args.max_disparity)
+	};
+	let (left, right) = generate_stereo_pair_with(&input_image, &depth_map, &params, None::<fn(f64)>)?;
 
 	let sbs = output::create_sbs_image(&left, &right);
 	sbs.save(&args.output)?;
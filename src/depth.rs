@@ -1,60 +1,293 @@
 #[cfg(feature = "onnx")]
 use crate::error::{SpatialError, SpatialResult};
 #[cfg(feature = "onnx")]
+use crate::model::ModelPreprocess;
+#[cfg(feature = "onnx")]
+use crate::SpatialConfig;
+#[cfg(feature = "onnx")]
 use image::DynamicImage;
 #[cfg(feature = "onnx")]
 use ndarray::Array2;
 #[cfg(feature = "onnx")]
+use ort::ep::CPU;
+#[cfg(feature = "onnx")]
 use ort::session::{builder::GraphOptimizationLevel, Session};
 
+const FUSION_TILE_FRACTION: f32 = 0.6;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LetterboxRegion {
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+}
+
+impl LetterboxRegion {
+	pub fn scaled(&self, from: u32, to_width: u32, to_height: u32) -> Self {
+		let sx = to_width as f32 / from as f32;
+		let sy = to_height as f32 / from as f32;
+		Self {
+			x: (self.x as f32 * sx).round() as u32,
+			y: (self.y as f32 * sy).round() as u32,
+			width: ((self.width as f32 * sx).round() as u32).clamp(1, to_width),
+			height: ((self.height as f32 * sy).round() as u32).clamp(1, to_height),
+		}
+	}
+}
+
+pub fn letterbox(image: &image::DynamicImage, size: u32) -> (image::DynamicImage, LetterboxRegion) {
+	let (w, h) = (image.width().max(1), image.height().max(1));
+	let scale = size as f32 / w.max(h) as f32;
+	let width = ((w as f32 * scale).round() as u32).clamp(1, size);
+	let height = ((h as f32 * scale).round() as u32).clamp(1, size);
+	let x = (size - width) / 2;
+	let y = (size - height) / 2;
+
+	let resized = image
+		.resize_exact(width, height, image::imageops::FilterType::Lanczos3)
+		.to_rgb8();
+	let padded = image::RgbImage::from_fn(size, size, |px, py| {
+		let sx = px.saturating_sub(x).min(width - 1);
+		let sy = py.saturating_sub(y).min(height - 1);
+		*resized.get_pixel(sx, sy)
+	});
+
+	(
+		image::DynamicImage::ImageRgb8(padded),
+		LetterboxRegion { x, y, width, height },
+	)
+}
+
+/// True if a `width`x`height` image is over a `max_pixels` budget (0 means no
+/// limit).
+pub fn exceeds_pixel_budget(width: u32, height: u32, max_pixels: u64) -> bool {
+	max_pixels != 0 && width as u64 * height as u64 > max_pixels
+}
+
+/// A copy of `image` scaled down to at most `max_pixels`, keeping its aspect
+/// ratio, or `None` when it already fits. The model sees a few hundred pixels
+/// either way, so estimating on the copy and upsampling the depth costs little
+/// detail. Only inference works on the copy; the depth is upsampled to full
+/// size before filtering and the stereo warp, which are not bounded by this.
+pub fn fit_pixel_budget(image: &image::DynamicImage, max_pixels: u64) -> Option<image::DynamicImage> {
+	if !exceeds_pixel_budget(image.width(), image.height(), max_pixels) {
+		return None;
+	}
+	let (w, h) = (image.width() as u64, image.height() as u64);
+	let scale = (max_pixels as f64 / (w * h) as f64).sqrt();
+	let width = ((w as f64 * scale).floor() as u32).max(1);
+	let height = ((h as f64 * scale).floor() as u32).max(1);
+	Some(image.resize_exact(width, height, image::imageops::FilterType::Triangle))
+}
+
+/// Crops a `width`x`height` model output to `region` and bilinearly resamples it
+/// straight into `out`, so no intermediate image is allocated per frame.
+#[cfg(any(feature = "onnx", all(target_os = "macos", feature = "coreml")))]
+pub(crate) fn resample_depth_into(
+	data: &[f32],
+	width: u32,
+	height: u32,
+	region: LetterboxRegion,
+	out: &mut ndarray::Array2<f32>,
+) -> crate::error::SpatialResult<()> {
+	let width = width as usize;
+	if data.len() != width * height as usize
+		|| region.x + region.width > width as u32
+		|| region.y + region.height > height
+		|| region.width == 0
+		|| region.height == 0
+	{
+		return Err(crate::error::SpatialError::TensorError("Depth output has unexpected size".to_string()));
+	}
+	let (out_height, out_width) = out.dim();
+	let scale_x = region.width as f32 / out_width as f32;
+	let scale_y = region.height as f32 / out_height as f32;
+	let max_x = (region.width - 1) as f32;
+	let max_y = (region.height - 1) as f32;
+	for ((y, x), v) in out.indexed_iter_mut() {
+		let sx = ((x as f32 + 0.5) * scale_x - 0.5).clamp(0.0, max_x);
+		let sy = ((y as f32 + 0.5) * scale_y - 0.5).clamp(0.0, max_y);
+		let (x0, y0) = (sx as usize, sy as usize);
+		let x1 = (x0 + 1).min(max_x as usize);
+		let y1 = (y0 + 1).min(max_y as usize);
+		let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+		let at = |cx: usize, cy: usize| data[(region.y as usize + cy) * width + region.x as usize + cx];
+		let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * fx;
+		let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * fx;
+		*v = top + (bottom - top) * fy;
+	}
+	Ok(())
+}
+
+/// Estimates depth once on the whole image and once on a 2x2 grid of overlapping
+/// tiles (roughly double the model resolution), then fuses the two so global
+/// structure comes from the whole-image pass and fine detail from the tiles.
+pub fn estimate_multires<F>(
+	image: &image::DynamicImage,
+	mut estimate: F,
+) -> crate::error::SpatialResult<ndarray::Array2<f32>>
+where
+	F: FnMut(&image::DynamicImage) -> crate::error::SpatialResult<ndarray::Array2<f32>>,
+{
+	let coarse = estimate(image)?;
+
+	let (w, h) = (image.width() as usize, image.height() as usize);
+	if w == 0 || h == 0 {
+		return Ok(coarse);
+	}
+	let tile_w = ((w as f32 * FUSION_TILE_FRACTION).ceil() as usize).clamp(1, w);
+	let tile_h = ((h as f32 * FUSION_TILE_FRACTION).ceil() as usize).clamp(1, h);
+	let mut sum = ndarray::Array2::<f32>::zeros((h, w));
+	let mut weight = ndarray::Array2::<f32>::zeros((h, w));
+
+	for (tx, ty) in [(0, 0), (w - tile_w, 0), (0, h - tile_h), (w - tile_w, h - tile_h)] {
+		let tile = image.crop_imm(tx as u32, ty as u32, tile_w as u32, tile_h as u32);
+		let depth = crate::depth_filter::resize_depth(&estimate(&tile)?, tile_w, tile_h);
+
+		for y in 0..tile_h {
+			for x in 0..tile_w {
+				let edge = x.min(tile_w - 1 - x).min(y).min(tile_h - 1 - y);
+				let wgt = (edge + 1) as f32;
+				sum[[ty + y, tx + x]] += depth[[y, x]] * wgt;
+				weight[[ty + y, tx + x]] += wgt;
+			}
+		}
+	}
+
+	let fine = sum / weight;
+	Ok(crate::depth_filter::fuse_depth(&coarse, &fine))
+}
+
 #[cfg(feature = "onnx")]
-const INPUT_SIZE: u32 = 518;
-#[cfg(feature = "onnx")]
-const IMAGENET_MEAN: [f32; 3] = [0.485, 0.456, 0.406];
-#[cfg(feature = "onnx")]
-const IMAGENET_STD: [f32; 3] = [0.229, 0.224, 0.225];
+fn default_thread_count() -> usize {
+	num_cpus::get_physical()
+}
 
 #[cfg(feature = "onnx")]
 pub struct OnnxDepthEstimator {
 	session: Session,
+	preserve_aspect: bool,
+	preprocess: ModelPreprocess,
+	input_scratch: Vec<f32>,
 }
 
 #[cfg(feature = "onnx")]
 impl OnnxDepthEstimator {
 	pub fn new(model_path: &str) -> SpatialResult<Self> {
-		let session = Session::builder()
+		Self::with_threads(model_path, None, None, false)
+	}
+
+	pub fn from_config(model_path: &str, config: &SpatialConfig) -> SpatialResult<Self> {
+		let estimator =
+			Self::with_threads(model_path, config.num_threads, config.inter_threads, config.memory_arena)?;
+		Ok(estimator
+			.with_preserve_aspect(config.preserve_aspect)
+			.with_preprocess(config.depth_preprocess()?))
+	}
+
+	pub fn with_preserve_aspect(mut self, preserve_aspect: bool) -> Self {
+		self.preserve_aspect = preserve_aspect;
+		self
+	}
+
+	pub fn with_preprocess(mut self, preprocess: ModelPreprocess) -> Self {
+		self.preprocess = preprocess;
+		self
+	}
+
+	pub fn with_threads(
+		model_path: &str,
+		intra_threads: Option<usize>,
+		inter_threads: Option<usize>,
+		memory_arena: bool,
+	) -> SpatialResult<Self> {
+		let intra_threads = intra_threads.unwrap_or_else(default_thread_count);
+
+		let mut builder = Session::builder()
 			.map_err(|e| SpatialError::ModelError(format!("Failed to create session: {}", e)))?
 			.with_optimization_level(GraphOptimizationLevel::Level3)
 			.map_err(|e| SpatialError::ModelError(format!("Failed to set opt level: {}", e)))?
-			.with_intra_threads(4)
-			.map_err(|e| SpatialError::ModelError(format!("Failed to set threads: {}", e)))?
+			.with_intra_threads(intra_threads)
+			.map_err(|e| SpatialError::ModelError(format!("Failed to set threads: {}", e)))?;
+
+		if let Some(inter_threads) = inter_threads {
+			builder = builder
+				.with_inter_threads(inter_threads)
+				.map_err(|e| SpatialError::ModelError(format!("Failed to set inter-op threads: {}", e)))?;
+		}
+
+		if memory_arena {
+			builder = builder
+				.with_execution_providers([CPU::default().with_arena_allocator(true).build()])
+				.map_err(|e| SpatialError::ModelError(format!("Failed to enable memory arena: {}", e)))?;
+		}
+
+		let session = builder
 			.commit_from_file(model_path)
 			.map_err(|e| SpatialError::ModelError(format!("Failed to load ONNX model: {}", e)))?;
 
-		Ok(Self { session })
+		Ok(Self {
+			session,
+			preserve_aspect: false,
+			preprocess: ModelPreprocess::default(),
+			input_scratch: Vec::new(),
+		})
 	}
 
+	/// Depth normalized to [0, 1]; see `depth_filter::normalize_depth`.
 	pub fn estimate(&mut self, image: &DynamicImage) -> SpatialResult<Array2<f32>> {
-		let (orig_width, orig_height) = (image.width(), image.height());
-		let size = INPUT_SIZE as usize;
+		let mut out = Array2::zeros((image.height() as usize, image.width() as usize));
+		self.estimate_into(image, &mut out)?;
+		Ok(out)
+	}
 
-		let resized = image.resize_exact(
-			INPUT_SIZE,
-			INPUT_SIZE,
-			image::imageops::FilterType::Lanczos3,
-		);
+	/// Like `estimate`, but writes into `out` (reallocated only if the image size
+	/// changes) and reuses the estimator's input tensor buffer across calls.
+	pub fn estimate_into(&mut self, image: &DynamicImage, out: &mut Array2<f32>) -> SpatialResult<()> {
+		self.estimate_raw_into(image, out)?;
+		crate::depth_filter::normalize_depth_in_place(out, crate::depth_filter::NormalizeStrategy::MinMax);
+		Ok(())
+	}
+
+	/// The model's relative inverse depth at the image's resolution, without any
+	/// normalization. Feed this to `DepthProcessor` for video.
+	pub fn estimate_raw(&mut self, image: &DynamicImage) -> SpatialResult<Array2<f32>> {
+		let mut out = Array2::zeros((image.height() as usize, image.width() as usize));
+		self.estimate_raw_into(image, &mut out)?;
+		Ok(out)
+	}
+
+	/// Like `estimate_raw`, but writes into `out`, reallocated only when the
+	/// image size changes.
+	pub fn estimate_raw_into(&mut self, image: &DynamicImage, out: &mut Array2<f32>) -> SpatialResult<()> {
+		let (orig_width, orig_height) = (image.width() as usize, image.height() as usize);
+		let ModelPreprocess { input_size, mean, std } = self.preprocess;
+		let size = input_size as usize;
+
+		let (resized, region) = if self.preserve_aspect {
+			let (padded, region) = letterbox(image, input_size);
+			(padded, Some(region))
+		} else {
+			let resized = image.resize_exact(
+				input_size,
+				input_size,
+				image::imageops::FilterType::Lanczos3,
+			);
+			(resized, None)
+		};
 
 		let rgb = resized.to_rgb8();
-		let mut input_data = vec![0.0f32; 1 * 3 * size * size];
+		self.input_scratch.resize(3 * size * size, 0.0);
 
 		for (i, pixel) in rgb.pixels().enumerate() {
 			for c in 0..3 {
-				let normalized = (pixel[c] as f32 / 255.0 - IMAGENET_MEAN[c]) / IMAGENET_STD[c];
-				input_data[c * size * size + i] = normalized;
+				let normalized = (pixel[c] as f32 / 255.0 - mean[c]) / std[c];
+				self.input_scratch[c * size * size + i] = normalized;
 			}
 		}
 
-		let input_value = ort::value::Value::from_array(([1usize, 3, size, size], input_data))
+		let input_value = ort::value::TensorRef::from_array_view(([1usize, 3, size, size], &*self.input_scratch))
 			.map_err(|e| SpatialError::TensorError(format!("Failed to create input: {}", e)))?;
 
 		let outputs = self.session.run(ort::inputs![input_value])
@@ -67,31 +300,116 @@ impl OnnxDepthEstimator {
 		let h = dims[1];
 		let w = dims[2];
 
-		let depth_data: Vec<f32> = data.to_vec();
+		let region = match region {
+			Some(region) => region.scaled(input_size, w as u32, h as u32),
+			None => LetterboxRegion { x: 0, y: 0, width: w as u32, height: h as u32 },
+		};
+		if out.dim() != (orig_height, orig_width) {
+			*out = Array2::zeros((orig_height, orig_width));
+		}
+		resample_depth_into(data, w as u32, h as u32, region, out)?;
 
-		let min_val = depth_data.iter().copied().fold(f32::INFINITY, f32::min);
-		let max_val = depth_data.iter().copied().fold(f32::NEG_INFINITY, f32::max);
-		let range = max_val - min_val;
+		Ok(())
+	}
+}
 
-		let normalized: Vec<f32> = if range > 1e-6 {
-			depth_data.iter().map(|&v| (v - min_val) / range).collect()
-		} else {
-			vec![0.5; depth_data.len()]
-		};
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use image::DynamicImage;
+	use ndarray::Array2;
 
-		let depth_image = image::ImageBuffer::from_fn(w as u32, h as u32, |x, y| {
-			image::Luma([normalized[y as usize * w + x as usize]])
-		});
+	/// Stands in for a model: depth increases left to right across `image`.
+	fn gradient(image: &DynamicImage) -> crate::error::SpatialResult<Array2<f32>> {
+		let (w, h) = (image.width() as usize, image.height() as usize);
+		Ok(Array2::from_shape_fn((h, w), |(_, x)| x as f32 / w.max(1) as f32))
+	}
+
+	#[test]
+	fn multires_covers_every_pixel() {
+		let image = DynamicImage::new_rgb8(9, 5);
+		let fused = estimate_multires(&image, gradient).unwrap();
+		assert_eq!(fused.dim(), (5, 9));
+		assert!(fused.iter().all(|v| v.is_finite()));
+	}
+
+	#[test]
+	fn multires_tolerates_empty_images() {
+		for (w, h) in [(0, 4), (4, 0), (0, 0)] {
+			let fused = estimate_multires(&DynamicImage::new_rgb8(w, h), gradient).unwrap();
+			assert_eq!(fused.dim(), (h as usize, w as usize));
+		}
+	}
 
-		let resized_depth = image::imageops::resize(
-			&depth_image,
-			orig_width,
-			orig_height,
-			image::imageops::FilterType::Lanczos3,
-		);
+	#[test]
+	fn multires_handles_single_pixel_images() {
+		let fused = estimate_multires(&DynamicImage::new_rgb8(1, 1), gradient).unwrap();
+		assert_eq!(fused.dim(), (1, 1));
+	}
+
+	#[test]
+	fn wide_images_are_letterboxed_not_cropped_or_stretched() {
+		let bands = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 0]];
+		let wide =
+			DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 16, |x, _| image::Rgb(bands[x as usize / 16])));
+		let (padded, region) = letterbox(&wide, 32);
+
+		assert_eq!((padded.width(), padded.height()), (32, 32));
+		assert_eq!((region.x, region.y, region.width, region.height), (0, 12, 32, 8));
+		let padded = padded.to_rgb8();
+		for (i, band) in bands.iter().enumerate() {
+			let x = i as u32 * 8 + 4;
+			assert_eq!(padded.get_pixel(x, 16).0, *band);
+			assert_eq!(padded.get_pixel(x, 0), padded.get_pixel(x, region.y));
+			assert_eq!(padded.get_pixel(x, 31), padded.get_pixel(x, region.y + region.height - 1));
+		}
+	}
 
-		let data: Vec<f32> = resized_depth.pixels().map(|p| p[0]).collect();
-		Array2::from_shape_vec((orig_height as usize, orig_width as usize), data)
-			.map_err(|e| SpatialError::TensorError(format!("Failed to reshape depth: {}", e)))
+	#[cfg(any(feature = "onnx", all(target_os = "macos", feature = "coreml")))]
+	fn test_scene() -> DynamicImage {
+		DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 48, |x, y| {
+			image::Rgb([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8])
+		}))
+	}
+
+	#[cfg(any(feature = "onnx", all(target_os = "macos", feature = "coreml")))]
+	fn assert_minmax_of_raw(raw: Array2<f32>, depth: &Array2<f32>) {
+		let expected = crate::depth_filter::normalize_minmax(raw);
+		assert_eq!(depth.dim(), expected.dim());
+		assert!(depth.iter().zip(&expected).all(|(a, e)| (a - e).abs() < 1e-6));
+		assert_eq!(depth.iter().copied().fold(f32::INFINITY, f32::min), 0.0);
+		assert_eq!(depth.iter().copied().fold(f32::NEG_INFINITY, f32::max), 1.0);
+	}
+
+	#[cfg(feature = "onnx")]
+	#[test]
+	fn onnx_estimate_is_minmax_of_raw() {
+		let Ok(model_path) = crate::model::find_model("s") else {
+			eprintln!("skipping: no small depth model installed");
+			return;
+		};
+		let Ok(mut estimator) = OnnxDepthEstimator::new(model_path.to_str().unwrap()) else {
+			eprintln!("skipping: {} is not an ONNX model", model_path.display());
+			return;
+		};
+		let image = test_scene();
+		let raw = estimator.estimate_raw(&image).unwrap();
+		assert_minmax_of_raw(raw, &estimator.estimate(&image).unwrap());
+	}
+
+	#[cfg(all(target_os = "macos", feature = "coreml"))]
+	#[test]
+	fn coreml_estimate_is_minmax_of_raw() {
+		let Ok(model_path) = crate::model::find_model("s") else {
+			eprintln!("skipping: no small depth model installed");
+			return;
+		};
+		let Ok(estimator) = crate::CoreMLDepthEstimator::new(model_path.to_str().unwrap()) else {
+			eprintln!("skipping: {} is not a CoreML model", model_path.display());
+			return;
+		};
+		let image = test_scene();
+		let raw = estimator.estimate_raw(&image).unwrap();
+		assert_minmax_of_raw(raw, &estimator.estimate(&image).unwrap());
 	}
 }
@@ -0,0 +1,160 @@
+use crate::error::{SpatialError, SpatialResult};
+use crate::SpatialConfig;
+use ndarray::Array2;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"SMDC";
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 1 + 8;
+
+/// The depth cache directory: `SPATIAL_MAKER_CACHE` if set, otherwise
+/// `~/.spatial-maker/cache/depth`.
+pub fn default_cache_dir() -> SpatialResult<PathBuf> {
+	if let Ok(custom_dir) = std::env::var("SPATIAL_MAKER_CACHE") {
+		Ok(PathBuf::from(custom_dir))
+	} else {
+		let home = dirs::home_dir().ok_or_else(|| {
+			SpatialError::ConfigError("Could not determine home directory".to_string())
+		})?;
+		Ok(home.join(".spatial-maker").join("cache").join("depth"))
+	}
+}
+
+/// Cache key for the raw depth of `input_path` with `model_path` under `config`.
+pub fn cache_key(input_path: &Path, model_path: &Path, config: &SpatialConfig) -> SpatialResult<String> {
+	let input = std::fs::read(input_path)
+		.map_err(|e| SpatialError::IoError(format!("Failed to read {}: {}", input_path.display(), e)))?;
+	let model = std::fs::metadata(model_path)
+		.map_err(|e| SpatialError::IoError(format!("Failed to stat {}: {}", model_path.display(), e)))?;
+	let modified = model
+		.modified()
+		.ok()
+		.and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+		.map_or(0, |d| d.as_nanos());
+	let preprocess = config.depth_preprocess()?;
+	let backend = if cfg!(all(target_os = "macos", feature = "coreml")) { "coreml" } else { "onnx" };
+
+	let mut hasher = Sha256::new();
+	hasher.update([FORMAT_VERSION]);
+	hasher.update(Sha256::digest(&input));
+	hasher.update(
+		format!(
+			"{}|{}|{}|{}|{}|{:?}|{:?}|{}|{}",
+			backend,
+			config.encoder_size,
+			model_path.display(),
+			model.len(),
+			modified,
+			preprocess.mean,
+			preprocess.std,
+			preprocess.input_size,
+			config.preserve_aspect,
+		)
+		.as_bytes(),
+	);
+	hasher.update([config.fusion as u8]);
+	hasher.update(config.max_depth_pixels.to_le_bytes());
+	Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn entry_path(dir: &Path, key: &str) -> PathBuf {
+	dir.join(format!("{}.depth", key))
+}
+
+/// The cached depth map for `key`, or `None` on a miss. Unreadable or
+/// malformed entries count as misses.
+pub fn load(dir: &Path, key: &str) -> Option<Array2<f32>> {
+	let data = std::fs::read(entry_path(dir, key)).ok()?;
+	if data.len() < HEADER_LEN || &data[..4] != MAGIC || data[4] != FORMAT_VERSION {
+		return None;
+	}
+	let height = u32::from_le_bytes(data[5..9].try_into().ok()?) as usize;
+	let width = u32::from_le_bytes(data[9..13].try_into().ok()?) as usize;
+	let values: Vec<f32> = data[HEADER_LEN..]
+		.chunks_exact(4)
+		.map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+		.collect();
+	Array2::from_shape_vec((height, width), values).ok()
+}
+
+/// Stores `depth` under `key`: a short header (magic, format version, height,
+/// width) followed by little-endian f32 values. Written to a temp file and
+/// renamed, so concurrent runs never see a partial entry.
+pub fn store(dir: &Path, key: &str, depth: &Array2<f32>) -> SpatialResult<()> {
+	std::fs::create_dir_all(dir)
+		.map_err(|e| SpatialError::IoError(format!("Failed to create cache directory: {}", e)))?;
+	let (height, width) = depth.dim();
+	let mut data = Vec::with_capacity(HEADER_LEN + height * width * 4);
+	data.extend_from_slice(MAGIC);
+	data.push(FORMAT_VERSION);
+	data.extend_from_slice(&(height as u32).to_le_bytes());
+	data.extend_from_slice(&(width as u32).to_le_bytes());
+	for value in depth.iter() {
+		data.extend_from_slice(&value.to_le_bytes());
+	}
+
+	let mut file = crate::temp::temp_file_in(dir, ".depth")?;
+	file.write_all(&data)
+		.map_err(|e| SpatialError::IoError(format!("Failed to write depth cache: {}", e)))?;
+	file.persist(entry_path(dir, key))
+		.map_err(|e| SpatialError::IoError(format!("Failed to write depth cache: {}", e)))?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn sample_depth() -> Array2<f32> {
+		Array2::from_shape_fn((3, 5), |(y, x)| y as f32 * 0.5 - x as f32 * 0.25)
+	}
+
+	#[test]
+	fn stored_depth_loads_back_unchanged() {
+		let dir = tempfile::tempdir().unwrap();
+		store(dir.path(), "key", &sample_depth()).unwrap();
+		assert_eq!(load(dir.path(), "key"), Some(sample_depth()));
+		assert_eq!(load(dir.path(), "other"), None);
+	}
+
+	#[test]
+	fn truncated_entry_is_a_miss() {
+		let dir = tempfile::tempdir().unwrap();
+		store(dir.path(), "key", &sample_depth()).unwrap();
+		let path = entry_path(dir.path(), "key");
+		let data = std::fs::read(&path).unwrap();
+		std::fs::write(&path, &data[..data.len() - 4]).unwrap();
+		assert_eq!(load(dir.path(), "key"), None);
+		std::fs::write(&path, &data[..HEADER_LEN - 1]).unwrap();
+		assert_eq!(load(dir.path(), "key"), None);
+	}
+
+	#[test]
+	fn entry_from_another_format_version_is_a_miss() {
+		let dir = tempfile::tempdir().unwrap();
+		store(dir.path(), "key", &sample_depth()).unwrap();
+		let path = entry_path(dir.path(), "key");
+		let mut data = std::fs::read(&path).unwrap();
+		data[4] = FORMAT_VERSION + 1;
+		std::fs::write(&path, data).unwrap();
+		assert_eq!(load(dir.path(), "key"), None);
+	}
+
+	#[test]
+	fn key_changes_with_encoder_size_and_aspect() {
+		let dir = tempfile::tempdir().unwrap();
+		let (input, model) = (dir.path().join("photo.jpg"), dir.path().join("model.onnx"));
+		std::fs::write(&input, b"photo").unwrap();
+		std::fs::write(&model, b"model").unwrap();
+		let key = |config: &SpatialConfig| cache_key(&input, &model, config).unwrap();
+
+		let config = SpatialConfig::default();
+		let larger = SpatialConfig { encoder_size: "b".to_string(), ..config.clone() };
+		let aspect = SpatialConfig { preserve_aspect: !config.preserve_aspect, ..config.clone() };
+		assert_eq!(key(&config), key(&config.clone()));
+		assert_ne!(key(&config), key(&larger));
+		assert_ne!(key(&config), key(&aspect));
+	}
+}
@@ -1,10 +1,12 @@
+use crate::depth::{letterbox, resample_depth_into, LetterboxRegion};
+use crate::depth_filter::{normalize_depth_in_place, NormalizeStrategy};
 use crate::error::{SpatialError, SpatialResult};
-use image::{DynamicImage, ImageBuffer, Luma};
+use crate::model::ModelPreprocess;
+use crate::SpatialConfig;
+use image::DynamicImage;
 use ndarray::Array2;
 use std::ffi::CString;
 
-const INPUT_SIZE: u32 = 518;
-
 extern "C" {
 	fn coreml_load_model(path: *const std::os::raw::c_char) -> *mut std::os::raw::c_void;
 	fn coreml_unload_model(model: *mut std::os::raw::c_void);
@@ -19,6 +21,8 @@ extern "C" {
 
 pub struct CoreMLDepthEstimator {
 	model: *mut std::os::raw::c_void,
+	preserve_aspect: bool,
+	preprocess: ModelPreprocess,
 }
 
 impl CoreMLDepthEstimator {
@@ -37,28 +41,56 @@ impl CoreMLDepthEstimator {
 
 		tracing::info!("CoreML model loaded: {}", model_path);
 
-		Ok(Self { model })
+		Ok(Self {
+			model,
+			preserve_aspect: false,
+			preprocess: ModelPreprocess::default(),
+		})
+	}
+
+	pub fn from_config(model_path: &str, config: &SpatialConfig) -> SpatialResult<Self> {
+		Ok(Self::new(model_path)?
+			.with_preserve_aspect(config.preserve_aspect)
+			.with_preprocess(config.depth_preprocess()?))
 	}
 
-	fn infer_raw(&self, image: &DynamicImage) -> SpatialResult<Vec<f32>> {
-		let resized = image.resize_exact(
-			INPUT_SIZE,
-			INPUT_SIZE,
-			image::imageops::FilterType::Lanczos3,
-		);
+	pub fn with_preserve_aspect(mut self, preserve_aspect: bool) -> Self {
+		self.preserve_aspect = preserve_aspect;
+		self
+	}
+
+	/// Only `input_size` is used; the package normalizes internally.
+	pub fn with_preprocess(mut self, preprocess: ModelPreprocess) -> Self {
+		self.preprocess = preprocess;
+		self
+	}
+
+	/// Runs the model, returning its `input_size`x`input_size` output and the
+	/// part of it that covers the image.
+	fn infer_raw(&self, image: &DynamicImage) -> SpatialResult<(Vec<f32>, LetterboxRegion)> {
+		let input_size = self.preprocess.input_size;
+		let (resized, region) = if self.preserve_aspect {
+			letterbox(image, input_size)
+		} else {
+			let resized = image.resize_exact(
+				input_size,
+				input_size,
+				image::imageops::FilterType::Lanczos3,
+			);
+			(resized, LetterboxRegion { x: 0, y: 0, width: input_size, height: input_size })
+		};
 
 		let rgb = resized.to_rgb8();
-		let input_data: Vec<u8> = rgb.as_raw().to_vec();
 
-		let output_size = (INPUT_SIZE * INPUT_SIZE) as usize;
+		let output_size = (input_size * input_size) as usize;
 		let mut output_data = vec![0.0f32; output_size];
 
 		let result = unsafe {
 			coreml_infer_depth(
 				self.model,
-				input_data.as_ptr(),
-				INPUT_SIZE as i32,
-				INPUT_SIZE as i32,
+				rgb.as_raw().as_ptr(),
+				input_size as i32,
+				input_size as i32,
 				output_data.as_mut_ptr(),
 			)
 		};
@@ -70,70 +102,35 @@ impl CoreMLDepthEstimator {
 			)));
 		}
 
-		Ok(output_data)
+		Ok((output_data, region))
 	}
 
-	pub fn estimate_unnormalized(&self, image: &DynamicImage) -> SpatialResult<Array2<f32>> {
-		let (orig_width, orig_height) = (image.width(), image.height());
-		let output_data = self.infer_raw(image)?;
-
-		let depth_image = ImageBuffer::from_fn(INPUT_SIZE, INPUT_SIZE, |x, y| {
-			let idx = (y * INPUT_SIZE + x) as usize;
-			Luma([output_data[idx]])
-		});
-
-		let resized_depth = image::imageops::resize(
-			&depth_image,
-			orig_width,
-			orig_height,
-			image::imageops::FilterType::Lanczos3,
-		);
-
-		let (w, h) = resized_depth.dimensions();
-		let data: Vec<f32> = resized_depth.pixels().map(|p| p[0]).collect();
-		Array2::from_shape_vec((h as usize, w as usize), data)
-			.map_err(|e| SpatialError::TensorError(format!("Failed to reshape depth: {}", e)))
+	/// Raw model output resized to the image, without normalization. Feed this to
+	/// `DepthProcessor` for video.
+	pub fn estimate_raw(&self, image: &DynamicImage) -> SpatialResult<Array2<f32>> {
+		let mut out = Array2::zeros((image.height() as usize, image.width() as usize));
+		self.estimate_raw_into(image, &mut out)?;
+		Ok(out)
 	}
 
-	pub fn estimate_raw(&self, image: &DynamicImage) -> SpatialResult<ImageBuffer<Luma<f32>, Vec<f32>>> {
-		let (orig_width, orig_height) = (image.width(), image.height());
-		let mut output_data = self.infer_raw(image)?;
-
-		let min_val = output_data.iter().copied().fold(f32::INFINITY, f32::min);
-		let max_val = output_data
-			.iter()
-			.copied()
-			.fold(f32::NEG_INFINITY, f32::max);
-		let range = max_val - min_val;
-
-		if range > 1e-6 {
-			for v in &mut output_data {
-				*v = (*v - min_val) / range;
-			}
+	/// Like `estimate_raw`, but writes into `out`, reallocated only when the
+	/// image size changes.
+	pub fn estimate_raw_into(&self, image: &DynamicImage, out: &mut Array2<f32>) -> SpatialResult<()> {
+		let (orig_width, orig_height) = (image.width() as usize, image.height() as usize);
+		let (data, region) = self.infer_raw(image)?;
+		if out.dim() != (orig_height, orig_width) {
+			*out = Array2::zeros((orig_height, orig_width));
 		}
-
-		let depth_image = ImageBuffer::from_fn(INPUT_SIZE, INPUT_SIZE, |x, y| {
-			let idx = (y * INPUT_SIZE + x) as usize;
-			Luma([output_data[idx]])
-		});
-
-		let resized_depth = image::imageops::resize(
-			&depth_image,
-			orig_width,
-			orig_height,
-			image::imageops::FilterType::Lanczos3,
-		);
-
-		Ok(resized_depth)
+		let input_size = self.preprocess.input_size;
+		resample_depth_into(&data, input_size, input_size, region, out)
 	}
 
+	/// Depth normalized to [0, 1] the same way as the ONNX backend; see
+	/// `depth_filter::normalize_depth`.
 	pub fn estimate(&self, image: &DynamicImage) -> SpatialResult<Array2<f32>> {
-		let depth_image = self.estimate_raw(image)?;
-		let (width, height) = depth_image.dimensions();
-		let data: Vec<f32> = depth_image.pixels().map(|p| p[0]).collect();
-		let depth_2d = Array2::from_shape_vec((height as usize, width as usize), data)
-			.map_err(|e| SpatialError::TensorError(format!("Failed to reshape depth: {}", e)))?;
-		Ok(depth_2d)
+		let mut depth = self.estimate_raw(image)?;
+		normalize_depth_in_place(&mut depth, NormalizeStrategy::MinMax);
+		Ok(depth)
 	}
 }
 
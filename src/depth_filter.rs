@@ -1,19 +1,61 @@
-use crate::NormalizeMode;
+use crate::error::{SpatialError, SpatialResult};
+use crate::{NormalizeMode, SpatialConfig, TemporalMode};
+use image::{DynamicImage, ImageBuffer, Luma, RgbImage};
 use ndarray::Array2;
 use rayon::prelude::*;
+use std::collections::VecDeque;
+use std::path::Path;
+
+const EDGE_BLUR_THRESHOLD: f32 = 0.05;
+const NORMAL_DEPTH_SCALE: f32 = 0.1;
+const EQUALIZE_MIN_RANGE: f32 = 1e-3;
+const EQUALIZE_CLIP_LIMIT: f32 = 4.0;
+const FUSION_SIGMA_FRACTION: f32 = 0.01;
+const STATE_MAGIC: &[u8; 4] = b"SMDP";
+const STATE_HEADER_LEN: usize = 41;
+/// Per-frame weight of the running depth range and convergence updates.
+const RANGE_ADAPT_RATE: f32 = 0.05;
+/// Window (Gaussian sigma, pixels) for the local luminance variance.
+const TEXTURE_SIGMA: f32 = 2.0;
+/// Reach of the flat-region depth average, as a fraction of the larger dimension.
+const LOW_TEXTURE_SIGMA_FRACTION: f32 = 0.03;
+/// Depth spread (see `depth_spread`) that `auto_disparity` scales every scene to.
+const TARGET_DEPTH_SPREAD: f32 = 0.5;
+/// Bounds of the `auto_disparity` multiplier, so near-flat scenes aren't blown
+/// up into extreme disparity.
+const MIN_DISPARITY_SCALE: f32 = 0.5;
+const MAX_DISPARITY_SCALE: f32 = 2.0;
+/// `max_disparity` at which `adaptive_smoothing` leaves the filter strengths as
+/// configured; the default `max_disparity`, so default settings are unchanged.
+const SMOOTHING_REFERENCE_DISPARITY: f32 = 30.0;
+/// Bounds of the `adaptive_smoothing` multiplier. The bilateral filter's cost
+/// grows with the square of its sigma, so very strong 3D doesn't get a
+/// runaway window.
+const MIN_SMOOTHING_SCALE: f32 = 0.25;
+const MAX_SMOOTHING_SCALE: f32 = 3.0;
 
 pub struct DepthProcessor {
     prev_depth: Option<Array2<f32>>,
+    history: VecDeque<Array2<f32>>,
     ema_min: f32,
     ema_max: f32,
     global_min: f32,
     global_max: f32,
     temporal_alpha: f32,
+    temporal_mode: TemporalMode,
     bilateral_sigma_space: f32,
     bilateral_sigma_color: f32,
     depth_blur_sigma: f32,
+    edge_blur_only: bool,
+    equalize_bins: Option<usize>,
+    low_texture_threshold: Option<f32>,
     normalize_mode: NormalizeMode,
     frame_index: u32,
+    convergence: f32,
+    global_median_sum: f64,
+    global_median_count: u32,
+    auto_disparity: bool,
+    depth_spread: Option<f32>,
 }
 
 impl DepthProcessor {
@@ -26,19 +68,54 @@ impl DepthProcessor {
     ) -> Self {
         Self {
             prev_depth: None,
+            history: VecDeque::new(),
             ema_min: 0.0,
             ema_max: 0.0,
             global_min: f32::INFINITY,
             global_max: f32::NEG_INFINITY,
             temporal_alpha,
+            temporal_mode: TemporalMode::Ema,
             bilateral_sigma_space,
             bilateral_sigma_color,
             depth_blur_sigma,
+            edge_blur_only: false,
+            equalize_bins: None,
+            low_texture_threshold: None,
             normalize_mode,
             frame_index: 0,
+            convergence: 0.5,
+            global_median_sum: 0.0,
+            global_median_count: 0,
+            auto_disparity: false,
+            depth_spread: None,
         }
     }
 
+    pub fn from_config(config: &SpatialConfig) -> Self {
+        let smoothing = if config.adaptive_smoothing {
+            smoothing_scale(config.max_disparity)
+        } else {
+            1.0
+        };
+        let mut processor = Self::new(
+            config.temporal_alpha,
+            config.bilateral_sigma_space * smoothing,
+            config.bilateral_sigma_color,
+            config.depth_blur_sigma * smoothing,
+            config.normalize_mode.clone(),
+        );
+        processor.edge_blur_only = config.edge_blur_only;
+        processor.temporal_mode = config.temporal_mode;
+        processor.auto_disparity = config.auto_disparity;
+        if config.equalize_depth {
+            processor.equalize_bins = Some(config.equalize_bins as usize);
+        }
+        if config.suppress_low_texture {
+            processor.low_texture_threshold = Some(config.low_texture_threshold);
+        }
+        processor
+    }
+
     pub fn set_global_range(&mut self, min: f32, max: f32) {
         self.global_min = min;
         self.global_max = max;
@@ -49,11 +126,180 @@ impl DepthProcessor {
         let max = raw_depth.iter().copied().fold(f32::NEG_INFINITY, f32::max);
         self.global_min = self.global_min.min(min);
         self.global_max = self.global_max.max(max);
+        self.global_median_sum += median_depth(raw_depth) as f64;
+        self.global_median_count += 1;
+    }
+
+    /// Normalized depth of the zero-disparity plane for automatic convergence:
+    /// the median depth, averaged over the whole video in `NormalizeMode::Global`
+    /// and otherwise smoothed across frames like the running depth range, so the
+    /// screen plane doesn't jump with every frame.
+    pub fn convergence(&self) -> f32 {
+        self.convergence
+    }
+
+    /// `max_disparity` multiplier for `SpatialConfig::auto_disparity`, from the
+    /// depth spread smoothed across frames like the running depth range, so the
+    /// 3D strength doesn't pulse from frame to frame. 1 until a frame has been
+    /// processed with `auto_disparity` on. The smoothed spread isn't part of
+    /// `save_state`; a resumed run starts again from its first frame.
+    pub fn disparity_scale(&self) -> f32 {
+        self.depth_spread.map_or(1.0, disparity_scale)
+    }
+
+    fn update_depth_spread(&mut self, depth: &Array2<f32>) {
+        let spread = depth_spread(depth);
+        self.depth_spread = Some(match self.depth_spread {
+            Some(prev) => prev + RANGE_ADAPT_RATE * (spread - prev),
+            None => spread,
+        });
+    }
+
+    fn update_convergence(&mut self, depth: &Array2<f32>) {
+        match self.normalize_mode {
+            NormalizeMode::Global if self.global_median_count > 0 => {
+                let mean = (self.global_median_sum / self.global_median_count as f64) as f32;
+                let range = self.global_max - self.global_min;
+                self.convergence = if range > 1e-6 {
+                    ((mean - self.global_min) / range).clamp(0.0, 1.0)
+                } else {
+                    0.5
+                };
+            }
+            // Resumed global runs keep the convergence restored with the state.
+            NormalizeMode::Global => {}
+            _ => {
+                let median = median_depth(depth);
+                self.convergence = if self.frame_index == 0 {
+                    median
+                } else {
+                    self.convergence + RANGE_ADAPT_RATE * (median - self.convergence)
+                };
+            }
+        }
+    }
+
+    /// Writes the running state (normalization ranges and temporal history) so a
+    /// later run can continue where this one stopped. Settings aren't included;
+    /// restore into a processor built from the same config.
+    pub fn save_state(&self, path: &Path) -> SpatialResult<()> {
+        let frames: Vec<&Array2<f32>> = self.prev_depth.iter().chain(&self.history).collect();
+        let (rows, cols) = frames.first().map(|f| f.dim()).unwrap_or((0, 0));
+        if frames.iter().any(|f| f.dim() != (rows, cols)) {
+            return Err(SpatialError::Other(
+                "Depth history has mixed frame sizes".to_string(),
+            ));
+        }
+
+        let mut buf = Vec::with_capacity(STATE_HEADER_LEN + frames.len() * rows * cols * 4);
+        buf.extend_from_slice(STATE_MAGIC);
+        buf.extend_from_slice(&self.frame_index.to_le_bytes());
+        for v in [
+            self.ema_min,
+            self.ema_max,
+            self.global_min,
+            self.global_max,
+            self.convergence,
+        ] {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [rows as u32, cols as u32, self.history.len() as u32] {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        buf.push(self.prev_depth.is_some() as u8);
+        for frame in frames {
+            buf.extend(frame.iter().flat_map(|v| v.to_le_bytes()));
+        }
+
+        std::fs::write(path, buf)
+            .map_err(|e| SpatialError::IoError(format!("Failed to write {:?}: {}", path, e)))
+    }
+
+    /// Restores state written by `save_state`.
+    pub fn load_state(&mut self, path: &Path) -> SpatialResult<()> {
+        let buf = std::fs::read(path)
+            .map_err(|e| SpatialError::IoError(format!("Failed to read {:?}: {}", path, e)))?;
+        let invalid =
+            || SpatialError::Other(format!("Invalid depth processor state in {:?}", path));
+
+        if buf.len() < STATE_HEADER_LEN || &buf[..4] != STATE_MAGIC {
+            return Err(invalid());
+        }
+        let word = |i: usize| {
+            [
+                buf[4 + i * 4],
+                buf[5 + i * 4],
+                buf[6 + i * 4],
+                buf[7 + i * 4],
+            ]
+        };
+        let frame_index = u32::from_le_bytes(word(0));
+        let [ema_min, ema_max, global_min, global_max, convergence] =
+            [1, 2, 3, 4, 5].map(|i| f32::from_le_bytes(word(i)));
+        let [rows, cols, history_len] = [6, 7, 8].map(|i| u32::from_le_bytes(word(i)) as usize);
+        let has_prev = buf[STATE_HEADER_LEN - 1] != 0;
+
+        let frame_len = rows * cols;
+        let count = history_len + has_prev as usize;
+        let data = &buf[STATE_HEADER_LEN..];
+        if data.len() != count * frame_len * 4 {
+            return Err(invalid());
+        }
+        let mut frames = data.chunks_exact((frame_len * 4).max(1)).map(|bytes| {
+            let values = bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+            Array2::from_shape_vec((rows, cols), values).map_err(|_| invalid())
+        });
+
+        self.prev_depth = if has_prev {
+            Some(frames.next().ok_or_else(invalid)??)
+        } else {
+            None
+        };
+        self.history = frames.collect::<SpatialResult<VecDeque<_>>>()?;
+        self.frame_index = frame_index;
+        self.ema_min = ema_min;
+        self.ema_max = ema_max;
+        self.global_min = global_min;
+        self.global_max = global_max;
+        self.convergence = convergence;
+        Ok(())
     }
 
     pub fn process(&mut self, raw_depth: Array2<f32>) -> Array2<f32> {
+        self.process_frame(raw_depth, None)
+    }
+
+    /// Like `process`, with the frame the depth came from for the filters that
+    /// look at it (low-texture suppression).
+    pub fn process_with_image(
+        &mut self,
+        raw_depth: Array2<f32>,
+        image: &DynamicImage,
+    ) -> Array2<f32> {
+        self.process_frame(raw_depth, Some(image))
+    }
+
+    fn process_frame(
+        &mut self,
+        raw_depth: Array2<f32>,
+        image: Option<&DynamicImage>,
+    ) -> Array2<f32> {
         let mut depth = self.normalize(raw_depth);
 
+        if let (Some(threshold), Some(image)) = (self.low_texture_threshold, image) {
+            depth = match image.as_rgb8() {
+                Some(rgb) => suppress_lowtexture(&depth, rgb, threshold),
+                None => suppress_lowtexture(&depth, &image.to_rgb8(), threshold),
+            };
+        }
+
+        if let Some(bins) = self.equalize_bins {
+            depth = histogram_equalize(&depth, bins);
+        }
+
         if self.bilateral_sigma_space > 0.0 {
             depth = bilateral_filter(
                 &depth,
@@ -63,67 +309,196 @@ impl DepthProcessor {
         }
 
         if self.depth_blur_sigma > 0.0 {
-            depth = gaussian_blur(&depth, self.depth_blur_sigma);
+            depth = if self.edge_blur_only {
+                edge_blur(&depth, self.depth_blur_sigma, EDGE_BLUR_THRESHOLD)
+            } else {
+                gaussian_blur(&depth, self.depth_blur_sigma)
+            };
         }
 
-        if self.temporal_alpha > 0.0 && self.temporal_alpha < 1.0 {
-            if let Some(ref prev) = self.prev_depth {
-                if prev.dim() == depth.dim() {
-                    let alpha = self.temporal_alpha;
-                    depth.zip_mut_with(prev, |curr, &prev_val| {
-                        *curr = alpha * *curr + (1.0 - alpha) * prev_val;
-                    });
+        match self.temporal_mode {
+            TemporalMode::Off => self.prev_depth = Some(depth.clone()),
+            TemporalMode::Ema => {
+                // `temporal_alpha` is the current frame's weight. 1 passes frames
+                // through unchanged; 0 would freeze on the first frame, so it means
+                // off instead. History is kept either way, so a resumed run that
+                // turns smoothing on blends from the last frame.
+                let alpha = self.temporal_alpha.clamp(0.0, 1.0);
+                if alpha > 0.0 && alpha < 1.0 {
+                    if let Some(ref prev) = self.prev_depth {
+                        if prev.dim() == depth.dim() {
+                            depth.zip_mut_with(prev, |curr, &prev_val| {
+                                *curr = alpha * *curr + (1.0 - alpha) * prev_val;
+                            });
+                        }
+                    }
                 }
+                self.prev_depth = Some(depth.clone());
             }
-            self.prev_depth = Some(depth.clone());
+            TemporalMode::Window(n) => depth = self.window_smooth(depth, n),
         }
 
+        self.update_convergence(&depth);
+        if self.auto_disparity {
+            self.update_depth_spread(&depth);
+        }
         self.frame_index += 1;
         depth
     }
 
-    fn normalize(&mut self, raw: Array2<f32>) -> Array2<f32> {
-        match self.normalize_mode {
-            NormalizeMode::PerFrame => normalize_minmax(raw),
+    fn window_smooth(&mut self, depth: Array2<f32>, n: usize) -> Array2<f32> {
+        if self.history.front().is_some_and(|d| d.dim() != depth.dim()) {
+            self.history.clear();
+        }
+        self.history.push_front(depth);
+        self.history.truncate(n.max(1));
+
+        let sigma = (n as f32 / 2.0).max(1.0);
+        let mut out = Array2::<f32>::zeros(self.history[0].dim());
+        let mut total = 0.0;
+        for (age, frame) in self.history.iter().enumerate() {
+            let w = (-0.5 * (age as f32 / sigma).powi(2)).exp();
+            out.scaled_add(w, frame);
+            total += w;
+        }
+        out / total
+    }
+
+    fn normalize(&mut self, mut raw: Array2<f32>) -> Array2<f32> {
+        let strategy = match self.normalize_mode {
+            NormalizeMode::PerFrame => NormalizeStrategy::MinMax,
             NormalizeMode::RunningEMA => {
                 let min = raw.iter().copied().fold(f32::INFINITY, f32::min);
                 let max = raw.iter().copied().fold(f32::NEG_INFINITY, f32::max);
 
-                let adapt_rate = 0.05;
                 if self.frame_index == 0 {
                     self.ema_min = min;
                     self.ema_max = max;
                 } else {
-                    self.ema_min = self.ema_min + adapt_rate * (min - self.ema_min);
-                    self.ema_max = self.ema_max + adapt_rate * (max - self.ema_max);
+                    self.ema_min = self.ema_min + RANGE_ADAPT_RATE * (min - self.ema_min);
+                    self.ema_max = self.ema_max + RANGE_ADAPT_RATE * (max - self.ema_max);
                 }
 
-                let range = self.ema_max - self.ema_min;
-                if range > 1e-6 {
-                    raw.mapv(|v| ((v - self.ema_min) / range).clamp(0.0, 1.0))
-                } else {
-                    raw.mapv(|_| 0.5)
-                }
+                NormalizeStrategy::FixedRange(self.ema_min, self.ema_max)
             }
             NormalizeMode::Global => {
-                let range = self.global_max - self.global_min;
-                if range > 1e-6 {
-                    raw.mapv(|v| ((v - self.global_min) / range).clamp(0.0, 1.0))
-                } else {
-                    raw.mapv(|_| 0.5)
+                NormalizeStrategy::FixedRange(self.global_min, self.global_max)
+            }
+        };
+        normalize_depth_in_place(&mut raw, strategy);
+        raw
+    }
+}
+
+/// Median of a depth map, used as the automatic convergence plane.
+pub fn median_depth(depth: &Array2<f32>) -> f32 {
+    let mut values: Vec<f32> = depth.iter().copied().filter(|v| v.is_finite()).collect();
+    if values.is_empty() {
+        return 0.5;
+    }
+    let mid = values.len() / 2;
+    *values.select_nth_unstable_by(mid, f32::total_cmp).1
+}
+
+/// Spread of a normalized depth map: the distance between its 5th and 95th
+/// percentiles, so a few stray near or far pixels don't count.
+pub fn depth_spread(depth: &Array2<f32>) -> f32 {
+    let mut values: Vec<f32> = depth.iter().copied().filter(|v| v.is_finite()).collect();
+    if values.is_empty() {
+        return 0.0;
+    }
+    let last = values.len() - 1;
+    let high = *values
+        .select_nth_unstable_by(last * 95 / 100, f32::total_cmp)
+        .1;
+    let low = *values
+        .select_nth_unstable_by(last * 5 / 100, f32::total_cmp)
+        .1;
+    high - low
+}
+
+/// `max_disparity` multiplier that renders a scene with the given
+/// `depth_spread` as deep as one spanning `TARGET_DEPTH_SPREAD`: shallow scenes
+/// get more disparity and deep ones less, within 0.5-2x.
+pub fn disparity_scale(spread: f32) -> f32 {
+    (TARGET_DEPTH_SPREAD / spread.max(f32::EPSILON)).clamp(MIN_DISPARITY_SCALE, MAX_DISPARITY_SCALE)
+}
+
+/// Multiplier on the spatial filter sigmas for `SpatialConfig::adaptive_smoothing`:
+/// `max_disparity / 30`, within 0.25-3x. Depth noise is shifted by a number of
+/// pixels proportional to `max_disparity`, so smoothing over a proportionally
+/// wider area keeps the warp artifacts about the same size on screen; 60 doubles
+/// the sigmas and 15 halves them. The bilateral range sigma is left alone, since
+/// it's a depth tolerance rather than a distance.
+pub fn smoothing_scale(max_disparity: u32) -> f32 {
+    (max_disparity as f32 / SMOOTHING_REFERENCE_DISPARITY)
+        .clamp(MIN_SMOOTHING_SCALE, MAX_SMOOTHING_SCALE)
+}
+
+/// How `normalize_depth` picks the raw values that map to 0 and 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NormalizeStrategy {
+    /// The map's own minimum and maximum.
+    MinMax,
+    /// The given low and high percentiles (0-100), so a few stray values don't
+    /// squeeze the rest of the scene into a narrow band.
+    Percentile(f32, f32),
+    /// A fixed range, such as `DepthProcessor`'s running or whole-video range.
+    FixedRange(f32, f32),
+}
+
+impl NormalizeStrategy {
+    /// The raw `(min, max)` mapped to 0 and 1 for `depth`. NaNs are ignored.
+    pub fn range(&self, depth: &Array2<f32>) -> (f32, f32) {
+        match *self {
+            NormalizeStrategy::MinMax => (
+                depth.iter().copied().fold(f32::INFINITY, f32::min),
+                depth.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+            ),
+            NormalizeStrategy::Percentile(lo, hi) => {
+                let mut values: Vec<f32> = depth.iter().copied().filter(|v| !v.is_nan()).collect();
+                if values.is_empty() {
+                    return (0.0, 0.0);
                 }
+                let (lo, hi) = (lo.clamp(0.0, 100.0), hi.clamp(0.0, 100.0));
+                let last = (values.len() - 1) as f32;
+                let mut at = |p: f32| {
+                    let rank = (p / 100.0 * last).round() as usize;
+                    *values.select_nth_unstable_by(rank, f32::total_cmp).1
+                };
+                (at(lo.min(hi)), at(lo.max(hi)))
             }
+            NormalizeStrategy::FixedRange(min, max) => (min, max),
         }
     }
 }
 
-fn normalize_minmax(mut depth: Array2<f32>) -> Array2<f32> {
-    let min = depth.iter().copied().fold(f32::INFINITY, f32::min);
-    let max = depth.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+/// Maps `depth` to [0, 1] by `strategy`, clamping values outside the range. A
+/// degenerate range (flat, empty or all-NaN input) gives 0.5 everywhere.
+///
+/// Every backend's `estimate` normalizes with `NormalizeStrategy::MinMax`, and
+/// `DepthProcessor` routes all three `NormalizeMode`s through here, so a given
+/// `max_disparity` produces the same disparity range on CoreML and ONNX.
+pub fn normalize_depth(depth: &Array2<f32>, strategy: NormalizeStrategy) -> Array2<f32> {
+    let mut out = depth.clone();
+    normalize_depth_in_place(&mut out, strategy);
+    out
+}
+
+pub(crate) fn normalize_depth_in_place(depth: &mut Array2<f32>, strategy: NormalizeStrategy) {
+    let (min, max) = strategy.range(depth);
     let range = max - min;
     if range > 1e-6 {
-        depth.mapv_inplace(|v| (v - min) / range);
+        depth.mapv_inplace(|v| ((v - min) / range).clamp(0.0, 1.0));
+    } else {
+        depth.fill(0.5);
     }
+}
+
+/// Per-image min-max normalization to [0, 1] (0.5 for flat input); shorthand for
+/// `normalize_depth` with `NormalizeStrategy::MinMax`.
+pub fn normalize_minmax(mut depth: Array2<f32>) -> Array2<f32> {
+    normalize_depth_in_place(&mut depth, NormalizeStrategy::MinMax);
     depth
 }
 
@@ -133,10 +508,12 @@ pub fn bilateral_filter(depth: &Array2<f32>, sigma_space: f32, sigma_color: f32)
     let space_coeff = -0.5 / (sigma_space * sigma_space);
     let color_coeff = -0.5 / (sigma_color * sigma_color);
 
-    let flat: Vec<f32> = (0..h)
-        .into_par_iter()
-        .flat_map(|y| {
-            let mut row = vec![0.0f32; w];
+    // Rows are independent and only read `depth`, so they're written in place in
+    // parallel; the result doesn't depend on the thread count.
+    let mut out = vec![0.0f32; h * w];
+    out.par_chunks_mut(w.max(1))
+        .enumerate()
+        .for_each(|(y, row)| {
             for x in 0..w {
                 let center = depth[[y, x]];
                 let mut sum = 0.0f32;
@@ -167,11 +544,9 @@ pub fn bilateral_filter(depth: &Array2<f32>, sigma_space: f32, sigma_color: f32)
                     center
                 };
             }
-            row
-        })
-        .collect();
+        });
 
-    Array2::from_shape_vec((h, w), flat).unwrap()
+    Array2::from_shape_vec((h, w), out).unwrap()
 }
 
 pub fn gaussian_blur(depth: &Array2<f32>, sigma: f32) -> Array2<f32> {
@@ -191,10 +566,10 @@ pub fn gaussian_blur(depth: &Array2<f32>, sigma: f32) -> Array2<f32> {
 
     let (h, w) = depth.dim();
 
-    let temp_flat: Vec<f32> = (0..h)
-        .into_par_iter()
-        .flat_map(|y| {
-            let mut row = vec![0.0f32; w];
+    let mut temp = vec![0.0f32; h * w];
+    temp.par_chunks_mut(w.max(1))
+        .enumerate()
+        .for_each(|(y, row)| {
             for x in 0..w {
                 let mut sum = 0.0f32;
                 for i in 0..kernel_size {
@@ -203,15 +578,13 @@ pub fn gaussian_blur(depth: &Array2<f32>, sigma: f32) -> Array2<f32> {
                 }
                 row[x] = sum;
             }
-            row
-        })
-        .collect();
-    let temp = Array2::from_shape_vec((h, w), temp_flat).unwrap();
+        });
+    let temp = Array2::from_shape_vec((h, w), temp).unwrap();
 
-    let out_flat: Vec<f32> = (0..h)
-        .into_par_iter()
-        .flat_map(|y| {
-            let mut row = vec![0.0f32; w];
+    let mut out = vec![0.0f32; h * w];
+    out.par_chunks_mut(w.max(1))
+        .enumerate()
+        .for_each(|(y, row)| {
             for x in 0..w {
                 let mut sum = 0.0f32;
                 for i in 0..kernel_size {
@@ -220,9 +593,544 @@ pub fn gaussian_blur(depth: &Array2<f32>, sigma: f32) -> Array2<f32> {
                 }
                 row[x] = sum;
             }
+        });
+
+    Array2::from_shape_vec((h, w), out).unwrap()
+}
+
+/// Flattens depth in textureless regions (blank walls, clear sky), where depth
+/// models tend to invent structure that turns into spurious parallax. Pixels
+/// whose local luminance variance (0-1 scale) is below `variance_threshold` are
+/// pulled toward the smooth average depth of the surrounding flat region, fully
+/// at zero variance and not at all at the threshold; textured pixels are left
+/// untouched.
+pub fn suppress_lowtexture(
+    depth: &Array2<f32>,
+    rgb: &RgbImage,
+    variance_threshold: f32,
+) -> Array2<f32> {
+    let (h, w) = depth.dim();
+    if h == 0 || w == 0 || variance_threshold <= 0.0 {
+        return depth.clone();
+    }
+    let resized;
+    let rgb = if rgb.dimensions() == (w as u32, h as u32) {
+        rgb
+    } else {
+        resized = image::imageops::resize(
+            rgb,
+            w as u32,
+            h as u32,
+            image::imageops::FilterType::Triangle,
+        );
+        &resized
+    };
+
+    let luma = Array2::from_shape_fn((h, w), |(y, x)| {
+        let p = rgb.get_pixel(x as u32, y as u32);
+        (0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32) / 255.0
+    });
+    let mean = gaussian_blur(&luma, TEXTURE_SIGMA);
+    let mean_sq = gaussian_blur(&luma.mapv(|v| v * v), TEXTURE_SIGMA);
+    let flatness = ndarray::Zip::from(&mean)
+        .and(&mean_sq)
+        .map_collect(|&m, &m2| (1.0 - (m2 - m * m).max(0.0) / variance_threshold).max(0.0));
+    if flatness.iter().all(|&f| f == 0.0) {
+        return depth.clone();
+    }
+
+    // Normalized convolution: average depth over flat pixels only, so textured
+    // foreground next to a flat region doesn't bleed into it.
+    let sigma = (w.max(h) as f32 * LOW_TEXTURE_SIGMA_FRACTION).max(1.0);
+    let weighted = gaussian_blur(&(depth * &flatness), sigma);
+    let weight = gaussian_blur(&flatness, sigma);
+
+    let mut out = depth.clone();
+    ndarray::Zip::from(&mut out)
+        .and(&flatness)
+        .and(&weighted)
+        .and(&weight)
+        .for_each(|v, &f, &sum, &wt| {
+            if f > 0.0 && wt > 1e-6 {
+                *v += f * (sum / wt - *v);
+            }
+        });
+    out
+}
+
+pub fn edge_blur(depth: &Array2<f32>, sigma: f32, edge_threshold: f32) -> Array2<f32> {
+    let band = (sigma * 3.0).ceil() as usize;
+    let near_edge = dilate(&depth_edges(depth, edge_threshold), band);
+    let blurred = gaussian_blur(depth, sigma);
+
+    let mut out = depth.clone();
+    ndarray::Zip::from(&mut out)
+        .and(&blurred)
+        .and(&near_edge)
+        .for_each(|v, &b, &edge| {
+            if edge {
+                *v = b;
+            }
+        });
+    out
+}
+
+fn depth_edges(depth: &Array2<f32>, threshold: f32) -> Array2<bool> {
+    let (h, w) = depth.dim();
+    Array2::from_shape_fn((h, w), |(y, x)| {
+        let left = depth[[y, x.saturating_sub(1)]];
+        let right = depth[[y, (x + 1).min(w - 1)]];
+        let up = depth[[y.saturating_sub(1), x]];
+        let down = depth[[(y + 1).min(h - 1), x]];
+        (right - left).abs().max((down - up).abs()) > threshold
+    })
+}
+
+fn dilate(mask: &Array2<bool>, radius: usize) -> Array2<bool> {
+    let (h, w) = mask.dim();
+
+    let horizontal = Array2::from_shape_fn((h, w), |(y, x)| {
+        let x0 = x.saturating_sub(radius);
+        let x1 = (x + radius).min(w - 1);
+        (x0..=x1).any(|nx| mask[[y, nx]])
+    });
+
+    Array2::from_shape_fn((h, w), |(y, x)| {
+        let y0 = y.saturating_sub(radius);
+        let y1 = (y + radius).min(h - 1);
+        (y0..=y1).any(|ny| horizontal[[ny, x]])
+    })
+}
+
+pub fn depth_to_normals(depth: &Array2<f32>, strength: f32) -> RgbImage {
+    let (h, w) = depth.dim();
+    if h == 0 || w == 0 {
+        return RgbImage::new(w as u32, h as u32);
+    }
+    let scale = strength * NORMAL_DEPTH_SCALE * w.max(h) as f32 / 8.0;
+    let at = |y: isize, x: isize| {
+        depth[[y.clamp(0, h as isize - 1) as usize, x.clamp(0, w as isize - 1) as usize]]
+    };
+
+    let flat: Vec<u8> = (0..h)
+        .into_par_iter()
+        .flat_map(|y| {
+            let y = y as isize;
+            let mut row = Vec::with_capacity(w * 3);
+            for x in 0..w as isize {
+                let gx = (at(y - 1, x + 1) + 2.0 * at(y, x + 1) + at(y + 1, x + 1))
+                    - (at(y - 1, x - 1) + 2.0 * at(y, x - 1) + at(y + 1, x - 1));
+                let gy = (at(y + 1, x - 1) + 2.0 * at(y + 1, x) + at(y + 1, x + 1))
+                    - (at(y - 1, x - 1) + 2.0 * at(y - 1, x) + at(y - 1, x + 1));
+
+                let (nx, ny, nz) = (-gx * scale, gy * scale, 1.0f32);
+                let len = (nx * nx + ny * ny + nz * nz).sqrt();
+                for n in [nx / len, ny / len, nz / len] {
+                    row.push(((n * 0.5 + 0.5) * 255.0).round() as u8);
+                }
+            }
             row
         })
         .collect();
 
-    Array2::from_shape_vec((h, w), out_flat).unwrap()
+    RgbImage::from_raw(w as u32, h as u32, flat).unwrap()
+}
+
+pub fn histogram_equalize(depth: &Array2<f32>, bins: usize) -> Array2<f32> {
+    let bins = bins.max(2);
+    let min = depth.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = depth.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let range = max - min;
+    if range.is_nan() || range <= EQUALIZE_MIN_RANGE {
+        return depth.clone();
+    }
+
+    let position = |v: f32| ((v - min) / range).clamp(0.0, 1.0) * (bins - 1) as f32;
+
+    let mut hist = vec![0.0f32; bins];
+    for &v in depth.iter() {
+        hist[position(v).round() as usize] += 1.0;
+    }
+
+    let clip = depth.len() as f32 / bins as f32 * EQUALIZE_CLIP_LIMIT;
+    let mut excess = 0.0f32;
+    for count in &mut hist {
+        if *count > clip {
+            excess += *count - clip;
+            *count = clip;
+        }
+    }
+    let share = excess / bins as f32;
+
+    let mut cdf = Vec::with_capacity(bins);
+    let mut total = 0.0f32;
+    for count in &hist {
+        total += count + share;
+        cdf.push(total);
+    }
+    let (first, last) = (cdf[0], cdf[bins - 1]);
+    let span = (last - first).max(f32::EPSILON);
+
+    depth.mapv(|v| {
+        let pos = position(v);
+        let i = pos.floor() as usize;
+        let j = (i + 1).min(bins - 1);
+        let t = pos - i as f32;
+        let c = cdf[i] + (cdf[j] - cdf[i]) * t;
+        ((c - first) / span).clamp(0.0, 1.0)
+    })
+}
+
+pub fn resize_depth(depth: &Array2<f32>, width: usize, height: usize) -> Array2<f32> {
+    let (h, w) = depth.dim();
+    if (h, w) == (height, width) {
+        return depth.clone();
+    }
+
+    let buf: ImageBuffer<Luma<f32>, Vec<f32>> =
+        ImageBuffer::from_raw(w as u32, h as u32, depth.iter().copied().collect()).unwrap();
+    let resized = image::imageops::resize(
+        &buf,
+        width as u32,
+        height as u32,
+        image::imageops::FilterType::Triangle,
+    );
+    Array2::from_shape_vec((height, width), resized.into_raw()).unwrap()
+}
+
+/// Size of the depth the model actually produced: `size`x`size`, or the longest
+/// side scaled to `size` when the input was letterboxed.
+pub fn native_depth_dims(width: usize, height: usize, size: u32, preserve_aspect: bool) -> (usize, usize) {
+    let size = size as usize;
+    if !preserve_aspect || width == 0 || height == 0 {
+        return (size, size);
+    }
+    let scale = size as f32 / width.max(height) as f32;
+    (
+        ((width as f32 * scale).round() as usize).max(1),
+        ((height as f32 * scale).round() as usize).max(1),
+    )
+}
+
+/// Combines the low-frequency structure of `coarse` with the high-frequency detail
+/// of `fine`. `coarse` is resampled to the size of `fine` if needed.
+pub fn fuse_depth(coarse: &Array2<f32>, fine: &Array2<f32>) -> Array2<f32> {
+    let (h, w) = fine.dim();
+    let coarse = resize_depth(coarse, w, h);
+    let sigma = (w.max(h) as f32 * FUSION_SIGMA_FRACTION).max(1.0);
+
+    let base = gaussian_blur(&coarse, sigma);
+    let detail = fine - &gaussian_blur(fine, sigma);
+    normalize_minmax(base + detail)
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct DepthStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub p05: f32,
+    pub p50: f32,
+    pub p95: f32,
+}
+
+impl DepthStats {
+    pub fn from_depth(depth: &Array2<f32>) -> Self {
+        let mut values: Vec<f32> = depth.iter().copied().filter(|v| v.is_finite()).collect();
+        if values.is_empty() {
+            return Self {
+                min: 0.0,
+                max: 0.0,
+                mean: 0.0,
+                p05: 0.0,
+                p50: 0.0,
+                p95: 0.0,
+            };
+        }
+        values.sort_unstable_by(|a, b| a.total_cmp(b));
+
+        let percentile = |p: f32| values[((values.len() - 1) as f32 * p).round() as usize];
+        Self {
+            min: values[0],
+            max: values[values.len() - 1],
+            mean: values.iter().sum::<f32>() / values.len() as f32,
+            p05: percentile(0.05),
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    fn assert_close(actual: &Array2<f32>, expected: &Array2<f32>) {
+        assert_eq!(actual.dim(), expected.dim());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-6, "{} != {}\n{:?}", a, e, actual);
+        }
+    }
+
+    #[test]
+    fn minmax_spans_zero_to_one() {
+        let depth = array![[2.0, 4.0], [6.0, 10.0]];
+        let out = normalize_depth(&depth, NormalizeStrategy::MinMax);
+        assert_close(&out, &array![[0.0, 0.25], [0.5, 1.0]]);
+    }
+
+    #[test]
+    fn minmax_ignores_nan_for_the_range() {
+        let depth = array![[f32::NAN, 1.0, 3.0]];
+        assert_eq!(NormalizeStrategy::MinMax.range(&depth), (1.0, 3.0));
+    }
+
+    #[test]
+    fn percentile_clips_outliers() {
+        let mut depth = Array2::from_shape_fn((1, 101), |(_, x)| x as f32);
+        depth[[0, 100]] = 1000.0;
+        let (lo, hi) = NormalizeStrategy::Percentile(0.0, 99.0).range(&depth);
+        assert_eq!((lo, hi), (0.0, 99.0));
+        let out = normalize_depth(&depth, NormalizeStrategy::Percentile(0.0, 99.0));
+        assert_eq!(out[[0, 100]], 1.0);
+        assert!((out[[0, 50]] - 50.0 / 99.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn percentile_skips_nans() {
+        let depth = array![[f32::NAN, 0.0, 5.0, f32::NAN, 10.0]];
+        assert_eq!(NormalizeStrategy::Percentile(0.0, 100.0).range(&depth), (0.0, 10.0));
+    }
+
+    #[test]
+    fn percentile_bounds_are_ordered_and_clamped() {
+        let depth = array![[0.0, 5.0, 10.0]];
+        assert_eq!(NormalizeStrategy::Percentile(100.0, 0.0).range(&depth), (0.0, 10.0));
+        assert_eq!(NormalizeStrategy::Percentile(-20.0, 250.0).range(&depth), (0.0, 10.0));
+    }
+
+    #[test]
+    fn fixed_range_clamps_values_outside_it() {
+        let depth = array![[-5.0, 0.0, 5.0, 10.0, 20.0]];
+        let out = normalize_depth(&depth, NormalizeStrategy::FixedRange(0.0, 10.0));
+        assert_close(&out, &array![[0.0, 0.0, 0.5, 1.0, 1.0]]);
+    }
+
+    #[test]
+    fn minmax_ignores_the_raw_scale_and_offset() {
+        let raw = array![[0.1, 0.4], [0.7, 0.9]];
+        let rescaled = raw.mapv(|v| v * 1200.0 + 35.0);
+        assert_close(&normalize_minmax(rescaled), &normalize_minmax(raw));
+    }
+
+    #[test]
+    fn equalized_skewed_depth_fills_the_range_evenly() {
+        let depth =
+            Array2::from_shape_fn((64, 64), |(y, x)| ((y * 64 + x) as f32 / 4095.0).powi(2));
+        let out = histogram_equalize(&depth, 256);
+        for quarter in 0..4 {
+            let (lo, hi) = (quarter as f32 / 4.0, (quarter + 1) as f32 / 4.0);
+            let count = out
+                .iter()
+                .filter(|&&v| v >= lo && (v < hi || quarter == 3))
+                .count();
+            let share = count as f32 / out.len() as f32;
+            assert!((share - 0.25).abs() < 0.07, "quarter {} holds {}", quarter, share);
+        }
+    }
+
+    #[test]
+    fn equalizing_flat_depth_leaves_it_unchanged() {
+        for flat in [Array2::from_elem((8, 8), 0.4), Array2::zeros((8, 8))] {
+            let out = histogram_equalize(&flat, 256);
+            assert!(out.iter().all(|v| v.is_finite()));
+            assert_close(&out, &flat);
+        }
+    }
+
+    #[test]
+    fn degenerate_input_is_mid_gray() {
+        let flat = Array2::from_elem((2, 3), 7.0);
+        let all_nan = Array2::from_elem((2, 2), f32::NAN);
+        for strategy in [
+            NormalizeStrategy::MinMax,
+            NormalizeStrategy::Percentile(2.0, 98.0),
+            NormalizeStrategy::FixedRange(1.0, 1.0),
+        ] {
+            assert_close(&normalize_depth(&flat, strategy), &Array2::from_elem((2, 3), 0.5));
+            assert_close(&normalize_depth(&all_nan, strategy), &Array2::from_elem((2, 2), 0.5));
+            assert_eq!(normalize_depth(&Array2::zeros((0, 0)), strategy).dim(), (0, 0));
+        }
+    }
+
+    fn test_processor(temporal_alpha: f32, normalize_mode: NormalizeMode) -> DepthProcessor {
+        DepthProcessor::new(temporal_alpha, 0.0, 0.0, 0.0, normalize_mode)
+    }
+
+    #[test]
+    fn running_range_starts_at_the_first_frame() {
+        let mut processor = test_processor(1.0, NormalizeMode::RunningEMA);
+        let out = processor.process(array![[2.0, 4.0], [6.0, 10.0]]);
+        assert_close(&out, &array![[0.0, 0.25], [0.5, 1.0]]);
+    }
+
+    #[test]
+    fn running_range_survives_flat_frames() {
+        // A flat first frame collapses the range to a point.
+        let mut processor = test_processor(1.0, NormalizeMode::RunningEMA);
+        let out = processor.process(Array2::from_elem((2, 2), 7.0));
+        assert_close(&out, &Array2::from_elem((2, 2), 0.5));
+        let out = processor.process(array![[0.0, 7.0], [7.0, 20.0]]);
+        assert!(out.iter().all(|v| (0.0..=1.0).contains(v)), "{:?}", out);
+
+        // A flat frame after a ranged one only nudges the range.
+        let mut processor = test_processor(1.0, NormalizeMode::RunningEMA);
+        processor.process(array![[0.0, 10.0]]);
+        let out = processor.process(array![[5.0, 5.0]]);
+        assert_close(&out, &array![[0.5, 0.5]]);
+    }
+
+    #[test]
+    fn temporal_alpha_is_the_current_frame_weight() {
+        let frames = || [array![[0.0, 1.0]], array![[1.0, 0.0]]];
+        let second = |alpha: f32| {
+            let mut processor = test_processor(alpha, NormalizeMode::PerFrame);
+            let [first, second] = frames();
+            processor.process(first);
+            processor.process(second)
+        };
+        assert_close(&second(0.5), &array![[0.5, 0.5]]);
+        assert_close(&second(0.25), &array![[0.25, 0.75]]);
+        // 1 passes frames through, and 0 means off rather than frozen.
+        assert_close(&second(1.0), &array![[1.0, 0.0]]);
+        assert_close(&second(0.0), &array![[1.0, 0.0]]);
+        assert_close(&second(-1.0), &array![[1.0, 0.0]]);
+        assert_close(&second(2.0), &array![[1.0, 0.0]]);
+    }
+
+    fn checkerboard(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb(if (x + y) % 2 == 0 { [255; 3] } else { [0; 3] })
+        })
+    }
+
+    #[test]
+    fn low_texture_depth_is_flattened() {
+        let noisy =
+            Array2::from_shape_fn((16, 16), |(y, x)| if (x + y) % 2 == 0 { 0.4 } else { 0.6 });
+        let blank = RgbImage::from_pixel(16, 16, image::Rgb([128; 3]));
+        let out = suppress_lowtexture(&noisy, &blank, 0.01);
+        assert!(out.iter().all(|v| (v - 0.5).abs() < 0.05), "{:?}", out);
+    }
+
+    #[test]
+    fn textured_depth_is_untouched() {
+        let depth = Array2::from_shape_fn((16, 16), |(y, x)| (x + y) as f32 / 30.0);
+        let out = suppress_lowtexture(&depth, &checkerboard(16, 16), 0.01);
+        assert_close(&out, &depth);
+    }
+
+    #[test]
+    fn low_texture_suppression_is_off_at_zero_threshold_and_for_empty_depth() {
+        let depth = Array2::from_shape_fn((8, 8), |(y, x)| (x * y) as f32 / 49.0);
+        let blank = RgbImage::from_pixel(8, 8, image::Rgb([128; 3]));
+        assert_close(&suppress_lowtexture(&depth, &blank, 0.0), &depth);
+        assert_eq!(suppress_lowtexture(&Array2::zeros((0, 0)), &blank, 0.01).dim(), (0, 0));
+    }
+
+    #[test]
+    fn textured_foreground_does_not_bleed_into_a_flat_background() {
+        // Left half is a blank wall at depth 0.2, right half a textured object at 0.9.
+        let mut rgb = checkerboard(32, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                rgb.put_pixel(x, y, image::Rgb([128; 3]));
+            }
+        }
+        let depth = Array2::from_shape_fn((16, 32), |(_, x)| if x < 16 { 0.2 } else { 0.9 });
+        let out = suppress_lowtexture(&depth, &rgb, 0.01);
+        for y in 0..16 {
+            assert!((out[[y, 2]] - 0.2).abs() < 0.02, "{}", out[[y, 2]]);
+            assert_eq!(out[[y, 28]], 0.9);
+        }
+    }
+
+    #[test]
+    fn convergence_holds_steady_on_a_static_scene() {
+        let frame = |k: usize| {
+            Array2::from_shape_fn((16, 16), |(y, x)| {
+                let noise = if x < 8 { 0.03 * (k % 3) as f32 - 0.03 } else { 0.0 };
+                (y * 16 + x) as f32 / 255.0 + noise
+            })
+        };
+        let frames: Vec<Array2<f32>> = (0..30).map(frame).collect();
+
+        let mut running = test_processor(1.0, NormalizeMode::RunningEMA);
+        let (mut medians, mut smoothed) = (Vec::new(), Vec::new());
+        for depth in &frames {
+            medians.push(median_depth(&normalize_minmax(depth.clone())));
+            running.process(depth.clone());
+            smoothed.push(running.convergence());
+        }
+        let largest_step = |values: &[f32]| {
+            values.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max)
+        };
+        assert!(largest_step(&medians) > 0.01);
+        assert!(largest_step(&smoothed) < largest_step(&medians) / 5.0);
+
+        let mut global = test_processor(1.0, NormalizeMode::Global);
+        frames.iter().for_each(|depth| global.update_global_range(depth));
+        let planes: Vec<f32> = frames
+            .iter()
+            .map(|depth| {
+                global.process(depth.clone());
+                global.convergence()
+            })
+            .collect();
+        assert!(planes.iter().all(|&c| c == planes[0]));
+    }
+
+    #[test]
+    fn disparity_scale_is_bounded() {
+        assert_eq!(disparity_scale(TARGET_DEPTH_SPREAD), 1.0);
+        assert_eq!(disparity_scale(0.0), MAX_DISPARITY_SCALE);
+        assert_eq!(disparity_scale(-1.0), MAX_DISPARITY_SCALE);
+        assert_eq!(disparity_scale(0.01), MAX_DISPARITY_SCALE);
+        assert_eq!(disparity_scale(10.0), MIN_DISPARITY_SCALE);
+        assert!((disparity_scale(0.4) - 1.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn depth_spread_ignores_stray_pixels() {
+        let mut depth = Array2::from_shape_fn((1, 100), |(_, x)| 0.25 + x as f32 / 400.0);
+        depth[[0, 0]] = -50.0;
+        depth[[0, 99]] = f32::NAN;
+        depth[[0, 98]] = 50.0;
+        let spread = depth_spread(&depth);
+        assert!(spread > 0.2 && spread < 0.25, "{}", spread);
+        assert_eq!(depth_spread(&Array2::zeros((0, 0))), 0.0);
+        assert_eq!(depth_spread(&Array2::from_elem((2, 2), f32::NAN)), 0.0);
+    }
+
+    #[test]
+    fn fusion_keeps_coarse_structure_and_fine_detail() {
+        // The coarse map rises left to right; the fine map falls, with a checkerboard on top.
+        let coarse = Array2::from_shape_fn((8, 8), |(_, x)| x as f32 / 7.0);
+        let fine = Array2::from_shape_fn((64, 64), |(y, x)| {
+            let checker = if (x + y) % 2 == 0 { 0.1 } else { -0.1 };
+            1.0 - x as f32 / 63.0 + checker
+        });
+        let fused = fuse_depth(&coarse, &fine);
+        assert_eq!(fused.dim(), (64, 64));
+
+        let column_mean = |x: usize| fused.column(x).mean().unwrap();
+        assert!(column_mean(8) < column_mean(32) && column_mean(32) < column_mean(56));
+        for (y, x) in [(20, 20), (31, 40), (45, 12)] {
+            let step = fused[[y, x]] - fused[[y, x + 1]];
+            let expected_sign = if (x + y) % 2 == 0 { 1.0 } else { -1.0 };
+            assert!(step * expected_sign > 0.05, "checkerboard lost at ({}, {}): {}", y, x, step);
+        }
+    }
+
 }
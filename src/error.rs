@@ -9,6 +9,8 @@ pub enum SpatialError {
 	TensorError(String),
 	IoError(String),
 	ConfigError(String),
+	MissingTool { tool: String, install_hint: String },
+	Cancelled,
 	Other(String),
 }
 
@@ -20,6 +22,10 @@ impl fmt::Display for SpatialError {
 			SpatialError::TensorError(msg) => write!(f, "Tensor error: {}", msg),
 			SpatialError::IoError(msg) => write!(f, "I/O error: {}", msg),
 			SpatialError::ConfigError(msg) => write!(f, "Configuration error: {}", msg),
+			SpatialError::MissingTool { tool, install_hint } => {
+				write!(f, "`{}` was not found in PATH. {}", tool, install_hint)
+			}
+			SpatialError::Cancelled => write!(f, "Cancelled"),
 			SpatialError::Other(msg) => write!(f, "Error: {}", msg),
 		}
 	}
@@ -38,3 +44,110 @@ impl From<image::ImageError> for SpatialError {
 		SpatialError::ImageError(e.to_string())
 	}
 }
+
+/// Exit status for any failure without a more specific code below.
+pub const EXIT_FAILURE: i32 = 1;
+/// Invalid arguments or configuration (clap uses 2 for parse errors too).
+pub const EXIT_USAGE: i32 = 2;
+/// A required external tool such as ffmpeg or `spatial` is missing.
+pub const EXIT_MISSING_TOOL: i32 = 3;
+/// The model couldn't be found, downloaded, verified or loaded.
+pub const EXIT_MODEL: i32 = 4;
+/// A batch finished, but some inputs or outputs failed.
+pub const EXIT_PARTIAL: i32 = 5;
+/// The run was interrupted (128 + SIGINT, as shells report it).
+pub const EXIT_CANCELLED: i32 = 130;
+
+/// The CLI exit status for a run that failed with `err`.
+pub fn exit_code(err: &SpatialError) -> i32 {
+	match err {
+		SpatialError::ConfigError(_) => EXIT_USAGE,
+		SpatialError::MissingTool { .. } => EXIT_MISSING_TOOL,
+		SpatialError::ModelError(_) => EXIT_MODEL,
+		SpatialError::Cancelled => EXIT_CANCELLED,
+		SpatialError::ImageError(_)
+		| SpatialError::TensorError(_)
+		| SpatialError::IoError(_)
+		| SpatialError::Other(_) => EXIT_FAILURE,
+	}
+}
+
+/// A non-fatal condition worth surfacing alongside a successful result.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Warning {
+	/// The native decoder failed and ffmpeg was used instead.
+	DecoderFallback { format: String, reason: String },
+	/// An embedded ICC profile couldn't be applied; colors may shift.
+	ColorProfile { reason: String },
+	/// A 16-bit or float image was reduced to 8 bits per channel.
+	PrecisionReduced { color: String },
+	/// The input already looks like a stereo pair.
+	AlreadyStereo,
+	/// `max_disparity` was lowered to fit the image width.
+	DisparityClamped { requested: u32, clamped: u32, image_width: u32 },
+	/// The depth map had no usable range.
+	FlatDepth { ground_plane: bool },
+	/// HDR video was converted to SDR BT.709.
+	HdrToneMapped { transfer: String, zscale: bool },
+	/// A requested output isn't supported for this input and was not written.
+	OutputSkipped { output: String, reason: String },
+	/// A requested option has no effect for this input.
+	OptionIgnored { option: String, reason: String },
+	/// Much of the warped view had to be filled, so `max_disparity` is likely
+	/// too high for the scene.
+	HighDisocclusion { fraction: f32, max_disparity: u32 },
+	/// The input exceeded `SpatialConfig::max_depth_pixels`, so depth was
+	/// estimated on a downscaled copy and upsampled.
+	DepthDownscaled { width: u32, height: u32, max_pixels: u64 },
+}
+
+impl fmt::Display for Warning {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Warning::DecoderFallback { format, reason } => {
+				write!(f, "Native {} decoder failed ({}), fell back to ffmpeg", format, reason)
+			}
+			Warning::ColorProfile { reason } => write!(f, "{}; colors may shift", reason),
+			Warning::PrecisionReduced { color } => write!(f, "{} input was reduced to 8 bits per channel", color),
+			Warning::AlreadyStereo => write!(
+				f,
+				"Input looks like a stereo pair already; pass --input-is-stereo to repackage it without depth estimation"
+			),
+			Warning::DisparityClamped { requested, clamped, image_width } => write!(
+				f,
+				"max_disparity {} exceeds 15% of image width ({}px), clamped to {}",
+				requested, image_width, clamped
+			),
+			Warning::FlatDepth { ground_plane: true } => {
+				write!(f, "Scene has no depth variation; applied ground-plane parallax")
+			}
+			Warning::FlatDepth { ground_plane: false } => {
+				write!(f, "Scene has no depth variation; stereo output will look flat")
+			}
+			Warning::HdrToneMapped { transfer, zscale: true } => {
+				write!(f, "HDR input ({}) was tone-mapped to SDR BT.709", transfer)
+			}
+			Warning::HdrToneMapped { transfer, zscale: false } => write!(
+				f,
+				"HDR input ({}) converted to SDR without the zscale filter; output may look washed out",
+				transfer
+			),
+			Warning::OutputSkipped { output, reason } => write!(f, "Skipped {} output: {}", output, reason),
+			Warning::OptionIgnored { option, reason } => write!(f, "Ignored {}: {}", option, reason),
+			Warning::HighDisocclusion { fraction, max_disparity } => write!(
+				f,
+				"{:.1}% of the warped view was disoccluded at max_disparity {}; consider lowering it",
+				fraction * 100.0,
+				max_disparity
+			),
+			Warning::DepthDownscaled { width, height, max_pixels } => write!(
+				f,
+				"{}x{} input exceeds the {:.0} MP depth budget; estimated depth on a downscaled copy",
+				width,
+				height,
+				*max_pixels as f64 / 1e6
+			),
+		}
+	}
+}
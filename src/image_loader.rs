@@ -1,10 +1,22 @@
-use crate::error::{SpatialError, SpatialResult};
-use image::DynamicImage;
+use crate::error::{SpatialError, SpatialResult, Warning};
+use crate::tools::require_tool;
+use image::{DynamicImage, ImageDecoder};
 use std::path::Path;
 use std::process::Command;
 
 pub async fn load_image(path: impl AsRef<Path>) -> SpatialResult<DynamicImage> {
+	let (img, warnings) = load_image_with_warnings(path).await?;
+	for warning in warnings {
+		tracing::warn!("{}", warning);
+	}
+	Ok(img)
+}
+
+/// Like `load_image`, but returns decoder fallbacks, color profile problems
+/// and precision loss instead of logging them.
+pub async fn load_image_with_warnings(path: impl AsRef<Path>) -> SpatialResult<(DynamicImage, Vec<Warning>)> {
 	let path = path.as_ref();
+	let mut warnings = Vec::new();
 
 	if !path.exists() {
 		return Err(SpatialError::ImageError(format!(
@@ -19,35 +31,147 @@ pub async fn load_image(path: impl AsRef<Path>) -> SpatialResult<DynamicImage> {
 		.map(|s| s.to_lowercase())
 		.ok_or_else(|| SpatialError::ImageError(format!("File has no extension: {:?}", path)))?;
 
-	match extension.as_str() {
-		"avif" => load_avif(path).await,
-		"jxl" => load_jxl(path).await,
-		"heic" | "heif" => load_heic(path).await,
-		"jpg" | "jpeg" | "png" | "gif" | "bmp" | "tiff" | "tif" | "webp" => load_standard(path),
-		_ => Err(SpatialError::ImageError(format!(
-			"Unsupported image format: .{}",
-			extension
+	let img = match extension.as_str() {
+		"avif" => load_avif(path, &mut warnings).await?,
+		"jxl" => load_jxl(path).await?,
+		"heic" | "heif" => load_heic(path, &mut warnings).await?,
+		"jpg" | "jpeg" | "png" | "gif" | "bmp" | "tiff" | "tif" | "webp" => load_standard(path, &mut warnings)?,
+		_ => {
+			return Err(SpatialError::ImageError(format!(
+				"Unsupported image format: .{}",
+				extension
+			)))
+		}
+	};
+
+	let img = to_supported_color(img, path, &mut warnings)?;
+	Ok((img, warnings))
+}
+
+/// Converts decoded images to 8-bit RGB(A), which is what the estimators and
+/// stereo warp expect.
+fn to_supported_color(img: DynamicImage, path: &Path, warnings: &mut Vec<Warning>) -> SpatialResult<DynamicImage> {
+	use image::ColorType;
+
+	match img.color() {
+		ColorType::Rgb8 | ColorType::Rgba8 => Ok(img),
+		ColorType::L8 => {
+			tracing::debug!("Converting {:?} {:?} to RGB8", path, img.color());
+			Ok(DynamicImage::ImageRgb8(img.to_rgb8()))
+		}
+		ColorType::La8 => {
+			tracing::debug!("Converting {:?} {:?} to RGBA8", path, img.color());
+			Ok(DynamicImage::ImageRgba8(img.to_rgba8()))
+		}
+		ColorType::L16 => {
+			tracing::debug!("Converting {:?} {:?} to RGB8", path, img.color());
+			warnings.push(Warning::PrecisionReduced { color: format!("{:?}", img.color()) });
+			Ok(DynamicImage::ImageRgb8(img.to_rgb8()))
+		}
+		ColorType::La16 | ColorType::Rgba16 | ColorType::Rgba32F => {
+			tracing::debug!("Converting {:?} {:?} to RGBA8", path, img.color());
+			warnings.push(Warning::PrecisionReduced { color: format!("{:?}", img.color()) });
+			Ok(DynamicImage::ImageRgba8(img.to_rgba8()))
+		}
+		ColorType::Rgb16 | ColorType::Rgb32F => {
+			tracing::debug!("Converting {:?} {:?} to RGB8", path, img.color());
+			warnings.push(Warning::PrecisionReduced { color: format!("{:?}", img.color()) });
+			Ok(DynamicImage::ImageRgb8(img.to_rgb8()))
+		}
+		other => Err(SpatialError::ImageError(format!(
+			"Unsupported color type {:?} in {:?}",
+			other, path
 		))),
 	}
 }
 
-fn load_standard(path: impl AsRef<Path>) -> SpatialResult<DynamicImage> {
+fn load_standard(path: impl AsRef<Path>, warnings: &mut Vec<Warning>) -> SpatialResult<DynamicImage> {
 	let path = path.as_ref();
-	let img = image::open(path)
-		.map_err(|e| SpatialError::ImageError(format!("Failed to load image {:?}: {}", path, e)))?;
-	Ok(img)
+	open_color_managed(path, warnings)
+		.map_err(|e| SpatialError::ImageError(format!("Failed to load image {:?}: {}", path, e)))
 }
 
-async fn load_avif(path: &Path) -> SpatialResult<DynamicImage> {
+fn open_color_managed(path: &Path, warnings: &mut Vec<Warning>) -> image::ImageResult<DynamicImage> {
+	let mut decoder = image::ImageReader::open(path)?
+		.with_guessed_format()?
+		.into_decoder()?;
+	let icc = decoder.icc_profile().ok().flatten();
+	if matches!(
+		decoder.original_color_type(),
+		image::ExtendedColorType::Cmyk8 | image::ExtendedColorType::Cmyk16
+	) {
+		tracing::debug!("Converting CMYK {:?} to RGB", path);
+	}
+	let orientation = decoder.orientation().unwrap_or(image::metadata::Orientation::NoTransforms);
+	let mut img = DynamicImage::from_decoder(decoder)?;
+	// Work in display space so disparity runs along the viewer's horizontal.
+	img.apply_orientation(orientation);
+	Ok(match icc {
+		Some(icc) => convert_to_srgb(img, &icc, path, warnings),
+		None => img,
+	})
+}
+
+/// Converts pixels tagged with an embedded ICC profile (Display P3, Adobe RGB, ...)
+/// to sRGB so outputs, which carry no profile, look the same as the input.
+fn convert_to_srgb(img: DynamicImage, icc: &[u8], path: &Path, warnings: &mut Vec<Warning>) -> DynamicImage {
+	let profile = match moxcms::ColorProfile::new_from_slice(icc) {
+		Ok(profile) => profile,
+		Err(e) => {
+			warnings.push(Warning::ColorProfile {
+				reason: format!("Ignoring unreadable ICC profile in {:?} ({:?})", path, e),
+			});
+			return img;
+		}
+	};
+	if profile.color_space != moxcms::DataColorSpace::Rgb {
+		return img;
+	}
+
+	let srgb = moxcms::ColorProfile::new_srgb();
+	let has_alpha = img.color().has_alpha();
+	let layout = if has_alpha { moxcms::Layout::Rgba } else { moxcms::Layout::Rgb };
+	let transform = match profile.create_transform_8bit(layout, &srgb, layout, moxcms::TransformOptions::default()) {
+		Ok(transform) => transform,
+		Err(e) => {
+			warnings.push(Warning::ColorProfile {
+				reason: format!("Cannot convert ICC profile in {:?} to sRGB ({:?})", path, e),
+			});
+			return img;
+		}
+	};
+
+	let (width, height) = (img.width(), img.height());
+	let src = if has_alpha { img.to_rgba8().into_raw() } else { img.to_rgb8().into_raw() };
+	let mut dst = vec![0u8; src.len()];
+	if let Err(e) = transform.transform(&src, &mut dst) {
+		warnings.push(Warning::ColorProfile {
+			reason: format!("ICC conversion failed for {:?} ({:?})", path, e),
+		});
+		return img;
+	}
+
+	let converted = if has_alpha {
+		image::RgbaImage::from_raw(width, height, dst).map(DynamicImage::ImageRgba8)
+	} else {
+		image::RgbImage::from_raw(width, height, dst).map(DynamicImage::ImageRgb8)
+	};
+	converted.unwrap_or(img)
+}
+
+async fn load_avif(path: &Path, warnings: &mut Vec<Warning>) -> SpatialResult<DynamicImage> {
 	#[cfg(feature = "avif")]
 	{
-		match image::open(path) {
+		match open_color_managed(path, warnings) {
 			Ok(img) => return Ok(img),
-			Err(e) => {
-				tracing::warn!("Native AVIF decoder failed: {}, falling back to ffmpeg", e);
-			}
+			Err(e) => warnings.push(Warning::DecoderFallback {
+				format: "AVIF".to_string(),
+				reason: e.to_string(),
+			}),
 		}
 	}
+	#[cfg(not(feature = "avif"))]
+	let _ = warnings;
 	load_with_ffmpeg(path, "avif").await
 }
 
@@ -62,16 +186,19 @@ async fn load_jxl(path: &Path) -> SpatialResult<DynamicImage> {
 	}
 }
 
-async fn load_heic(path: &Path) -> SpatialResult<DynamicImage> {
+async fn load_heic(path: &Path, warnings: &mut Vec<Warning>) -> SpatialResult<DynamicImage> {
 	#[cfg(feature = "heic")]
 	{
-		match load_heic_native(path) {
+		match load_heic_native(path, warnings) {
 			Ok(img) => return Ok(img),
-			Err(e) => {
-				tracing::warn!("Native HEIC decoder failed: {}, falling back to ffmpeg", e);
-			}
+			Err(e) => warnings.push(Warning::DecoderFallback {
+				format: "HEIC".to_string(),
+				reason: e.to_string(),
+			}),
 		}
 	}
+	#[cfg(not(feature = "heic"))]
+	let _ = warnings;
 	load_with_ffmpeg(path, "heic").await
 }
 
@@ -129,7 +256,7 @@ fn load_jxl_native(path: &Path) -> SpatialResult<DynamicImage> {
 }
 
 #[cfg(feature = "heic")]
-fn load_heic_native(path: &Path) -> SpatialResult<DynamicImage> {
+fn load_heic_native(path: &Path, warnings: &mut Vec<Warning>) -> SpatialResult<DynamicImage> {
 	use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
 
 	let lib_heif = LibHeif::new();
@@ -145,6 +272,7 @@ fn load_heic_native(path: &Path) -> SpatialResult<DynamicImage> {
 
 	let width = handle.width();
 	let height = handle.height();
+	let icc = handle.color_profile_raw().map(|p| p.data);
 
 	let image = lib_heif
 		.decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)
@@ -166,27 +294,18 @@ fn load_heic_native(path: &Path) -> SpatialResult<DynamicImage> {
 		SpatialError::ImageError("Failed to create image buffer from HEIC data".to_string())
 	})?;
 
-	Ok(DynamicImage::ImageRgb8(img_buffer))
+	let img = DynamicImage::ImageRgb8(img_buffer);
+	Ok(match icc {
+		Some(icc) => convert_to_srgb(img, &icc, path, warnings),
+		None => img,
+	})
 }
 
 async fn load_with_ffmpeg(path: &Path, format: &str) -> SpatialResult<DynamicImage> {
-	if !is_ffmpeg_available() {
-		return Err(SpatialError::ImageError(format!(
-			"{} format requires ffmpeg for conversion (not installed or not in PATH)",
-			format.to_uppercase()
-		)));
-	}
+	require_tool("ffmpeg")?;
 
-	let temp_dir = std::env::temp_dir();
-	let temp_filename = format!(
-		"spatial_maker_convert_{}_{}.jpg",
-		format,
-		std::time::SystemTime::now()
-			.duration_since(std::time::UNIX_EPOCH)
-			.unwrap_or_default()
-			.as_millis()
-	);
-	let temp_path = temp_dir.join(temp_filename);
+	let temp_file = crate::temp::temp_file(".jpg")?;
+	let temp_path = temp_file.path();
 
 	let input_str = path
 		.to_str()
@@ -209,20 +328,83 @@ async fn load_with_ffmpeg(path: &Path, format: &str) -> SpatialResult<DynamicIma
 		)));
 	}
 
-	let img = image::open(&temp_path).map_err(|e| {
-		let _ = std::fs::remove_file(&temp_path);
+	let img = image::open(temp_path).map_err(|e| {
 		SpatialError::ImageError(format!("Failed to load converted image: {}", e))
 	})?;
 
-	let _ = std::fs::remove_file(&temp_path);
-
 	Ok(img)
 }
 
-fn is_ffmpeg_available() -> bool {
-	Command::new("ffmpeg")
-		.arg("-version")
-		.output()
-		.map(|output| output.status.success())
-		.unwrap_or(false)
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use image::{ImageBuffer, Luma, Rgb};
+
+	#[test]
+	fn rotated_photo_gets_disparity_along_its_displayed_rows() {
+		use image::ImageEncoder;
+
+		let stored = image::RgbImage::from_fn(8, 4, |x, y| Rgb([x as u8 * 30, y as u8 * 60, 7]));
+		let rotate_90_cw: &[u8] = &[
+			b'I', b'I', 42, 0, 8, 0, 0, 0, 1, 0, 0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0,
+		];
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("rotated.png");
+		let mut encoder = image::codecs::png::PngEncoder::new(std::fs::File::create(&path).unwrap());
+		encoder.set_exif_metadata(rotate_90_cw.to_vec()).unwrap();
+		encoder.write_image(stored.as_raw(), 8, 4, image::ExtendedColorType::Rgb8).unwrap();
+
+		let img = load_standard(&path, &mut Vec::new()).unwrap();
+		assert_eq!((img.width(), img.height()), (4, 8));
+		let display = img.to_rgb8();
+		for (x, y, pixel) in display.enumerate_pixels() {
+			assert_eq!(pixel, stored.get_pixel(y, 3 - x));
+		}
+
+		let depth = ndarray::Array2::from_shape_fn((8, 4), |(_, x)| x as f32 / 3.0);
+		let (_, right) = crate::stereo::generate_stereo_pair(&img, &depth, 2).unwrap();
+		let right = right.to_rgb8();
+		assert_ne!(right, display);
+		for (_, y, pixel) in right.enumerate_pixels() {
+			assert_eq!(pixel[0], y as u8 * 30, "row {} took a pixel from another displayed row", y);
+		}
+	}
+
+	#[test]
+	fn grayscale_becomes_rgb8() {
+		let gray = DynamicImage::ImageLuma8(ImageBuffer::from_fn(2, 1, |x, _| Luma([x as u8 * 200])));
+		let mut warnings = Vec::new();
+		let img = to_supported_color(gray, Path::new("gray.png"), &mut warnings).unwrap();
+
+		let rgb = img.as_rgb8().expect("RGB8");
+		assert_eq!(rgb.get_pixel(0, 0), &Rgb([0, 0, 0]));
+		assert_eq!(rgb.get_pixel(1, 0), &Rgb([200, 200, 200]));
+		assert!(warnings.is_empty());
+	}
+
+	#[test]
+	fn rgb16_is_scaled_to_rgb8() {
+		let deep = DynamicImage::ImageRgb16(ImageBuffer::from_fn(3, 1, |x, _| match x {
+			0 => Rgb([0, 0, 0]),
+			1 => Rgb([u16::MAX, 0, 32896]),
+			_ => Rgb([257 * 100, 257 * 200, 257 * 50]),
+		}));
+		let mut warnings = Vec::new();
+		let img = to_supported_color(deep, Path::new("deep.tif"), &mut warnings).unwrap();
+
+		let rgb = img.as_rgb8().expect("RGB8");
+		assert_eq!(rgb.get_pixel(0, 0), &Rgb([0, 0, 0]));
+		assert_eq!(rgb.get_pixel(1, 0), &Rgb([255, 0, 128]));
+		assert_eq!(rgb.get_pixel(2, 0), &Rgb([100, 200, 50]));
+		assert!(matches!(warnings.as_slice(), [Warning::PrecisionReduced { .. }]));
+	}
+
+	#[test]
+	fn rgb8_passes_through() {
+		let img = DynamicImage::new_rgb8(2, 2);
+		let mut warnings = Vec::new();
+		let out = to_supported_color(img.clone(), Path::new("plain.png"), &mut warnings).unwrap();
+		assert_eq!(out, img);
+		assert!(warnings.is_empty());
+	}
 }
@@ -1,10 +1,13 @@
 pub mod depth;
+pub mod depth_cache;
 pub mod depth_filter;
 pub mod error;
 pub mod image_loader;
 pub mod model;
 pub mod output;
 pub mod stereo;
+pub mod temp;
+pub mod tools;
 pub mod video;
 
 #[cfg(feature = "cli")]
@@ -13,17 +16,31 @@ pub mod tui;
 #[cfg(all(target_os = "macos", feature = "coreml"))]
 pub mod depth_coreml;
 
-pub use depth_filter::DepthProcessor;
-pub use error::{SpatialError, SpatialResult};
-pub use image_loader::load_image;
-pub use model::{find_model, get_checkpoint_dir, model_exists};
+pub use depth_filter::{normalize_depth, DepthProcessor, NormalizeStrategy};
+pub use error::{exit_code, SpatialError, SpatialResult, Warning};
+pub use image_loader::{load_image, load_image_with_warnings};
+pub use model::{
+	find_checkpoint, find_model, get_checkpoint_dir, load_manifest, model_exists, model_preprocess, set_checkpoint_dir, DownloadProgress, ManifestEntry,
+	ModelManifest, ModelMetadata, ModelPreprocess,
+};
 pub use output::{
-	create_sbs_image, save_stereo_image,
-	DepthFormat, ImageEncoding, MVHEVCConfig, OutputFormat, OutputOptions, OutputType,
-	depth_formats, load_depth_map, needs_depth, needs_stereo, parse_output_types, save_depth_map, stereo_types,
+	create_sbs_image, encode_mvhevc_from_eyes, encode_mvhevc_with_layout, save_stereo_image, save_stereo_image_with_paths,
+	DepthFormat, DepthNaming, ImageEncoding, MVHEVCConfig, SPATIAL_PHOTO_EXTENSION, SPATIAL_VIDEO_EXTENSION, OutputFormat, OutputOptions, OutputType,
+	depth_formats, is_depth_only, load_depth_map, needs_depth, needs_disparity_mask, needs_normals, needs_preview, needs_stereo, needs_stereo_clip, needs_views,
+	needs_aux_depth, parse_output_types, quantize_with_dither, save_depth_map, save_depth_map_with_dither, save_depth_maps, save_disparity_mask, save_normal_map,
+	create_preview_sheet, parse_preview_panels, save_preview_sheet, save_stereo_clip, save_views, PreviewPanel, save_with_aux_depth, stereo_types, validate_aux_depth, validate_heif,
+	validate_quality,
+};
+pub use tools::{backends_available, check_spatial_cli, Backends, SpatialCliInfo};
+pub use stereo::{
+	detect_stereo_layout, generate_multiview, generate_stereo_pair, generate_stereo_pair_with, generate_stereo_pair_with_mask,
+	generate_stereo_pair_with_progress, generate_stereo_pair_with_stats,
+	match_luminance, split_stereo_image, validate_max_disparity, validate_max_disparity_with_warnings, DisocclusionFill, StereoMode, StereoParams, StereoStats,
+};
+pub use video::{
+	extract_single_frame, get_video_metadata, is_stdin, process_video, process_video_with_warnings, select_video_codec, start_video_job, ColorInfo,
+	ImageSequence, ProcessVideoOutput, ProgressCallback, VideoCodec, VideoJob, VideoMetadata, VideoProgress, STDIN_PATH,
 };
-pub use stereo::{generate_stereo_pair, generate_stereo_pair_with_progress};
-pub use video::{get_video_metadata, process_video, ProgressCallback, VideoMetadata, VideoProgress};
 
 #[cfg(all(target_os = "macos", feature = "coreml"))]
 pub use depth_coreml::CoreMLDepthEstimator;
@@ -33,6 +50,8 @@ pub use depth::OnnxDepthEstimator;
 
 use std::path::Path;
 
+const DEPTH_ASPECT_TOLERANCE: f32 = 0.02;
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub enum NormalizeMode {
 	PerFrame,
@@ -68,31 +87,303 @@ impl std::str::FromStr for NormalizeMode {
 	}
 }
 
+/// Where the two-pass `NormalizeMode::Global` scan keeps raw depth between passes.
+///
+/// - `Reinfer` (default) keeps only the running min/max and runs the model again in
+///   the second pass: constant memory, but inference time doubles.
+/// - `Memory` keeps every frame's depth in RAM: no second inference, but uses
+///   about `width * height * 4` bytes per frame (~33 MB per 4K frame), so only
+///   suits short clips.
+/// - `Disk` streams depth to a temp file and reads it back: no second inference
+///   and little RAM, at the cost of the same amount of temp disk space.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum GlobalCache {
+	Memory,
+	Disk,
+	#[default]
+	Reinfer,
+}
+
+impl std::fmt::Display for GlobalCache {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Memory => write!(f, "memory"),
+			Self::Disk => write!(f, "disk"),
+			Self::Reinfer => write!(f, "reinfer"),
+		}
+	}
+}
+
+impl std::str::FromStr for GlobalCache {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"memory" | "mem" => Ok(Self::Memory),
+			"disk" => Ok(Self::Disk),
+			"reinfer" | "none" => Ok(Self::Reinfer),
+			_ => Err(format!("Unknown global cache: '{}'. Use: memory, disk, reinfer", s)),
+		}
+	}
+}
+
+/// How video depth is smoothed over time. `Ema` blends with the previous smoothed
+/// frame using `temporal_alpha` and reacts quickly; `Window(n)` takes a weighted
+/// average of the last `n` frames, which removes more flicker but lags motion by
+/// roughly `n / 3` frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum TemporalMode {
+	Off,
+	#[default]
+	Ema,
+	Window(usize),
+}
+
+impl std::fmt::Display for TemporalMode {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Off => write!(f, "off"),
+			Self::Ema => write!(f, "ema"),
+			Self::Window(n) => write!(f, "window:{}", n),
+		}
+	}
+}
+
+impl std::str::FromStr for TemporalMode {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let s = s.to_lowercase();
+		match s.as_str() {
+			"off" | "none" => Ok(Self::Off),
+			"ema" | "running" => Ok(Self::Ema),
+			_ => match s.strip_prefix("window:").map(str::parse::<usize>) {
+				Some(Ok(n)) if n >= 2 => Ok(Self::Window(n)),
+				Some(_) => Err(format!("Invalid window size in '{}': must be an integer >= 2", s)),
+				None => Err(format!("Unknown temporal mode: '{}'. Use: off, ema, window:N", s)),
+			},
+		}
+	}
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct SpatialConfig {
 	pub encoder_size: String,
 	pub max_disparity: u32,
-	pub target_depth_size: u32,
+	/// Square resolution the depth model runs at, rounded to a multiple of 14;
+	/// `None` uses the model's own input size. Lower is faster, higher keeps
+	/// finer detail; see `ModelPreprocess::with_input_size` for which models
+	/// accept sizes they weren't exported at.
+	pub target_depth_size: Option<u32>,
+	/// `None` uses the model's own normalization.
+	pub preprocess: Option<ModelPreprocess>,
+	/// Weight of the current frame in `TemporalMode::Ema`, from 0 to 1: 0.7 keeps
+	/// 70% of the new frame and 30% of the smoothed history. 1 disables
+	/// smoothing, and so does 0 (it would otherwise freeze on the first frame).
 	pub temporal_alpha: f32,
+	pub temporal_mode: TemporalMode,
 	pub bilateral_sigma_space: f32,
 	pub bilateral_sigma_color: f32,
 	pub depth_blur_sigma: f32,
+	/// Scale `bilateral_sigma_space` and `depth_blur_sigma` with `max_disparity`,
+	/// so stronger 3D gets proportionally more depth smoothing; see
+	/// `depth_filter::smoothing_scale`. The configured values apply as is at a
+	/// `max_disparity` of 30. Only video runs these filters.
+	pub adaptive_smoothing: bool,
+	pub edge_blur_only: bool,
+	pub input_is_stereo: bool,
 	pub normalize_mode: NormalizeMode,
+	pub global_cache: GlobalCache,
+	/// Frames per checkpointed video chunk; `None` runs in one pass.
+	pub chunk_frames: Option<u32>,
+	pub resume: bool,
+	pub num_threads: Option<usize>,
+	pub inter_threads: Option<usize>,
+	/// Enable the ONNX Runtime memory arena, which speeds up repeated inference
+	/// such as video frames at the cost of holding on to peak memory.
+	pub memory_arena: bool,
+	pub preserve_aspect: bool,
+	pub normal_strength: f32,
+	pub num_views: u32,
+	pub equalize_depth: bool,
+	pub equalize_bins: u32,
+	pub suppress_low_texture: bool,
+	/// Luminance variance (0-1) below which a region counts as textureless.
+	pub low_texture_threshold: f32,
+	pub flat_depth_fallback: bool,
+	pub fusion: bool,
+	/// Reuse photo depth maps across runs from this directory (see
+	/// `depth_cache`), so re-running with new stereo settings skips inference.
+	/// `None` disables the cache.
+	pub depth_cache_dir: Option<std::path::PathBuf>,
+	/// Pixel budget for depth estimation; 0 disables the limit.
+	pub max_depth_pixels: u64,
+	pub skip_duplicate_frames: bool,
+	pub match_luminance: bool,
+	/// Extra disparity toward the bottom of the frame (0-0.25 of `max_disparity`);
+	/// see `StereoParams::ground_plane_bias`.
+	pub ground_plane_bias: f32,
+	/// Taper disparity to zero near the left and right frame edges; see
+	/// `StereoParams::floating_window`.
+	pub floating_window: bool,
+	/// Taper band width as a fraction of the image width.
+	pub floating_window_width: f32,
+	/// Clamp isolated disparity spikes to their neighborhood median; see
+	/// `StereoParams::disparity_median_clamp`.
+	pub disparity_median_clamp: bool,
+	/// The input is a 360° equirectangular panorama: the warp wraps across the
+	/// left/right seam and stereo is written over-under, tagged as 360 for VR
+	/// viewers.
+	pub equirect: bool,
+	/// How disocclusions are filled; `Black` leaves them visible for debugging.
+	pub disocclusion_fill: DisocclusionFill,
+	/// Which eyes are warped; see `StereoMode::Symmetric` for the tradeoff.
+	pub stereo_mode: StereoMode,
+	/// Put the median depth on the screen plane instead of the farthest point.
+	/// For video the plane is held steady across frames; see
+	/// `DepthProcessor::convergence`.
+	pub auto_convergence: bool,
+	/// Scale `max_disparity` per image (0.5-2x) so scenes with shallow and deep
+	/// depth ranges come out with a similar amount of 3D. For video the scale
+	/// follows the depth spread smoothed across frames; see
+	/// `DepthProcessor::disparity_scale`.
+	pub auto_disparity: bool,
+	/// `None` uses the output container's default.
+	pub video_codec: Option<VideoCodec>,
+	/// Extension of spatial (MV-HEVC) videos, without the dot; `None` uses
+	/// `SPATIAL_VIDEO_EXTENSION`. Photos take theirs from `MVHEVCConfig::extension`.
+	pub spatial_extension: Option<String>,
+	/// Approximate size in MB to fit stereo video output into, via a bitrate
+	/// computed from the duration; `None` encodes at the codec's constant quality.
+	pub target_size_mb: Option<f64>,
+	/// Frame rate for image sequence input, which has none of its own.
+	pub sequence_fps: f64,
+	/// Frame rate for video output; `None` keeps the source rate. See
+	/// `VideoMetadata::with_output_fps`.
+	pub output_fps: Option<f64>,
+	/// Seconds to wait for ffmpeg to decode each video frame before treating the
+	/// input as stalled and failing; 0 waits indefinitely.
+	pub frame_timeout_secs: f64,
+	/// Extra ffmpeg options for the stereo video encoder, e.g. `["-tune", "film"]`.
+	pub extra_encoder_args: Vec<String>,
+	/// Extra ffmpeg options for video frame extraction.
+	pub extra_extract_args: Vec<String>,
 }
 
 pub type StereoOutputFormat = OutputFormat;
 
+impl SpatialConfig {
+	pub const PRESETS: &'static [&'static str] = &["portrait", "landscape", "subtle", "strong", "video-stable"];
+
+	/// Default config with tuned values for a named preset:
+	///
+	/// - `portrait`: max_disparity 25, bilateral 7.0/0.05, depth blur 1.0 on edges only
+	/// - `landscape`: max_disparity 40, depth blur 2.0
+	/// - `subtle`: max_disparity 15
+	/// - `strong`: max_disparity 60, depth blur 2.0
+	/// - `video-stable`: global normalization, temporal_alpha 0.5 (stronger smoothing)
+	pub fn preset(name: &str) -> SpatialResult<Self> {
+		let base = Self::default();
+		let config = match name.to_lowercase().as_str() {
+			"portrait" => Self {
+				max_disparity: 25,
+				bilateral_sigma_space: 7.0,
+				bilateral_sigma_color: 0.05,
+				depth_blur_sigma: 1.0,
+				edge_blur_only: true,
+				..base
+			},
+			"landscape" => Self {
+				max_disparity: 40,
+				depth_blur_sigma: 2.0,
+				..base
+			},
+			"subtle" => Self {
+				max_disparity: 15,
+				..base
+			},
+			"strong" => Self {
+				max_disparity: 60,
+				depth_blur_sigma: 2.0,
+				..base
+			},
+			"video-stable" => Self {
+				normalize_mode: NormalizeMode::Global,
+				temporal_alpha: 0.5,
+				..base
+			},
+			_ => {
+				return Err(SpatialError::ConfigError(format!(
+					"Unknown preset: '{}'. Use: {}",
+					name,
+					Self::PRESETS.join(", ")
+				)))
+			}
+		};
+		Ok(config)
+	}
+
+	/// The preprocessing `encoder_size`'s model is run with: `preprocess`, or
+	/// the model's own, with the validated, rounded `target_depth_size` applied.
+	pub fn depth_preprocess(&self) -> SpatialResult<ModelPreprocess> {
+		let preprocess = self
+			.preprocess
+			.or_else(|| model::model_preprocess(&self.encoder_size))
+			.unwrap_or_default();
+		preprocess.with_input_size(self.target_depth_size.unwrap_or(preprocess.input_size))
+	}
+}
+
 impl Default for SpatialConfig {
 	fn default() -> Self {
 		Self {
 			encoder_size: "s".to_string(),
 			max_disparity: 30,
-			target_depth_size: 518,
+			target_depth_size: None,
+			preprocess: None,
 			temporal_alpha: 0.7,
+			temporal_mode: TemporalMode::Ema,
 			bilateral_sigma_space: 5.0,
 			bilateral_sigma_color: 0.1,
 			depth_blur_sigma: 1.5,
+			adaptive_smoothing: false,
+			edge_blur_only: false,
+			input_is_stereo: false,
 			normalize_mode: NormalizeMode::RunningEMA,
+			global_cache: GlobalCache::default(),
+			chunk_frames: None,
+			resume: false,
+			num_threads: None,
+			inter_threads: None,
+			memory_arena: false,
+			preserve_aspect: false,
+			normal_strength: 1.0,
+			num_views: 5,
+			equalize_depth: false,
+			equalize_bins: 256,
+			suppress_low_texture: false,
+			low_texture_threshold: 4e-4,
+			flat_depth_fallback: true,
+			fusion: false,
+			depth_cache_dir: None,
+			max_depth_pixels: 50_000_000,
+			skip_duplicate_frames: false,
+			match_luminance: false,
+			ground_plane_bias: 0.0,
+			floating_window: false,
+			floating_window_width: 0.05,
+			disparity_median_clamp: false,
+			equirect: false,
+			disocclusion_fill: DisocclusionFill::default(),
+			stereo_mode: StereoMode::default(),
+			auto_convergence: false,
+			auto_disparity: false,
+			video_codec: None,
+			spatial_extension: None,
+			target_size_mb: None,
+			sequence_fps: 24.0,
+			output_fps: None,
+			frame_timeout_secs: 60.0,
+			extra_encoder_args: Vec::new(),
+			extra_extract_args: Vec::new(),
 		}
 	}
 }
@@ -100,8 +391,93 @@ impl Default for SpatialConfig {
 pub struct ProcessPhotoOutput {
 	pub depth_paths: Vec<std::path::PathBuf>,
 	pub stereo_paths: Vec<std::path::PathBuf>,
+	pub normal_paths: Vec<std::path::PathBuf>,
+	pub view_paths: Vec<std::path::PathBuf>,
+	pub sidecar_path: Option<std::path::PathBuf>,
+	/// Disocclusion stats for the warped pair; `None` when stereo wasn't warped.
+	pub stereo_stats: Option<StereoStats>,
+	/// The final depth map used for stereo, when `OutputOptions::keep_depth` is set.
+	pub depth_map: Option<ndarray::Array2<f32>>,
+	pub errors: Vec<(std::path::PathBuf, SpatialError)>,
+	/// Non-fatal issues encountered while producing the outputs.
+	pub warnings: Vec<Warning>,
+}
+
+/// What this build and machine can do, from `capabilities`.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Capabilities {
+	pub version: &'static str,
+	/// External tools and the formats they or compiled-in codecs enable.
+	pub backends: Backends,
+	/// `spatial --version` output, when the CLI passed `check_spatial_cli`.
+	pub spatial_cli_version: Option<String>,
+	/// Depth backends compiled in and usable on this OS: `coreml`, `onnx`.
+	pub depth_backends: Vec<&'static str>,
+	/// Image formats decoded without ffmpeg, beyond the always-native JPEG, PNG,
+	/// TIFF, WebP and BMP.
+	pub native_decoders: Vec<&'static str>,
+	pub checkpoint_dir: Option<std::path::PathBuf>,
+	/// The built-in model sizes and where each one's checkpoint was found.
+	pub checkpoints: Vec<CheckpointStatus>,
 }
 
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct CheckpointStatus {
+	pub size: &'static str,
+	pub path: Option<std::path::PathBuf>,
+}
+
+/// Probes external tools, compiled-in features and downloaded checkpoints in
+/// one place, so a GUI or CI job can check up front why an input or output
+/// type won't work. Runs the tools, so it takes a moment.
+pub fn capabilities() -> Capabilities {
+	let depth_backends = [
+		(cfg!(all(target_os = "macos", feature = "coreml")), "coreml"),
+		(cfg!(feature = "onnx"), "onnx"),
+	];
+	let native_decoders = [
+		(cfg!(feature = "avif"), "avif"),
+		(cfg!(feature = "jxl"), "jxl"),
+		(cfg!(feature = "heic"), "heic"),
+	];
+	let enabled = |list: &[(bool, &'static str)]| list.iter().filter(|(on, _)| *on).map(|(_, name)| *name).collect();
+
+	Capabilities {
+		version: env!("CARGO_PKG_VERSION"),
+		backends: backends_available(),
+		spatial_cli_version: check_spatial_cli(Path::new("spatial")).ok().map(|info| info.version),
+		depth_backends: enabled(&depth_backends),
+		native_decoders: enabled(&native_decoders),
+		checkpoint_dir: get_checkpoint_dir().ok(),
+		checkpoints: ["small", "base", "large"]
+			.into_iter()
+			.map(|size| CheckpointStatus { size, path: find_model(size).ok() })
+			.collect(),
+	}
+}
+
+#[derive(serde::Serialize)]
+struct PhotoSidecar<'a> {
+	version: &'static str,
+	input: &'a Path,
+	model: &'a str,
+	disparity_range: [u32; 2],
+	/// The model's raw output before normalization; absent when depth came from
+	/// the cache or existing depth files.
+	depth: Option<depth_filter::DepthStats>,
+	stereo: Option<StereoStats>,
+	config: &'a SpatialConfig,
+}
+
+fn write_sidecar(path: &Path, sidecar: &PhotoSidecar) -> SpatialResult<()> {
+	let json = serde_json::to_string_pretty(sidecar)
+		.map_err(|e| SpatialError::Other(format!("Failed to serialize sidecar: {}", e)))?;
+	std::fs::write(path, json)
+		.map_err(|e| SpatialError::IoError(format!("Failed to write {:?}: {}", path, e)))
+}
+
+/// Depth-only requests (see `is_depth_only`) ignore `output_options` apart from
+/// the depth settings, so `OutputOptions::default()` is enough for them.
 pub async fn process_photo(
 	input_path: &Path,
 	output_base_path: &Path,
@@ -110,38 +486,143 @@ pub async fn process_photo(
 	output_options: OutputOptions,
 	force: bool,
 ) -> SpatialResult<ProcessPhotoOutput> {
+	process_photo_with_progress(
+		input_path,
+		output_base_path,
+		config,
+		output_types,
+		output_options,
+		force,
+		None::<fn(&str, f64)>,
+	)
+	.await
+}
+
+pub async fn process_photo_with_progress<F>(
+	input_path: &Path,
+	output_base_path: &Path,
+	config: SpatialConfig,
+	output_types: &[OutputType],
+	mut output_options: OutputOptions,
+	force: bool,
+	mut progress: Option<F>,
+) -> SpatialResult<ProcessPhotoOutput>
+where
+	F: FnMut(&str, f64),
+{
+	let mut report = |stage: &str, value: f64| {
+		if let Some(cb) = progress.as_mut() {
+			cb(stage, value);
+		}
+	};
+
+	output_options.validate()?;
+	let write_sidecar_json = output_options.write_sidecar;
+	let keep_depth = output_options.keep_depth;
+	let image_format = output_options.image_format;
+	let preview_panels = std::mem::take(&mut output_options.preview_panels);
+
 	let do_depth = needs_depth(output_types);
 	let do_stereo = needs_stereo(output_types);
+	let do_normals = needs_normals(output_types);
+	let do_views = needs_views(output_types);
+	let do_aux = output::needs_aux_depth(output_types);
 
 	let parent = output_base_path.parent().unwrap_or_else(|| Path::new("."));
 	let stem = output_base_path.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
 
+	if config.input_is_stereo && (do_depth || do_normals || do_views || do_aux) {
+		return Err(SpatialError::ConfigError(
+			"Depth, auxiliary depth, normal map and multi-view outputs are not available for stereo input".to_string(),
+		));
+	}
+
 	let depth_paths: Vec<(std::path::PathBuf, DepthFormat)> = if do_depth {
 		depth_formats(output_types)
 			.into_iter()
 			.map(|fmt| {
-				let filename = format!("{}-depth{}.{}", stem, fmt.suffix(), fmt.extension());
-				(parent.join(&filename), fmt)
+				let path = parent.join(fmt.filename(stem, output_options.depth_naming));
+				// Reuse a depth map an earlier run wrote under the other naming scheme.
+				let existing = (!force && !path.exists())
+					.then(|| {
+						DepthNaming::all()
+							.iter()
+							.map(|&naming| parent.join(fmt.filename(stem, naming)))
+							.find(|p| p.exists())
+					})
+					.flatten();
+				(existing.unwrap_or(path), fmt)
 			})
 			.collect()
 	} else {
 		Vec::new()
 	};
+	for (i, (path, fmt)) in depth_paths.iter().enumerate() {
+		if let Some((_, other)) = depth_paths[..i].iter().find(|(p, _)| p == path) {
+			return Err(SpatialError::ConfigError(format!(
+				"Depth formats {} and {} would both write {:?}",
+				other.to_token(),
+				fmt.to_token(),
+				path
+			)));
+		}
+	}
 
 	let all_depth_exist = !depth_paths.is_empty() && depth_paths.iter().all(|(p, _)| p.exists());
-	let skip_estimation = all_depth_exist && !force;
+	let skip_estimation = config.input_is_stereo || (all_depth_exist && !force);
 
 	let mut result = ProcessPhotoOutput {
 		depth_paths: Vec::new(),
 		stereo_paths: Vec::new(),
+		normal_paths: Vec::new(),
+		view_paths: Vec::new(),
+		sidecar_path: None,
+		stereo_stats: None,
+		depth_map: None,
+		errors: Vec::new(),
+		warnings: Vec::new(),
 	};
+	if config.equirect {
+		result.warnings.extend(use_equirect_layout(&mut output_options, output_types));
+	}
+
+	let input_image = if !skip_estimation || do_stereo || do_views || do_aux {
+		report("loading", 0.0);
+		let (img, warnings) = image_loader::load_image_with_warnings(input_path).await?;
+		result.warnings.extend(warnings);
+		Some(img)
+	} else {
+		None
+	};
+
+	if let (Some(img), false) = (&input_image, config.input_is_stereo) {
+		if stereo::detect_stereo_layout(img).is_some() {
+			result.warnings.push(Warning::AlreadyStereo);
+		}
+	}
+
+	let max_disparity = match (&input_image, (do_stereo || do_views) && !config.input_is_stereo) {
+		(Some(img), true) => {
+			let (max_disparity, warning) = stereo::validate_max_disparity_with_warnings(config.max_disparity, img.width())?;
+			result.warnings.extend(warning);
+			max_disparity
+		}
+		_ => config.max_disparity,
+	};
+	// What the warp actually used, after `auto_disparity` scaling.
+	let mut effective_disparity = max_disparity;
+
+	let mut raw_depth_stats = None;
+	let depth_map = if config.input_is_stereo {
+		None
+	} else if skip_estimation {
+		report("depth cached", 1.0);
 
-	let depth_map = if skip_estimation {
 		for (p, _) in &depth_paths {
 			result.depth_paths.push(p.clone());
 		}
 
-		if do_stereo {
+		if do_stereo || do_normals || do_views || do_aux {
 			let best = depth_paths.iter()
 				.find(|(_, fmt)| matches!(fmt, DepthFormat::Png16))
 				.or_else(|| depth_paths.iter().find(|(_, fmt)| matches!(fmt, DepthFormat::Png)))
@@ -155,66 +636,458 @@ pub async fn process_photo(
 			None
 		}
 	} else {
-		let input_image = load_image(input_path).await?;
+		let input_image = input_image.as_ref().ok_or_else(|| {
+			SpatialError::ImageError("Input image not loaded".to_string())
+		})?;
 
-		model::ensure_model_exists::<fn(u64, u64)>(&config.encoder_size, None).await?;
+		report("loading model", 0.0);
+		model::ensure_model_exists_with_progress(
+			&config.encoder_size,
+			Some(|p: DownloadProgress| report("downloading model", p.percent)),
+		)
+		.await?;
 
-		#[cfg(all(target_os = "macos", feature = "coreml"))]
-		let dm = {
-			let model_path = model::find_model(&config.encoder_size)?;
-			let model_str = model_path.to_str().ok_or_else(|| {
-				SpatialError::ModelError("Invalid model path encoding".to_string())
-			})?;
-			let estimator = CoreMLDepthEstimator::new(model_str)?;
-			estimator.estimate(&input_image)?
-		};
-
-		#[cfg(not(all(target_os = "macos", feature = "coreml")))]
-		let dm = {
-			#[cfg(feature = "onnx")]
-			{
-				let model_path = model::find_model(&config.encoder_size)?;
-				let estimator = OnnxDepthEstimator::new(model_path.to_str().unwrap())?;
-				estimator.estimate(&input_image)?
+		let model_path = model::find_model(&config.encoder_size)?;
+		let cache = config.depth_cache_dir.as_deref().and_then(|dir| {
+			match depth_cache::cache_key(input_path, &model_path, &config) {
+				Ok(key) => Some((dir, key)),
+				Err(e) => {
+					tracing::warn!("Depth cache skipped: {}", e);
+					None
+				}
 			}
-			#[cfg(not(feature = "onnx"))]
-			{
-				return Err(SpatialError::ConfigError(
-					"No depth backend enabled. Enable 'coreml' (macOS) or 'onnx' feature.".to_string(),
-				));
+		});
+		// Cached depth was estimated under the same budget, so it warns too.
+		let (width, height) = (input_image.width(), input_image.height());
+		if depth::exceeds_pixel_budget(width, height, config.max_depth_pixels) {
+			result.warnings.push(Warning::DepthDownscaled {
+				width,
+				height,
+				max_pixels: config.max_depth_pixels,
+			});
+		}
+		let dm = match cache.as_ref().and_then(|(dir, key)| depth_cache::load(dir, key)) {
+			Some(dm) => {
+				report("depth cached", 1.0);
+				dm
+			}
+			None => {
+				report("estimating depth", 0.0);
+				let dm = match depth::fit_pixel_budget(input_image, config.max_depth_pixels) {
+					Some(downscaled) => {
+						let (dm, stats) = estimate_photo_depth(&downscaled, &model_path, &config)?;
+						raw_depth_stats = Some(stats);
+						depth_filter::resize_depth(&dm, width as usize, height as usize)
+					}
+					None => {
+						let (dm, stats) = estimate_photo_depth(input_image, &model_path, &config)?;
+						raw_depth_stats = Some(stats);
+						dm
+					}
+				};
+				if let Some((dir, key)) = &cache {
+					if let Err(e) = depth_cache::store(dir, key, &dm) {
+						tracing::warn!("Failed to cache depth: {}", e);
+					}
+				}
+				dm
 			}
 		};
 
 		if do_depth {
-			for (depth_path, fmt) in &depth_paths {
-				save_depth_map(&dm, depth_path, *fmt)?;
-				result.depth_paths.push(depth_path.clone());
+			report("saving depth", 0.0);
+			let native;
+			let saved = if output_options.depth_native_resolution {
+				let (h, w) = dm.dim();
+				let size = config.depth_preprocess()?.input_size;
+				let (nw, nh) = depth_filter::native_depth_dims(w, h, size, config.preserve_aspect);
+				native = depth_filter::resize_depth(&dm, nw, nh);
+				&native
+			} else {
+				&dm
+			};
+			for (depth_path, outcome) in output::save_depth_maps(saved, &depth_paths, output_options.dither) {
+				match outcome {
+					Ok(()) => result.depth_paths.push(depth_path),
+					Err(e) => result.errors.push((depth_path, e)),
+				}
 			}
 		}
 
 		Some(dm)
 	};
 
+	let depth_map = depth_map.map(|dm| {
+		let (dm, warning) = stereo::handle_flat_depth_with_warnings(dm, config.flat_depth_fallback);
+		result.warnings.extend(warning);
+		dm
+	});
+
+	let depth_map = match (depth_map, input_image.as_ref()) {
+		(Some(dm), Some(img)) if config.suppress_low_texture => Some(depth_filter::suppress_lowtexture(
+			&dm,
+			&img.to_rgb8(),
+			config.low_texture_threshold,
+		)),
+		(depth_map, _) => depth_map,
+	};
+
+	let depth_map = match depth_map {
+		Some(dm) if config.equalize_depth => {
+			Some(depth_filter::histogram_equalize(&dm, config.equalize_bins as usize))
+		}
+		other => other,
+	};
+
+	if let (true, Some(dm)) = (do_normals, depth_map.as_ref()) {
+		let normals_path = parent.join(format!("{}-normals.png", stem));
+		match save_normal_map(dm, &normals_path, config.normal_strength) {
+			Ok(()) => result.normal_paths.push(normals_path),
+			Err(e) => result.errors.push((normals_path, e)),
+		}
+	}
+
+	if let (true, Some(dm), Some(img)) = (do_aux, depth_map.as_ref(), input_image.as_ref()) {
+		let aux_path = parent.join(format!("{}-with-depth.avif", stem));
+		match output::save_with_aux_depth(img, dm, &aux_path) {
+			Ok(()) => result.depth_paths.push(aux_path),
+			Err(e) => result.errors.push((aux_path, e)),
+		}
+	}
+
+	if let (true, Some(dm), Some(img)) = (do_views, depth_map.as_ref(), input_image.as_ref()) {
+		report("generating views", 0.0);
+		let views = stereo::generate_multiview(img, dm, max_disparity, config.num_views)
+			.and_then(|views| save_views(&views, parent, stem, output_options.image_format));
+		match views {
+			Ok(paths) => result.view_paths.extend(paths),
+			Err(e) => result.errors.push((parent.join(format!("{}-view", stem)), e)),
+		}
+	}
+
 	if do_stereo {
-		let dm = depth_map.as_ref().ok_or_else(|| {
-			SpatialError::ConfigError("Depth map required for stereo but not available".to_string())
+		let input_image = input_image.as_ref().ok_or_else(|| {
+			SpatialError::ImageError("Input image not loaded".to_string())
 		})?;
-		let input_image = load_image(input_path).await?;
-		let (left, right) = generate_stereo_pair(&input_image, dm, config.max_disparity)?;
-		let src_ext = input_path.extension().and_then(|s| s.to_str()).unwrap_or("").to_lowercase();
-		let stereo_ext = match src_ext.as_str() {
-			"heic" | "heif" | "avif" | "jxl" => "jpg",
-			"" => "jpg",
-			other => other,
+
+		let mut disparity_mask = None;
+		let pair = if config.input_is_stereo {
+			let layout = stereo::detect_stereo_layout(input_image).unwrap_or(
+				if input_image.width() >= input_image.height() {
+					OutputFormat::SideBySide
+				} else {
+					OutputFormat::TopAndBottom
+				},
+			);
+			stereo::split_stereo_image(input_image, layout)
+		} else {
+			let dm = depth_map.as_ref().ok_or_else(|| {
+				SpatialError::ConfigError("Depth map required for stereo but not available".to_string())
+			})?;
+			report("generating stereo", 0.0);
+			let mut params = StereoParams::from_config(&config, max_disparity);
+			if config.auto_convergence {
+				params.convergence = depth_filter::median_depth(dm);
+			}
+			if config.auto_disparity {
+				let scale = depth_filter::disparity_scale(depth_filter::depth_spread(dm));
+				params = params.with_disparity_scale(scale, input_image.width());
+			}
+			effective_disparity = params.max_disparity;
+			generate_stereo_pair_with_mask(
+				input_image,
+				dm,
+				&params,
+				Some(|p| report("generating stereo", p)),
+			)
+			.map(|(left, right, mask)| {
+				let stats = StereoStats::from_mask(&mask, params.mode);
+				result.warnings.extend(stats.warning(params.max_disparity));
+				result.stereo_stats = Some(stats);
+				disparity_mask = Some(mask);
+				stereo::apply_luminance_match((left, right), config.match_luminance)
+			})
 		};
-		let stereo_path = parent.join(format!("{}-spatial.{}", stem, stereo_ext));
-		save_stereo_image(&left, &right, &stereo_path, output_options)?;
-		result.stereo_paths.push(stereo_path);
+
+		let stereo_path = stereo_output_path(input_path, parent, stem);
+		match pair {
+			Ok((left, right)) => {
+				report("saving", 0.0);
+				if !stereo_types(output_types).is_empty() {
+					match save_stereo_image_with_paths(&left, &right, &stereo_path, output_options) {
+						Ok(paths) => result.stereo_paths.extend(paths),
+						Err(e) => result.errors.push((stereo_path, e)),
+					}
+				}
+				if needs_stereo_clip(output_types) {
+					let clip_path = parent.join(format!("{}-stereo-clip.mp4", stem));
+					match save_stereo_clip(&left, &right, &clip_path) {
+						Ok(()) => result.stereo_paths.push(clip_path),
+						Err(e) => result.errors.push((clip_path, e)),
+					}
+				}
+				if needs_disparity_mask(output_types) {
+					match disparity_mask {
+						Some(mask) => {
+							let mask_path = parent.join(format!("{}-disparity-mask.png", stem));
+							match save_disparity_mask(&mask, &mask_path) {
+								Ok(()) => result.stereo_paths.push(mask_path),
+								Err(e) => result.errors.push((mask_path, e)),
+							}
+						}
+						None => result.warnings.push(Warning::OutputSkipped {
+							output: "disparity mask".to_string(),
+							reason: "stereo input is not warped".to_string(),
+						}),
+					}
+				}
+				if needs_preview(output_types) {
+					let preview_path = parent.join(format!(
+						"{}-preview.{}",
+						stem,
+						image_format.extension()
+					));
+					let sheet = create_preview_sheet(
+						input_image,
+						depth_map.as_ref(),
+						&left,
+						&right,
+						&preview_panels,
+					);
+					match save_preview_sheet(&sheet, &preview_path, image_format) {
+						Ok(()) => result.stereo_paths.push(preview_path),
+						Err(e) => result.errors.push((preview_path, e)),
+					}
+				}
+			}
+			Err(e) => result.errors.push((stereo_path, e)),
+		}
+	}
+
+	if write_sidecar_json {
+		let sidecar_path = parent.join(format!("{}.spatial.json", stem));
+		let sidecar = PhotoSidecar {
+			version: env!("CARGO_PKG_VERSION"),
+			input: input_path,
+			model: &config.encoder_size,
+			disparity_range: [0, effective_disparity],
+			depth: raw_depth_stats,
+			stereo: result.stereo_stats,
+			config: &config,
+		};
+		match write_sidecar(&sidecar_path, &sidecar) {
+			Ok(()) => result.sidecar_path = Some(sidecar_path),
+			Err(e) => result.errors.push((sidecar_path, e)),
+		}
+	}
+
+	if keep_depth {
+		result.depth_map = depth_map;
+	}
+
+	if result.depth_paths.is_empty()
+		&& result.stereo_paths.is_empty()
+		&& result.normal_paths.is_empty()
+		&& result.view_paths.is_empty()
+		&& !result.errors.is_empty()
+	{
+		return Err(result.errors.remove(0).1);
 	}
 
 	Ok(result)
 }
 
+/// Runs the configured depth backend on a photo, with multi-resolution fusion
+/// when `config.fusion` is set. Also returns statistics of the model's raw
+/// output for the whole image, taken before normalization.
+fn estimate_photo_depth(
+	image: &image::DynamicImage,
+	model_path: &Path,
+	config: &SpatialConfig,
+) -> SpatialResult<(ndarray::Array2<f32>, depth_filter::DepthStats)> {
+	#[cfg(all(target_os = "macos", feature = "coreml"))]
+	{
+		let model_str = model_path.to_str().ok_or_else(|| {
+			SpatialError::ModelError("Invalid model path encoding".to_string())
+		})?;
+		let estimator = CoreMLDepthEstimator::from_config(model_str, config)?;
+		return estimate_with_raw_stats(image, config.fusion, |img| estimator.estimate_raw(img));
+	}
+
+	#[cfg(not(all(target_os = "macos", feature = "coreml")))]
+	{
+		#[cfg(feature = "onnx")]
+		{
+			let mut estimator = OnnxDepthEstimator::from_config(model_path.to_str().unwrap(), config)?;
+			estimate_with_raw_stats(image, config.fusion, |img| estimator.estimate_raw(img))
+		}
+		#[cfg(not(feature = "onnx"))]
+		{
+			let _ = (image, model_path, config);
+			Err(SpatialError::ConfigError(
+				"No depth backend enabled. Enable 'coreml' (macOS) or 'onnx' feature.".to_string(),
+			))
+		}
+	}
+}
+
+/// Normalizes each raw estimate like the estimators' `estimate` does, keeping
+/// the statistics of the first one: the whole image, which `estimate_multires`
+/// also estimates first.
+#[cfg(any(feature = "onnx", all(target_os = "macos", feature = "coreml")))]
+fn estimate_with_raw_stats<F>(
+	image: &image::DynamicImage,
+	fusion: bool,
+	mut estimate_raw: F,
+) -> SpatialResult<(ndarray::Array2<f32>, depth_filter::DepthStats)>
+where
+	F: FnMut(&image::DynamicImage) -> SpatialResult<ndarray::Array2<f32>>,
+{
+	let mut raw_stats = None;
+	let mut estimate = |img: &image::DynamicImage| {
+		let mut depth = estimate_raw(img)?;
+		raw_stats.get_or_insert_with(|| depth_filter::DepthStats::from_depth(&depth));
+		depth_filter::normalize_depth_in_place(&mut depth, NormalizeStrategy::MinMax);
+		Ok(depth)
+	};
+	let depth = if fusion {
+		depth::estimate_multires(image, &mut estimate)?
+	} else {
+		estimate(image)?
+	};
+	let stats = raw_stats.unwrap_or_else(|| depth_filter::DepthStats::from_depth(&depth));
+	Ok((depth, stats))
+}
+
+/// Switches `options` to tagged over-under output for a 360° panorama, since
+/// side-by-side halves the horizontal resolution players need for the sphere.
+/// Warns when side-by-side was explicitly requested.
+fn use_equirect_layout(options: &mut OutputOptions, output_types: &[OutputType]) -> Option<Warning> {
+	options.equirect = true;
+	if options.layout != OutputFormat::SideBySide {
+		return None;
+	}
+	options.layout = OutputFormat::TopAndBottom;
+	output_types.contains(&OutputType::SideBySide).then(|| Warning::OptionIgnored {
+		option: "sbs layout".to_string(),
+		reason: "360 stereo is written over-under".to_string(),
+	})
+}
+
+fn stereo_output_path(input_path: &Path, parent: &Path, stem: &str) -> std::path::PathBuf {
+	let src_ext = input_path.extension().and_then(|s| s.to_str()).unwrap_or("").to_lowercase();
+	let stereo_ext = match src_ext.as_str() {
+		"heic" | "heif" | "avif" | "jxl" => "jpg",
+		"" => "jpg",
+		other => other,
+	};
+	parent.join(format!("{}-spatial.{}", stem, stereo_ext))
+}
+
+/// Generates stereo output from an image and a precomputed depth map without
+/// loading a depth model. The depth map is resampled to the image size, so only
+/// the aspect ratios need to match.
+pub async fn process_stereo_from_depth(
+	image_path: &Path,
+	depth_path: &Path,
+	output_base_path: &Path,
+	config: &SpatialConfig,
+	output_types: &[OutputType],
+	output_options: OutputOptions,
+) -> SpatialResult<Vec<std::path::PathBuf>> {
+	let (written, warnings) = process_stereo_from_depth_with_warnings(
+		image_path,
+		depth_path,
+		output_base_path,
+		config,
+		output_types,
+		output_options,
+	)
+	.await?;
+	for warning in warnings {
+		tracing::warn!("{}", warning);
+	}
+	Ok(written)
+}
+
+/// `process_stereo_from_depth`, returning its non-fatal warnings instead of
+/// logging them.
+pub async fn process_stereo_from_depth_with_warnings(
+	image_path: &Path,
+	depth_path: &Path,
+	output_base_path: &Path,
+	config: &SpatialConfig,
+	output_types: &[OutputType],
+	mut output_options: OutputOptions,
+) -> SpatialResult<(Vec<std::path::PathBuf>, Vec<Warning>)> {
+	output_options.validate()?;
+	let mut warnings = Vec::new();
+	if config.equirect {
+		warnings.extend(use_equirect_layout(&mut output_options, output_types));
+	}
+
+	if !needs_stereo(output_types) {
+		return Err(SpatialError::ConfigError(
+			"Stereo from depth needs at least one stereo output type (sbs, tab, sep, spatial)".to_string(),
+		));
+	}
+
+	let (image, load_warnings) = image_loader::load_image_with_warnings(image_path).await?;
+	warnings.extend(load_warnings);
+	let depth = output::load_depth_map(depth_path)?;
+
+	let (depth_h, depth_w) = depth.dim();
+	let image_aspect = image.width() as f32 / image.height() as f32;
+	let depth_aspect = depth_w as f32 / depth_h as f32;
+	if (image_aspect / depth_aspect - 1.0).abs() > DEPTH_ASPECT_TOLERANCE {
+		return Err(SpatialError::ConfigError(format!(
+			"Depth map {}x{} does not match the aspect ratio of the {}x{} image",
+			depth_w,
+			depth_h,
+			image.width(),
+			image.height()
+		)));
+	}
+
+	let (max_disparity, clamped) = stereo::validate_max_disparity_with_warnings(config.max_disparity, image.width())?;
+	let (depth, flat) = stereo::handle_flat_depth_with_warnings(depth, config.flat_depth_fallback);
+	warnings.extend(clamped.into_iter().chain(flat));
+	let mut params = StereoParams::from_config(config, max_disparity);
+	if config.auto_convergence {
+		params.convergence = depth_filter::median_depth(&depth);
+	}
+	if config.auto_disparity {
+		let scale = depth_filter::disparity_scale(depth_filter::depth_spread(&depth));
+		params = params.with_disparity_scale(scale, image.width());
+	}
+	let (left, right, mask) = generate_stereo_pair_with_mask(
+		&image,
+		&depth,
+		&params,
+		None::<fn(f64)>,
+	)?;
+	warnings.extend(StereoStats::from_mask(&mask, params.mode).warning(params.max_disparity));
+	let (left, right) = stereo::apply_luminance_match((left, right), config.match_luminance);
+
+	let parent = output_base_path.parent().unwrap_or_else(|| Path::new("."));
+	let stem = output_base_path.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+	let mut written = Vec::new();
+	if !stereo_types(output_types).is_empty() {
+		written.extend(save_stereo_image_with_paths(&left, &right, stereo_output_path(image_path, parent, stem), output_options)?);
+	}
+	if needs_stereo_clip(output_types) {
+		let clip_path = parent.join(format!("{}-stereo-clip.mp4", stem));
+		save_stereo_clip(&left, &right, &clip_path)?;
+		written.push(clip_path);
+	}
+	if needs_disparity_mask(output_types) {
+		let mask_path = parent.join(format!("{}-disparity-mask.png", stem));
+		save_disparity_mask(&mask, &mask_path)?;
+		written.push(mask_path);
+	}
+	Ok((written, warnings))
+}
+
 pub async fn process_video_sbs(
 	input_path: &Path,
 	output_path: &Path,
@@ -224,3 +1097,15 @@ pub async fn process_video_sbs(
 ) -> SpatialResult<()> {
 	video::process_video(input_path, output_path, config, &[OutputType::Spatial], progress_cb, force).await
 }
+
+/// `process_video_sbs`, also returning the conversion's non-fatal warnings.
+pub async fn process_video_sbs_with_warnings(
+	input_path: &Path,
+	output_path: &Path,
+	config: SpatialConfig,
+	progress_cb: Option<ProgressCallback>,
+	force: bool,
+) -> SpatialResult<ProcessVideoOutput> {
+	video::process_video_with_warnings(input_path, output_path, config, &[OutputType::Spatial], progress_cb, force)
+		.await
+}
@@ -1,18 +1,26 @@
 use clap::{Parser, Subcommand};
 use spatial_maker::{
-	process_video, ImageEncoding, MVHEVCConfig, NormalizeMode, OutputFormat,
-	OutputOptions, OutputType, SpatialConfig, VideoProgress,
-	needs_stereo, parse_output_types,
+	check_spatial_cli, exit_code, extract_single_frame, process_photo_with_progress, process_stereo_from_depth_with_warnings, process_video_with_warnings, ImageEncoding, NormalizeMode,
+	DepthNaming, DisocclusionFill, GlobalCache, ImageSequence, OutputOptions, OutputType, SpatialConfig, StereoMode, TemporalMode, VideoCodec, VideoProgress,
+	is_depth_only, parse_output_types, parse_preview_panels, SpatialError,
+	error::{EXIT_FAILURE, EXIT_PARTIAL, EXIT_USAGE},
 	tui::{self, AppState, FileStatus, MediaType},
-	load_image, model, generate_stereo_pair_with_progress,
-	needs_depth, depth_formats, save_depth_map, load_depth_map, save_stereo_image,
-	CoreMLDepthEstimator,
+	model,
 };
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
-use std::path::Path;
 use tokio::sync::mpsc;
 
+/// Mirrors the `EXIT_*` constants in `spatial_maker::error`.
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0    success
+  1    failure not covered below (image, I/O or processing error)
+  2    invalid arguments or configuration
+  3    missing external tool (ffmpeg, spatial)
+  4    model not found, or download, checksum or load failed
+  5    some files or outputs in the batch failed
+  130  cancelled";
+
 #[derive(Parser)]
 #[command(name = "spatial-maker")]
 #[command(about = "Convert 2D images and videos to stereoscopic 3D spatial content")]
@@ -22,66 +30,406 @@ use tokio::sync::mpsc;
 	"\nRepository: ",
 	env!("CARGO_PKG_REPOSITORY")
 ))]
+#[command(after_long_help = EXIT_CODES_HELP)]
 struct Cli {
 	#[command(subcommand)]
 	command: Option<Commands>,
 
-	/// Input image or video files
+	/// Input image or video files, or image sequences (a directory of numbered
+	/// images or a pattern such as frames/%04d.png). `-` reads a video from stdin:
+	/// it must be streamable (MPEG-TS, Matroska, fragmented MP4), audio is dropped,
+	/// and progress is approximate
 	inputs: Vec<PathBuf>,
 
 	/// Output file (only valid with a single input)
 	#[arg(short, long)]
 	output: Option<PathBuf>,
 
-	/// Model size: s (small, 48MB), b (base, 186MB), l (large, 638MB)
+	/// Model size: s (small, 48MB), b (base, 186MB), l (large, 638MB), or a checkpoint
+	/// filename (.mlpackage/.onnx) in the checkpoint directory
 	#[arg(short, long, default_value = "s")]
 	model: String,
 
-	/// Maximum disparity in pixels (higher = more 3D depth)
-	#[arg(long, default_value = "30")]
-	max_disparity: u32,
+	/// Maximum disparity in pixels (higher = more 3D depth). 1-5% of image width is
+	/// recommended; values above 15% are clamped and 50% or more is rejected (default 30)
+	#[arg(long)]
+	max_disparity: Option<u32>,
 
-	/// Output types (comma-separated): depth, depth:avif,png,png16, sbs, tab, sep, spatial
+	/// Tuned settings: portrait, landscape, subtle, strong, video-stable. Individual
+	/// flags override the preset's values
+	#[arg(long)]
+	preset: Option<String>,
+
+	/// Output types (comma-separated): depth, depth:avif,png,png16,colormap, sbs, tab, sep, spatial, clip, mask, aux,
+	/// normals, views, preview, or all (depth:png + spatial). Depth formats go after depth:, e.g. depth:png,sbs
 	#[arg(long, default_value = "spatial")]
 	output_types: String,
 
+	/// Only write depth maps (same as --output-types depth). Skips the stereo and
+	/// MV-HEVC setup, so no external tools are needed for PNG depth
+	#[arg(long, conflicts_with = "output_types")]
+	depth_only: bool,
+
 	/// JPEG quality for photos (1-100)
-	#[arg(long, default_value = "95")]
+	#[arg(long, default_value = "95", value_parser = clap::value_parser!(u8).range(1..=100))]
 	quality: u8,
 
-	/// Temporal EMA blend factor for video depth (0=off, 1=no smoothing, default 0.7)
-	#[arg(long, default_value = "0.7")]
-	temporal_alpha: f32,
+	/// Keep the stereo image the spatial photo is encoded from. Without this flag,
+	/// spatial keeps it only if sbs/tab is also requested (sep keeps just the L/R
+	/// images); with it, spatial always keeps the sbs (or tab) image as well
+	#[arg(long)]
+	keep_intermediate: bool,
+
+	/// Extension for spatial (MV-HEVC) photos instead of heic
+	#[arg(long, value_name = "EXT")]
+	spatial_photo_ext: Option<String>,
+
+	/// Extension for spatial (MV-HEVC) videos instead of mov
+	#[arg(long, value_name = "EXT")]
+	spatial_video_ext: Option<String>,
+
+	/// Save depth maps at the model's processing resolution (518px) instead of the
+	/// input image size
+	#[arg(long)]
+	depth_native_resolution: bool,
+
+	/// Write <stem>.spatial.json next to photo outputs with depth statistics and the
+	/// settings used, for reproducing runs
+	#[arg(long)]
+	write_sidecar: bool,
+
+	/// Dither 8-bit depth maps (png, avif) to reduce banding on smooth gradients
+	#[arg(long)]
+	dither: bool,
+
+	/// Weight of the current frame in temporal EMA smoothing for video depth, 0-1
+	/// (default 0.7). Lower is smoother; 1 and 0 both turn smoothing off
+	#[arg(long)]
+	temporal_alpha: Option<f32>,
+
+	/// Temporal depth smoothing for video: ema (default, uses --temporal-alpha), off, or
+	/// window:N to average the last N frames (smoother, but lags motion more)
+	#[arg(long, default_value = "ema")]
+	temporal: String,
 
 	/// Bilateral filter spatial sigma (0=off, default 5.0)
-	#[arg(long, default_value = "5.0")]
-	bilateral_sigma: f32,
+	#[arg(long)]
+	bilateral_sigma: Option<f32>,
 
 	/// Bilateral filter range sigma (default 0.1)
-	#[arg(long, default_value = "0.1")]
-	bilateral_range: f32,
+	#[arg(long)]
+	bilateral_range: Option<f32>,
 
 	/// Gaussian blur sigma for depth edge softening (0=off, default 1.5)
-	#[arg(long, default_value = "1.5")]
-	depth_blur: f32,
+	#[arg(long)]
+	depth_blur: Option<f32>,
+
+	/// Scale --bilateral-sigma and --depth-blur by --max-disparity / 30 (0.25-3x), so
+	/// stronger 3D gets proportionally more depth smoothing (video)
+	#[arg(long)]
+	adaptive_smoothing: bool,
+
+	/// Only blur depth near depth discontinuities, keeping flat interiors sharp.
+	/// Overrides the preset; --edge-blur-only=false turns it off
+	#[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+	edge_blur_only: Option<bool>,
+
+	/// Histogram-equalize depth to spread it across the full disparity range
+	#[arg(long)]
+	equalize_depth: bool,
+
+	/// Number of histogram bins for --equalize-depth
+	#[arg(long, default_value = "256")]
+	equalize_bins: u32,
+
+	/// Flatten depth in textureless regions (blank walls, sky) so they don't pick
+	/// up spurious parallax
+	#[arg(long)]
+	suppress_low_texture: bool,
+
+	/// Local luminance variance (0-1 scale) below which --suppress-low-texture
+	/// treats a region as textureless
+	#[arg(long, default_value = "0.0004")]
+	low_texture_threshold: f32,
+
+	/// Fuse whole-image depth with a tiled higher-resolution pass for finer detail
+	/// (photos only; about 5x slower)
+	#[arg(long)]
+	fusion: bool,
+
+	/// Cache photo depth maps on disk, keyed by the input and model settings, so
+	/// re-runs with new stereo settings skip inference. Stored in
+	/// SPATIAL_MAKER_CACHE (setting it also enables the cache), else
+	/// ~/.spatial-maker/cache/depth
+	#[arg(long)]
+	cache_depth: bool,
+
+	/// Photos larger than this many megapixels get depth estimated on a
+	/// downscaled copy, to bound inference memory on huge panoramas (0 = no limit)
+	#[arg(long, value_name = "MP")]
+	max_depth_megapixels: Option<f64>,
+
+	/// Don't use the depth cache, even when SPATIAL_MAKER_CACHE is set
+	#[arg(long, conflicts_with = "cache_depth")]
+	no_cache: bool,
+
+	/// Keep flat depth as-is instead of falling back to ground-plane parallax
+	#[arg(long = "no-flat-depth-fallback", action = clap::ArgAction::SetFalse)]
+	flat_depth_fallback: bool,
+
+	/// Codec for stereo video: h264, hevc, vp9, av1, prores. Must suit the --output
+	/// extension (.mov, .mp4, .m4v, .mkv, .webm); defaults to the container's usual codec
+	#[arg(long)]
+	video_codec: Option<String>,
+
+	/// Fit stereo video into roughly this many MB by encoding at a matching bitrate
+	/// (two-pass for h264, hevc and vp9). The source's audio is copied in and counts
+	/// toward the size. Approximate: expect a few percent either way
+	#[arg(long, value_name = "MB")]
+	target_size: Option<f64>,
+
+	/// Add disparity toward the bottom of the frame to emphasize a receding ground
+	/// plane, as a fraction of --max-disparity (0 = off, max 0.25)
+	#[arg(long, default_value = "0.0")]
+	ground_plane_bias: f32,
+
+	/// Ease disparity to zero near the left and right frame edges so objects cut
+	/// off by the frame don't break the stereo window (a floating window)
+	#[arg(long)]
+	floating_window: bool,
+
+	/// Width of the --floating-window taper on each side, as a fraction of the
+	/// image width
+	#[arg(long, default_value = "0.05", value_name = "FRACTION")]
+	floating_window_width: f32,
+
+	/// Clamp each pixel's disparity to near its neighborhood median, removing the
+	/// sparkle that isolated depth errors leave in the stereo view
+	#[arg(long)]
+	disparity_median_clamp: bool,
+
+	/// Treat the input as a 360° equirectangular panorama (2:1): the warp wraps
+	/// across the left/right seam and stereo is written over-under, tagged as 360
+	#[arg(long)]
+	equirect: bool,
+
+	/// Leave disocclusions unfilled (black) to see where the holes are; pair with
+	/// --output-types mask for a mask of them
+	#[arg(long)]
+	no_fill: bool,
+
+	/// How disocclusions are filled: background (default, copy the farther side),
+	/// nearest, source (sample the original image where the background continues,
+	/// when depth allows), reproject (like source, but mirrors background texture
+	/// into wide holes behind an occluder), or black
+	#[arg(long, value_name = "MODE", conflicts_with = "no_fill")]
+	fill: Option<String>,
+
+	/// Depth map filenames: distinct (default; -depth-avif.avif, -depth-png8.png,
+	/// -depth-png16.png) or short (-depth.avif, -depth.png, -depth-16bit.png, as
+	/// earlier releases wrote). Existing depth maps under either name are reused
+	#[arg(long, value_name = "SCHEME")]
+	depth_naming: Option<String>,
+
+	/// Which eyes to synthesize: right (keep the source as the left eye) or
+	/// symmetric (warp both eyes from the center so their artifacts match, at the
+	/// cost of some sharpness in both)
+	#[arg(long, value_name = "MODE")]
+	stereo_mode: Option<String>,
+
+	/// Put the median depth on the screen plane (for video, held steady across
+	/// frames) instead of placing the whole scene behind the screen
+	#[arg(long)]
+	auto_convergence: bool,
+
+	/// Scale --max-disparity per image (0.5-2x) by its depth range, so shallow and
+	/// deep scenes get a similar amount of 3D (for video, smoothed across frames)
+	#[arg(long)]
+	auto_disparity: bool,
+
+	/// Put the right eye first (left/top) in sbs and tab photo outputs, for
+	/// cross-eyed free-viewing or players that expect that order. The default is
+	/// parallel: left eye on the left. Sep (_L/_R) and spatial (MV-HEVC) output
+	/// are unaffected
+	#[arg(long)]
+	cross_eyed: bool,
+
+	/// Scale the generated right eye's brightness to match the left eye, which the
+	/// warp and hole filling can slightly darken
+	#[arg(long)]
+	match_luminance: bool,
+
+	/// Reuse the previous frame's depth and stereo output for identical consecutive
+	/// video frames (screen recordings, animation)
+	#[arg(long)]
+	skip_duplicate_frames: bool,
+
+	/// Treat photo inputs as existing side-by-side or top-and-bottom stereo pairs and
+	/// repackage them without depth estimation
+	#[arg(long)]
+	input_is_stereo: bool,
 
 	/// Depth normalization mode for video: running (default), per-frame, global (two-pass)
-	#[arg(long, default_value = "running")]
-	normalize: String,
+	#[arg(long)]
+	normalize: Option<String>,
+
+	/// Where --normalize global keeps depth between passes: reinfer (default, runs the
+	/// model twice), memory (fast, ~4 bytes per pixel per frame), or disk (temp file)
+	#[arg(long)]
+	global_cache: Option<String>,
+
+	/// Process video in chunks of N frames, checkpointing after each so an
+	/// interrupted run can continue with --resume
+	#[arg(long)]
+	chunk_frames: Option<u32>,
+
+	/// Frame rate for image sequence inputs (a directory of numbered images or a
+	/// pattern such as frames/%04d.png)
+	#[arg(long, value_name = "FPS")]
+	sequence_fps: Option<f64>,
+
+	/// Frame rate for video output. Lower than the source drops frames before
+	/// depth estimation (faster); higher repeats frames at encode time
+	#[arg(long, value_name = "FPS")]
+	output_fps: Option<f64>,
+
+	/// Give up on a video if ffmpeg produces no frame for this many seconds
+	/// (0 = wait indefinitely)
+	#[arg(long, value_name = "SECS")]
+	frame_timeout: Option<f64>,
+
+	/// Extra ffmpeg option for the stereo video encoder, placed before the output
+	/// path; repeat once per word (--ffmpeg-arg=-tune --ffmpeg-arg=film). Inputs
+	/// and filters are rejected; anything else is passed through as is
+	#[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+	ffmpeg_arg: Vec<String>,
+
+	/// Extra ffmpeg option for video frame extraction, repeatable like
+	/// --ffmpeg-arg. Options that change the frame size, format or rate are rejected
+	#[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+	ffmpeg_extract_arg: Vec<String>,
+
+	/// Continue a chunked video from its last finished chunk
+	#[arg(long, requires = "chunk_frames")]
+	resume: bool,
+
+	/// Number of evenly spaced views for the `views` output (view 0 is the original)
+	#[arg(long, default_value = "5")]
+	views: u32,
+
+	/// Panels of the `preview` contact sheet, in order (comma-separated):
+	/// original, depth, stereo, anaglyph
+	#[arg(long, default_value = "original,depth,stereo,anaglyph", value_name = "PANELS")]
+	preview_panels: String,
+
+	/// Normal map strength; higher values exaggerate surface relief (default 1.0)
+	#[arg(long, default_value = "1.0")]
+	normal_strength: f32,
+
+	/// Letterbox the input to the depth model instead of stretching it to a square
+	#[arg(long)]
+	preserve_aspect: bool,
+
+	/// Resolution the depth model runs at, rounded to a multiple of 14 (default: the
+	/// model's, 518 for Depth-Anything-V2).
+	/// Lower is faster; the bundled CoreML models only support 518
+	#[arg(long, value_name = "PIXELS")]
+	depth_size: Option<u32>,
+
+	/// ONNX Runtime intra-op thread count (default: number of physical CPU cores)
+	#[arg(long)]
+	onnx_threads: Option<usize>,
+
+	/// ONNX Runtime inter-op thread count (default: ONNX Runtime's)
+	#[arg(long)]
+	onnx_inter_threads: Option<usize>,
+
+	/// Enable the ONNX Runtime memory arena; speeds up repeated inference on
+	/// video at the cost of holding on to peak memory
+	#[arg(long)]
+	onnx_memory_arena: bool,
+
+	/// For video inputs, only process the single frame at this timestamp (e.g. 00:01:30)
+	/// as a photo, to quickly try out disparity and filter settings
+	#[arg(long, value_name = "TIMESTAMP")]
+	preview_at: Option<String>,
 
 	/// Force regeneration of depth maps even if they already exist
 	#[arg(short, long)]
 	force: bool,
+
+	/// Directory models are read from and downloaded to. Takes precedence over
+	/// SPATIAL_MAKER_CHECKPOINTS, which takes precedence over ~/.spatial-maker/checkpoints
+	#[arg(long, global = true, value_name = "PATH")]
+	checkpoints_dir: Option<PathBuf>,
+
+	/// Increase log verbosity (-v info, -vv debug); RUST_LOG overrides
+	#[arg(short, long, action = clap::ArgAction::Count)]
+	verbose: u8,
+
+	/// Hide the progress display and only print errors
+	#[arg(short, long, conflicts_with = "verbose")]
+	quiet: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+	/// Generate stereo output from an image and an existing depth map, without running a depth model
+	Stereo {
+		/// Input image
+		#[arg(long)]
+		image: PathBuf,
+
+		/// Depth map (PNG, near = bright); resampled to the image size
+		#[arg(long)]
+		depth: PathBuf,
+
+		/// Output path base (default: next to the image)
+		#[arg(short, long)]
+		output: Option<PathBuf>,
+
+		/// Maximum disparity in pixels
+		#[arg(long, default_value = "30")]
+		max_disparity: u32,
+
+		/// Stereo output types (comma-separated): sbs, tab, sep, spatial, clip
+		#[arg(long, default_value = "spatial")]
+		output_types: String,
+
+		/// JPEG quality (1-100)
+		#[arg(long, default_value = "95", value_parser = clap::value_parser!(u8).range(1..=100))]
+		quality: u8,
+	},
+	/// Time depth inference and the in-memory photo pipeline; writes no files
+	Bench {
+		/// Model size (s, b, l) or checkpoint filename
+		#[arg(short, long, default_value = "s")]
+		model: String,
+
+		/// Number of timed iterations
+		#[arg(long, default_value = "20", value_parser = clap::value_parser!(u32).range(1..))]
+		frames: u32,
+
+		/// Untimed iterations run first, so model load and warm-up aren't measured
+		#[arg(long, default_value = "3")]
+		warmup: u32,
+
+		/// Image to run on (default: a synthetic 1920x1080 image)
+		#[arg(long)]
+		image: Option<PathBuf>,
+	},
 	/// Update spatial-maker to the latest release
 	#[command(name = "self")]
 	Self_ {
 		#[command(subcommand)]
 		action: SelfAction,
 	},
+	/// Report available tools, depth backends, decoders and downloaded models
+	Doctor {
+		/// Print the report as JSON
+		#[arg(long)]
+		json: bool,
+	},
 }
 
 #[derive(Subcommand)]
@@ -90,7 +438,22 @@ enum SelfAction {
 	Update,
 }
 
+/// Strips a leading dot from an extension flag's value, exiting on anything that
+/// isn't a bare extension.
+fn parse_extension(flag: &str, ext: &str) -> String {
+	let ext = ext.trim_start_matches('.');
+	if ext.is_empty() || ext.contains(['.', '/', '\\']) {
+		eprintln!("{} must be a file extension such as heic or mov", flag);
+		exit(EXIT_USAGE);
+	}
+	ext.to_string()
+}
+
 fn detect_media_type(path: &PathBuf) -> MediaType {
+	if spatial_maker::is_stdin(path) || ImageSequence::is_sequence_path(path) {
+		return MediaType::Video;
+	}
+
 	let ext = path
 		.extension()
 		.and_then(|s| s.to_str())
@@ -106,6 +469,11 @@ fn detect_media_type(path: &PathBuf) -> MediaType {
 }
 
 fn generate_output_base(input: &PathBuf, model: &str) -> PathBuf {
+	if spatial_maker::is_stdin(input) {
+		return PathBuf::from(format!("stdin-{}", model));
+	}
+	let sequence_stem = ImageSequence::detect(input).ok().flatten().map(|s| s.stem_path());
+	let input = sequence_stem.as_ref().unwrap_or(input);
 	let stem = input.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
 	let parent = input.parent().unwrap_or_else(|| std::path::Path::new("."));
 	parent.join(format!("{}-{}", stem, model))
@@ -124,7 +492,9 @@ enum TuiEvent {
 	FileStarted(usize),
 	StageUpdate { index: usize, stage: String, progress: f64 },
 	FileDone { index: usize, outputs: Vec<String>, duration: std::time::Duration },
-	FileError { index: usize, error: String },
+	FileError { index: usize, error: String, code: i32 },
+	OutputFailed { index: usize, output: String, error: String },
+	Warning { index: usize, message: String },
 	VideoProgress { index: usize, progress: VideoProgress, fps: f64, eta: String },
 	AllDone,
 }
@@ -132,41 +502,214 @@ enum TuiEvent {
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	let cli = Cli::parse();
+	let _temp_cleanup = spatial_maker::temp::CleanupGuard;
+	init_logging(cli.verbose, cli.quiet);
+	spatial_maker::set_checkpoint_dir(cli.checkpoints_dir.clone());
 
-	if let Some(Commands::Self_ { action: SelfAction::Update }) = cli.command {
-		return self_update().await;
+	match cli.command {
+		Some(Commands::Doctor { json }) => {
+			return exit_on_error(doctor(json));
+		}
+		Some(Commands::Self_ { action: SelfAction::Update }) => {
+			return exit_on_error(self_update().await);
+		}
+		Some(Commands::Stereo {
+			ref image,
+			ref depth,
+			ref output,
+			max_disparity,
+			ref output_types,
+			quality,
+		}) => {
+			return exit_on_error(
+				stereo_from_depth(image, depth, output.as_deref(), max_disparity, output_types, quality).await,
+			);
+		}
+		Some(Commands::Bench {
+			ref model,
+			frames,
+			warmup,
+			ref image,
+		}) => {
+			return exit_on_error(bench(model, frames, warmup, image.as_deref()).await);
+		}
+		None => {}
 	}
 
 	if cli.inputs.is_empty() {
 		eprintln!("No input files provided. Usage: spatial-maker <files...>");
 		eprintln!("Run 'spatial-maker --help' for more information.");
-		std::process::exit(1);
+		exit(EXIT_USAGE);
 	}
 
 	if cli.output.is_some() && cli.inputs.len() > 1 {
 		eprintln!("--output cannot be used with multiple inputs");
-		std::process::exit(1);
+		exit(EXIT_USAGE);
 	}
 
-	let output_types = parse_output_types(&cli.output_types).unwrap_or_else(|e| {
+	let requested_types = if cli.depth_only { "depth" } else { cli.output_types.as_str() };
+	let output_types = parse_output_types(requested_types).unwrap_or_else(|e| {
 		eprintln!("Invalid --output-types: {}", e);
-		std::process::exit(1);
+		exit(EXIT_USAGE);
+	});
+	let preview_panels = parse_preview_panels(&cli.preview_panels).unwrap_or_else(|e| {
+		eprintln!("Invalid --preview-panels: {}", e);
+		exit(EXIT_USAGE);
 	});
 
-	let normalize_mode: NormalizeMode = cli.normalize.parse().unwrap_or_else(|e| {
+	if output_types.contains(&OutputType::Spatial) {
+		if let Err(e) = check_spatial_cli(Path::new("spatial")) {
+			eprintln!("Warning: --output-types spatial requested but {}", e);
+		}
+	}
+
+	let temporal_mode: TemporalMode = cli.temporal.parse().unwrap_or_else(|e| {
 		eprintln!("{}", e);
-		std::process::exit(1);
+		exit(EXIT_USAGE);
+	});
+
+	let video_codec: Option<VideoCodec> = cli.video_codec.as_deref().map(|codec| {
+		codec.parse().unwrap_or_else(|e| {
+			eprintln!("{}", e);
+			exit(EXIT_USAGE);
+		})
+	});
+
+	if cli.temporal_alpha.is_some_and(|alpha| !(0.0..=1.0).contains(&alpha)) {
+		eprintln!("--temporal-alpha must be between 0 and 1");
+		exit(EXIT_USAGE);
+	}
+
+	let spatial_photo_extension = cli.spatial_photo_ext.as_deref().map(|ext| {
+		let ext = parse_extension("--spatial-photo-ext", ext);
+		// Photos are written as JPEG before `spatial make` encodes them.
+		if ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg") {
+			eprintln!("--spatial-photo-ext can't be {}, the stereo image uses it", ext);
+			exit(EXIT_USAGE);
+		}
+		ext
 	});
+	let spatial_video_extension = cli.spatial_video_ext.as_deref().map(|ext| parse_extension("--spatial-video-ext", ext));
+
+	if cli.max_depth_megapixels.is_some_and(|mp| !(mp.is_finite() && mp >= 0.0)) {
+		eprintln!("--max-depth-megapixels must be 0 (no limit) or a positive number");
+		exit(EXIT_USAGE);
+	}
+
+	if cli.frame_timeout.is_some_and(|secs| !(secs.is_finite() && secs >= 0.0)) {
+		eprintln!("--frame-timeout must be 0 (wait indefinitely) or a positive number of seconds");
+		exit(EXIT_USAGE);
+	}
+
+	if cli.target_size.is_some_and(|mb| !(mb.is_finite() && mb > 0.0)) {
+		eprintln!("--target-size must be a positive number of MB");
+		exit(EXIT_USAGE);
+	}
+
+	let fill: Option<DisocclusionFill> = cli.fill.as_deref().map(|fill| {
+		fill.parse().unwrap_or_else(|e| {
+			eprintln!("{}", e);
+			exit(EXIT_USAGE);
+		})
+	});
+
+	let depth_naming: Option<DepthNaming> = cli.depth_naming.as_deref().map(|naming| {
+		naming.parse().unwrap_or_else(|e| {
+			eprintln!("{}", e);
+			exit(EXIT_USAGE);
+		})
+	});
+
+	let stereo_mode: Option<StereoMode> = cli.stereo_mode.as_deref().map(|mode| {
+		mode.parse().unwrap_or_else(|e| {
+			eprintln!("{}", e);
+			exit(EXIT_USAGE);
+		})
+	});
+
+	let global_cache: Option<GlobalCache> = cli.global_cache.as_deref().map(|cache| {
+		cache.parse().unwrap_or_else(|e| {
+			eprintln!("{}", e);
+			exit(EXIT_USAGE);
+		})
+	});
+
+	let depth_cache_dir = (!cli.no_cache && (cli.cache_depth || std::env::var_os("SPATIAL_MAKER_CACHE").is_some()))
+		.then(|| {
+			spatial_maker::depth_cache::default_cache_dir().unwrap_or_else(|e| {
+				eprintln!("{}", e);
+				exit(EXIT_USAGE);
+			})
+		});
+
+	let base = match cli.preset.as_deref() {
+		Some(name) => SpatialConfig::preset(name).unwrap_or_else(|e| {
+			eprintln!("{}", e);
+			exit(EXIT_USAGE);
+		}),
+		None => SpatialConfig::default(),
+	};
+
+	let normalize_mode: NormalizeMode = match cli.normalize.as_deref() {
+		Some(mode) => mode.parse().unwrap_or_else(|e| {
+			eprintln!("{}", e);
+			exit(EXIT_USAGE);
+		}),
+		None => base.normalize_mode.clone(),
+	};
 
 	let config = SpatialConfig {
 		encoder_size: cli.model.clone(),
-		max_disparity: cli.max_disparity,
-		target_depth_size: 518,
-		temporal_alpha: cli.temporal_alpha,
-		bilateral_sigma_space: cli.bilateral_sigma,
-		bilateral_sigma_color: cli.bilateral_range,
-		depth_blur_sigma: cli.depth_blur,
+		max_disparity: cli.max_disparity.unwrap_or(base.max_disparity),
+		temporal_alpha: cli.temporal_alpha.unwrap_or(base.temporal_alpha),
+		temporal_mode,
+		bilateral_sigma_space: cli.bilateral_sigma.unwrap_or(base.bilateral_sigma_space),
+		bilateral_sigma_color: cli.bilateral_range.unwrap_or(base.bilateral_sigma_color),
+		depth_blur_sigma: cli.depth_blur.unwrap_or(base.depth_blur_sigma),
+		adaptive_smoothing: cli.adaptive_smoothing,
+		edge_blur_only: cli.edge_blur_only.unwrap_or(base.edge_blur_only),
+		input_is_stereo: cli.input_is_stereo,
 		normalize_mode,
+		global_cache: global_cache.unwrap_or(base.global_cache),
+		chunk_frames: cli.chunk_frames.or(base.chunk_frames),
+		resume: cli.resume,
+		num_threads: cli.onnx_threads,
+		inter_threads: cli.onnx_inter_threads.or(base.inter_threads),
+		memory_arena: cli.onnx_memory_arena || base.memory_arena,
+		preserve_aspect: cli.preserve_aspect,
+		target_depth_size: cli.depth_size.or(base.target_depth_size),
+		normal_strength: cli.normal_strength,
+		num_views: cli.views,
+		equalize_depth: cli.equalize_depth,
+		equalize_bins: cli.equalize_bins,
+		suppress_low_texture: cli.suppress_low_texture,
+		low_texture_threshold: cli.low_texture_threshold,
+		flat_depth_fallback: cli.flat_depth_fallback,
+		fusion: cli.fusion,
+		depth_cache_dir,
+		max_depth_pixels: cli
+			.max_depth_megapixels
+			.map_or(base.max_depth_pixels, |mp| (mp * 1e6) as u64),
+		skip_duplicate_frames: cli.skip_duplicate_frames,
+		match_luminance: cli.match_luminance,
+		ground_plane_bias: cli.ground_plane_bias,
+		floating_window: cli.floating_window,
+		floating_window_width: cli.floating_window_width,
+		disparity_median_clamp: cli.disparity_median_clamp,
+		equirect: cli.equirect,
+		auto_convergence: cli.auto_convergence,
+		auto_disparity: cli.auto_disparity,
+		disocclusion_fill: if cli.no_fill { DisocclusionFill::Black } else { fill.unwrap_or(base.disocclusion_fill) },
+		stereo_mode: stereo_mode.unwrap_or(base.stereo_mode),
+		video_codec: video_codec.or(base.video_codec),
+		spatial_extension: spatial_video_extension,
+		target_size_mb: cli.target_size.or(base.target_size_mb),
+		sequence_fps: cli.sequence_fps.unwrap_or(base.sequence_fps),
+		output_fps: cli.output_fps.or(base.output_fps),
+		frame_timeout_secs: cli.frame_timeout.unwrap_or(base.frame_timeout_secs),
+		extra_encoder_args: cli.ffmpeg_arg.clone(),
+		extra_extract_args: cli.ffmpeg_extract_arg.clone(),
+		..base
 	};
 
 	let (model_name, model_mb) = model_display_name(&cli.model);
@@ -182,15 +725,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 		.collect();
 
 	let mut state = AppState::new(filenames, model_name, model_mb);
-	let mut terminal = tui::init_terminal()?;
+	let mut terminal = if cli.quiet { None } else { Some(tui::init_terminal()?) };
 
 	let (tx, mut rx) = mpsc::unbounded_channel::<TuiEvent>();
 
 	let inputs_owned: Vec<PathBuf> = cli.inputs.clone();
 	let output_opt = cli.output.clone();
 	let model_str = cli.model.clone();
-	let quality = cli.quality;
 	let force = cli.force;
+	let preview_at = cli.preview_at.clone();
+	let mut output_options = if is_depth_only(&output_types) {
+		OutputOptions::default()
+	} else {
+		let mut options = OutputOptions::for_output_types(&output_types, ImageEncoding::Jpeg { quality: cli.quality })
+			.with_keep_intermediate(cli.keep_intermediate)
+			.with_spatial_extension(spatial_photo_extension);
+		options.swap_eyes = cli.cross_eyed;
+		options
+	};
+	output_options.depth_native_resolution = cli.depth_native_resolution;
+	output_options.write_sidecar = cli.write_sidecar;
+	output_options.dither = cli.dither;
+	output_options.depth_naming = depth_naming.unwrap_or_default();
+	output_options.preview_panels = preview_panels;
 	let output_types_owned = output_types.clone();
 	let config_owned = config.clone();
 
@@ -211,7 +768,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 				output,
 				config_owned.clone(),
 				&output_types_owned,
-				quality,
+				output_options.clone(),
+				preview_at.as_deref(),
 				force,
 			)
 			.await;
@@ -226,6 +784,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 					let _ = tx.send(TuiEvent::FileError {
 						index: i,
 						error: e.to_string(),
+						code: e.downcast_ref::<SpatialError>().map_or(EXIT_FAILURE, exit_code),
 					});
 				}
 			}
@@ -235,11 +794,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 	let mut tick_interval = tokio::time::interval(std::time::Duration::from_millis(100));
 	let mut done = false;
+	let mut failed_outputs: Vec<(usize, String, String)> = Vec::new();
+	let mut warnings: Vec<(usize, String)> = Vec::new();
+	let mut error_codes: Vec<i32> = Vec::new();
 
 	loop {
 		tokio::select! {
 			_ = tick_interval.tick() => {
-				tui::render_frame(&mut terminal, &state)?;
+				if let Some(terminal) = terminal.as_mut() {
+					tui::render_frame(terminal, &state)?;
+				}
 			}
 			event = rx.recv() => {
 				match event {
@@ -251,13 +815,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 				}
 					Some(TuiEvent::FileDone { index, outputs, duration }) => {
 						state.mark_done(index, outputs, duration);
-						let file_state = state.files[index].clone();
-						tui::insert_completed_line(&mut terminal, &file_state, index, &state)?;
+						if let Some(terminal) = terminal.as_mut() {
+							let file_state = state.files[index].clone();
+							tui::insert_completed_line(terminal, &file_state, index, &state)?;
+						}
 					}
-					Some(TuiEvent::FileError { index, error }) => {
+					Some(TuiEvent::FileError { index, error, code }) => {
+						error_codes.push(code);
+						if terminal.is_none() {
+							eprintln!("{}: {}", cli.inputs[index].display(), error);
+						}
 						state.mark_error(index, error);
-						let file_state = state.files[index].clone();
-						tui::insert_completed_line(&mut terminal, &file_state, index, &state)?;
+						if let Some(terminal) = terminal.as_mut() {
+							let file_state = state.files[index].clone();
+							tui::insert_completed_line(terminal, &file_state, index, &state)?;
+						}
+					}
+					Some(TuiEvent::OutputFailed { index, output, error }) => {
+						failed_outputs.push((index, output, error));
+					}
+					Some(TuiEvent::Warning { index, message }) => {
+						warnings.push((index, message));
 					}
 					Some(TuiEvent::VideoProgress { index, progress, fps, eta }) => {
 						state.update_video_progress(index, &progress, fps, eta);
@@ -266,7 +844,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 						done = true;
 					}
 				}
-				tui::render_frame(&mut terminal, &state)?;
+				if let Some(terminal) = terminal.as_mut() {
+					tui::render_frame(terminal, &state)?;
+				}
 				if done {
 					break;
 				}
@@ -274,7 +854,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 		}
 	}
 
-	tui::restore_terminal();
+	if terminal.is_some() {
+		tui::restore_terminal();
+	}
+
+	if !cli.quiet {
+		print_warnings(&cli.inputs, &warnings);
+	}
+
+	for (index, output, error) in &failed_outputs {
+		eprintln!(
+			"{}: {} failed: {}",
+			cli.inputs[*index].display(),
+			output,
+			error,
+		);
+	}
 
 	let error_count = state
 		.files
@@ -288,9 +883,138 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 			error_count,
 			state.total,
 		);
-		std::process::exit(1);
+		// Report the failure itself when every file failed the same way.
+		let code = match error_codes.first() {
+			Some(&first) if error_count == state.total && error_codes.iter().all(|&c| c == first) => first,
+			_ => EXIT_PARTIAL,
+		};
+		exit(code);
 	}
 
+	if !failed_outputs.is_empty() {
+		eprintln!("\n{} outputs failed", failed_outputs.len());
+		exit(EXIT_PARTIAL);
+	}
+
+	Ok(())
+}
+
+/// Removes the temp run directory and exits with `code`.
+fn exit(code: i32) -> ! {
+	spatial_maker::temp::cleanup();
+	std::process::exit(code)
+}
+
+/// Exits with `exit_code` for library errors; anything else is returned and
+/// exits with the default status 1.
+fn exit_on_error(result: Result<(), Box<dyn std::error::Error>>) -> Result<(), Box<dyn std::error::Error>> {
+	if let Some(err) = result.as_ref().err().and_then(|e| e.downcast_ref::<SpatialError>()) {
+		eprintln!("Error: {}", err);
+		exit(exit_code(err));
+	}
+	result
+}
+
+/// Prints each file's warnings as dimmed lines under its name.
+fn print_warnings(inputs: &[PathBuf], warnings: &[(usize, String)]) {
+	use std::io::IsTerminal;
+	let (dim, reset) = if std::io::stderr().is_terminal() { ("\x1b[2m", "\x1b[0m") } else { ("", "") };
+	let mut last = None;
+	for (index, message) in warnings {
+		if last != Some(*index) {
+			eprintln!("{}{}:{}", dim, inputs[*index].display(), reset);
+			last = Some(*index);
+		}
+		eprintln!("{}  warning: {}{}", dim, message, reset);
+	}
+}
+
+fn init_logging(verbose: u8, quiet: bool) {
+	let level = match (quiet, verbose) {
+		(true, _) => "error",
+		(false, 0) => "warn",
+		(false, 1) => "info",
+		(false, _) => "debug",
+	};
+	let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+		.unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(format!("warn,spatial_maker={}", level)));
+
+	tracing_subscriber::fmt()
+		.with_env_filter(filter)
+		.with_writer(std::io::stderr)
+		.with_target(false)
+		.init();
+}
+
+fn doctor(json: bool) -> Result<(), Box<dyn std::error::Error>> {
+	let caps = spatial_maker::capabilities();
+	if json {
+		println!("{}", serde_json::to_string_pretty(&caps)?);
+		return Ok(());
+	}
+
+	let mark = |ok: bool| if ok { "ok" } else { "missing" };
+	let list = |items: &[&str]| if items.is_empty() { "none".to_string() } else { items.join(", ") };
+	println!("spatial-maker v{}", caps.version);
+	println!();
+	println!("ffmpeg          {}", mark(caps.backends.ffmpeg));
+	println!("ffprobe         {}", mark(caps.backends.ffprobe));
+	match &caps.spatial_cli_version {
+		Some(version) => println!("spatial CLI     ok ({})", version),
+		None => println!("spatial CLI     missing (needed for spatial/MV-HEVC output)"),
+	}
+	println!("video           {}", if caps.backends.video { "ok" } else { "unavailable (needs ffmpeg and ffprobe)" });
+	println!();
+	println!("depth backends  {}", list(&caps.depth_backends));
+	println!("native decoders {}", list(&caps.native_decoders));
+	println!(
+		"avif/jxl/heic   {}/{}/{} (native or via ffmpeg)",
+		mark(caps.backends.avif_decode),
+		mark(caps.backends.jxl_decode),
+		mark(caps.backends.heic_decode)
+	);
+	println!("avif depth      {}", mark(caps.backends.avif_encode));
+	println!();
+	match &caps.checkpoint_dir {
+		Some(dir) => println!("checkpoints     {}", dir.display()),
+		None => println!("checkpoints     no directory (home directory unknown)"),
+	}
+	for checkpoint in &caps.checkpoints {
+		match &checkpoint.path {
+			Some(path) => println!("  {:<13} {}", checkpoint.size, path.display()),
+			None => println!("  {:<13} not downloaded", checkpoint.size),
+		}
+	}
+	Ok(())
+}
+
+async fn stereo_from_depth(
+	image: &Path,
+	depth: &Path,
+	output: Option<&Path>,
+	max_disparity: u32,
+	output_types: &str,
+	quality: u8,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let output_types = parse_output_types(output_types)?;
+	let output_base = output.map(Path::to_path_buf).unwrap_or_else(|| {
+		let stem = image.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+		image.with_file_name(stem)
+	});
+	let config = SpatialConfig {
+		max_disparity,
+		..SpatialConfig::default()
+	};
+	let output_options = OutputOptions::for_output_types(&output_types, ImageEncoding::Jpeg { quality });
+
+	let (written, warnings) =
+		process_stereo_from_depth_with_warnings(image, depth, &output_base, &config, &output_types, output_options)
+			.await?;
+	for path in written {
+		println!("{}", path.display());
+	}
+	let warnings: Vec<(usize, String)> = warnings.iter().map(|w| (0, w.to_string())).collect();
+	print_warnings(&[image.to_path_buf()], &warnings);
 	Ok(())
 }
 
@@ -301,183 +1025,87 @@ async fn process_file(
 	output: PathBuf,
 	config: SpatialConfig,
 	output_types: &[OutputType],
-	quality: u8,
+	output_options: OutputOptions,
+	preview_at: Option<&str>,
 	force: bool,
 ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-	let media_type = detect_media_type(input);
+	let mut media_type = detect_media_type(input);
+	let mut input = input.clone();
+	let mut output = output;
+	let mut _preview_frame = None;
+
+	if let (MediaType::Video, Some(timestamp)) = (&media_type, preview_at) {
+		let _ = tx.send(TuiEvent::StageUpdate {
+			index,
+			stage: "extracting frame".to_string(),
+			progress: 0.0,
+		});
+		let frame = extract_single_frame(&input, timestamp).await?;
+		let frame_path = spatial_maker::temp::temp_file(".png")?.into_temp_path();
+		frame.save(&frame_path)?;
+
+		let stem = output.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+		output = output.with_file_name(format!("{}-preview-{}", stem, timestamp.replace([':', '.'], "-")));
+		input = frame_path.to_path_buf();
+		media_type = MediaType::Photo;
+		_preview_frame = Some(frame_path);
+	}
 
 	match media_type {
 		MediaType::Photo => {
-			let parent = output.parent().unwrap_or_else(|| Path::new("."));
-			let stem = output.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
-
-			let do_depth = needs_depth(output_types);
-			let do_stereo = needs_stereo(output_types);
-
-			let depth_paths: Vec<(std::path::PathBuf, spatial_maker::DepthFormat)> = if do_depth {
-				depth_formats(output_types)
-					.into_iter()
-					.map(|fmt| {
-						let filename = format!("{}-depth{}.{}", stem, fmt.suffix(), fmt.extension());
-						(parent.join(&filename), fmt)
-					})
-					.collect()
-			} else {
-				Vec::new()
-			};
-
-			let all_depth_exist = !depth_paths.is_empty() && depth_paths.iter().all(|(p, _)| p.exists());
-			let skip_estimation = all_depth_exist && !force;
-
-			let mut outputs = Vec::new();
-
-			let depth_map = if skip_estimation {
-				let _ = tx.send(TuiEvent::StageUpdate {
-					index,
-					stage: "depth cached".to_string(),
-					progress: 1.0,
-				});
-
-				for (p, _) in &depth_paths {
-					if let Some(name) = p.file_name().and_then(|s| s.to_str()) {
-						outputs.push(name.to_string());
-					}
-				}
-
-				if do_stereo {
-					let best = depth_paths.iter()
-						.find(|(_, fmt)| matches!(fmt, spatial_maker::DepthFormat::Png16))
-						.or_else(|| depth_paths.iter().find(|(_, fmt)| matches!(fmt, spatial_maker::DepthFormat::Png)))
-						.or_else(|| depth_paths.first())
-						.map(|(p, _)| p);
-					match best {
-						Some(p) => Some(load_depth_map(p)?),
-						None => None,
-					}
-				} else {
-					None
-				}
-			} else {
-				let _ = tx.send(TuiEvent::StageUpdate {
-					index,
-					stage: "loading".to_string(),
-					progress: 0.0,
-				});
-				let input_image_for_depth = load_image(input).await?;
-
-				let _ = tx.send(TuiEvent::StageUpdate {
-					index,
-					stage: "loading model".to_string(),
-					progress: 0.0,
-				});
-				model::ensure_model_exists::<fn(u64, u64)>(&config.encoder_size, None).await?;
-				let model_path = model::find_model(&config.encoder_size)?;
-				let model_str = model_path.to_str().ok_or("Invalid model path encoding")?;
-				let estimator = CoreMLDepthEstimator::new(model_str)?;
-
-				let _ = tx.send(TuiEvent::StageUpdate {
-					index,
-					stage: "estimating depth".to_string(),
-					progress: 0.0,
-				});
-				let dm = estimator.estimate(&input_image_for_depth)?;
+			let tx_clone = tx.clone();
 
-				if do_depth {
-					let _ = tx.send(TuiEvent::StageUpdate {
+			let result = process_photo_with_progress(
+				&input,
+				&output,
+				config,
+				output_types,
+				output_options,
+				force,
+				Some(move |stage: &str, progress: f64| {
+					let _ = tx_clone.send(TuiEvent::StageUpdate {
 						index,
-						stage: "saving depth".to_string(),
-						progress: 0.0,
+						stage: stage.to_string(),
+						progress,
 					});
+				}),
+			)
+			.await?;
 
-					for (depth_path, fmt) in &depth_paths {
-						save_depth_map(&dm, depth_path, *fmt)?;
-						if let Some(name) = depth_path.file_name().and_then(|s| s.to_str()) {
-							outputs.push(name.to_string());
-						}
-					}
-				}
-
-				Some(dm)
-			};
-
-			if do_stereo {
-				let dm = depth_map.as_ref().ok_or("Depth map required for stereo but not available")?;
-				let input_image = load_image(input).await?;
-
-				let _ = tx.send(TuiEvent::StageUpdate {
+			for warning in &result.warnings {
+				let _ = tx.send(TuiEvent::Warning {
 					index,
-					stage: "generating stereo".to_string(),
-					progress: 0.0,
+					message: warning.to_string(),
 				});
+			}
 
-				let tx_clone = tx.clone();
-				let (left, right) = generate_stereo_pair_with_progress(
-					&input_image,
-					dm,
-					config.max_disparity,
-					Some(move |progress| {
-						let _ = tx_clone.send(TuiEvent::StageUpdate {
-							index,
-							stage: "generating stereo".to_string(),
-							progress,
-						});
-					}),
-				)?;
-
-				let _ = tx.send(TuiEvent::StageUpdate {
+			for (path, error) in &result.errors {
+				let _ = tx.send(TuiEvent::OutputFailed {
 					index,
-					stage: "saving".to_string(),
-					progress: 0.0,
+					output: path.file_name().and_then(|s| s.to_str()).unwrap_or("output").to_string(),
+					error: error.to_string(),
 				});
-
-				let stereo = spatial_maker::stereo_types(output_types);
-				let layout = match stereo.first() {
-					Some(OutputType::TopAndBottom) => OutputFormat::TopAndBottom,
-					Some(OutputType::Separate) => OutputFormat::Separate,
-					_ => OutputFormat::SideBySide,
-				};
-
-				let has_spatial = output_types.iter().any(|t| matches!(t, OutputType::Spatial));
-
-				let output_options = OutputOptions {
-					layout,
-					image_format: ImageEncoding::Jpeg { quality },
-					mvhevc: if has_spatial {
-						Some(MVHEVCConfig {
-							spatial_cli_path: None,
-							enabled: true,
-							quality,
-							keep_intermediate: output_types.iter().any(|t| matches!(t, OutputType::SideBySide | OutputType::TopAndBottom | OutputType::Separate)),
-						})
-					} else {
-						None
-					},
-				};
-
-				let src_ext = input.extension().and_then(|s| s.to_str()).unwrap_or("").to_lowercase();
-				let stereo_ext = match src_ext.as_str() {
-					"heic" | "heif" | "avif" | "jxl" => "jpg",
-					"" => "jpg",
-					other => other,
-				};
-				let parent = output.parent().unwrap_or_else(|| Path::new("."));
-				let stem = output.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
-				let stereo_path = parent.join(format!("{}-spatial.{}", stem, stereo_ext));
-				save_stereo_image(&left, &right, &stereo_path, output_options)?;
-
-				if let Some(name) = stereo_path.file_name().and_then(|s| s.to_str()) {
-					outputs.push(name.to_string());
-				}
 			}
 
+			let outputs = result
+				.depth_paths
+				.iter()
+				.chain(result.normal_paths.iter())
+				.chain(result.view_paths.iter())
+				.chain(result.stereo_paths.iter())
+				.chain(result.sidecar_path.iter())
+				.filter_map(|p| p.file_name().and_then(|s| s.to_str()))
+				.map(|s| s.to_string())
+				.collect();
+
 			Ok(outputs)
 		}
 		MediaType::Video => {
 			let start = Instant::now();
 			let tx_clone = tx.clone();
 
-			process_video(
-				input,
+			let result = process_video_with_warnings(
+				&input,
 				&output,
 				config,
 				output_types,
@@ -512,6 +1140,13 @@ async fn process_file(
 			)
 			.await?;
 
+			for warning in &result.warnings {
+				let _ = tx.send(TuiEvent::Warning {
+					index,
+					message: warning.to_string(),
+				});
+			}
+
 			let out_name = output
 				.file_name()
 				.and_then(|s| s.to_str())
@@ -523,6 +1158,97 @@ async fn process_file(
 	}
 }
 
+/// Times `frames` depth estimates, and the same estimates followed by the stereo
+/// warp and side-by-side compose, after `warmup` untimed runs.
+async fn bench(
+	encoder_size: &str,
+	frames: u32,
+	warmup: u32,
+	image: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+	use spatial_maker::{create_sbs_image, generate_stereo_pair_with, StereoParams};
+
+	let image = match image {
+		Some(path) => spatial_maker::load_image(path).await?,
+		None => image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(1920, 1080, |x, y| {
+			image::Rgb([(x * 255 / 1920) as u8, (y * 255 / 1080) as u8, ((x ^ y) & 0xff) as u8])
+		})),
+	};
+	let config = SpatialConfig {
+		encoder_size: encoder_size.to_string(),
+		..SpatialConfig::default()
+	};
+
+	let load_start = Instant::now();
+	model::ensure_model_exists(encoder_size, None::<fn(u64, u64)>).await?;
+	let model_path = model::find_model(encoder_size)?;
+	let model_str = model_path.to_str().ok_or("Invalid model path encoding")?;
+
+	#[cfg(all(target_os = "macos", feature = "coreml"))]
+	let estimator = spatial_maker::CoreMLDepthEstimator::from_config(model_str, &config)?;
+	#[cfg(all(target_os = "macos", feature = "coreml"))]
+	let estimate = |img: &image::DynamicImage| estimator.estimate(img);
+
+	#[cfg(all(feature = "onnx", not(all(target_os = "macos", feature = "coreml"))))]
+	let mut estimator = spatial_maker::OnnxDepthEstimator::from_config(model_str, &config)?;
+	#[cfg(all(feature = "onnx", not(all(target_os = "macos", feature = "coreml"))))]
+	let mut estimate = |img: &image::DynamicImage| estimator.estimate(img);
+
+	#[cfg(not(any(all(target_os = "macos", feature = "coreml"), feature = "onnx")))]
+	let mut estimate = |_: &image::DynamicImage| -> spatial_maker::SpatialResult<ndarray::Array2<f32>> {
+		let _ = model_str;
+		Err(spatial_maker::SpatialError::ConfigError(
+			"No depth backend enabled. Enable 'coreml' (macOS) or 'onnx' feature.".to_string(),
+		))
+	};
+
+	println!(
+		"Model {} on {}x{} (loaded in {:.2}s)",
+		encoder_size,
+		image.width(),
+		image.height(),
+		load_start.elapsed().as_secs_f64()
+	);
+
+	for _ in 0..warmup {
+		estimate(&image)?;
+	}
+
+	let (max_disparity, _) = spatial_maker::validate_max_disparity_with_warnings(config.max_disparity, image.width())?;
+	let params = StereoParams::from_config(&config, max_disparity);
+	let mut inference = Vec::with_capacity(frames as usize);
+	let mut end_to_end = Vec::with_capacity(frames as usize);
+	for _ in 0..frames {
+		let start = Instant::now();
+		let depth = estimate(&image)?;
+		inference.push(start.elapsed());
+		let (depth, _) = spatial_maker::stereo::handle_flat_depth_with_warnings(depth, config.flat_depth_fallback);
+		let (left, right) = generate_stereo_pair_with(&image, &depth, &params, None::<fn(f64)>)?;
+		std::hint::black_box(create_sbs_image(&left, &right));
+		end_to_end.push(start.elapsed());
+	}
+
+	print_timings("inference", &mut inference);
+	print_timings("end-to-end", &mut end_to_end);
+	Ok(())
+}
+
+fn print_timings(label: &str, samples: &mut [std::time::Duration]) {
+	samples.sort();
+	let n = samples.len();
+	let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.0;
+	let median = samples[n / 2];
+	let p95 = samples[(n * 95).div_ceil(100) - 1];
+	let mean = samples.iter().sum::<std::time::Duration>() / n as u32;
+	println!(
+		"{:<10}  median {:>8.1} ms  p95 {:>8.1} ms  {:>6.2} fps",
+		label,
+		ms(median),
+		ms(p95),
+		1.0 / mean.as_secs_f64()
+	);
+}
+
 async fn self_update() -> Result<(), Box<dyn std::error::Error>> {
 	let current_version = env!("CARGO_PKG_VERSION");
 	let repo = "mrgnw/spatial-maker";
@@ -532,13 +1258,8 @@ async fn self_update() -> Result<(), Box<dyn std::error::Error>> {
 
 	let client = reqwest::Client::new();
 
-	let release: serde_json::Value = client
-		.get(format!("https://api.github.com/repos/{}/releases/latest", repo))
-		.header("User-Agent", "spatial-maker")
-		.send()
-		.await?
-		.json()
-		.await?;
+	let release_url = format!("https://api.github.com/repos/{}/releases/latest", repo);
+	let release: serde_json::Value = serde_json::from_slice(&model::fetch_bytes(&client, &release_url).await?)?;
 
 	let latest_tag = release["tag_name"]
 		.as_str()
@@ -566,37 +1287,50 @@ async fn self_update() -> Result<(), Box<dyn std::error::Error>> {
 		.as_array()
 		.ok_or("No assets in release")?;
 
-	let download_url = assets
+	let asset = assets
 		.iter()
 		.find(|a| a["name"].as_str() == Some(&asset_name))
-		.and_then(|a| a["browser_download_url"].as_str())
 		.ok_or_else(|| format!("No release asset found for {}", target))?;
+	let download_url = asset["browser_download_url"]
+		.as_str()
+		.ok_or_else(|| format!("No download URL for {}", asset_name))?;
 
 	eprintln!("Downloading {}...", asset_name);
 
-	let response = client
-		.get(download_url)
-		.header("User-Agent", "spatial-maker")
-		.send()
-		.await?;
-
-	let bytes = response.bytes().await?;
+	let bytes = model::fetch_bytes(&client, download_url).await?;
+
+	match release_checksum(&client, asset, assets, &asset_name).await? {
+		Some(expected) => {
+			use sha2::{Digest, Sha256};
+			let actual = format!("{:x}", Sha256::digest(&bytes));
+			if actual != expected {
+				return Err(format!(
+					"Checksum mismatch for {}: expected {}, got {}; keeping the current version",
+					asset_name, expected, actual
+				)
+				.into());
+			}
+			eprintln!("Checksum verified");
+		}
+		None => eprintln!("No checksum published for {}, skipping verification", asset_name),
+	}
 
 	eprintln!("Extracting...");
 
 	let decoder = flate2::read::GzDecoder::new(&bytes[..]);
 	let mut archive = tar::Archive::new(decoder);
 
-	let temp_dir = std::env::temp_dir().join("spatial-maker-update");
-	let _ = std::fs::remove_dir_all(&temp_dir);
-	std::fs::create_dir_all(&temp_dir)?;
+	let temp_dir = tempfile::tempdir_in(spatial_maker::temp::run_dir()?)?;
 
-	archive.unpack(&temp_dir)?;
+	archive.unpack(temp_dir.path())?;
 
-	let new_binary = temp_dir.join("spatial-maker");
+	let new_binary = temp_dir.path().join("spatial-maker");
 	if !new_binary.exists() {
 		return Err("Binary not found in release archive".into());
 	}
+	verify_new_binary(&new_binary, latest_version)
+		.await
+		.map_err(|e| format!("Downloaded binary failed verification ({}); keeping the current version", e))?;
 
 	let current_exe = std::env::current_exe()?;
 	let install_path = if is_writable(&current_exe) {
@@ -626,8 +1360,6 @@ async fn self_update() -> Result<(), Box<dyn std::error::Error>> {
 
 	std::fs::rename(&staging, &install_path)?;
 
-	let _ = std::fs::remove_dir_all(&temp_dir);
-
 	eprintln!(
 		"Updated to v{} at {}",
 		latest_version,
@@ -641,6 +1373,82 @@ async fn self_update() -> Result<(), Box<dyn std::error::Error>> {
 	Ok(())
 }
 
+/// The SHA-256 a release publishes for `asset_name`: GitHub's `digest` field on
+/// the asset, or else a `<asset>.sha256` file uploaded next to it.
+async fn release_checksum(
+	client: &reqwest::Client,
+	asset: &serde_json::Value,
+	assets: &[serde_json::Value],
+	asset_name: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+	if let Some(digest) = asset["digest"].as_str().and_then(|d| d.strip_prefix("sha256:")) {
+		return Ok(Some(digest.to_lowercase()));
+	}
+
+	let checksum_name = format!("{}.sha256", asset_name);
+	let Some(url) = assets
+		.iter()
+		.find(|a| a["name"].as_str() == Some(&checksum_name))
+		.and_then(|a| a["browser_download_url"].as_str())
+	else {
+		return Ok(None);
+	};
+	let text = String::from_utf8(model::fetch_bytes(client, url).await?)?;
+	let checksum = text
+		.split_whitespace()
+		.next()
+		.filter(|c| c.len() == 64 && c.chars().all(|ch| ch.is_ascii_hexdigit()))
+		.ok_or_else(|| format!("Malformed checksum file {}", checksum_name))?;
+	Ok(Some(checksum.to_lowercase()))
+}
+
+const VERSION_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Checks that `path` is a non-empty executable for this platform that runs and
+/// reports `expected_version` from `--version`.
+async fn verify_new_binary(path: &Path, expected_version: &str) -> Result<(), Box<dyn std::error::Error>> {
+	use std::io::Read;
+
+	let mut magic = [0u8; 4];
+	let len = std::fs::metadata(path)?.len();
+	if len < magic.len() as u64 {
+		return Err("file is empty or truncated".into());
+	}
+	std::fs::File::open(path)?.read_exact(&mut magic)?;
+	let valid_magic: &[[u8; 4]] = if cfg!(target_os = "macos") {
+		// 64-bit Mach-O (little-endian) and universal (fat) binaries.
+		&[[0xcf, 0xfa, 0xed, 0xfe], [0xca, 0xfe, 0xba, 0xbe]]
+	} else if cfg!(target_os = "linux") {
+		&[[0x7f, b'E', b'L', b'F']]
+	} else {
+		&[]
+	};
+	if !valid_magic.is_empty() && !valid_magic.contains(&magic) {
+		return Err("not an executable for this platform".into());
+	}
+
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+	}
+
+	let output = tokio::time::timeout(
+		VERSION_CHECK_TIMEOUT,
+		tokio::process::Command::new(path).arg("--version").kill_on_drop(true).output(),
+	)
+	.await
+	.map_err(|_| "`--version` timed out")??;
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	if !output.status.success() {
+		return Err(format!("`--version` exited with {}", output.status).into());
+	}
+	if !stdout.split_whitespace().any(|word| word.trim_start_matches('v') == expected_version) {
+		return Err(format!("reports {:?}, expected v{}", stdout.trim(), expected_version).into());
+	}
+	Ok(())
+}
+
 fn is_newer_version(current: &str, latest: &str) -> bool {
 	let parse = |v: &str| -> Vec<u32> {
 		v.split('.').filter_map(|s| s.parse().ok()).collect()
@@ -1,9 +1,22 @@
 use crate::error::{SpatialError, SpatialResult};
 use std::path::{Path, PathBuf};
-use tokio::io::AsyncWriteExt;
 
+static CHECKPOINT_DIR_OVERRIDE: std::sync::RwLock<Option<PathBuf>> = std::sync::RwLock::new(None);
+
+/// Overrides the checkpoint directory for the rest of the process (the CLI's
+/// `--checkpoints-dir`); `None` restores the usual lookup.
+pub fn set_checkpoint_dir(dir: Option<PathBuf>) {
+	*CHECKPOINT_DIR_OVERRIDE.write().unwrap_or_else(|e| e.into_inner()) = dir;
+}
+
+/// Where models are cached, in order of precedence: the `set_checkpoint_dir`
+/// override, the `SPATIAL_MAKER_CHECKPOINTS` env var, then
+/// `~/.spatial-maker/checkpoints`.
 pub fn get_checkpoint_dir() -> SpatialResult<PathBuf> {
-	if let Ok(custom_dir) = std::env::var("SPATIAL_MAKER_CHECKPOINTS") {
+	let override_dir = CHECKPOINT_DIR_OVERRIDE.read().unwrap_or_else(|e| e.into_inner()).clone();
+	if let Some(dir) = override_dir {
+		Ok(dir)
+	} else if let Ok(custom_dir) = std::env::var("SPATIAL_MAKER_CHECKPOINTS") {
 		Ok(PathBuf::from(custom_dir))
 	} else {
 		let home = dirs::home_dir().ok_or_else(|| {
@@ -13,12 +26,68 @@ pub fn get_checkpoint_dir() -> SpatialResult<PathBuf> {
 	}
 }
 
+/// Input preprocessing a depth model expects: square input size and the
+/// per-channel mean/std applied to RGB scaled to 0-1. CoreML packages take raw
+/// 8-bit RGB and normalize internally, so only `input_size` applies to them.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ModelPreprocess {
+	pub input_size: u32,
+	pub mean: [f32; 3],
+	pub std: [f32; 3],
+}
+
+impl ModelPreprocess {
+	/// Depth-Anything-V2: 518px input, ImageNet normalization.
+	pub const DEPTH_ANYTHING_V2: Self = Self {
+		input_size: 518,
+		mean: [0.485, 0.456, 0.406],
+		std: [0.229, 0.224, 0.225],
+	};
+}
+
+/// Depth-Anything's ViT encoder works on 14px patches, so input sizes are
+/// rounded to a multiple of this.
+pub const PATCH_SIZE: u32 = 14;
+pub const MIN_INPUT_SIZE: u32 = PATCH_SIZE * 8;
+pub const MAX_INPUT_SIZE: u32 = PATCH_SIZE * 128;
+
+impl ModelPreprocess {
+	/// Returns a copy estimating at `size`, rounded to the nearest multiple of
+	/// `PATCH_SIZE`. Sizes outside `MIN_INPUT_SIZE..=MAX_INPUT_SIZE` are rejected.
+	///
+	/// The ONNX exports accept any such size. CoreML packages only do if they
+	/// were converted with flexible input shapes; the published ones are fixed
+	/// at 518 and fail to predict at other sizes.
+	pub fn with_input_size(self, size: u32) -> SpatialResult<Self> {
+		if !(MIN_INPUT_SIZE..=MAX_INPUT_SIZE).contains(&size) {
+			return Err(SpatialError::ConfigError(format!(
+				"Depth input size {} is out of range ({}-{})",
+				size, MIN_INPUT_SIZE, MAX_INPUT_SIZE
+			)));
+		}
+		let input_size = ((size + PATCH_SIZE / 2) / PATCH_SIZE) * PATCH_SIZE;
+		if input_size != size {
+			tracing::debug!("Rounded depth input size {} to {}", size, input_size);
+		}
+		Ok(Self { input_size, ..self })
+	}
+}
+
+impl Default for ModelPreprocess {
+	fn default() -> Self {
+		Self::DEPTH_ANYTHING_V2
+	}
+}
+
 #[derive(Clone, Debug)]
 pub struct ModelMetadata {
 	pub name: String,
 	pub filename: String,
 	pub url: String,
 	pub size_mb: u32,
+	pub preprocess: ModelPreprocess,
+	/// Lowercase hex SHA-256 of the downloaded file, verified before install.
+	pub sha256: Option<String>,
 }
 
 impl ModelMetadata {
@@ -29,18 +98,24 @@ impl ModelMetadata {
 				filename: "DepthAnythingV2SmallF16.mlpackage".to_string(),
 				url: "https://huggingface.co/mrgnw/depth-anything-v2-coreml/resolve/main/DepthAnythingV2SmallF16.mlpackage.tar.gz".to_string(),
 				size_mb: 48,
+				preprocess: ModelPreprocess::DEPTH_ANYTHING_V2,
+				sha256: None,
 			}),
 			"b" | "base" => Ok(ModelMetadata {
 				name: "depth-anything-v2-base".to_string(),
 				filename: "DepthAnythingV2BaseF16.mlpackage".to_string(),
 				url: "https://huggingface.co/mrgnw/depth-anything-v2-coreml/resolve/main/DepthAnythingV2BaseF16.mlpackage.tar.gz".to_string(),
 				size_mb: 186,
+				preprocess: ModelPreprocess::DEPTH_ANYTHING_V2,
+				sha256: None,
 			}),
 			"l" | "large" => Ok(ModelMetadata {
 				name: "depth-anything-v2-large".to_string(),
 				filename: "DepthAnythingV2LargeF16.mlpackage".to_string(),
 				url: "https://huggingface.co/mrgnw/depth-anything-v2-coreml/resolve/main/DepthAnythingV2LargeF16.mlpackage.tar.gz".to_string(),
 				size_mb: 638,
+				preprocess: ModelPreprocess::DEPTH_ANYTHING_V2,
+				sha256: None,
 			}),
 			other => Err(SpatialError::ConfigError(
 				format!("Unknown encoder size: '{}'. Use 's', 'b', or 'l'", other)
@@ -56,34 +131,239 @@ impl ModelMetadata {
 				filename: "depth_anything_v2_small.onnx".to_string(),
 				url: "https://huggingface.co/onnx-community/depth-anything-v2-small/resolve/main/onnx/model.onnx".to_string(),
 				size_mb: 99,
+				preprocess: ModelPreprocess::DEPTH_ANYTHING_V2,
+				sha256: None,
 			}),
 			"b" | "base" => Ok(ModelMetadata {
 				name: "depth-anything-v2-base".to_string(),
 				filename: "depth_anything_v2_base.onnx".to_string(),
 				url: "https://huggingface.co/onnx-community/depth-anything-v2-base/resolve/main/onnx/model.onnx".to_string(),
 				size_mb: 380,
+				preprocess: ModelPreprocess::DEPTH_ANYTHING_V2,
+				sha256: None,
 			}),
 			"l" | "large" => Ok(ModelMetadata {
 				name: "depth-anything-v2-large".to_string(),
 				filename: "depth_anything_v2_large.onnx".to_string(),
 				url: "https://huggingface.co/onnx-community/depth-anything-v2-large/resolve/main/onnx/model.onnx".to_string(),
 				size_mb: 1300,
+				preprocess: ModelPreprocess::DEPTH_ANYTHING_V2,
+				sha256: None,
 			}),
 			other => Err(SpatialError::ConfigError(
 				format!("Unknown encoder size: '{}'. Use 's', 'b', or 'l'", other)
 			)),
 		}
 	}
+
+	/// Looks `encoder_size` up in the remote model manifest for the active
+	/// backend, falling back to the built-in list when the manifest is
+	/// unreachable or doesn't list that size.
+	pub async fn from_manifest(encoder_size: &str) -> SpatialResult<Self> {
+		if let Some(meta) = load_manifest()
+			.await
+			.and_then(|m| m.lookup(ACTIVE_BACKEND, encoder_size))
+		{
+			return Ok(meta);
+		}
+		Self::builtin(encoder_size)
+	}
+
+	/// Like `from_manifest`, but only consults the cached manifest, never the
+	/// network.
+	pub fn from_cached_manifest(encoder_size: &str) -> Option<Self> {
+		read_cached_manifest()?.lookup(ACTIVE_BACKEND, encoder_size)
+	}
+
+	/// Metadata from the cached manifest, else the built-in list; never touches
+	/// the network.
+	fn resolved(encoder_size: &str) -> Option<Self> {
+		Self::from_cached_manifest(encoder_size).or_else(|| Self::builtin(encoder_size).ok())
+	}
+
+	fn builtin(encoder_size: &str) -> SpatialResult<Self> {
+		#[cfg(all(feature = "onnx", not(all(target_os = "macos", feature = "coreml"))))]
+		return Self::onnx(encoder_size);
+		#[cfg(not(all(feature = "onnx", not(all(target_os = "macos", feature = "coreml")))))]
+		Self::coreml(encoder_size)
+	}
 }
 
-pub fn find_model(encoder_size: &str) -> SpatialResult<PathBuf> {
+/// Backend name used to select manifest entries.
+const ACTIVE_BACKEND: &str = if cfg!(all(target_os = "macos", feature = "coreml")) {
+	"coreml"
+} else {
+	"onnx"
+};
+
+/// Default location of the model manifest. Override with
+/// `SPATIAL_MAKER_MANIFEST_URL`; set it to an empty string to disable
+/// remote lookups entirely.
+pub const DEFAULT_MANIFEST_URL: &str =
+	"https://huggingface.co/mrgnw/depth-anything-v2-coreml/resolve/main/manifest.json";
+
+const MANIFEST_CACHE_FILE: &str = "manifest.json";
+const MANIFEST_TTL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+const MANIFEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Remote list of downloadable models, so new checkpoints can ship without a
+/// new release.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ModelManifest {
+	pub models: Vec<ManifestEntry>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ManifestEntry {
+	/// Encoder size key, e.g. `"s"`.
+	pub size: String,
+	/// Extra names accepted for `size`, e.g. `["small"]`.
+	#[serde(default)]
+	pub aliases: Vec<String>,
+	/// `"coreml"` or `"onnx"`.
+	pub backend: String,
+	pub name: String,
+	pub filename: String,
+	pub url: String,
+	pub size_mb: u32,
+	#[serde(default)]
+	pub sha256: Option<String>,
+	#[serde(default)]
+	pub preprocess: Option<ModelPreprocess>,
+}
+
+impl ModelManifest {
+	pub fn lookup(&self, backend: &str, encoder_size: &str) -> Option<ModelMetadata> {
+		let size = encoder_size.to_lowercase();
+		self.models
+			.iter()
+			.filter(|e| e.backend.eq_ignore_ascii_case(backend))
+			.find(|e| e.size.eq_ignore_ascii_case(&size) || e.aliases.iter().any(|a| a.eq_ignore_ascii_case(&size)))
+			.map(|e| ModelMetadata {
+				name: e.name.clone(),
+				filename: e.filename.clone(),
+				url: e.url.clone(),
+				size_mb: e.size_mb,
+				preprocess: e.preprocess.unwrap_or_default(),
+				sha256: e.sha256.as_ref().map(|h| h.to_lowercase()),
+			})
+	}
+}
+
+fn manifest_url() -> Option<String> {
+	match std::env::var("SPATIAL_MAKER_MANIFEST_URL") {
+		Ok(url) if url.trim().is_empty() => None,
+		Ok(url) => Some(url),
+		Err(_) => Some(DEFAULT_MANIFEST_URL.to_string()),
+	}
+}
+
+fn manifest_cache_path() -> Option<PathBuf> {
+	get_checkpoint_dir().ok().map(|dir| dir.join(MANIFEST_CACHE_FILE))
+}
+
+fn read_cached_manifest() -> Option<ModelManifest> {
+	let bytes = std::fs::read(manifest_cache_path()?).ok()?;
+	serde_json::from_slice(&bytes).ok()
+}
+
+fn manifest_cache_fresh(path: &Path) -> bool {
+	std::fs::metadata(path)
+		.and_then(|m| m.modified())
+		.ok()
+		.and_then(|t| t.elapsed().ok())
+		.is_some_and(|age| age < MANIFEST_TTL)
+}
+
+/// Returns the model manifest, from the local cache if it's younger than
+/// `MANIFEST_TTL`, otherwise from the network. A stale cache is still used
+/// when the fetch fails; `None` means no manifest is available at all.
+pub async fn load_manifest() -> Option<ModelManifest> {
+	let url = manifest_url()?;
+	let cache_path = manifest_cache_path();
+	if cache_path.as_deref().is_some_and(manifest_cache_fresh) {
+		if let Some(manifest) = read_cached_manifest() {
+			return Some(manifest);
+		}
+	}
+
+	match fetch_manifest(&url).await {
+		Ok((manifest, bytes)) => {
+			if let Some(path) = cache_path {
+				if let Some(parent) = path.parent() {
+					let _ = std::fs::create_dir_all(parent);
+				}
+				if let Err(e) = std::fs::write(&path, &bytes) {
+					tracing::debug!("Failed to cache model manifest at {:?}: {}", path, e);
+				}
+			}
+			Some(manifest)
+		}
+		Err(e) => {
+			tracing::debug!("Model manifest unavailable ({}), using cached or built-in list", e);
+			read_cached_manifest()
+		}
+	}
+}
+
+async fn fetch_manifest(url: &str) -> SpatialResult<(ModelManifest, Vec<u8>)> {
+	let client = reqwest::Client::builder()
+		.timeout(MANIFEST_TIMEOUT)
+		.build()
+		.map_err(|e| SpatialError::Other(format!("Failed to build HTTP client: {}", e)))?;
+	let bytes = fetch_bytes_once(&client, url).await.map_err(|f| f.error)?;
+	let manifest = serde_json::from_slice(&bytes)
+		.map_err(|e| SpatialError::ConfigError(format!("Invalid model manifest from {}: {}", url, e)))?;
+	Ok((manifest, bytes))
+}
+
+fn dev_checkpoint_dirs() -> [PathBuf; 2] {
+	[
+		PathBuf::from("checkpoints"),
+		dirs::home_dir()
+			.unwrap_or_default()
+			.join(".spatial-maker")
+			.join("checkpoints"),
+	]
+}
+
+/// True if `name` names a model file (`.mlpackage` or `.onnx`) rather than a size.
+pub fn is_checkpoint_filename(name: &str) -> bool {
+	let lower = name.to_lowercase();
+	lower.ends_with(".mlpackage") || lower.ends_with(".onnx")
+}
+
+/// Finds a checkpoint by exact filename in `get_checkpoint_dir()` or the
+/// development paths.
+pub fn find_checkpoint(filename: &str) -> SpatialResult<PathBuf> {
 	let checkpoint_dir = get_checkpoint_dir()?;
+	std::iter::once(checkpoint_dir.clone())
+		.chain(dev_checkpoint_dirs())
+		.map(|dir| dir.join(filename))
+		.find(|path| path.exists())
+		.ok_or_else(|| {
+			SpatialError::ModelError(format!(
+				"Checkpoint '{}' not found in {:?} or ./checkpoints",
+				filename, checkpoint_dir
+			))
+		})
+}
+
+pub fn find_model(encoder_size: &str) -> SpatialResult<PathBuf> {
+	if is_checkpoint_filename(encoder_size) {
+		return find_checkpoint(encoder_size);
+	}
+
+	if let Some(meta) = ModelMetadata::from_cached_manifest(encoder_size) {
+		if let Ok(model_path) = find_checkpoint(&meta.filename) {
+			return Ok(model_path);
+		}
+	}
 
 	#[cfg(all(target_os = "macos", feature = "coreml"))]
 	{
 		let meta = ModelMetadata::coreml(encoder_size)?;
-		let model_path = checkpoint_dir.join(&meta.filename);
-		if model_path.exists() {
+		if let Ok(model_path) = find_checkpoint(&meta.filename) {
 			return Ok(model_path);
 		}
 	}
@@ -91,22 +371,13 @@ pub fn find_model(encoder_size: &str) -> SpatialResult<PathBuf> {
 	#[cfg(feature = "onnx")]
 	{
 		let meta = ModelMetadata::onnx(encoder_size)?;
-		let model_path = checkpoint_dir.join(&meta.filename);
-		if model_path.exists() {
+		if let Ok(model_path) = find_checkpoint(&meta.filename) {
 			return Ok(model_path);
 		}
 	}
 
 	// Also check development paths
-	let dev_paths = [
-		PathBuf::from("checkpoints"),
-		dirs::home_dir()
-			.unwrap_or_default()
-			.join(".spatial-maker")
-			.join("checkpoints"),
-	];
-
-	for dir in &dev_paths {
+	for dir in &dev_checkpoint_dirs() {
 		if dir.exists() {
 			if let Ok(entries) = std::fs::read_dir(dir) {
 				for entry in entries.flatten() {
@@ -138,20 +409,77 @@ pub fn find_model(encoder_size: &str) -> SpatialResult<PathBuf> {
 	)))
 }
 
+/// The preprocessing `encoder_size`'s model expects, per its metadata. `None`
+/// for checkpoint filenames and unknown sizes, which have no metadata.
+pub fn model_preprocess(encoder_size: &str) -> Option<ModelPreprocess> {
+	if is_checkpoint_filename(encoder_size) {
+		return None;
+	}
+	ModelMetadata::resolved(encoder_size).map(|meta| meta.preprocess)
+}
+
 pub fn model_exists(encoder_size: &str) -> bool {
 	find_model(encoder_size).is_ok()
 }
 
+#[derive(Clone, Debug)]
+pub struct DownloadProgress {
+	pub downloaded: u64,
+	pub total: u64,
+	pub bytes_per_sec: f64,
+	pub stage: String,
+	pub percent: f64,
+}
+
+impl DownloadProgress {
+	pub fn new(downloaded: u64, total: u64, bytes_per_sec: f64, stage: String) -> Self {
+		let percent = if total > 0 {
+			(downloaded as f64 / total as f64 * 100.0).min(100.0)
+		} else {
+			0.0
+		};
+		Self {
+			downloaded,
+			total,
+			bytes_per_sec,
+			stage,
+			percent,
+		}
+	}
+
+	pub fn eta_secs(&self) -> Option<f64> {
+		if self.bytes_per_sec > 0.0 && self.total > self.downloaded {
+			Some((self.total - self.downloaded) as f64 / self.bytes_per_sec)
+		} else {
+			None
+		}
+	}
+}
+
 pub async fn ensure_model_exists<F>(
 	encoder_size: &str,
 	progress_fn: Option<F>,
 ) -> SpatialResult<PathBuf>
 where
 	F: FnMut(u64, u64),
+{
+	let progress_fn = progress_fn.map(|mut f| move |p: DownloadProgress| f(p.downloaded, p.total));
+	ensure_model_exists_with_progress(encoder_size, progress_fn).await
+}
+
+pub async fn ensure_model_exists_with_progress<F>(
+	encoder_size: &str,
+	progress_fn: Option<F>,
+) -> SpatialResult<PathBuf>
+where
+	F: FnMut(DownloadProgress),
 {
 	if let Ok(path) = find_model(encoder_size) {
 		return Ok(path);
 	}
+	if is_checkpoint_filename(encoder_size) {
+		return find_checkpoint(encoder_size);
+	}
 
 	let checkpoint_dir = get_checkpoint_dir()?;
 	tokio::fs::create_dir_all(&checkpoint_dir)
@@ -160,18 +488,13 @@ where
 			SpatialError::IoError(format!("Failed to create checkpoint directory: {}", e))
 		})?;
 
-	#[cfg(all(target_os = "macos", feature = "coreml"))]
-	{
-		let meta = ModelMetadata::coreml(encoder_size)?;
-		let model_path = checkpoint_dir.join(&meta.filename);
-		download_model(&meta, &model_path, progress_fn).await?;
-		return Ok(model_path);
-	}
-
-	#[cfg(all(feature = "onnx", not(all(target_os = "macos", feature = "coreml"))))]
+	#[cfg(any(all(target_os = "macos", feature = "coreml"), feature = "onnx"))]
 	{
-		let meta = ModelMetadata::onnx(encoder_size)?;
+		let meta = ModelMetadata::from_manifest(encoder_size).await?;
 		let model_path = checkpoint_dir.join(&meta.filename);
+		if model_path.exists() {
+			return Ok(model_path);
+		}
 		download_model(&meta, &model_path, progress_fn).await?;
 		return Ok(model_path);
 	}
@@ -185,76 +508,249 @@ where
 	}
 }
 
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+
+/// A failed fetch. Network failures are reported as `SpatialError::Other`, since
+/// `fetch_bytes` also serves self-update; model downloads turn them into
+/// `ModelError` with `into_model_error`.
+struct DownloadFailure {
+	error: SpatialError,
+	retryable: bool,
+}
+
+impl DownloadFailure {
+	#[cfg(any(all(target_os = "macos", feature = "coreml"), feature = "onnx"))]
+	fn fatal(error: SpatialError) -> Self {
+		Self { error, retryable: false }
+	}
+
+	#[cfg(any(all(target_os = "macos", feature = "coreml"), feature = "onnx"))]
+	fn into_model_error(self) -> SpatialError {
+		match self.error {
+			SpatialError::Other(msg) => SpatialError::ModelError(msg),
+			error => error,
+		}
+	}
+
+	fn from_reqwest(context: &str, e: reqwest::Error) -> Self {
+		let retryable = e.is_timeout()
+			|| e.is_connect()
+			|| e.is_body()
+			|| e.is_request()
+			|| e.status().is_some_and(|s| s.is_server_error());
+		Self {
+			error: SpatialError::Other(format!("{}: {}", context, e)),
+			retryable,
+		}
+	}
+
+	fn from_status(status: reqwest::StatusCode, url: &str) -> Self {
+		Self {
+			error: SpatialError::Other(format!("Download failed: HTTP {} from {}", status, url)),
+			retryable: status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS,
+		}
+	}
+}
+
+async fn wait_before_retry(attempt: u32, error: &SpatialError) {
+	let delay = std::time::Duration::from_secs(1 << (attempt - 1));
+	tracing::warn!("{} (attempt {}/{}), retrying in {:?}", error, attempt, DOWNLOAD_ATTEMPTS, delay);
+	tokio::time::sleep(delay).await;
+}
+
+pub async fn fetch_bytes(client: &reqwest::Client, url: &str) -> SpatialResult<Vec<u8>> {
+	let mut attempt = 1;
+	loop {
+		match fetch_bytes_once(client, url).await {
+			Ok(bytes) => return Ok(bytes),
+			Err(f) if f.retryable && attempt < DOWNLOAD_ATTEMPTS => {
+				wait_before_retry(attempt, &f.error).await;
+				attempt += 1;
+			}
+			Err(f) => return Err(f.error),
+		}
+	}
+}
+
+async fn fetch_bytes_once(client: &reqwest::Client, url: &str) -> Result<Vec<u8>, DownloadFailure> {
+	let response = client
+		.get(url)
+		.header("User-Agent", "spatial-maker")
+		.send()
+		.await
+		.map_err(|e| DownloadFailure::from_reqwest("Request failed", e))?;
+
+	if !response.status().is_success() {
+		return Err(DownloadFailure::from_status(response.status(), url));
+	}
+
+	let bytes = response
+		.bytes()
+		.await
+		.map_err(|e| DownloadFailure::from_reqwest("Download interrupted", e))?;
+	Ok(bytes.to_vec())
+}
+
+#[cfg(any(all(target_os = "macos", feature = "coreml"), feature = "onnx"))]
+async fn download_to_file<F>(
+	url: &str,
+	destination: &Path,
+	fallback_total: u64,
+	sha256: Option<&str>,
+	progress_fn: &mut Option<F>,
+) -> Result<(), DownloadFailure>
+where
+	F: FnMut(DownloadProgress),
+{
+	let response = reqwest::get(url)
+		.await
+		.map_err(|e| DownloadFailure::from_reqwest("Failed to download model", e))?;
+
+	if !response.status().is_success() {
+		return Err(DownloadFailure::from_status(response.status(), url));
+	}
+
+	use futures_util::StreamExt;
+	let content_length = response.content_length();
+	let chunks = response
+		.bytes_stream()
+		.map(|chunk| chunk.map_err(|e| DownloadFailure::from_reqwest("Download interrupted", e)));
+	save_stream(chunks, content_length, fallback_total, destination, sha256, progress_fn).await
+}
+
+/// Streams `chunks` into a `.part` file beside `destination` and moves it into
+/// place only once its length matches `content_length` and its digest
+/// `sha256`. On failure the `.part` file is removed and `destination` is left
+/// as it was.
+#[cfg(any(all(target_os = "macos", feature = "coreml"), feature = "onnx"))]
+async fn save_stream<S, B, F>(
+	chunks: S,
+	content_length: Option<u64>,
+	fallback_total: u64,
+	destination: &Path,
+	sha256: Option<&str>,
+	progress_fn: &mut Option<F>,
+) -> Result<(), DownloadFailure>
+where
+	S: futures_util::Stream<Item = Result<B, DownloadFailure>>,
+	B: AsRef<[u8]>,
+	F: FnMut(DownloadProgress),
+{
+	use futures_util::StreamExt;
+	use tokio::io::AsyncWriteExt;
+	let write_error =
+		|e: std::io::Error| DownloadFailure::fatal(SpatialError::IoError(format!("Failed to write to file: {}", e)));
+
+	let start = std::time::Instant::now();
+	let total_bytes = content_length.unwrap_or(fallback_total);
+	let parent = destination
+		.parent()
+		.ok_or_else(|| DownloadFailure::fatal(SpatialError::IoError("Invalid destination path".to_string())))?;
+	let part = crate::temp::temp_file_in(parent, ".part").map_err(DownloadFailure::fatal)?;
+	let mut file = tokio::fs::File::from_std(part.as_file().try_clone().map_err(write_error)?);
+
+	let mut downloaded = 0u64;
+	let mut last_pct: u64 = 0;
+	let mut chunks = std::pin::pin!(chunks);
+	while let Some(chunk) = chunks.next().await {
+		let chunk = chunk?;
+		let chunk = chunk.as_ref();
+		file.write_all(chunk).await.map_err(write_error)?;
+		downloaded += chunk.len() as u64;
+		if let Some(ref mut f) = progress_fn {
+			let elapsed = start.elapsed().as_secs_f64();
+			let rate = if elapsed > 0.0 { downloaded as f64 / elapsed } else { 0.0 };
+			f(DownloadProgress::new(downloaded, total_bytes, rate, "downloading".to_string()));
+		}
+		if let Some(pct) = (downloaded * 100).checked_div(total_bytes) {
+			if pct != last_pct {
+				last_pct = pct;
+				eprint!("\rDownloading... {}%", pct);
+			}
+		}
+	}
+	eprintln!();
+
+	// A connection closed early can end the stream without an error.
+	if let Some(expected) = content_length.filter(|&n| n != downloaded) {
+		return Err(DownloadFailure {
+			error: SpatialError::Other(format!(
+				"Download truncated: received {} of {} bytes",
+				downloaded, expected
+			)),
+			retryable: true,
+		});
+	}
+	file.sync_all().await.map_err(write_error)?;
+	drop(file);
+
+	if let Some(expected) = sha256 {
+		verify_sha256(part.path(), expected).map_err(DownloadFailure::fatal)?;
+	}
+	part.persist(destination).map_err(|e| {
+		DownloadFailure::fatal(SpatialError::IoError(format!(
+			"Failed to move downloaded file into place: {}",
+			e.error
+		)))
+	})?;
+	Ok(())
+}
+
+#[cfg(any(all(target_os = "macos", feature = "coreml"), feature = "onnx"))]
 async fn download_model<F>(
 	metadata: &ModelMetadata,
 	destination: &Path,
 	mut progress_fn: Option<F>,
 ) -> SpatialResult<()>
 where
-	F: FnMut(u64, u64),
+	F: FnMut(DownloadProgress),
 {
 	eprintln!("Downloading model: {} ({} MB)...", metadata.name, metadata.size_mb);
 	tracing::info!("Downloading model: {} from {}", metadata.name, metadata.url);
 
-	let response = reqwest::get(&metadata.url)
-		.await
-		.map_err(|e| SpatialError::Other(format!("Failed to download model: {}", e)))?;
-
-	if !response.status().is_success() {
-		return Err(SpatialError::Other(format!(
-			"Failed to download model: HTTP {} from {}",
-			response.status(),
-			metadata.url
-		)));
-	}
-
-	let total_bytes = response
-		.content_length()
-		.unwrap_or(metadata.size_mb as u64 * 1_000_000);
-
 	let is_tar_gz = metadata.url.ends_with(".tar.gz");
-
-	if is_tar_gz {
-		let temp_path = destination.with_extension("tar.gz");
-		let mut file = tokio::fs::File::create(&temp_path)
-			.await
-			.map_err(|e| SpatialError::IoError(format!("Failed to create file: {}", e)))?;
-
-		let mut downloaded = 0u64;
-		let mut stream = response.bytes_stream();
-		use futures_util::StreamExt;
-
-		let mut last_pct: u64 = 0;
-		while let Some(chunk) = stream.next().await {
-			let chunk = chunk.map_err(|e| SpatialError::Other(format!("Download interrupted: {}", e)))?;
-			file.write_all(&chunk)
-				.await
-				.map_err(|e| SpatialError::IoError(format!("Failed to write to file: {}", e)))?;
-			downloaded += chunk.len() as u64;
-			if let Some(ref mut f) = progress_fn {
-				f(downloaded, total_bytes);
-			}
-			if total_bytes > 0 {
-				let pct = downloaded * 100 / total_bytes;
-				if pct != last_pct {
-					last_pct = pct;
-					eprint!("\rDownloading... {}%", pct);
-				}
+	let parent = destination
+		.parent()
+		.ok_or_else(|| SpatialError::IoError("Invalid destination path".to_string()))?;
+	let archive_dir = if is_tar_gz {
+		Some(crate::temp::temp_dir_in(&crate::temp::run_dir()?, ".download")?)
+	} else {
+		None
+	};
+	let download_path = match archive_dir {
+		Some(ref dir) => dir.path().join("model.tar.gz"),
+		None => destination.to_path_buf(),
+	};
+	let fallback_total = metadata.size_mb as u64 * 1_000_000;
+
+	// Nothing appears at `destination` until it is complete, so an interrupted
+	// download or extraction never leaves a model that `find_model` would pick up.
+	let mut attempt = 1;
+	loop {
+		let sha256 = metadata.sha256.as_deref();
+		match download_to_file(&metadata.url, &download_path, fallback_total, sha256, &mut progress_fn).await {
+			Ok(()) => break,
+			Err(f) if f.retryable && attempt < DOWNLOAD_ATTEMPTS => {
+				wait_before_retry(attempt, &f.error).await;
+				attempt += 1;
 			}
+			Err(f) => return Err(f.into_model_error()),
 		}
-		eprintln!();
-		drop(file);
-
-		let parent = destination
-			.parent()
-			.ok_or_else(|| SpatialError::IoError("Invalid destination path".to_string()))?;
+	}
 
+	if let Some(archive_dir) = archive_dir {
 		eprintln!("Extracting...");
+		if let Some(ref mut f) = progress_fn {
+			let size = std::fs::metadata(&download_path).map_or(fallback_total, |m| m.len());
+			f(DownloadProgress::new(size, size, 0.0, "extracting".to_string()));
+		}
+		crate::tools::require_tool("tar")?;
+		let staging = crate::temp::temp_dir_in(parent, ".extracting")?;
 		let output = std::process::Command::new("tar")
 			.args(&["xzf"])
-			.arg(&temp_path)
+			.arg(&download_path)
 			.arg("-C")
-			.arg(parent)
+			.arg(staging.path())
 			.output()
 			.map_err(|e| SpatialError::IoError(format!("Failed to extract tar.gz: {}", e)))?;
 
@@ -263,35 +759,93 @@ where
 			return Err(SpatialError::IoError(format!("tar extraction failed: {}", stderr)));
 		}
 
-		let _ = tokio::fs::remove_file(&temp_path).await;
+		drop(archive_dir);
 
-		if !destination.exists() {
+		let extracted = staging.path().join(destination.file_name().unwrap_or_default());
+		if !extracted.exists() {
 			return Err(SpatialError::ModelError(format!(
-				"Extraction succeeded but model not found at {:?}",
-				destination
+				"Extraction succeeded but archive has no {:?}",
+				destination.file_name().unwrap_or_default()
 			)));
 		}
-	} else {
-		let mut file = tokio::fs::File::create(destination)
-			.await
-			.map_err(|e| SpatialError::IoError(format!("Failed to create file: {}", e)))?;
-
-		let mut downloaded = 0u64;
-		let mut stream = response.bytes_stream();
-		use futures_util::StreamExt;
-
-		while let Some(chunk) = stream.next().await {
-			let chunk = chunk.map_err(|e| SpatialError::Other(format!("Download interrupted: {}", e)))?;
-			file.write_all(&chunk)
-				.await
-				.map_err(|e| SpatialError::IoError(format!("Failed to write to file: {}", e)))?;
-			downloaded += chunk.len() as u64;
-			if let Some(ref mut f) = progress_fn {
-				f(downloaded, total_bytes);
+		// Another process may have finished the same download first.
+		if let Err(e) = std::fs::rename(&extracted, destination) {
+			if !destination.exists() {
+				return Err(SpatialError::IoError(format!(
+					"Failed to move extracted model into place: {}",
+					e
+				)));
 			}
 		}
 	}
 
+	if let Some(ref mut f) = progress_fn {
+		f(DownloadProgress::new(1, 1, 0.0, "complete".to_string()));
+	}
+
 	tracing::info!("Model downloaded: {:?}", destination);
 	Ok(())
 }
+
+#[cfg(any(all(target_os = "macos", feature = "coreml"), feature = "onnx"))]
+fn verify_sha256(path: &Path, expected: &str) -> SpatialResult<()> {
+	use sha2::{Digest, Sha256};
+	let mut file = std::fs::File::open(path)?;
+	let mut hasher = Sha256::new();
+	std::io::copy(&mut file, &mut hasher)?;
+	let actual = format!("{:x}", hasher.finalize());
+	if actual != expected.to_lowercase() {
+		return Err(SpatialError::ModelError(format!(
+			"Checksum mismatch for {:?}: expected {}, got {}",
+			path, expected, actual
+		)));
+	}
+	Ok(())
+}
+
+#[cfg(all(test, any(all(target_os = "macos", feature = "coreml"), feature = "onnx")))]
+mod tests {
+	use super::*;
+
+	fn chunks(parts: &[&'static [u8]]) -> impl futures_util::Stream<Item = Result<&'static [u8], DownloadFailure>> {
+		futures_util::stream::iter(parts.iter().map(|&part| Ok(part)).collect::<Vec<_>>())
+	}
+
+	fn entries(dir: &Path) -> Vec<std::ffi::OsString> {
+		std::fs::read_dir(dir).unwrap().map(|e| e.unwrap().file_name()).collect()
+	}
+
+	#[tokio::test]
+	async fn truncated_download_leaves_no_file() {
+		let dir = tempfile::tempdir().unwrap();
+		let destination = dir.path().join("model.onnx");
+		let result = save_stream(chunks(&[b"abc", b"def"]), Some(10), 0, &destination, None, &mut None::<fn(DownloadProgress)>).await;
+
+		let failure = result.unwrap_err();
+		assert!(failure.retryable);
+		assert!(!destination.exists());
+		assert!(entries(dir.path()).is_empty());
+	}
+
+	#[tokio::test]
+	async fn complete_download_is_moved_into_place() {
+		let dir = tempfile::tempdir().unwrap();
+		let destination = dir.path().join("model.onnx");
+		save_stream(chunks(&[b"abc", b"def"]), Some(6), 0, &destination, None, &mut None::<fn(DownloadProgress)>)
+			.await
+			.unwrap_or_else(|f| panic!("{}", f.error));
+
+		assert_eq!(std::fs::read(&destination).unwrap(), b"abcdef");
+		assert_eq!(entries(dir.path()), [std::ffi::OsString::from("model.onnx")]);
+	}
+
+	#[tokio::test]
+	async fn checksum_mismatch_leaves_no_file() {
+		let dir = tempfile::tempdir().unwrap();
+		let destination = dir.path().join("model.onnx");
+		let result = save_stream(chunks(&[b"abc"]), Some(3), 0, &destination, Some("00"), &mut None::<fn(DownloadProgress)>).await;
+
+		assert!(result.is_err_and(|f| !f.retryable));
+		assert!(entries(dir.path()).is_empty());
+	}
+}
@@ -1,6 +1,8 @@
+use crate::depth_filter::NormalizeStrategy;
 use crate::error::{SpatialError, SpatialResult};
 use image::DynamicImage;
 use ndarray::Array2;
+use rayon::prelude::*;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
@@ -9,28 +11,101 @@ pub enum DepthFormat {
     Avif,
     Png,
     Png16,
+    Colormap,
 }
 
 impl DepthFormat {
+    pub fn all() -> &'static [DepthFormat] {
+        &[DepthFormat::Avif, DepthFormat::Png, DepthFormat::Png16, DepthFormat::Colormap]
+    }
+
+    /// Token used after `depth:` in `parse_output_types`.
+    pub fn to_token(&self) -> &'static str {
+        match self {
+            DepthFormat::Avif => "avif",
+            DepthFormat::Png => "png",
+            DepthFormat::Png16 => "png16",
+            DepthFormat::Colormap => "colormap",
+        }
+    }
+
+    pub fn from_token(s: &str) -> Option<Self> {
+        Self::all().iter().copied().find(|f| f.to_token() == s)
+    }
+
     pub fn extension(&self) -> &'static str {
         match self {
             DepthFormat::Avif => "avif",
             DepthFormat::Png => "png",
             DepthFormat::Png16 => "png",
+            DepthFormat::Colormap => "png",
         }
     }
 
+    /// Filename suffix for `DepthNaming::Short`.
     pub fn suffix(&self) -> &'static str {
         match self {
             DepthFormat::Avif => "",
             DepthFormat::Png => "",
             DepthFormat::Png16 => "-16bit",
+            DepthFormat::Colormap => "-color",
+        }
+    }
+
+    /// Filename suffix for `DepthNaming::Distinct`, unique per format.
+    pub fn distinct_suffix(&self) -> &'static str {
+        match self {
+            DepthFormat::Avif => "-avif",
+            DepthFormat::Png => "-png8",
+            DepthFormat::Png16 => "-png16",
+            DepthFormat::Colormap => "-colormap",
+        }
+    }
+
+    /// `{stem}-depth{suffix}.{ext}` for this format under `naming`.
+    pub fn filename(&self, stem: &str, naming: DepthNaming) -> String {
+        let suffix = match naming {
+            DepthNaming::Distinct => self.distinct_suffix(),
+            DepthNaming::Short => self.suffix(),
+        };
+        format!("{}-depth{}.{}", stem, suffix, self.extension())
+    }
+}
+
+/// How depth map filenames are built from the output stem.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DepthNaming {
+    /// Every format gets its own name (`-depth-avif.avif`, `-depth-png8.png`,
+    /// `-depth-png16.png`), so any combination of formats can be written together.
+    #[default]
+    Distinct,
+    /// The shorter earlier names (`-depth.avif`, `-depth.png`, `-depth-16bit.png`).
+    /// Formats whose names coincide are rejected rather than overwritten.
+    Short,
+}
+
+impl DepthNaming {
+    pub fn all() -> &'static [DepthNaming] {
+        &[DepthNaming::Distinct, DepthNaming::Short]
+    }
+}
+
+impl std::str::FromStr for DepthNaming {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "distinct" => Ok(Self::Distinct),
+            "short" => Ok(Self::Short),
+            _ => Err(format!("Unknown depth naming: '{}'. Use: distinct, short", s)),
         }
     }
 }
 
 pub const DEFAULT_DEPTH_FORMAT: DepthFormat = DepthFormat::Avif;
 
+/// `parse_output_types` token that expands to `OutputType::all`.
+const ALL_TOKEN: &str = "all";
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum OutputType {
     Depth(Vec<DepthFormat>),
@@ -38,12 +113,87 @@ pub enum OutputType {
     TopAndBottom,
     Separate,
     Spatial,
+    /// Two-frame video (left eye, then right eye) used by some headset apps for
+    /// still spatial photos.
+    StereoClip,
+    /// Binary mask of where the stereo warp left holes (white), before fill.
+    DisparityMask,
+    /// The photo as an AVIF with the depth map as an auxiliary depth image; see
+    /// `save_with_aux_depth`.
+    AuxDepth,
+    Normals,
+    Views,
+    /// One contact sheet of the original, depth and stereo; see
+    /// `create_preview_sheet`.
+    Preview,
+}
+
+impl OutputType {
+    /// Top-level tokens accepted by `parse_output_types`.
+    pub fn tokens() -> &'static [&'static str] {
+        &[
+            "depth", "sbs", "tab", "sep", "spatial", "clip", "mask", "aux", "normals", "views",
+            "preview", ALL_TOKEN,
+        ]
+    }
+
+    /// What the `all` token expands to: a PNG depth map and the spatial output,
+    /// the usual deliverables without the debugging and auxiliary outputs.
+    pub fn all() -> Vec<OutputType> {
+        vec![
+            OutputType::Depth(vec![DepthFormat::Png]),
+            OutputType::Spatial,
+        ]
+    }
+
+    /// Token for this output type. `Depth` maps to `depth`; its formats are
+    /// spelled as `depth:<format>`.
+    pub fn to_token(&self) -> &'static str {
+        match self {
+            OutputType::Depth(_) => "depth",
+            OutputType::SideBySide => "sbs",
+            OutputType::TopAndBottom => "tab",
+            OutputType::Separate => "sep",
+            OutputType::Spatial => "spatial",
+            OutputType::StereoClip => "clip",
+            OutputType::DisparityMask => "mask",
+            OutputType::AuxDepth => "aux",
+            OutputType::Normals => "normals",
+            OutputType::Views => "views",
+            OutputType::Preview => "preview",
+        }
+    }
+
+    /// Parses a single top-level token. `depth` yields the default depth format.
+    pub fn from_token(s: &str) -> Option<Self> {
+        match s {
+            "depth" => Some(OutputType::Depth(vec![DEFAULT_DEPTH_FORMAT])),
+            "sbs" => Some(OutputType::SideBySide),
+            "tab" => Some(OutputType::TopAndBottom),
+            "sep" => Some(OutputType::Separate),
+            "spatial" => Some(OutputType::Spatial),
+            "clip" => Some(OutputType::StereoClip),
+            "mask" => Some(OutputType::DisparityMask),
+            "aux" => Some(OutputType::AuxDepth),
+            "normals" => Some(OutputType::Normals),
+            "views" => Some(OutputType::Views),
+            "preview" => Some(OutputType::Preview),
+            _ => None,
+        }
+    }
 }
 
 pub fn needs_depth(types: &[OutputType]) -> bool {
     types.iter().any(|t| matches!(t, OutputType::Depth(_)))
 }
 
+/// True when only depth maps were requested. Depth-only runs need nothing beyond
+/// inference and the depth format's encoder: no stereo warp, no layout, and no
+/// `spatial` CLI.
+pub fn is_depth_only(types: &[OutputType]) -> bool {
+    !types.is_empty() && types.iter().all(|t| matches!(t, OutputType::Depth(_)))
+}
+
 pub fn needs_stereo(types: &[OutputType]) -> bool {
     types.iter().any(|t| {
         matches!(
@@ -52,10 +202,37 @@ pub fn needs_stereo(types: &[OutputType]) -> bool {
                 | OutputType::TopAndBottom
                 | OutputType::Separate
                 | OutputType::Spatial
+                | OutputType::StereoClip
+                | OutputType::DisparityMask
+                | OutputType::Preview
         )
     })
 }
 
+pub fn needs_stereo_clip(types: &[OutputType]) -> bool {
+    types.iter().any(|t| matches!(t, OutputType::StereoClip))
+}
+
+pub fn needs_disparity_mask(types: &[OutputType]) -> bool {
+    types.iter().any(|t| matches!(t, OutputType::DisparityMask))
+}
+
+pub fn needs_aux_depth(types: &[OutputType]) -> bool {
+    types.iter().any(|t| matches!(t, OutputType::AuxDepth))
+}
+
+pub fn needs_normals(types: &[OutputType]) -> bool {
+    types.iter().any(|t| matches!(t, OutputType::Normals))
+}
+
+pub fn needs_views(types: &[OutputType]) -> bool {
+    types.iter().any(|t| matches!(t, OutputType::Views))
+}
+
+pub fn needs_preview(types: &[OutputType]) -> bool {
+    types.iter().any(|t| matches!(t, OutputType::Preview))
+}
+
 pub fn depth_formats(types: &[OutputType]) -> Vec<DepthFormat> {
     types
         .iter()
@@ -86,46 +263,33 @@ pub fn stereo_types(types: &[OutputType]) -> Vec<&OutputType> {
 }
 
 fn is_depth_format(s: &str) -> bool {
-    matches!(s, "avif" | "png" | "png16")
-}
-
-fn is_stereo_type(s: &str) -> bool {
-    matches!(s, "sbs" | "tab" | "sep" | "spatial")
+    DepthFormat::from_token(s).is_some()
 }
 
 fn parse_depth_format(s: &str) -> Result<DepthFormat, String> {
-    match s {
-        "avif" => Ok(DepthFormat::Avif),
-        "png" => Ok(DepthFormat::Png),
-        "png16" => Ok(DepthFormat::Png16),
-        _ => Err(format!(
-            "Unknown depth format: '{}'. Use: avif, png, png16",
-            s
-        )),
-    }
-}
-
-fn parse_stereo_type(s: &str) -> Result<OutputType, String> {
-    match s {
-        "sbs" => Ok(OutputType::SideBySide),
-        "tab" => Ok(OutputType::TopAndBottom),
-        "sep" => Ok(OutputType::Separate),
-        "spatial" => Ok(OutputType::Spatial),
-        _ => Err(format!("Unknown output type: '{}'", s)),
-    }
+    DepthFormat::from_token(s).ok_or_else(|| {
+        let tokens: Vec<&str> = DepthFormat::all().iter().map(|f| f.to_token()).collect();
+        format!("Unknown depth format: '{}'. Use: {}", s, tokens.join(", "))
+    })
 }
 
+/// Parses a comma-separated list of output types. Depth formats are written as
+/// `depth:<format>`; more bare formats may directly follow `depth` or another
+/// depth format (`depth:avif,png16`), but anywhere else a bare format is
+/// rejected as ambiguous. `all` expands to `OutputType::all`. Repeats are
+/// merged, and only one stereo layout (sbs, tab, sep) may be given since a run
+/// writes one.
 pub fn parse_output_types(s: &str) -> Result<Vec<OutputType>, String> {
     let parts: Vec<&str> = s
         .split(',')
         .map(|p| p.trim())
         .filter(|p| !p.is_empty())
         .collect();
-    let mut types = Vec::new();
+    let mut types: Vec<OutputType> = Vec::new();
     let mut depth_fmts = Vec::new();
     let mut has_depth = false;
 
-    for part in &parts {
+    for (i, part) in parts.iter().enumerate() {
         if *part == "depth" {
             has_depth = true;
             continue;
@@ -137,28 +301,82 @@ pub fn parse_output_types(s: &str) -> Result<Vec<OutputType>, String> {
             continue;
         }
 
-        if has_depth && is_depth_format(part) {
-            depth_fmts.push(parse_depth_format(part)?);
-            continue;
+        if is_depth_format(part) {
+            let previous = i.checked_sub(1).map(|p| parts[p]);
+            let continues_depth = previous
+                .is_some_and(|p| p == "depth" || p.starts_with("depth:") || is_depth_format(p));
+            if continues_depth {
+                depth_fmts.push(parse_depth_format(part)?);
+                continue;
+            }
+            return Err(match previous {
+                Some(previous) if has_depth => format!(
+                    "'{}' after '{}' is ambiguous: write depth formats as depth:{} or list \
+                     them right after depth (depth,{})",
+                    part, previous, part, part
+                ),
+                _ => format!(
+                    "'{}' is a depth map format, not an output type: use depth:{} to write \
+                     the depth map as {}",
+                    part,
+                    part,
+                    part.to_uppercase()
+                ),
+            });
         }
 
-        if is_stereo_type(part) {
-            types.push(parse_stereo_type(part)?);
-        } else if is_depth_format(part) {
+        if *part == ALL_TOKEN {
+            for output_type in OutputType::all() {
+                match output_type {
+                    OutputType::Depth(fmts) => {
+                        has_depth = true;
+                        depth_fmts.extend(fmts);
+                    }
+                    other => types.push(other),
+                }
+            }
+        } else if let Some(output_type) = OutputType::from_token(part) {
+            types.push(output_type);
+        } else {
             return Err(format!(
-                "'{}' must be specified as a depth sub-format: depth:{}",
-                part, part
+                "Unknown output type: '{}'. Use: {} (depth formats as depth:<format>)",
+                part,
+                OutputType::tokens().join(", ")
             ));
-        } else {
-            return Err(format!("Unknown output type: '{}'", part));
         }
     }
 
+    let mut unique: Vec<OutputType> = Vec::new();
+    for output_type in types {
+        if !unique.contains(&output_type) {
+            unique.push(output_type);
+        }
+    }
+    let mut types = unique;
+
+    let layouts: Vec<&str> = stereo_types(&types)
+        .into_iter()
+        .filter(|t| !matches!(t, OutputType::Spatial))
+        .map(|t| t.to_token())
+        .collect();
+    if layouts.len() > 1 {
+        return Err(format!(
+            "Only one stereo layout is written per run, got {}",
+            layouts.join(" and ")
+        ));
+    }
+
     if has_depth {
         if depth_fmts.is_empty() {
             depth_fmts.push(DEFAULT_DEPTH_FORMAT);
         }
-        types.insert(0, OutputType::Depth(depth_fmts));
+        let mut unique_fmts = Vec::new();
+        for fmt in depth_fmts {
+            if !unique_fmts.contains(&fmt) {
+                unique_fmts.push(fmt);
+            }
+        }
+        types.insert(0, OutputType::Depth(unique_fmts));
     }
 
     if types.is_empty() {
@@ -191,26 +409,13 @@ pub fn load_depth_map(path: &Path) -> SpatialResult<Array2<f32>> {
 
 // --- Depth map saving ---
 
-fn normalize_depth(depth: &Array2<f32>) -> (f32, f32) {
-    let mut min_val = f32::INFINITY;
-    let mut max_val = f32::NEG_INFINITY;
-    for &v in depth.iter() {
-        if v < min_val {
-            min_val = v;
-        }
-        if v > max_val {
-            max_val = v;
-        }
+fn quantize_depth_8bit(depth: &Array2<f32>, dither: bool) -> Vec<u8> {
+    if dither {
+        return quantize_with_dither(depth, 256);
     }
-    (min_val, max_val)
-}
-
-pub fn save_depth_png8(depth: &Array2<f32>, path: &Path) -> SpatialResult<()> {
-    let (h, w) = depth.dim();
-    let (min_val, max_val) = normalize_depth(depth);
+    let (min_val, max_val) = NormalizeStrategy::MinMax.range(depth);
     let range = max_val - min_val;
-
-    let pixels: Vec<u8> = depth
+    depth
         .iter()
         .map(|&v| {
             if range > 1e-6 {
@@ -219,7 +424,61 @@ pub fn save_depth_png8(depth: &Array2<f32>, path: &Path) -> SpatialResult<()> {
                 128u8
             }
         })
+        .collect()
+}
+
+/// Normalizes `depth` and quantizes it to `levels` evenly spaced 8-bit values
+/// with Floyd–Steinberg error diffusion, which breaks up banding on smooth
+/// gradients. `levels` is clamped to 2..=256.
+pub fn quantize_with_dither(depth: &Array2<f32>, levels: usize) -> Vec<u8> {
+    let (h, w) = depth.dim();
+    let (min_val, max_val) = NormalizeStrategy::MinMax.range(depth);
+    let range = max_val - min_val;
+    if range <= 1e-6 {
+        return vec![128u8; h * w];
+    }
+
+    let steps = (levels.clamp(2, 256) - 1) as f32;
+    let mut values: Vec<f32> = depth
+        .iter()
+        .map(|&v| (v - min_val) / range * steps)
         .collect();
+    let mut pixels = vec![0u8; h * w];
+
+    for y in 0..h {
+        for x in 0..w {
+            let i = y * w + x;
+            let level = values[i].round().clamp(0.0, steps);
+            pixels[i] = (level * 255.0 / steps).round() as u8;
+
+            let err = values[i] - level;
+            if x + 1 < w {
+                values[i + 1] += err * 7.0 / 16.0;
+            }
+            if y + 1 < h {
+                if x > 0 {
+                    values[i + w - 1] += err * 3.0 / 16.0;
+                }
+                values[i + w] += err * 5.0 / 16.0;
+                if x + 1 < w {
+                    values[i + w + 1] += err * 1.0 / 16.0;
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+pub fn save_depth_png8(depth: &Array2<f32>, path: &Path) -> SpatialResult<()> {
+    save_depth_png8_with_dither(depth, path, false)
+}
+
+/// Like `save_depth_png8`, but dithers the quantization when `dither` is set.
+pub fn save_depth_png8_with_dither(depth: &Array2<f32>, path: &Path, dither: bool) -> SpatialResult<()> {
+    let (h, w) = depth.dim();
+
+    let pixels = quantize_depth_8bit(depth, dither);
 
     let img = image::GrayImage::from_raw(w as u32, h as u32, pixels)
         .ok_or_else(|| SpatialError::ImageError("Failed to create grayscale image".to_string()))?;
@@ -232,7 +491,7 @@ pub fn save_depth_png8(depth: &Array2<f32>, path: &Path) -> SpatialResult<()> {
 
 pub fn save_depth_png16(depth: &Array2<f32>, path: &Path) -> SpatialResult<()> {
     let (h, w) = depth.dim();
-    let (min_val, max_val) = normalize_depth(depth);
+    let (min_val, max_val) = NormalizeStrategy::MinMax.range(depth);
     let range = max_val - min_val;
 
     let pixels: Vec<u16> = depth
@@ -267,28 +526,49 @@ pub fn save_depth_png16(depth: &Array2<f32>, path: &Path) -> SpatialResult<()> {
     Ok(())
 }
 
+/// Encodes with `ravif` when the `avif-encode` feature is on, otherwise with
+/// ffmpeg's `libsvtav1`.
 pub fn save_depth_avif(depth: &Array2<f32>, path: &Path) -> SpatialResult<()> {
-    let (h, w) = depth.dim();
-    let (min_val, max_val) = normalize_depth(depth);
-    let range = max_val - min_val;
+    save_depth_avif_with_dither(depth, path, false)
+}
 
-    let pixels: Vec<u8> = depth
-        .iter()
-        .map(|&v| {
-            if range > 1e-6 {
-                ((v - min_val) / range * 255.0).round() as u8
-            } else {
-                128u8
-            }
-        })
-        .collect();
+/// Like `save_depth_avif`, but dithers the quantization when `dither` is set.
+pub fn save_depth_avif_with_dither(depth: &Array2<f32>, path: &Path, dither: bool) -> SpatialResult<()> {
+    let pixels = quantize_depth_8bit(depth, dither);
+
+    #[cfg(feature = "avif-encode")]
+    {
+        save_depth_avif_native(&pixels, depth.dim(), path)
+    }
+    #[cfg(not(feature = "avif-encode"))]
+    {
+        save_depth_avif_ffmpeg(&pixels, depth.dim(), path)
+    }
+}
+
+#[cfg(feature = "avif-encode")]
+fn save_depth_avif_native(pixels: &[u8], (h, w): (usize, usize), path: &Path) -> SpatialResult<()> {
+    let rgb: Vec<ravif::RGB8> = pixels.iter().map(|&v| ravif::RGB8::new(v, v, v)).collect();
+    let encoded = ravif::Encoder::new()
+        .with_quality(80.0)
+        .with_speed(6)
+        .encode_rgb(ravif::Img::new(rgb.as_slice(), w, h))
+        .map_err(|e| SpatialError::ImageError(format!("AVIF encoding failed: {}", e)))?;
+
+    std::fs::write(path, encoded.avif_file)
+        .map_err(|e| SpatialError::IoError(format!("Failed to write {}: {}", path.display(), e)))
+}
 
+#[cfg(not(feature = "avif-encode"))]
+fn save_depth_avif_ffmpeg(pixels: &[u8], (h, w): (usize, usize), path: &Path) -> SpatialResult<()> {
     let rgb_pixels: Vec<u8> = pixels.iter().flat_map(|&v| [v, v, v]).collect();
 
     let path_str = path
         .to_str()
         .ok_or_else(|| SpatialError::ImageError("Invalid output path".to_string()))?;
 
+    crate::tools::require_tool("ffmpeg")?;
+
     let mut child = Command::new("ffmpeg")
         .args([
             "-f",
@@ -339,19 +619,354 @@ pub fn save_depth_avif(depth: &Array2<f32>, path: &Path) -> SpatialResult<()> {
 }
 
 pub fn save_depth_map(depth: &Array2<f32>, path: &Path, format: DepthFormat) -> SpatialResult<()> {
+    save_depth_map_with_dither(depth, path, format, false)
+}
+
+/// Like `save_depth_map`, but dithers the 8-bit formats (PNG, AVIF) when `dither`
+/// is set. 16-bit PNG is never dithered.
+pub fn save_depth_map_with_dither(
+    depth: &Array2<f32>,
+    path: &Path,
+    format: DepthFormat,
+    dither: bool,
+) -> SpatialResult<()> {
+    if let Some(parent) = path.parent() {
+        create_output_dir(parent)?;
+    }
+    write_depth_map(depth, path, format, dither)
+}
+
+/// Saves `depth` to every `(path, format)` in `outputs` concurrently. The
+/// formats are independent, so a failure doesn't stop the others; each path
+/// is returned with its own result, in input order.
+pub fn save_depth_maps(
+    depth: &Array2<f32>,
+    outputs: &[(PathBuf, DepthFormat)],
+    dither: bool,
+) -> Vec<(PathBuf, SpatialResult<()>)> {
+    // Create shared parents up front rather than from each worker.
+    let mut parents: Vec<&Path> = outputs.iter().filter_map(|(p, _)| p.parent()).collect();
+    parents.sort();
+    parents.dedup();
+    let dir_errors: Vec<(&Path, String)> = parents
+        .into_iter()
+        .filter_map(|dir| {
+            std::fs::create_dir_all(dir)
+                .err()
+                .map(|e| (dir, e.to_string()))
+        })
+        .collect();
+
+    outputs
+        .par_iter()
+        .map(|(path, format)| {
+            let dir_error = dir_errors
+                .iter()
+                .find(|(dir, _)| path.parent() == Some(*dir));
+            let result = match dir_error {
+                Some((_, e)) => Err(SpatialError::ImageError(format!(
+                    "Failed to create output directory: {}",
+                    e
+                ))),
+                None => write_depth_map(depth, path, *format, dither),
+            };
+            (path.clone(), result)
+        })
+        .collect()
+}
+
+fn create_output_dir(dir: &Path) -> SpatialResult<()> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| SpatialError::ImageError(format!("Failed to create output directory: {}", e)))
+}
+
+fn write_depth_map(
+    depth: &Array2<f32>,
+    path: &Path,
+    format: DepthFormat,
+    dither: bool,
+) -> SpatialResult<()> {
+    match format {
+        DepthFormat::Avif => save_depth_avif_with_dither(depth, path, dither),
+        DepthFormat::Png => save_depth_png8_with_dither(depth, path, dither),
+        DepthFormat::Png16 => save_depth_png16(depth, path),
+        DepthFormat::Colormap => colorize_depth(depth)
+            .save(path)
+            .map_err(|e| SpatialError::ImageError(format!("Failed to save colormap depth: {}", e))),
+    }
+}
+
+pub fn save_normal_map(depth: &Array2<f32>, path: &Path, strength: f32) -> SpatialResult<()> {
     if let Some(parent) = path.parent() {
         std::fs::create_dir_all(parent).map_err(|e| {
             SpatialError::ImageError(format!("Failed to create output directory: {}", e))
         })?;
     }
 
-    match format {
-        DepthFormat::Avif => save_depth_avif(depth, path)?,
-        DepthFormat::Png => save_depth_png8(depth, path)?,
-        DepthFormat::Png16 => save_depth_png16(depth, path)?,
+    crate::depth_filter::depth_to_normals(depth, strength)
+        .save(path)
+        .map_err(|e| SpatialError::ImageError(format!("Failed to save normal map: {}", e)))
+}
+
+// --- Preview sheet ---
+
+/// A panel of the `OutputType::Preview` contact sheet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreviewPanel {
+    Original,
+    /// The depth map through a turbo colormap (near is red, far is blue).
+    Depth,
+    /// The side-by-side pair.
+    Stereo,
+    /// Red-cyan anaglyph of the pair.
+    Anaglyph,
+}
+
+impl PreviewPanel {
+    pub fn all() -> &'static [PreviewPanel] {
+        &[
+            PreviewPanel::Original,
+            PreviewPanel::Depth,
+            PreviewPanel::Stereo,
+            PreviewPanel::Anaglyph,
+        ]
     }
 
-    Ok(())
+    pub fn to_token(&self) -> &'static str {
+        match self {
+            PreviewPanel::Original => "original",
+            PreviewPanel::Depth => "depth",
+            PreviewPanel::Stereo => "stereo",
+            PreviewPanel::Anaglyph => "anaglyph",
+        }
+    }
+
+    pub fn from_token(s: &str) -> Option<Self> {
+        Self::all().iter().copied().find(|p| p.to_token() == s)
+    }
+}
+
+/// Parses a comma-separated panel list such as `original,depth,stereo`.
+pub fn parse_preview_panels(s: &str) -> Result<Vec<PreviewPanel>, String> {
+    let mut panels = Vec::new();
+    for part in s.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        let panel = PreviewPanel::from_token(part).ok_or_else(|| {
+            let tokens: Vec<&str> = PreviewPanel::all().iter().map(|p| p.to_token()).collect();
+            format!(
+                "Unknown preview panel: '{}'. Use: {}",
+                part,
+                tokens.join(", ")
+            )
+        })?;
+        if !panels.contains(&panel) {
+            panels.push(panel);
+        }
+    }
+    if panels.is_empty() {
+        return Err("No preview panels specified".to_string());
+    }
+    Ok(panels)
+}
+
+const PREVIEW_CELL_WIDTH: u32 = 480;
+const PREVIEW_LABEL_HEIGHT: u32 = 22;
+const PREVIEW_GAP: u32 = 4;
+const PREVIEW_FONT_SCALE: u32 = 2;
+const PREVIEW_BACKGROUND: image::Rgb<u8> = image::Rgb([32, 32, 32]);
+const PREVIEW_TEXT: image::Rgb<u8> = image::Rgb([235, 235, 235]);
+
+/// 5x7 glyphs for `A`-`Z`, one byte per row with the leftmost pixel in bit 4.
+const FONT_5X7: [[u8; 7]; 26] = [
+    [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+    [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+    [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+    [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+    [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+    [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+    [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+    [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+    [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+    [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+    [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+    [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+    [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+    [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+    [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+    [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04],
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+];
+
+/// Draws `text` in uppercase at (`x`, `y`); characters outside `A`-`Z` are
+/// left blank.
+fn draw_label(sheet: &mut image::RgbImage, text: &str, x: u32, y: u32) {
+    let advance = 6 * PREVIEW_FONT_SCALE;
+    for (i, c) in text.to_ascii_uppercase().bytes().enumerate() {
+        let Some(glyph) = c.checked_sub(b'A').and_then(|g| FONT_5X7.get(g as usize)) else {
+            continue;
+        };
+        let gx = x + i as u32 * advance;
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..5 {
+                if bits & (0x10 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..PREVIEW_FONT_SCALE {
+                    for dx in 0..PREVIEW_FONT_SCALE {
+                        let px = gx + col * PREVIEW_FONT_SCALE + dx;
+                        let py = y + row as u32 * PREVIEW_FONT_SCALE + dy;
+                        if px < sheet.width() && py < sheet.height() {
+                            sheet.put_pixel(px, py, PREVIEW_TEXT);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Polynomial fit of Google's Turbo colormap for `t` in 0-1.
+fn turbo(t: f32) -> image::Rgb<u8> {
+    let t = t.clamp(0.0, 1.0);
+    let poly = |c: [f32; 6]| {
+        let v = c[0] + t * (c[1] + t * (c[2] + t * (c[3] + t * (c[4] + t * c[5]))));
+        (v.clamp(0.0, 1.0) * 255.0).round() as u8
+    };
+    image::Rgb([
+        poly([
+            0.135_721_4,
+            4.615_392_6,
+            -42.660_32,
+            132.131_08,
+            -152.942_4,
+            59.286_38,
+        ]),
+        poly([
+            0.091_402_61,
+            2.194_188_4,
+            4.842_966_6,
+            -14.185_033,
+            4.277_298_6,
+            2.829_566,
+        ]),
+        poly([
+            0.106_673_3,
+            12.641_946,
+            -60.582_05,
+            110.362_77,
+            -89.903_11,
+            27.348_25,
+        ]),
+    ])
+}
+
+/// The depth map through `turbo`, stretched to its own range.
+pub(crate) fn colorize_depth(depth: &Array2<f32>) -> DynamicImage {
+    let (h, w) = depth.dim();
+    let (min_val, max_val) = NormalizeStrategy::MinMax.range(depth);
+    let range = (max_val - min_val).max(f32::EPSILON);
+    let img = image::RgbImage::from_fn(w as u32, h as u32, |x, y| {
+        turbo((depth[[y as usize, x as usize]] - min_val) / range)
+    });
+    DynamicImage::ImageRgb8(img)
+}
+
+/// Red channel from the left eye, green and blue from the right.
+fn anaglyph(left: &DynamicImage, right: &DynamicImage) -> DynamicImage {
+    let left = left.to_rgb8();
+    let right = right.to_rgb8();
+    let img = image::RgbImage::from_fn(left.width(), left.height(), |x, y| {
+        let r = right
+            .get_pixel_checked(x, y)
+            .copied()
+            .unwrap_or(image::Rgb([0, 0, 0]));
+        image::Rgb([left.get_pixel(x, y)[0], r[1], r[2]])
+    });
+    DynamicImage::ImageRgb8(img)
+}
+
+/// Composes the requested `panels` into one labeled contact sheet: each panel is
+/// fitted into a cell sized to the original's aspect ratio, in one row, or a
+/// 2x2 grid for four panels. The depth panel is left out when `depth` is `None`
+/// (stereo input).
+pub fn create_preview_sheet(
+    original: &DynamicImage,
+    depth: Option<&Array2<f32>>,
+    left: &DynamicImage,
+    right: &DynamicImage,
+    panels: &[PreviewPanel],
+) -> image::RgbImage {
+    let images: Vec<(&str, DynamicImage)> = panels
+        .iter()
+        .filter_map(|panel| {
+            let image = match panel {
+                PreviewPanel::Original => original.clone(),
+                PreviewPanel::Depth => colorize_depth(depth?),
+                PreviewPanel::Stereo => {
+                    let mut sbs = DynamicImage::new_rgb8(left.width() * 2, left.height());
+                    image::imageops::overlay(&mut sbs, left, 0, 0);
+                    image::imageops::overlay(&mut sbs, right, left.width() as i64, 0);
+                    sbs
+                }
+                PreviewPanel::Anaglyph => anaglyph(left, right),
+            };
+            Some((panel.to_token(), image))
+        })
+        .collect();
+
+    let cell_w = PREVIEW_CELL_WIDTH;
+    let cell_h = ((cell_w as f32 * original.height() as f32 / original.width().max(1) as f32)
+        .round() as u32)
+        .max(1);
+    let columns = if images.len() == 4 {
+        2
+    } else {
+        images.len().max(1)
+    } as u32;
+    let rows = (images.len() as u32).div_ceil(columns).max(1);
+    let pitch_x = cell_w + PREVIEW_GAP;
+    let pitch_y = PREVIEW_LABEL_HEIGHT + cell_h + PREVIEW_GAP;
+
+    let mut sheet = image::RgbImage::from_pixel(
+        columns * pitch_x + PREVIEW_GAP,
+        rows * pitch_y + PREVIEW_GAP,
+        PREVIEW_BACKGROUND,
+    );
+    for (i, (label, image)) in images.iter().enumerate() {
+        let x = PREVIEW_GAP + (i as u32 % columns) * pitch_x;
+        let y = PREVIEW_GAP + (i as u32 / columns) * pitch_y;
+        let text_y = y + (PREVIEW_LABEL_HEIGHT - 7 * PREVIEW_FONT_SCALE) / 2;
+        draw_label(&mut sheet, label, x, text_y);
+
+        let fitted = image
+            .resize(cell_w, cell_h, image::imageops::FilterType::Triangle)
+            .to_rgb8();
+        let offset_x = x + (cell_w - fitted.width()) / 2;
+        let offset_y = y + PREVIEW_LABEL_HEIGHT + (cell_h - fitted.height()) / 2;
+        image::imageops::overlay(&mut sheet, &fitted, offset_x as i64, offset_y as i64);
+    }
+    sheet
+}
+
+pub fn save_preview_sheet(
+    sheet: &image::RgbImage,
+    path: &Path,
+    encoding: ImageEncoding,
+) -> SpatialResult<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| {
+            SpatialError::ImageError(format!("Failed to create output directory: {}", e))
+        })?;
+    }
+    save_image(&DynamicImage::ImageRgb8(sheet.clone()), path, encoding)
 }
 
 // --- Existing stereo output ---
@@ -377,6 +992,10 @@ impl OutputFormat {
 pub enum ImageEncoding {
     Jpeg { quality: u8 },
     Png,
+    Tiff,
+    Bmp,
+    /// Binary PPM (P6). Alpha is dropped.
+    Ppm,
 }
 
 impl ImageEncoding {
@@ -384,6 +1003,9 @@ impl ImageEncoding {
         match self {
             ImageEncoding::Jpeg { .. } => "jpg",
             ImageEncoding::Png => "png",
+            ImageEncoding::Tiff => "tiff",
+            ImageEncoding::Bmp => "bmp",
+            ImageEncoding::Ppm => "ppm",
         }
     }
 
@@ -397,17 +1019,36 @@ impl ImageEncoding {
 
         match ext.as_str() {
             "png" => ImageEncoding::Png,
+            "tif" | "tiff" => ImageEncoding::Tiff,
+            "bmp" => ImageEncoding::Bmp,
+            "ppm" => ImageEncoding::Ppm,
             _ => ImageEncoding::Jpeg { quality: 95 },
         }
     }
 }
 
+/// Default extension of spatial (MV-HEVC) photos.
+pub const SPATIAL_PHOTO_EXTENSION: &str = "heic";
+/// Default extension of spatial videos; `spatial make` writes QuickTime.
+pub const SPATIAL_VIDEO_EXTENSION: &str = "mov";
+
 #[derive(Clone, Debug)]
 pub struct MVHEVCConfig {
     pub spatial_cli_path: Option<PathBuf>,
     pub enabled: bool,
     pub quality: u8,
     pub keep_intermediate: bool,
+    /// Extension of the encoded file, without the dot; `None` uses
+    /// `SPATIAL_PHOTO_EXTENSION`.
+    pub extension: Option<String>,
+}
+
+impl MVHEVCConfig {
+    /// Where the spatial photo for `stereo_path` is written: beside it, with
+    /// `extension`.
+    pub fn output_path(&self, stereo_path: &Path) -> PathBuf {
+        stereo_path.with_extension(self.extension.as_deref().unwrap_or(SPATIAL_PHOTO_EXTENSION))
+    }
 }
 
 impl Default for MVHEVCConfig {
@@ -417,6 +1058,7 @@ impl Default for MVHEVCConfig {
             enabled: false,
             quality: 95,
             keep_intermediate: false,
+            extension: None,
         }
     }
 }
@@ -424,16 +1066,148 @@ impl Default for MVHEVCConfig {
 #[derive(Clone, Debug)]
 pub struct OutputOptions {
     pub layout: OutputFormat,
+    /// The `layout` image is an output in its own right, not just the source
+    /// MV-HEVC is encoded from, so it is kept after encoding.
+    pub layout_requested: bool,
     pub image_format: ImageEncoding,
     pub mvhevc: Option<MVHEVCConfig>,
+    /// Save depth maps at the model's processing resolution instead of upscaling
+    /// them to the input image size.
+    pub depth_native_resolution: bool,
+    /// Fail when the left and right images differ in size. When false, both are
+    /// cropped to their common size with a warning.
+    pub strict_dimensions: bool,
+    /// Write `<stem>.spatial.json` with depth statistics and the settings used.
+    pub write_sidecar: bool,
+    /// Dither 8-bit depth maps (PNG, AVIF) to reduce banding on smooth gradients.
+    pub dither: bool,
+    /// Return the final depth map in `ProcessPhotoOutput::depth_map`.
+    pub keep_depth: bool,
+    /// Eye order for SBS/TAB images. By default (parallel viewing) the left eye
+    /// is on the left (or top), which is what VR players and parallel
+    /// free-viewing expect. Set this for cross-eyed viewing or players that expect
+    /// the right eye first; depth looks inside-out if the order is wrong.
+    /// Separate `_L`/`_R` images and MV-HEVC output label each eye and are
+    /// unaffected.
+    pub swap_eyes: bool,
+    /// Depth map filename scheme; see `DepthNaming`.
+    pub depth_naming: DepthNaming,
+    /// Tag JPEG stereo images as 360° equirectangular panoramas (GPano XMP) so
+    /// VR photo viewers project them onto a sphere.
+    pub equirect: bool,
+    /// Panels of the `OutputType::Preview` sheet, in order.
+    pub preview_panels: Vec<PreviewPanel>,
+}
+
+pub fn validate_quality(quality: u8) -> SpatialResult<u8> {
+    if (1..=100).contains(&quality) {
+        Ok(quality)
+    } else {
+        Err(SpatialError::ConfigError(format!(
+            "Quality must be between 1 and 100, got {}",
+            quality
+        )))
+    }
+}
+
+impl OutputOptions {
+    pub fn validate(&self) -> SpatialResult<()> {
+        if let ImageEncoding::Jpeg { quality } = self.image_format {
+            validate_quality(quality)?;
+        }
+        if let Some(mvhevc) = &self.mvhevc {
+            validate_quality(mvhevc.quality)?;
+            if mvhevc
+                .extension
+                .as_deref()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case(self.image_format.extension()))
+            {
+                return Err(SpatialError::ConfigError(format!(
+                    "Spatial photo extension can't be {}, the stereo image it is encoded from uses it",
+                    self.image_format.extension()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn for_output_types(types: &[OutputType], image_format: ImageEncoding) -> Self {
+        let layout = types
+            .iter()
+            .find_map(|t| match t {
+                OutputType::SideBySide => Some(OutputFormat::SideBySide),
+                OutputType::TopAndBottom => Some(OutputFormat::TopAndBottom),
+                OutputType::Separate => Some(OutputFormat::Separate),
+                _ => None,
+            });
+        let quality = match image_format {
+            ImageEncoding::Jpeg { quality } => quality,
+            _ => 95,
+        };
+        let mvhevc = types
+            .iter()
+            .any(|t| matches!(t, OutputType::Spatial))
+            .then_some(MVHEVCConfig {
+                spatial_cli_path: None,
+                enabled: true,
+                quality,
+                keep_intermediate: false,
+                extension: None,
+            });
+
+        Self {
+            layout: layout.unwrap_or(OutputFormat::SideBySide),
+            layout_requested: layout.is_some(),
+            image_format,
+            mvhevc,
+            depth_native_resolution: false,
+            strict_dimensions: true,
+            write_sidecar: false,
+            dither: false,
+            keep_depth: false,
+            swap_eyes: false,
+            depth_naming: DepthNaming::default(),
+            equirect: false,
+            preview_panels: PreviewPanel::all().to_vec(),
+        }
+    }
+
+    /// Always keep the SBS/TAB image MV-HEVC is encoded from, even when no layout
+    /// output type was requested. Has no effect without MV-HEVC output.
+    pub fn with_keep_intermediate(mut self, keep: bool) -> Self {
+        if let Some(ref mut mvhevc) = self.mvhevc {
+            mvhevc.keep_intermediate |= keep;
+        }
+        self
+    }
+
+    /// Write spatial photos with `extension` instead of `SPATIAL_PHOTO_EXTENSION`.
+    /// Has no effect without MV-HEVC output. It must differ from the stereo
+    /// image's extension, which `validate` checks.
+    pub fn with_spatial_extension(mut self, extension: Option<String>) -> Self {
+        if let Some(ref mut mvhevc) = self.mvhevc {
+            mvhevc.extension = extension;
+        }
+        self
+    }
 }
 
 impl Default for OutputOptions {
     fn default() -> Self {
         Self {
             layout: OutputFormat::SideBySide,
+            layout_requested: true,
             image_format: ImageEncoding::Jpeg { quality: 95 },
             mvhevc: None,
+            depth_native_resolution: false,
+            strict_dimensions: true,
+            write_sidecar: false,
+            dither: false,
+            keep_depth: false,
+            swap_eyes: false,
+            depth_naming: DepthNaming::default(),
+            equirect: false,
+            preview_panels: PreviewPanel::all().to_vec(),
         }
     }
 }
@@ -457,6 +1231,16 @@ pub fn save_stereo_image(
     output_path: impl AsRef<Path>,
     options: OutputOptions,
 ) -> SpatialResult<()> {
+    save_stereo_image_with_paths(left, right, output_path, options).map(|_| ())
+}
+
+/// Like `save_stereo_image`, returning the paths of the files it kept.
+pub fn save_stereo_image_with_paths(
+    left: &DynamicImage,
+    right: &DynamicImage,
+    output_path: impl AsRef<Path>,
+    options: OutputOptions,
+) -> SpatialResult<Vec<PathBuf>> {
     let output_path = output_path.as_ref();
 
     if let Some(parent) = output_path.parent() {
@@ -465,30 +1249,363 @@ pub fn save_stereo_image(
         })?;
     }
 
-    match options.layout {
+    let cropped;
+    let (left, right) = if !options.strict_dimensions
+        && (left.width(), left.height()) != (right.width(), right.height())
+    {
+        cropped = crop_to_common(left, right);
+        (&cropped.0, &cropped.1)
+    } else {
+        (left, right)
+    };
+
+    let (first, second) = if options.swap_eyes {
+        (right, left)
+    } else {
+        (left, right)
+    };
+
+    let mut written = match options.layout {
         OutputFormat::SideBySide => {
-            save_side_by_side(left, right, output_path, options.image_format)?;
+            save_side_by_side(first, second, output_path, options.image_format)?;
+            vec![output_path.to_path_buf()]
         }
         OutputFormat::TopAndBottom => {
-            save_top_and_bottom(left, right, output_path, options.image_format)?;
+            save_top_and_bottom(first, second, output_path, options.image_format)?;
+            vec![output_path.to_path_buf()]
         }
         OutputFormat::Separate => {
-            save_separate(left, right, output_path, options.image_format)?;
+            save_separate(left, right, output_path, options.image_format)?
+        }
+    };
+
+    if options.equirect && matches!(options.image_format, ImageEncoding::Jpeg { .. }) {
+        let (width, height) = match options.layout {
+            OutputFormat::SideBySide => (first.width() + second.width(), first.height()),
+            OutputFormat::TopAndBottom => (first.width(), first.height() + second.height()),
+            OutputFormat::Separate => (first.width(), first.height()),
+        };
+        let xmp = panorama_xmp(width, height);
+        for path in &written {
+            insert_jpeg_xmp(path, &xmp)?;
         }
     }
 
     if let Some(mvhevc_config) = options.mvhevc {
         if mvhevc_config.enabled {
-            encode_mvhevc(output_path, &mvhevc_config)?;
-            if !mvhevc_config.keep_intermediate {
+            let hevc_path = mvhevc_config.output_path(output_path);
+            if hevc_path == output_path {
+                return Err(SpatialError::ConfigError(format!(
+                    "Spatial output would overwrite the stereo image {}; choose another extension",
+                    output_path.display()
+                )));
+            }
+            let encoded = if options.swap_eyes {
+                let source =
+                    crate::temp::temp_file(&format!(".{}", options.image_format.extension()))?;
+                save_side_by_side(left, right, source.path(), options.image_format)?;
+                encode_mvhevc_to(source.path(), &hevc_path, &mvhevc_config, "sbs")
+            } else {
+                let format = match options.layout {
+                    OutputFormat::Separate => {
+                        save_side_by_side(left, right, output_path, options.image_format)?;
+                        "sbs"
+                    }
+                    OutputFormat::TopAndBottom => "hou",
+                    OutputFormat::SideBySide => "sbs",
+                };
+                encode_mvhevc_to(output_path, &hevc_path, &mvhevc_config, format)
+            };
+
+            // Never delete the stereo image when the spatial file didn't come out.
+            let encoded = encoded.map_err(|e| {
+                SpatialError::ImageError(format!(
+                    "{} (stereo image kept at {})",
+                    e,
+                    output_path.display()
+                ))
+            })?;
+
+            // The SBS image `sep` encodes from is never a requested output.
+            let requested = options.layout_requested && options.layout != OutputFormat::Separate;
+            if !mvhevc_config.keep_intermediate && !requested {
                 let _ = std::fs::remove_file(output_path);
+                written.retain(|p| p != output_path);
+            } else if output_path.exists() && !written.iter().any(|p| p == output_path) {
+                written.push(output_path.to_path_buf());
             }
+            written.push(encoded);
+        }
+    }
+
+    Ok(written)
+}
+
+/// GPano XMP describing a `width`x`height` stored image as a full, uncropped
+/// 360° equirectangular panorama. For a stereo image that is both eyes together.
+fn panorama_xmp(width: u32, height: u32) -> String {
+    format!(
+        concat!(
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">"#,
+            r#"<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">"#,
+            r#"<rdf:Description rdf:about="" xmlns:GPano="http://ns.google.com/photos/1.0/panorama/""#,
+            r#" GPano:ProjectionType="equirectangular" GPano:UsePanoramaViewer="True""#,
+            r#" GPano:FullPanoWidthPixels="{w}" GPano:FullPanoHeightPixels="{h}""#,
+            r#" GPano:CroppedAreaImageWidthPixels="{w}" GPano:CroppedAreaImageHeightPixels="{h}""#,
+            r#" GPano:CroppedAreaLeftPixels="0" GPano:CroppedAreaTopPixels="0"/>"#,
+            r#"</rdf:RDF></x:xmpmeta>"#,
+        ),
+        w = width,
+        h = height
+    )
+}
+
+/// Inserts an XMP packet as an APP1 segment after the JPEG's SOI and JFIF
+/// segments.
+fn insert_jpeg_xmp(path: &Path, xmp: &str) -> SpatialResult<()> {
+    const XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+    let data = std::fs::read(path)
+        .map_err(|e| SpatialError::IoError(format!("Failed to read {}: {}", path.display(), e)))?;
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err(SpatialError::ImageError(format!(
+            "{} is not a JPEG",
+            path.display()
+        )));
+    }
+    let mut pos = 2;
+    if data.len() >= pos + 4 && data[pos..pos + 2] == [0xFF, 0xE0] {
+        pos += 2 + u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+    }
+    let segment_len = u16::try_from(2 + XMP_NAMESPACE.len() + xmp.len())
+        .map_err(|_| SpatialError::ImageError("XMP packet too large".to_string()))?;
+
+    let mut out = Vec::with_capacity(data.len() + segment_len as usize + 2);
+    out.extend_from_slice(&data[..pos.min(data.len())]);
+    out.extend_from_slice(&[0xFF, 0xE1]);
+    out.extend_from_slice(&segment_len.to_be_bytes());
+    out.extend_from_slice(XMP_NAMESPACE);
+    out.extend_from_slice(xmp.as_bytes());
+    out.extend_from_slice(&data[pos.min(data.len())..]);
+    std::fs::write(path, out)
+        .map_err(|e| SpatialError::IoError(format!("Failed to write {}: {}", path.display(), e)))
+}
+
+pub fn save_disparity_mask(mask: &image::GrayImage, path: impl AsRef<Path>) -> SpatialResult<()> {
+    let path = path.as_ref();
+    mask.save_with_format(path, image::ImageFormat::Png)
+        .map_err(|e| {
+            SpatialError::ImageError(format!("Failed to save disparity mask {:?}: {}", path, e))
+        })
+}
+
+/// `auxC` type of an AVIF alpha plane, and of an AVIF depth image. They are the
+/// same length, so relabelling one as the other leaves every box size intact.
+#[cfg(feature = "avif-encode")]
+const AUX_ALPHA_URN: &[u8] = b"urn:mpeg:mpegB:cicp:systems:auxiliary:alpha";
+#[cfg(feature = "avif-encode")]
+const AUX_DEPTH_URN: &[u8] = b"urn:mpeg:mpegB:cicp:systems:auxiliary:depth";
+
+/// Writes `image` as an AVIF whose auxiliary image is `depth` (8-bit, nearer is
+/// brighter), resampled to the image size. Viewers that understand HEIF depth
+/// items, libheif among them, see a single photo with depth attached.
+///
+/// Needs the `avif-encode` feature. HEIC isn't supported: neither libheif nor
+/// ffmpeg can write auxiliary depth into one.
+pub fn save_with_aux_depth(
+    image: &DynamicImage,
+    depth: &Array2<f32>,
+    path: impl AsRef<Path>,
+) -> SpatialResult<()> {
+    let path = path.as_ref();
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    if ext != "avif" {
+        return Err(SpatialError::ConfigError(format!(
+            "Auxiliary depth can only be written to .avif, not {:?}",
+            path
+        )));
+    }
+
+    #[cfg(feature = "avif-encode")]
+    {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let depth = crate::depth_filter::resize_depth(depth, width, height);
+        let mut alpha = quantize_depth_8bit(&depth, false);
+        // ravif drops an all-opaque alpha plane, which would drop the depth.
+        if alpha.iter().all(|&v| v == u8::MAX) {
+            alpha[0] = u8::MAX - 1;
         }
+        let rgba: Vec<ravif::RGBA8> = image
+            .to_rgb8()
+            .pixels()
+            .zip(alpha)
+            .map(|(p, a)| ravif::RGBA8::new(p[0], p[1], p[2], a))
+            .collect();
+        let encoded = ravif::Encoder::new()
+            .with_quality(90.0)
+            .with_alpha_quality(80.0)
+            .with_speed(6)
+            .with_alpha_color_mode(ravif::AlphaColorMode::UnassociatedDirty)
+            .encode_rgba(ravif::Img::new(rgba.as_slice(), width, height))
+            .map_err(|e| SpatialError::ImageError(format!("AVIF encoding failed: {}", e)))?;
+
+        let mut avif = encoded.avif_file;
+        let urn_at = avif
+            .windows(AUX_ALPHA_URN.len())
+            .position(|w| w == AUX_ALPHA_URN)
+            .ok_or_else(|| {
+                SpatialError::ImageError("AVIF encoder wrote no auxiliary image".to_string())
+            })?;
+        avif[urn_at..urn_at + AUX_DEPTH_URN.len()].copy_from_slice(AUX_DEPTH_URN);
+
+        std::fs::write(path, &avif).map_err(|e| {
+            SpatialError::IoError(format!("Failed to write {}: {}", path.display(), e))
+        })?;
+        validate_aux_depth(path)
+    }
+    #[cfg(not(feature = "avif-encode"))]
+    {
+        let _ = (image, depth);
+        Err(SpatialError::ConfigError(
+            "Writing auxiliary depth needs the 'avif-encode' feature".to_string(),
+        ))
     }
+}
 
+/// Checks that `path` parses as HEIF with a depth image attached to its primary
+/// item. Only libheif can tell, so without the `heic` feature this is a no-op.
+pub fn validate_aux_depth(path: &Path) -> SpatialResult<()> {
+    #[cfg(feature = "heic")]
+    {
+        let path_str = path
+            .to_str()
+            .ok_or_else(|| SpatialError::IoError("Invalid path encoding".to_string()))?;
+        let ctx = libheif_rs::HeifContext::read_from_file(path_str).map_err(|e| {
+            SpatialError::ImageError(format!("{} is not valid HEIF: {:?}", path.display(), e))
+        })?;
+        let handle = ctx.primary_image_handle().map_err(|e| {
+            SpatialError::ImageError(format!("{} has no primary image: {:?}", path.display(), e))
+        })?;
+        if !handle.has_depth_image() {
+            return Err(SpatialError::ImageError(format!(
+                "{} has no auxiliary depth image",
+                path.display()
+            )));
+        }
+    }
+    #[cfg(not(feature = "heic"))]
+    let _ = path;
     Ok(())
 }
 
+/// Encodes `left` and `right` as a two-frame H.264 MP4, left eye first.
+pub fn save_stereo_clip(
+    left: &DynamicImage,
+    right: &DynamicImage,
+    path: impl AsRef<Path>,
+) -> SpatialResult<()> {
+    let path = path.as_ref();
+    if (left.width(), left.height()) != (right.width(), right.height()) {
+        return Err(SpatialError::ImageError(format!(
+            "Left and right images must have the same size for a stereo clip: {}x{} != {}x{}",
+            left.width(),
+            left.height(),
+            right.width(),
+            right.height()
+        )));
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| {
+            SpatialError::ImageError(format!("Failed to create output directory: {}", e))
+        })?;
+    }
+
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| SpatialError::ImageError("Invalid output path".to_string()))?;
+
+    crate::tools::require_tool("ffmpeg")?;
+
+    let mut child = Command::new("ffmpeg")
+        .args([
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgb24",
+            "-s",
+            &format!("{}x{}", left.width(), left.height()),
+            "-r",
+            "1",
+            "-i",
+            "-",
+            "-frames:v",
+            "2",
+            "-vf",
+            "crop=trunc(iw/2)*2:trunc(ih/2)*2",
+            "-c:v",
+            "libx264",
+            "-crf",
+            "18",
+            "-pix_fmt",
+            "yuv420p",
+            "-y",
+            path_str,
+        ])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            SpatialError::Other(format!("Failed to spawn ffmpeg for stereo clip: {}", e))
+        })?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        use std::io::Write;
+        for eye in [left, right] {
+            stdin.write_all(eye.to_rgb8().as_raw()).map_err(|e| {
+                SpatialError::IoError(format!("Failed to write frame to ffmpeg: {}", e))
+            })?;
+        }
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| SpatialError::Other(format!("ffmpeg stereo clip encoding failed: {}", e)))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(SpatialError::ImageError(format!(
+            "ffmpeg stereo clip encoding failed: {}",
+            stderr
+        )));
+    }
+
+    Ok(())
+}
+
+fn crop_to_common(left: &DynamicImage, right: &DynamicImage) -> (DynamicImage, DynamicImage) {
+    let width = left.width().min(right.width());
+    let height = left.height().min(right.height());
+    tracing::warn!(
+        "Left ({}x{}) and right ({}x{}) images differ in size, cropping both to {}x{}",
+        left.width(),
+        left.height(),
+        right.width(),
+        right.height(),
+        width,
+        height
+    );
+    (
+        left.crop_imm(0, 0, width, height),
+        right.crop_imm(0, 0, width, height),
+    )
+}
+
 fn save_side_by_side(
     left: &DynamicImage,
     right: &DynamicImage,
@@ -535,7 +1652,7 @@ fn save_separate(
     right: &DynamicImage,
     output_path: &Path,
     encoding: ImageEncoding,
-) -> SpatialResult<()> {
+) -> SpatialResult<Vec<PathBuf>> {
     let stem = output_path
         .file_stem()
         .and_then(|s| s.to_str())
@@ -550,7 +1667,26 @@ fn save_separate(
     save_image(left, &left_path, encoding)?;
     save_image(right, &right_path, encoding)?;
 
-    Ok(())
+    Ok(vec![left_path, right_path])
+}
+
+pub fn save_views(
+    views: &[DynamicImage],
+    parent: &Path,
+    stem: &str,
+    encoding: ImageEncoding,
+) -> SpatialResult<Vec<PathBuf>> {
+    std::fs::create_dir_all(parent).map_err(|e| {
+        SpatialError::ImageError(format!("Failed to create output directory: {}", e))
+    })?;
+
+    let mut paths = Vec::with_capacity(views.len());
+    for (i, view) in views.iter().enumerate() {
+        let path = parent.join(format!("{}-view{:02}.{}", stem, i, encoding.extension()));
+        save_image(view, &path, encoding)?;
+        paths.push(path);
+    }
+    Ok(paths)
 }
 
 fn save_image(image: &DynamicImage, path: &Path, encoding: ImageEncoding) -> SpatialResult<()> {
@@ -577,36 +1713,109 @@ fn save_image(image: &DynamicImage, path: &Path, encoding: ImageEncoding) -> Spa
                 .save(path)
                 .map_err(|e| SpatialError::ImageError(format!("Failed to save PNG: {}", e)))?;
         }
+        ImageEncoding::Tiff => {
+            image
+                .save_with_format(path, image::ImageFormat::Tiff)
+                .map_err(|e| SpatialError::ImageError(format!("Failed to save TIFF: {}", e)))?;
+        }
+        ImageEncoding::Bmp => {
+            image
+                .save_with_format(path, image::ImageFormat::Bmp)
+                .map_err(|e| SpatialError::ImageError(format!("Failed to save BMP: {}", e)))?;
+        }
+        ImageEncoding::Ppm => {
+            DynamicImage::ImageRgb8(image.to_rgb8())
+                .save_with_format(path, image::ImageFormat::Pnm)
+                .map_err(|e| SpatialError::ImageError(format!("Failed to save PPM: {}", e)))?;
+        }
     }
 
     Ok(())
 }
 
+/// Encodes `stereo_path` to a spatial photo beside it (see
+/// `MVHEVCConfig::output_path`), guessing the layout from the
+/// file name ("top-bottom" or "_tb_" means top-and-bottom). Prefer
+/// `encode_mvhevc_with_layout`, which takes the layout explicitly.
 pub fn encode_mvhevc(stereo_path: &Path, config: &MVHEVCConfig) -> SpatialResult<()> {
-    let spatial_path = config
-        .spatial_cli_path
-        .as_ref()
-        .map(|p| p.as_path())
-        .unwrap_or_else(|| Path::new("spatial"));
-
-    let hevc_path = stereo_path.with_extension("heic");
-
-    let format = if stereo_path.to_string_lossy().contains("top-bottom")
+    let layout = if stereo_path.to_string_lossy().contains("top-bottom")
         || stereo_path.to_string_lossy().contains("_tb_")
     {
-        "hou"
+        OutputFormat::TopAndBottom
     } else {
-        "sbs"
+        OutputFormat::SideBySide
     };
 
-    let quality_normalized = (config.quality as f32 / 100.0).clamp(0.0, 1.0);
+    encode_mvhevc_with_layout(
+        stereo_path,
+        &config.output_path(stereo_path),
+        layout,
+        config,
+    )
+}
+
+/// Encodes a side-by-side or top-and-bottom stereo image at `input` (left eye
+/// first) to an MV-HEVC HEIC at `output` with the `spatial` CLI.
+pub fn encode_mvhevc_with_layout(
+    input: &Path,
+    output: &Path,
+    layout: OutputFormat,
+    config: &MVHEVCConfig,
+) -> SpatialResult<()> {
+    let format = match layout {
+        OutputFormat::SideBySide => "sbs",
+        OutputFormat::TopAndBottom => "hou",
+        OutputFormat::Separate => {
+            return Err(SpatialError::ConfigError(
+                "MV-HEVC input must be a single side-by-side or top-and-bottom image".to_string(),
+            ))
+        }
+    };
+    encode_mvhevc_to(input, output, config, format)?;
+    Ok(())
+}
+
+/// Encodes two in-memory eyes to an MV-HEVC HEIC at `output`, going through a
+/// temporary lossless side-by-side image.
+pub fn encode_mvhevc_from_eyes(
+    left: &DynamicImage,
+    right: &DynamicImage,
+    output: &Path,
+    config: &MVHEVCConfig,
+) -> SpatialResult<()> {
+    let source = crate::temp::temp_file(".png")?;
+    save_side_by_side(left, right, source.path(), ImageEncoding::Png)?;
+    encode_mvhevc_with_layout(source.path(), output, OutputFormat::SideBySide, config)
+}
+
+fn encode_mvhevc_to(
+    stereo_path: &Path,
+    hevc_path: &Path,
+    config: &MVHEVCConfig,
+    format: &str,
+) -> SpatialResult<PathBuf> {
+    if hevc_path == stereo_path {
+        return Err(SpatialError::ConfigError(format!(
+            "Spatial output would overwrite its stereo source {}; choose another extension",
+            stereo_path.display()
+        )));
+    }
+
+    let spatial_path = config
+        .spatial_cli_path
+        .as_deref()
+        .unwrap_or_else(|| Path::new("spatial"));
+
+    crate::tools::check_spatial_cli(spatial_path)?;
+
+    let quality_normalized = config.quality.clamp(1, 100) as f32 / 100.0;
 
     let mut cmd = Command::new(spatial_path);
     cmd.arg("make")
         .arg("--input")
         .arg(stereo_path)
         .arg("--output")
-        .arg(&hevc_path)
+        .arg(hevc_path)
         .arg("--format")
         .arg(format)
         .arg("--quality")
@@ -628,5 +1837,277 @@ pub fn encode_mvhevc(stereo_path: &Path, config: &MVHEVCConfig) -> SpatialResult
         )));
     }
 
+    validate_heif(hevc_path)?;
+
+    Ok(hevc_path.to_path_buf())
+}
+
+const HEIF_BRANDS: &[&[u8; 4]] = &[
+    b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1",
+];
+
+/// Checks that `path` starts with an ISO-BMFF `ftyp` box naming a HEIF brand.
+/// `spatial` has been seen to exit 0 after writing an empty or truncated file
+/// (e.g. on a full disk), so its exit status alone isn't trusted.
+pub fn validate_heif(path: &Path) -> SpatialResult<()> {
+    let invalid = |reason: &str| {
+        SpatialError::ImageError(format!(
+            "`spatial` produced an invalid HEIC at {}: {}",
+            path.display(),
+            reason
+        ))
+    };
+
+    let len = std::fs::metadata(path)
+        .map_err(|_| invalid("file is missing"))?
+        .len();
+    if len == 0 {
+        return Err(invalid("file is empty"));
+    }
+
+    let mut header = [0u8; 32];
+    let read = {
+        use std::io::Read;
+        let mut file = std::fs::File::open(path)?;
+        let mut read = 0;
+        while read < header.len() {
+            match file.read(&mut header[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        read
+    };
+    if read < 16 || &header[4..8] != b"ftyp" {
+        return Err(invalid("missing ftyp box"));
+    }
+
+    let box_size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+    if box_size < 16 || box_size > len {
+        return Err(invalid("truncated ftyp box"));
+    }
+
+    // Major brand, then compatible brands after the minor version.
+    let brands_end = (box_size as usize).min(read);
+    let is_heif = std::iter::once(&header[8..12])
+        .chain(header[16..brands_end].chunks_exact(4))
+        .any(|brand| HEIF_BRANDS.iter().any(|b| &b[..] == brand));
+    if !is_heif {
+        return Err(invalid("not a HEIF file"));
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_expands_to_depth_and_spatial() {
+        assert_eq!(
+            parse_output_types("all").unwrap(),
+            vec![OutputType::Depth(vec![DepthFormat::Png]), OutputType::Spatial]
+        );
+        assert_eq!(
+            parse_output_types("all,all,spatial").unwrap(),
+            parse_output_types("all").unwrap()
+        );
+    }
+
+    #[test]
+    fn all_combines_with_other_tokens() {
+        assert_eq!(
+            parse_output_types("depth:avif,all,sbs").unwrap(),
+            vec![
+                OutputType::Depth(vec![DepthFormat::Avif, DepthFormat::Png]),
+                OutputType::Spatial,
+                OutputType::SideBySide,
+            ]
+        );
+        assert_eq!(
+            parse_output_types("all,depth:png16").unwrap()[0],
+            OutputType::Depth(vec![DepthFormat::Png, DepthFormat::Png16])
+        );
+    }
+
+    #[test]
+    fn ambiguous_output_type_lists_are_rejected() {
+        for input in [
+            "all,png",
+            "all,avif",
+            "png",
+            "avif,all",
+            "spatial,png",
+            "depth,sbs,png",
+            "all,sbs,tab",
+            "sbs,sep",
+            "all,bogus",
+            " , ",
+        ] {
+            assert!(parse_output_types(input).is_err(), "accepted '{}'", input);
+        }
+        let message = parse_output_types("all,png").unwrap_err();
+        assert!(message.contains("ambiguous") && message.contains("depth:png"), "{}", message);
+    }
+
+    #[test]
+    fn depth_formats_continue_a_depth_list() {
+        assert_eq!(
+            parse_output_types("depth,avif,png16,spatial").unwrap(),
+            vec![
+                OutputType::Depth(vec![DepthFormat::Avif, DepthFormat::Png16]),
+                OutputType::Spatial,
+            ]
+        );
+        assert_eq!(
+            parse_output_types("depth:avif,png").unwrap(),
+            vec![OutputType::Depth(vec![DepthFormat::Avif, DepthFormat::Png])]
+        );
+    }
+
+    #[test]
+    fn depth_formats_get_distinct_names_by_default() {
+        let names: Vec<String> = DepthFormat::all()
+            .iter()
+            .map(|fmt| fmt.filename("photo", DepthNaming::default()))
+            .collect();
+        assert_eq!(
+            names,
+            ["photo-depth-avif.avif", "photo-depth-png8.png", "photo-depth-png16.png", "photo-depth-colormap.png"]
+        );
+    }
+
+    #[test]
+    fn short_depth_names_match_earlier_releases() {
+        let names: Vec<String> = DepthFormat::all()
+            .iter()
+            .map(|fmt| fmt.filename("photo", DepthNaming::Short))
+            .collect();
+        assert_eq!(
+            names,
+            ["photo-depth.avif", "photo-depth.png", "photo-depth-16bit.png", "photo-depth-color.png"]
+        );
+    }
+
+    #[test]
+    fn dithering_tracks_a_gradient_that_rounding_bands() {
+        let (h, w, levels) = (64, 64, 4);
+        let depth = Array2::from_shape_fn((h, w), |(_, x)| x as f32 / (w - 1) as f32);
+        let dithered = quantize_with_dither(&depth, levels);
+        let steps = (levels - 1) as f32;
+        let banded: Vec<u8> = depth
+            .iter()
+            .map(|&v| ((v * steps).round() * 255.0 / steps).round() as u8)
+            .collect();
+
+        // Average distance of each column's mean from the true gradient.
+        let column_error = |pixels: &[u8]| {
+            (0..w)
+                .map(|x| {
+                    let mean = (0..h).map(|y| pixels[y * w + x] as f32).sum::<f32>() / h as f32;
+                    (mean / 255.0 - depth[[0, x]]).abs()
+                })
+                .sum::<f32>()
+                / w as f32
+        };
+        assert!(column_error(&dithered) < column_error(&banded) / 2.0);
+        assert!(dithered.iter().all(|&p| [0, 85, 170, 255].contains(&p)));
+    }
+
+    #[test]
+    fn flat_depth_quantizes_to_mid_gray() {
+        assert_eq!(quantize_with_dither(&Array2::from_elem((2, 3), 0.3), 256), vec![128; 6]);
+    }
+
+    #[test]
+    fn quality_must_be_between_1_and_100() {
+        assert!(validate_quality(0).is_err());
+        assert_eq!(validate_quality(1).unwrap(), 1);
+        assert_eq!(validate_quality(100).unwrap(), 100);
+        assert!(validate_quality(101).is_err());
+        assert!(validate_quality(u8::MAX).is_err());
+    }
+
+    #[test]
+    fn zero_quality_is_rejected_for_jpeg_and_spatial() {
+        let jpeg = OutputOptions::for_output_types(&[OutputType::SideBySide], ImageEncoding::Jpeg { quality: 0 });
+        assert!(matches!(jpeg.validate(), Err(SpatialError::ConfigError(_))));
+
+        let mut spatial = OutputOptions::for_output_types(&[OutputType::Spatial], ImageEncoding::Jpeg { quality: 90 });
+        spatial.mvhevc.as_mut().unwrap().quality = 0;
+        assert!(matches!(spatial.validate(), Err(SpatialError::ConfigError(_))));
+    }
+
+    #[test]
+    fn spatial_photo_defaults_to_heic() {
+        let config = MVHEVCConfig::default();
+        assert_eq!(
+            config.output_path(Path::new("out/photo-spatial.jpg")),
+            Path::new("out/photo-spatial.heic")
+        );
+    }
+
+    #[test]
+    fn spatial_photo_uses_configured_extension() {
+        let config = MVHEVCConfig {
+            extension: Some("heif".to_string()),
+            ..MVHEVCConfig::default()
+        };
+        assert_eq!(
+            config.output_path(Path::new("photo-spatial.jpg")),
+            Path::new("photo-spatial.heif")
+        );
+    }
+
+    #[test]
+    fn spatial_extension_must_differ_from_stereo_image() {
+        let options = OutputOptions::for_output_types(
+            &[OutputType::Spatial],
+            ImageEncoding::Jpeg { quality: 95 },
+        )
+        .with_spatial_extension(Some("JPG".to_string()));
+        assert!(options.validate().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn empty_spatial_output_keeps_the_stereo_image() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let spatial = dir.path().join("spatial");
+        std::fs::write(
+            &spatial,
+            r#"#!/bin/sh
+case "$1" in
+    --version) echo "spatial 0.0.0" ;;
+    make)
+        [ "$2" = "--help" ] && echo "--format sbs" && exit 0
+        while [ $# -gt 0 ]; do
+            [ "$1" = "--output" ] && : > "$2"
+            shift
+        done ;;
+esac
+"#,
+        )
+        .unwrap();
+        std::fs::set_permissions(&spatial, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let stereo_path = dir.path().join("photo-spatial.jpg");
+        let options = OutputOptions {
+            mvhevc: Some(MVHEVCConfig {
+                spatial_cli_path: Some(spatial),
+                enabled: true,
+                ..MVHEVCConfig::default()
+            }),
+            ..OutputOptions::default()
+        };
+        let eye = DynamicImage::new_rgb8(4, 4);
+        let err = save_stereo_image_with_paths(&eye, &eye, &stereo_path, options).unwrap_err();
+
+        assert!(err.to_string().contains("file is empty"), "{}", err);
+        assert!(stereo_path.exists());
+        assert_eq!(std::fs::metadata(dir.path().join("photo-spatial.heic")).unwrap().len(), 0);
+    }
+}
@@ -1,9 +1,276 @@
-use crate::error::SpatialResult;
-use image::{DynamicImage, ImageBuffer, Rgb};
+use crate::error::{SpatialError, SpatialResult, Warning};
+use crate::output::OutputFormat;
+use image::{DynamicImage, GenericImageView, GrayImage, ImageBuffer, Luma, Rgb};
 use ndarray::Array2;
 use rayon::prelude::*;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+const MAX_DISPARITY_WIDTH_FRACTION: f32 = 0.15;
+const ABSURD_DISPARITY_WIDTH_FRACTION: f32 = 0.5;
+const STEREO_ASPECT_RATIO: f32 = 1.9;
+const STEREO_HALF_SIMILARITY: f32 = 0.08;
+const MIN_DEPTH_VARIATION: f32 = 1e-3;
+const GROUND_PLANE_DEPTH: f32 = 0.5;
+const DISOCCLUSION_RADIUS_MARGIN: usize = 2;
+const LUMINANCE_MAX_GAIN: f32 = 1.5;
+const MAX_GROUND_PLANE_BIAS: f32 = 0.25;
+const DEFAULT_FLOATING_WINDOW_WIDTH: f32 = 0.05;
+const MAX_FLOATING_WINDOW_WIDTH: f32 = 0.5;
+/// How far a disparity may stray from its neighborhood median, as a fraction of
+/// `max_disparity`, before `StereoParams::disparity_median_clamp` pulls it back.
+const DISPARITY_OUTLIER_FRACTION: f32 = 0.1;
+/// How far (normalized depth) a source pixel may sit from the background it's
+/// standing in for before `DisocclusionFill::Source` and
+/// `DisocclusionFill::BackgroundReproject` reject it as occluder.
+const SOURCE_FILL_DEPTH_TOLERANCE: f32 = 0.05;
+/// Disoccluded fraction of the warped view above which `StereoStats::warning`
+/// suggests lowering the disparity.
+const HIGH_DISOCCLUSION_FRACTION: f32 = 0.05;
+
+/// Checks `max_disparity` against the image width. Values around 1-5% of the width
+/// (20-100px for a 1920px image) give comfortable 3D; values above 15% are clamped
+/// with a logged warning and values of half the width or more are rejected.
+pub fn validate_max_disparity(max_disparity: u32, image_width: u32) -> SpatialResult<u32> {
+    let (max_disparity, warning) = validate_max_disparity_with_warnings(max_disparity, image_width)?;
+    if let Some(warning) = warning {
+        tracing::warn!("{}", warning);
+    }
+    Ok(max_disparity)
+}
+
+/// `validate_max_disparity`, returning the clamp warning instead of logging it.
+pub fn validate_max_disparity_with_warnings(
+    max_disparity: u32,
+    image_width: u32,
+) -> SpatialResult<(u32, Option<Warning>)> {
+    let absurd = (image_width as f32 * ABSURD_DISPARITY_WIDTH_FRACTION) as u32;
+    if max_disparity >= absurd {
+        return Err(SpatialError::ConfigError(format!(
+            "max_disparity {} is too large for a {}px wide image (must be below {}px; 1-5% of the width is recommended)",
+            max_disparity, image_width, absurd
+        )));
+    }
+
+    let limit = (image_width as f32 * MAX_DISPARITY_WIDTH_FRACTION) as u32;
+    if max_disparity > limit {
+        let warning = Warning::DisparityClamped {
+            requested: max_disparity,
+            clamped: limit,
+            image_width,
+        };
+        return Ok((limit, Some(warning)));
+    }
+
+    Ok((max_disparity, None))
+}
+
+/// Which eyes are synthesized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum StereoMode {
+    /// The source is the left eye and only the right eye is warped.
+    #[default]
+    RightOnly,
+    /// Both eyes are warped from a central viewpoint by half the disparity in
+    /// opposite directions and both are filled. Each disocclusion is half as
+    /// wide and the artifacts are the same in both eyes, which is less
+    /// noticeable than one pristine and one patched eye, but neither eye keeps
+    /// the source's full sharpness. Costs two warps and two fills, roughly
+    /// twice the stereo step of `RightOnly`.
+    Symmetric,
+}
+
+impl std::fmt::Display for StereoMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RightOnly => write!(f, "right"),
+            Self::Symmetric => write!(f, "symmetric"),
+        }
+    }
+}
+
+impl std::str::FromStr for StereoMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "right" | "right-only" => Ok(Self::RightOnly),
+            "symmetric" | "both" => Ok(Self::Symmetric),
+            _ => Err(format!(
+                "Unknown stereo mode: '{}'. Use: right, symmetric",
+                s
+            )),
+        }
+    }
+}
+
+/// How pixels uncovered by the warp are filled.
+///
+/// `Background` is a safe default for any scene. `Nearest` suits holes between
+/// surfaces at similar depth, where "farther" is noise. `Source` wins in holes
+/// opened by gradual depth changes such as receding floors and walls.
+/// `BackgroundReproject` wins in wide holes behind a distinct occluder, such as
+/// the gap behind a head in a portrait with strong 3D, but can mirror a
+/// recognizable background feature into the hole.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DisocclusionFill {
+    /// Copy from the farther side of the hole by warped depth.
+    #[default]
+    Background,
+    /// Copy from whichever side of the hole is closer.
+    Nearest,
+    /// Pick the farther side like `Background`, then sample the source image
+    /// where that background would continue into the hole (its source position
+    /// plus the distance into the hole). Used only where the source pixel there
+    /// is itself background by depth, so occluders are never copied; otherwise
+    /// falls back to `Background`. Recovers real content in holes opened by
+    /// depth gradients instead of smearing the edge pixel.
+    Source,
+    /// Like `Source`, but where the reprojected source pixel is the occluder
+    /// itself (the background there was never visible), reflects it across the
+    /// occluder's edge into the visible background beside it. Fills wide holes
+    /// with real background texture instead of one stretched edge pixel.
+    BackgroundReproject,
+    /// Leave holes black, e.g. for inpainting downstream or to see exactly
+    /// where they are while tuning.
+    Black,
+}
+
+impl std::fmt::Display for DisocclusionFill {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Background => write!(f, "background"),
+            Self::Nearest => write!(f, "nearest"),
+            Self::Source => write!(f, "source"),
+            Self::BackgroundReproject => write!(f, "reproject"),
+            Self::Black => write!(f, "black"),
+        }
+    }
+}
+
+impl std::str::FromStr for DisocclusionFill {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "background" | "bg" => Ok(Self::Background),
+            "nearest" => Ok(Self::Nearest),
+            "source" => Ok(Self::Source),
+            "reproject" | "background-reproject" => Ok(Self::BackgroundReproject),
+            "black" | "none" => Ok(Self::Black),
+            _ => Err(format!(
+                "Unknown fill: '{}'. Use: background, nearest, source, reproject, black",
+                s
+            )),
+        }
+    }
+}
+
+/// Settings for `generate_stereo_pair_with`.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct StereoParams {
+    pub max_disparity: u32,
+    pub mode: StereoMode,
+    pub fill: DisocclusionFill,
+    /// Normalized depth (0-1) that lands on the screen plane. 0 puts everything
+    /// behind the screen; higher values pull the far end of the scene forward.
+    pub convergence: f32,
+    /// Exponent applied to normalized depth before it becomes disparity. Above 1
+    /// flattens the background and spends more disparity on near objects.
+    pub gamma: f32,
+    /// Adds disparity that grows linearly from nothing at the top row to
+    /// `ground_plane_bias * max_disparity` at the bottom, so the lower frame reads as
+    /// ground receding into the scene. An artistic control; 0 disables it. Clamped
+    /// to 0.25 so depth stays the dominant cue.
+    pub ground_plane_bias: f32,
+    /// Taper disparity to zero at the left and right frame edges, so near objects
+    /// cut off by the frame don't violate the stereo window (a floating window).
+    /// Ignored with `wrap`, since a panorama has no side edges.
+    pub floating_window: bool,
+    /// Width of the taper band on each side, as a fraction of the image width
+    /// (clamped to 0.5).
+    pub floating_window_width: f32,
+    /// Treat the image as a 360° equirectangular panorama: pixels shifted past
+    /// the left or right edge reappear on the other side, and hole filling
+    /// searches across the seam, so the seam stays continuous.
+    pub wrap: bool,
+    /// Clamp each pixel's disparity to within `DISPARITY_OUTLIER_FRACTION` of
+    /// `max_disparity` of its 3x3 neighborhood median. Removes the isolated
+    /// spikes that depth errors leave as sparkle in the warped view, at the cost
+    /// of pulling in single-pixel details such as stray hairs.
+    pub disparity_median_clamp: bool,
+}
+
+impl StereoParams {
+    pub fn new(max_disparity: u32) -> Self {
+        Self {
+            max_disparity,
+            mode: StereoMode::default(),
+            fill: DisocclusionFill::default(),
+            convergence: 0.0,
+            gamma: 1.0,
+            ground_plane_bias: 0.0,
+            floating_window: false,
+            floating_window_width: DEFAULT_FLOATING_WINDOW_WIDTH,
+            wrap: false,
+            disparity_median_clamp: false,
+        }
+    }
+
+    pub fn from_config(config: &crate::SpatialConfig, max_disparity: u32) -> Self {
+        Self {
+            mode: config.stereo_mode,
+            fill: config.disocclusion_fill,
+            ground_plane_bias: config.ground_plane_bias,
+            floating_window: config.floating_window,
+            floating_window_width: config.floating_window_width,
+            wrap: config.equirect,
+            disparity_median_clamp: config.disparity_median_clamp,
+            ..Self::new(max_disparity)
+        }
+    }
+
+    /// Scales `max_disparity` by `scale` (see `depth_filter::disparity_scale`),
+    /// keeping it within the limit `validate_max_disparity` clamps to for
+    /// `image_width`.
+    pub fn with_disparity_scale(self, scale: f32, image_width: u32) -> Self {
+        let limit = (image_width as f32 * MAX_DISPARITY_WIDTH_FRACTION) as u32;
+        let scaled = (self.max_disparity as f32 * scale).round() as u32;
+        Self {
+            max_disparity: scaled.min(limit.max(self.max_disparity)),
+            ..self
+        }
+    }
+
+    /// Disparity in pixels for a normalized depth value.
+    fn disparity(&self, max_disparity: f32, depth: f32) -> f32 {
+        let gamma = if self.gamma > 0.0 { self.gamma } else { 1.0 };
+        let convergence = self.convergence.clamp(0.0, 1.0);
+        (depth.clamp(0.0, 1.0).powf(gamma) - convergence) * max_disparity
+    }
+
+    /// Per-column disparity weights: 1 across the frame and easing to 0 at the
+    /// left and right edges when `floating_window` is on.
+    fn border_weights(&self, width: usize) -> Vec<f32> {
+        let band = width as f32
+            * self
+                .floating_window_width
+                .clamp(0.0, MAX_FLOATING_WINDOW_WIDTH);
+        if !self.floating_window || self.wrap || band < 1.0 {
+            return vec![1.0; width];
+        }
+        (0..width)
+            .map(|x| {
+                let t = (x.min(width - 1 - x) as f32 / band).min(1.0);
+                t * t * (3.0 - 2.0 * t)
+            })
+            .collect()
+    }
+}
+
+impl Default for StereoParams {
+    fn default() -> Self {
+        Self::new(crate::SpatialConfig::default().max_disparity)
+    }
+}
+
 pub fn generate_stereo_pair(
     image: &DynamicImage,
     depth: &Array2<f32>,
@@ -16,12 +283,235 @@ pub fn generate_stereo_pair_with_progress<F>(
     image: &DynamicImage,
     depth: &Array2<f32>,
     max_disparity: u32,
-    mut progress_callback: Option<F>,
+    progress_callback: Option<F>,
 ) -> SpatialResult<(DynamicImage, DynamicImage)>
 where
     F: FnMut(f64),
 {
+    generate_stereo_pair_with(
+        image,
+        depth,
+        &StereoParams::new(max_disparity),
+        progress_callback,
+    )
+}
+
+pub fn generate_stereo_pair_with<F>(
+    image: &DynamicImage,
+    depth: &Array2<f32>,
+    params: &StereoParams,
+    progress_callback: Option<F>,
+) -> SpatialResult<(DynamicImage, DynamicImage)>
+where
+    F: FnMut(f64),
+{
+    generate_stereo_pair_with_mask(image, depth, params, progress_callback)
+        .map(|(left, right, _)| (left, right))
+}
+
+/// Measurements of a generated stereo pair, for judging whether the disparity
+/// suits the scene.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize)]
+pub struct StereoStats {
+    /// Fraction of warped pixels that were holes before filling. Only the right
+    /// eye counts in `StereoMode::RightOnly`, since the left is the source.
+    pub disocclusion_fraction: f32,
+}
+
+impl StereoStats {
+    /// Computes the stats from a mask returned by `generate_stereo_pair_with_mask`
+    /// for the same `mode`.
+    pub fn from_mask(mask: &GrayImage, mode: StereoMode) -> Self {
+        let warped_pixels = match mode {
+            StereoMode::RightOnly => mask.len() / 2,
+            StereoMode::Symmetric => mask.len(),
+        };
+        let holes = mask.pixels().filter(|p| p[0] > 0).count();
+        Self {
+            disocclusion_fraction: holes as f32 / warped_pixels.max(1) as f32,
+        }
+    }
+
+    /// A warning when so much of the view was disoccluded that `max_disparity`
+    /// is probably too aggressive for the scene.
+    pub fn warning(&self, max_disparity: u32) -> Option<Warning> {
+        (self.disocclusion_fraction > HIGH_DISOCCLUSION_FRACTION).then_some(
+            Warning::HighDisocclusion {
+                fraction: self.disocclusion_fraction,
+                max_disparity,
+            },
+        )
+    }
+}
+
+/// Like `generate_stereo_pair_with`, but also returns `StereoStats` for the pair.
+pub fn generate_stereo_pair_with_stats<F>(
+    image: &DynamicImage,
+    depth: &Array2<f32>,
+    params: &StereoParams,
+    progress_callback: Option<F>,
+) -> SpatialResult<(DynamicImage, DynamicImage, StereoStats)>
+where
+    F: FnMut(f64),
+{
+    generate_stereo_pair_with_mask(image, depth, params, progress_callback)
+        .map(|(left, right, mask)| (left, right, StereoStats::from_mask(&mask, params.mode)))
+}
+
+/// Like `generate_stereo_pair_with`, but also returns the disocclusion mask:
+/// white where the warp left a hole (before any fill), laid out side by side
+/// like the pair, so the left half is blank unless `StereoMode::Symmetric`.
+pub fn generate_stereo_pair_with_mask<F>(
+    image: &DynamicImage,
+    depth: &Array2<f32>,
+    params: &StereoParams,
+    progress_callback: Option<F>,
+) -> SpatialResult<(DynamicImage, DynamicImage, GrayImage)>
+where
+    F: FnMut(f64),
+{
+    let img_rgb = image.to_rgb8();
+    let (width, height) = img_rgb.dimensions();
+    let max_disparity = params.max_disparity as f32;
+    let mut mask = GrayImage::new(width * 2, height);
+
+    let (left, right, right_filled) = match params.mode {
+        StereoMode::RightOnly => {
+            let (right_rgb, right_filled) = warp_view(
+                &img_rgb,
+                depth,
+                max_disparity,
+                params,
+                1.0,
+                progress_callback,
+            );
+            (image.clone(), right_rgb, right_filled)
+        }
+        StereoMode::Symmetric => {
+            let half = max_disparity / 2.0;
+            // Each eye reports half of the overall progress.
+            let progress = progress_callback.map(std::cell::RefCell::new);
+            let report = |offset: f64| {
+                progress
+                    .as_ref()
+                    .map(|cb| move |p: f64| (cb.borrow_mut())(offset + p / 2.0))
+            };
+            let (left_rgb, left_filled) =
+                warp_view(&img_rgb, depth, half, params, -1.0, report(0.0));
+            let (right_rgb, right_filled) =
+                warp_view(&img_rgb, depth, half, params, 1.0, report(50.0));
+            draw_holes(&mut mask, &left_filled, 0);
+            (DynamicImage::ImageRgb8(left_rgb), right_rgb, right_filled)
+        }
+    };
+    draw_holes(&mut mask, &right_filled, width);
+
+    Ok((left, DynamicImage::ImageRgb8(right), mask))
+}
+
+fn draw_holes(mask: &mut GrayImage, filled: &[bool], x_offset: u32) {
+    let width = mask.width() / 2;
+    for (i, _) in filled.iter().enumerate().filter(|(_, &f)| !f) {
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        mask.put_pixel(x_offset + x, y, Luma([255]));
+    }
+}
+
+/// Scales `target` so its mean luminance (Rec. 709) matches `reference`. Used to
+/// even out the two eyes of a stereo pair and reduce retinal rivalry.
+pub fn match_luminance(reference: &DynamicImage, target: &DynamicImage) -> DynamicImage {
+    let reference_mean = mean_luminance(reference);
+    let target_mean = mean_luminance(target);
+    if target_mean < 1.0 {
+        return target.clone();
+    }
+
+    let gain = (reference_mean / target_mean).clamp(1.0 / LUMINANCE_MAX_GAIN, LUMINANCE_MAX_GAIN);
+    let scale = |v: u8| (v as f32 * gain).round().clamp(0.0, 255.0) as u8;
+
+    if target.color().has_alpha() {
+        let mut rgba = target.to_rgba8();
+        for pixel in rgba.pixels_mut() {
+            for c in 0..3 {
+                pixel[c] = scale(pixel[c]);
+            }
+        }
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        let mut rgb = target.to_rgb8();
+        for pixel in rgb.pixels_mut() {
+            for c in 0..3 {
+                pixel[c] = scale(pixel[c]);
+            }
+        }
+        DynamicImage::ImageRgb8(rgb)
+    }
+}
+
+/// Applies `match_luminance` to the right eye of `pair` when `enabled`.
+pub(crate) fn apply_luminance_match(
+    pair: (DynamicImage, DynamicImage),
+    enabled: bool,
+) -> (DynamicImage, DynamicImage) {
+    if !enabled {
+        return pair;
+    }
+    let (left, right) = pair;
+    let right = match_luminance(&left, &right);
+    (left, right)
+}
+
+fn mean_luminance(image: &DynamicImage) -> f32 {
+    let rgb = image.to_rgb8();
+    let count = (rgb.width() as u64 * rgb.height() as u64).max(1);
+    let sum: f64 = rgb
+        .pixels()
+        .map(|p| 0.2126 * p[0] as f64 + 0.7152 * p[1] as f64 + 0.0722 * p[2] as f64)
+        .sum();
+    (sum / count as f64) as f32
+}
+
+pub fn generate_multiview(
+    image: &DynamicImage,
+    depth: &Array2<f32>,
+    max_disparity: u32,
+    n_views: u32,
+) -> SpatialResult<Vec<DynamicImage>> {
+    if n_views < 2 {
+        return Err(SpatialError::ConfigError(format!(
+            "At least 2 views are required, got {}",
+            n_views
+        )));
+    }
+
     let img_rgb = image.to_rgb8();
+    let mut views = Vec::with_capacity(n_views as usize);
+    views.push(image.clone());
+
+    let params = StereoParams::new(max_disparity);
+    for i in 1..n_views {
+        let disparity = max_disparity as f32 * i as f32 / (n_views - 1) as f32;
+        let (view, _) = warp_view(&img_rgb, depth, disparity, &params, 1.0, None::<fn(f64)>);
+        views.push(DynamicImage::ImageRgb8(view));
+    }
+
+    Ok(views)
+}
+
+/// Forward-warps `img_rgb` by depth. `direction` is 1.0 for a view to the right
+/// of the source and -1.0 for one to the left. Returns the filled view and which
+/// of its pixels the warp itself covered, i.e. before disocclusion fill.
+fn warp_view<F>(
+    img_rgb: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    depth: &Array2<f32>,
+    max_disparity: f32,
+    params: &StereoParams,
+    direction: f32,
+    mut progress_callback: Option<F>,
+) -> (ImageBuffer<Rgb<u8>, Vec<u8>>, Vec<bool>)
+where
+    F: FnMut(f64),
+{
     let width = img_rgb.width() as usize;
     let height = img_rgb.height() as usize;
 
@@ -29,18 +519,55 @@ where
         ImageBuffer::new(width as u32, height as u32);
     let mut depth_buffer = vec![f32::NEG_INFINITY; width * height];
     let mut filled = vec![false; width * height];
+    // Only the source-sampling fills need to map warped pixels back.
+    let track_source = matches!(
+        params.fill,
+        DisocclusionFill::Source | DisocclusionFill::BackgroundReproject
+    );
+    let sample_len = if track_source { width * height } else { 0 };
+    let mut source = SourceSamples {
+        offsets: vec![0; sample_len],
+        depth: vec![0.0; sample_len],
+    };
+
+    let ground_plane_bias = params.ground_plane_bias.clamp(0.0, MAX_GROUND_PLANE_BIAS);
+    let row_bias = |y: usize| {
+        ground_plane_bias * max_disparity * y as f32 / (height.saturating_sub(1)).max(1) as f32
+    };
+    let border_weights = params.border_weights(width);
+    let disparities = params.disparity_median_clamp.then(|| {
+        let field: Vec<f32> = (0..width * height)
+            .map(|i| {
+                params.disparity(
+                    max_disparity,
+                    get_depth_at(depth, i % width, i / width, width, height),
+                )
+            })
+            .collect();
+        let tolerance = (max_disparity * DISPARITY_OUTLIER_FRACTION).max(1.0);
+        clamp_disparity_outliers(&field, width, height, tolerance)
+    });
 
     for y in 0..height {
+        let bias = row_bias(y);
         for x in 0..width {
             let depth_val = get_depth_at(depth, x, y, width, height);
-            let disparity = (depth_val * max_disparity as f32).round() as i32;
-            let x_right = x as i32 - disparity;
-
-            if x_right >= 0 && x_right < width as i32 {
-                let idx = y * width + x_right as usize;
+            if track_source {
+                source.depth[y * width + x] = depth_val;
+            }
+            let base = match disparities {
+                Some(ref field) => field[y * width + x],
+                None => params.disparity(max_disparity, depth_val),
+            };
+            let disparity = ((base + bias) * border_weights[x] * direction).round() as i32;
+            if let Some(x_right) = column(x as i32 - disparity, width, params.wrap) {
+                let idx = y * width + x_right;
                 if depth_val > depth_buffer[idx] {
                     depth_buffer[idx] = depth_val;
                     filled[idx] = true;
+                    if track_source {
+                        source.offsets[idx] = disparity;
+                    }
                     if let Some(pixel) = img_rgb.get_pixel_checked(x as u32, y as u32) {
                         right_rgb.put_pixel(x_right as u32, y as u32, *pixel);
                     }
@@ -54,16 +581,60 @@ where
         }
     }
 
+    let radius = (max_disparity * (1.0 + ground_plane_bias + params.convergence.clamp(0.0, 1.0)))
+        .ceil() as usize
+        + DISOCCLUSION_RADIUS_MARGIN;
+    // When both sides of a hole are equally deep, the background is the side
+    // the view moved away from.
+    let background_left = direction > 0.0;
+    let holes = HoleFill {
+        source: img_rgb,
+        filled: &filled,
+        warped_depth: &depth_buffer,
+        source_samples: track_source.then_some(&source),
+        radius,
+        fill: params.fill,
+        background_left,
+        wrap: params.wrap,
+    };
     if let Some(ref mut cb) = progress_callback {
-        fill_disocclusions_with_progress(&mut right_rgb, &filled, width, height, Some(cb));
+        fill_disocclusions_with_progress(&mut right_rgb, &holes, Some(cb));
     } else {
-        fill_disocclusions(&mut right_rgb, &filled, width, height);
+        fill_disocclusions(&mut right_rgb, &holes);
     }
 
-    let left_image = image.clone();
-    let right_image = DynamicImage::ImageRgb8(right_rgb);
+    (right_rgb, filled)
+}
 
-    Ok((left_image, right_image))
+/// Pulls each value of the row-major `field` to within `tolerance` of the median
+/// of its 3x3 neighborhood (clipped at the borders). A lone spike has no support
+/// among its neighbors and is pulled in; along an edge, the median sides with
+/// the pixel's own region, so edges keep their position.
+fn clamp_disparity_outliers(
+    field: &[f32],
+    width: usize,
+    height: usize,
+    tolerance: f32,
+) -> Vec<f32> {
+    let mut out = vec![0.0; field.len()];
+    out.par_chunks_mut(width.max(1))
+        .enumerate()
+        .for_each(|(y, row)| {
+            let mut window = [0.0f32; 9];
+            for (x, value) in row.iter_mut().enumerate() {
+                let mut n = 0;
+                for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                        window[n] = field[ny * width + nx];
+                        n += 1;
+                    }
+                }
+                let neighborhood = &mut window[..n];
+                let median = *neighborhood.select_nth_unstable_by(n / 2, f32::total_cmp).1;
+                *value = field[y * width + x].clamp(median - tolerance, median + tolerance);
+            }
+        });
+    out
 }
 
 fn get_depth_at(
@@ -91,24 +662,61 @@ fn get_depth_at(
     }
 }
 
-fn fill_disocclusions(
-    image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-    filled: &[bool],
-    width: usize,
-    height: usize,
-) {
-    fill_disocclusions_with_progress(image, filled, width, height, None::<fn(f64)>);
+/// The warped view's holes and how to fill them; see
+/// `fill_disocclusions_with_progress`.
+#[derive(Clone, Copy)]
+struct HoleFill<'a> {
+    source: &'a ImageBuffer<Rgb<u8>, Vec<u8>>,
+    filled: &'a [bool],
+    warped_depth: &'a [f32],
+    source_samples: Option<&'a SourceSamples>,
+    radius: usize,
+    fill: DisocclusionFill,
+    background_left: bool,
+    wrap: bool,
 }
 
+fn fill_disocclusions(image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>, holes: &HoleFill) {
+    fill_disocclusions_with_progress(image, holes, None::<fn(f64)>);
+}
+
+/// Fills each hole from a filled pixel in its row within `radius`. `Background`
+/// takes the side whose warped depth (`warped_depth`, larger is nearer) is
+/// farther, so foreground edges never bleed into the gap they uncovered; when
+/// both sides are level it takes the left side if `background_left`, else the
+/// right. `Nearest` takes the closer side. Holes with nothing in range take the
+/// nearest filled pixel in their column, and failing that the source pixel.
+/// `Source` picks a side like `Background` but samples `source` through
+/// `source_samples` where it can; `BackgroundReproject` also reflects samples
+/// that land on the occluder into the background beside it. `Black` leaves
+/// holes untouched. With `wrap`, rows are searched and sampled across the
+/// left/right seam.
 fn fill_disocclusions_with_progress<F>(
     image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-    filled: &[bool],
-    width: usize,
-    height: usize,
+    holes: &HoleFill,
     mut progress_callback: Option<F>,
 ) where
     F: FnMut(f64),
 {
+    let HoleFill {
+        source,
+        filled,
+        warped_depth,
+        source_samples,
+        radius,
+        fill,
+        background_left,
+        wrap,
+    } = *holes;
+    if fill == DisocclusionFill::Black {
+        if let Some(ref mut cb) = progress_callback {
+            cb(100.0);
+        }
+        return;
+    }
+
+    let width = image.width() as usize;
+    let height = image.height() as usize;
     let original = image.clone();
     let original_raw = original.as_raw();
     let bytes_per_row = width * 3;
@@ -121,6 +729,7 @@ fn fill_disocclusions_with_progress<F>(
         .enumerate()
         .for_each(|(y, row_pixels)| {
             let row_filled = &filled[y * width..(y + 1) * width];
+            let row_depth = &warped_depth[y * width..(y + 1) * width];
             let orig_row = &original_raw[y * bytes_per_row..(y + 1) * bytes_per_row];
 
             for x in 0..width {
@@ -128,29 +737,63 @@ fn fill_disocclusions_with_progress<F>(
                     continue;
                 }
 
-                let mut left_pixel = None;
-                for lx in (0..x).rev() {
-                    if row_filled[lx] {
-                        let off = lx * 3;
-                        left_pixel = Some([orig_row[off], orig_row[off + 1], orig_row[off + 2]]);
-                        break;
-                    }
-                }
+                let pixel_at = |px: usize| {
+                    let off = px * 3;
+                    [orig_row[off], orig_row[off + 1], orig_row[off + 2]]
+                };
+                // The nearest filled pixel within `radius` on one side, and its distance.
+                let search = |side: i32| {
+                    (1..=radius.min(width - 1))
+                        .map_while(|d| Some(d).zip(column(x as i32 + side * d as i32, width, wrap)))
+                        .find(|&(_, px)| row_filled[px])
+                };
+                let (left, right) = (search(-1), search(1));
 
-                let mut right_pixel = None;
-                for rx in (x + 1)..width {
-                    if row_filled[rx] {
-                        let off = rx * 3;
-                        right_pixel = Some([orig_row[off], orig_row[off + 1], orig_row[off + 2]]);
-                        break;
+                let chosen = match (left, right) {
+                    (Some((dl, l)), Some((dr, r))) => Some(match fill {
+                        DisocclusionFill::Nearest if dr < dl => r,
+                        DisocclusionFill::Nearest => l,
+                        _ if row_depth[l] < row_depth[r] - MIN_DEPTH_VARIATION => l,
+                        _ if row_depth[r] < row_depth[l] - MIN_DEPTH_VARIATION => r,
+                        _ if background_left => l,
+                        _ => r,
+                    }),
+                    (left, right) => left.or(right).map(|(_, px)| px),
+                };
+                // The background pixel came from `px + offset` in the source, so
+                // its continuation into the hole is at `x + offset`.
+                let from_source = |px: usize| {
+                    let samples = source_samples?;
+                    let sx = x as i32 + samples.offsets[y * width + px];
+                    let is_background = |sx: usize| {
+                        (samples.depth[y * width + sx] - row_depth[px]).abs()
+                            <= SOURCE_FILL_DEPTH_TOLERANCE
+                    };
+                    let sample = |sx: usize| source.get_pixel(sx as u32, y as u32).0;
+                    if fill != DisocclusionFill::BackgroundReproject {
+                        let sx = column(sx, width, wrap)?;
+                        return is_background(sx).then(|| sample(sx));
                     }
-                }
-
-                let fill = match (left_pixel, right_pixel) {
-                    (Some(l), Some(_)) => l,
-                    (Some(l), None) => l,
-                    (None, Some(r)) => r,
-                    (None, None) => continue,
+                    // Walk toward the background side to the occluder's edge in
+                    // the source, then mirror the remaining distance past it.
+                    let side = if px > x { 1 } else { -1 };
+                    let side = if wrap && x.abs_diff(px) > width / 2 {
+                        -side
+                    } else {
+                        side
+                    };
+                    let (steps, edge) = (0..=radius as i32)
+                        .map_while(|k| Some(k).zip(column(sx + side * k, width, wrap)))
+                        .find(|&(_, e)| is_background(e))?;
+                    let mirrored = column(edge as i32 + side * (steps - 1).max(0), width, wrap)
+                        .filter(|&m| is_background(m))
+                        .unwrap_or(edge);
+                    Some(sample(mirrored))
+                };
+                let fill = match chosen {
+                    Some(px) => from_source(px).unwrap_or_else(|| pixel_at(px)),
+                    None => nearest_in_column(&original, filled, x, y)
+                        .unwrap_or_else(|| source.get_pixel(x as u32, y as u32).0),
                 };
                 let off = x * 3;
                 row_pixels[off] = fill[0];
@@ -167,3 +810,351 @@ fn fill_disocclusions_with_progress<F>(
         cb(fill_progress);
     }
 }
+
+/// Maps a possibly out-of-range column into the row: wrapped around when `wrap`,
+/// otherwise `None` past either edge.
+fn column(x: i32, width: usize, wrap: bool) -> Option<usize> {
+    if wrap {
+        Some(x.rem_euclid(width as i32) as usize)
+    } else {
+        usize::try_from(x).ok().filter(|&x| x < width)
+    }
+}
+
+/// Where each warped pixel came from, for `DisocclusionFill::Source`: its
+/// horizontal offset back into the source, and the source's depth per pixel.
+struct SourceSamples {
+    offsets: Vec<i32>,
+    depth: Vec<f32>,
+}
+
+fn nearest_in_column(
+    image: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    filled: &[bool],
+    x: usize,
+    y: usize,
+) -> Option<[u8; 3]> {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    (1..height.max(y + 1))
+        .flat_map(|d| [y.checked_sub(d), Some(y + d).filter(|&ny| ny < height)])
+        .flatten()
+        .find(|&ny| filled[ny * width + x])
+        .map(|ny| image.get_pixel(x as u32, ny as u32).0)
+}
+
+pub fn detect_stereo_layout(image: &DynamicImage) -> Option<OutputFormat> {
+    let (width, height) = image.dimensions();
+    if width < 2 || height < 2 {
+        return None;
+    }
+
+    let aspect = width as f32 / height as f32;
+    let layout = if aspect >= STEREO_ASPECT_RATIO {
+        OutputFormat::SideBySide
+    } else if aspect <= 1.0 / STEREO_ASPECT_RATIO {
+        OutputFormat::TopAndBottom
+    } else {
+        return None;
+    };
+
+    let (left, right) = split_stereo_image(image, layout).ok()?;
+    let thumb = |img: &DynamicImage| {
+        img.resize_exact(32, 32, image::imageops::FilterType::Triangle)
+            .to_luma8()
+    };
+    let (left, right) = (thumb(&left), thumb(&right));
+    let diff: f32 = left
+        .pixels()
+        .zip(right.pixels())
+        .map(|(a, b)| (a[0] as f32 - b[0] as f32).abs() / 255.0)
+        .sum::<f32>()
+        / (32.0 * 32.0);
+
+    (diff < STEREO_HALF_SIMILARITY).then_some(layout)
+}
+
+pub fn split_stereo_image(
+    image: &DynamicImage,
+    layout: OutputFormat,
+) -> SpatialResult<(DynamicImage, DynamicImage)> {
+    let (width, height) = image.dimensions();
+    match layout {
+        OutputFormat::SideBySide => {
+            let half = width / 2;
+            Ok((
+                image.crop_imm(0, 0, half, height),
+                image.crop_imm(half, 0, half, height),
+            ))
+        }
+        OutputFormat::TopAndBottom => {
+            let half = height / 2;
+            Ok((
+                image.crop_imm(0, 0, width, half),
+                image.crop_imm(0, half, width, half),
+            ))
+        }
+        OutputFormat::Separate => Err(SpatialError::ConfigError(
+            "Cannot split a single image into separate stereo views".to_string(),
+        )),
+    }
+}
+
+pub fn is_flat_depth(depth: &Array2<f32>) -> bool {
+    let min = depth.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = depth.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    depth.is_empty() || max - min < MIN_DEPTH_VARIATION
+}
+
+pub fn ground_plane_depth(height: usize, width: usize) -> Array2<f32> {
+    let denom = height.saturating_sub(1).max(1) as f32;
+    Array2::from_shape_fn((height, width), |(y, _)| y as f32 / denom * GROUND_PLANE_DEPTH)
+}
+
+/// Replaces a flat depth map with a ground plane when `ground_plane_fallback`
+/// is set, logging a warning either way.
+pub fn handle_flat_depth(depth: Array2<f32>, ground_plane_fallback: bool) -> Array2<f32> {
+    let (depth, warning) = handle_flat_depth_with_warnings(depth, ground_plane_fallback);
+    if let Some(warning) = warning {
+        tracing::warn!("{}", warning);
+    }
+    depth
+}
+
+/// `handle_flat_depth`, returning the flat-depth warning instead of logging it.
+pub fn handle_flat_depth_with_warnings(
+    depth: Array2<f32>,
+    ground_plane_fallback: bool,
+) -> (Array2<f32>, Option<Warning>) {
+    if !is_flat_depth(&depth) {
+        return (depth, None);
+    }
+
+    let warning = Warning::FlatDepth {
+        ground_plane: ground_plane_fallback,
+    };
+    if ground_plane_fallback {
+        let (h, w) = depth.dim();
+        (ground_plane_depth(h, w), Some(warning))
+    } else {
+        (depth, Some(warning))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column_image(width: u32) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+        ImageBuffer::from_fn(width, 2, |x, _| Rgb([x as u8 * 30, 0, 255 - x as u8 * 30]))
+    }
+
+    #[test]
+    fn equirect_warp_wraps_across_the_seam() {
+        let img = column_image(8);
+        let depth = Array2::from_elem((2, 8), 1.0);
+        let mut params = StereoParams::new(2);
+        params.wrap = true;
+
+        let (right, filled) = warp_view(&img, &depth, 2.0, &params, 1.0, None::<fn(f64)>);
+        assert!(filled.iter().all(|&f| f));
+        for y in 0..2 {
+            assert_eq!(right.get_pixel(6, y), img.get_pixel(0, y));
+            assert_eq!(right.get_pixel(7, y), img.get_pixel(1, y));
+        }
+    }
+
+    #[test]
+    fn flat_warp_leaves_the_edge_disoccluded() {
+        let img = column_image(8);
+        let depth = Array2::from_elem((2, 8), 1.0);
+
+        let (_, filled) = warp_view(&img, &depth, 2.0, &StereoParams::new(2), 1.0, None::<fn(f64)>);
+        assert!(!filled[6] && !filled[7]);
+        assert!(filled[..6].iter().all(|&f| f));
+    }
+
+    #[test]
+    fn multiview_starts_from_the_original_image() {
+        let img = DynamicImage::ImageRgb8(column_image(8));
+        let depth = Array2::from_shape_fn((2, 8), |(_, x)| x as f32 / 7.0);
+
+        let views = generate_multiview(&img, &depth, 4, 5).unwrap();
+        assert_eq!(views.len(), 5);
+        assert_eq!(views[0].to_rgb8(), img.to_rgb8());
+        assert!(views[1..].iter().all(|v| v.to_rgb8() != img.to_rgb8()));
+    }
+
+    #[test]
+    fn holes_wider_than_twenty_pixels_are_filled_from_the_background() {
+        let (background, foreground) = (Rgb([20, 40, 200]), Rgb([220, 30, 30]));
+        let img = ImageBuffer::from_fn(120, 2, |x, _| {
+            if (50..90).contains(&x) { foreground } else { background }
+        });
+        let depth =
+            Array2::from_shape_fn((2, 120), |(_, x)| if (50..90).contains(&x) { 1.0 } else { 0.0 });
+        let mut progress = Vec::new();
+
+        let (right, filled) = warp_view(
+            &img,
+            &depth,
+            40.0,
+            &StereoParams::new(40),
+            1.0,
+            Some(|p: f64| progress.push(p)),
+        );
+        assert!(filled[50..90].iter().all(|&f| !f));
+        for x in 50..90 {
+            for y in 0..2 {
+                assert_eq!(*right.get_pixel(x, y), background, "hole pixel ({}, {})", x, y);
+            }
+        }
+        assert!(right.pixels().all(|p| p.0 != [0, 0, 0] && p.0 != [128, 128, 128]));
+        assert_eq!(progress.last(), Some(&100.0));
+    }
+
+    #[test]
+    fn floating_window_tapers_disparity_to_zero_at_the_edges() {
+        let mut params = StereoParams::new(16);
+        params.floating_window = true;
+        params.floating_window_width = 0.1;
+        let weights = params.border_weights(100);
+
+        assert_eq!((weights[0], weights[99]), (0.0, 0.0));
+        assert!(weights[10..90].iter().all(|&w| w == 1.0));
+        assert!(weights[..11].windows(2).all(|w| w[0] < w[1]));
+        assert!(weights.iter().eq(weights.iter().rev()));
+    }
+
+    #[test]
+    fn floating_window_is_flat_when_off_wrapped_or_narrower_than_a_pixel() {
+        let mut params = StereoParams::new(16);
+        params.floating_window_width = 0.1;
+        assert!(params.border_weights(100).iter().all(|&w| w == 1.0));
+
+        params.floating_window = true;
+        assert!(params.border_weights(5).iter().all(|&w| w == 1.0));
+
+        params.wrap = true;
+        assert!(params.border_weights(100).iter().all(|&w| w == 1.0));
+
+        // Oversized bands are clamped to half the frame each side.
+        params.wrap = false;
+        params.floating_window_width = 3.0;
+        let weights = params.border_weights(100);
+        assert_eq!(weights[0], 0.0);
+        assert!(weights[49] < 1.0 && weights[49] > 0.9);
+    }
+
+    #[test]
+    fn disparity_scale_stays_within_the_width_limit() {
+        let params = StereoParams::new(20);
+        assert_eq!(params.with_disparity_scale(0.5, 1000).max_disparity, 10);
+        assert_eq!(params.with_disparity_scale(2.0, 1000).max_disparity, 40);
+        // 15% of 200px is 30px.
+        assert_eq!(params.with_disparity_scale(2.0, 200).max_disparity, 30);
+        // A configured value already past the limit isn't scaled up further.
+        assert_eq!(params.with_disparity_scale(2.0, 100).max_disparity, 20);
+    }
+
+    #[test]
+    fn single_pixel_disparity_spikes_are_clamped() {
+        let (width, height) = (5, 5);
+        let mut field = vec![4.0; width * height];
+        field[2 * width + 2] = 30.0;
+        field[0] = -20.0;
+        let out = clamp_disparity_outliers(&field, width, height, 1.0);
+        assert_eq!(out[2 * width + 2], 5.0);
+        assert_eq!(out[0], 3.0);
+        assert!(out.iter().all(|&d| (3.0..=5.0).contains(&d)));
+    }
+
+    #[test]
+    fn disparity_edges_survive_the_outlier_clamp() {
+        // A step from 2 to 12 halfway across: every pixel's median is its own side.
+        let (width, height) = (6, 4);
+        let field: Vec<f32> = (0..width * height)
+            .map(|i| if i % width < 3 { 2.0 } else { 12.0 })
+            .collect();
+        assert_eq!(clamp_disparity_outliers(&field, width, height, 1.0), field);
+        assert!(clamp_disparity_outliers(&[], 0, 0, 1.0).is_empty());
+    }
+
+    #[test]
+    fn ground_plane_bias_grows_disparity_down_the_frame() {
+        let (width, height) = (24, 5);
+        let img = ImageBuffer::from_pixel(width as u32, height as u32, Rgb([200, 100, 50]));
+        let depth = Array2::zeros((height, width));
+        let mut params = StereoParams::new(16);
+        params.ground_plane_bias = 0.25;
+
+        // With no depth shift, the holes at the right edge are the row's bias.
+        let (_, filled) = warp_view(&img, &depth, 16.0, &params, 1.0, None::<fn(f64)>);
+        let shifts: Vec<usize> = filled
+            .chunks(width)
+            .map(|row| row.iter().filter(|&&f| !f).count())
+            .collect();
+        assert_eq!(shifts, vec![0, 1, 2, 3, 4]);
+
+        params.ground_plane_bias = 0.0;
+        let (_, filled) = warp_view(&img, &depth, 16.0, &params, 1.0, None::<fn(f64)>);
+        assert!(filled.iter().all(|&f| f));
+    }
+
+    #[test]
+    fn constant_depth_is_flagged_and_kept_without_fallback() {
+        let depth = Array2::from_elem((4, 6), 0.5);
+        assert!(is_flat_depth(&depth));
+        let (out, warning) = handle_flat_depth_with_warnings(depth.clone(), false);
+        assert_eq!(out, depth);
+        assert!(matches!(warning, Some(Warning::FlatDepth { ground_plane: false })));
+    }
+
+    #[test]
+    fn constant_depth_falls_back_to_a_ground_plane() {
+        let (out, warning) = handle_flat_depth_with_warnings(Array2::from_elem((4, 6), 0.5), true);
+        assert!(matches!(warning, Some(Warning::FlatDepth { ground_plane: true })));
+        assert!(!is_flat_depth(&out));
+        for y in 1..4 {
+            assert!(out[[y, 0]] > out[[y - 1, 0]]);
+            assert!(out.row(y).iter().all(|&v| v == out[[y, 0]]));
+        }
+    }
+
+    #[test]
+    fn varied_depth_passes_through() {
+        let depth = Array2::from_shape_fn((4, 6), |(y, x)| (x + y) as f32 / 8.0);
+        let (out, warning) = handle_flat_depth_with_warnings(depth.clone(), true);
+        assert_eq!(out, depth);
+        assert!(warning.is_none());
+    }
+
+
+    fn gradient_image(offset: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(ImageBuffer::from_fn(16, 8, |x, y| {
+            let v = offset + (x * 4 + y * 2) as u8;
+            Rgb([v, v / 2 + 10, v])
+        }))
+    }
+
+    #[test]
+    fn luminance_match_brings_the_target_to_the_reference_mean() {
+        let reference = gradient_image(120);
+        let darker = gradient_image(90);
+        let matched = match_luminance(&reference, &darker);
+
+        let before = (mean_luminance(&reference) - mean_luminance(&darker)).abs();
+        let after = (mean_luminance(&reference) - mean_luminance(&matched)).abs();
+        assert!(before > 15.0);
+        assert!(after < 1.0, "still {} apart", after);
+    }
+
+    #[test]
+    fn luminance_gain_is_bounded() {
+        let reference = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(4, 4, Rgb([200, 200, 200])));
+        let target = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(4, 4, Rgb([50, 50, 50])));
+        let matched = match_luminance(&reference, &target);
+        assert_eq!(matched.to_rgb8().get_pixel(0, 0), &Rgb([75, 75, 75]));
+    }
+
+}
@@ -0,0 +1,53 @@
+use crate::error::{SpatialError, SpatialResult};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tempfile::{NamedTempFile, TempDir};
+
+static RUN_DIR: Mutex<Option<TempDir>> = Mutex::new(None);
+
+pub fn run_dir() -> SpatialResult<PathBuf> {
+	let mut guard = RUN_DIR.lock().unwrap_or_else(|e| e.into_inner());
+	if guard.is_none() {
+		let dir = tempfile::Builder::new()
+			.prefix("spatial-maker-")
+			.tempdir()
+			.map_err(|e| SpatialError::IoError(format!("Failed to create temp directory: {}", e)))?;
+		*guard = Some(dir);
+	}
+	Ok(guard.as_ref().map(|d| d.path().to_path_buf()).unwrap_or_default())
+}
+
+pub fn temp_file(suffix: &str) -> SpatialResult<NamedTempFile> {
+	temp_file_in(&run_dir()?, suffix)
+}
+
+pub fn temp_file_in(dir: &Path, suffix: &str) -> SpatialResult<NamedTempFile> {
+	tempfile::Builder::new()
+		.prefix(".spatial-maker-")
+		.suffix(suffix)
+		.tempfile_in(dir)
+		.map_err(|e| SpatialError::IoError(format!("Failed to create temp file: {}", e)))
+}
+
+pub fn temp_dir_in(dir: &Path, suffix: &str) -> SpatialResult<TempDir> {
+	tempfile::Builder::new()
+		.prefix(".spatial-maker-")
+		.suffix(suffix)
+		.tempdir_in(dir)
+		.map_err(|e| SpatialError::IoError(format!("Failed to create temp directory: {}", e)))
+}
+
+pub fn cleanup() {
+	let dir = RUN_DIR.lock().unwrap_or_else(|e| e.into_inner()).take();
+	drop(dir);
+}
+
+/// Calls `cleanup` when dropped, so the run directory is removed however `main`
+/// returns. `std::process::exit` skips destructors; call `cleanup` before it.
+pub struct CleanupGuard;
+
+impl Drop for CleanupGuard {
+	fn drop(&mut self) {
+		cleanup();
+	}
+}
@@ -0,0 +1,159 @@
+use crate::error::{SpatialError, SpatialResult};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+pub fn is_ffmpeg_available() -> bool {
+	is_tool_available("ffmpeg") && is_tool_available("ffprobe")
+}
+
+pub fn ensure_ffmpeg() -> SpatialResult<()> {
+	require_tool("ffmpeg")?;
+	require_tool("ffprobe")
+}
+
+/// Returns `SpatialError::MissingTool` with an install hint for this OS if `name`
+/// can't be run.
+pub fn require_tool(name: &str) -> SpatialResult<()> {
+	if is_tool_available(name) {
+		return Ok(());
+	}
+	Err(SpatialError::MissingTool {
+		tool: name.to_string(),
+		install_hint: install_hint(name),
+	})
+}
+
+fn install_hint(name: &str) -> String {
+	let package = match name {
+		"ffmpeg" | "ffprobe" => "ffmpeg",
+		other => other,
+	};
+	match package {
+		"spatial" => "Install with `brew install spatial` (macOS only), see https://blog.mikeswanson.com/spatial_docs/"
+			.to_string(),
+		"ffmpeg" if cfg!(target_os = "macos") => "Install with `brew install ffmpeg`".to_string(),
+		"ffmpeg" if cfg!(target_os = "windows") => "Install with `winget install ffmpeg`".to_string(),
+		"ffmpeg" => "Install with your package manager, e.g. `sudo apt install ffmpeg`, \
+		             or download from https://ffmpeg.org/download.html"
+			.to_string(),
+		other if cfg!(target_os = "macos") => format!("Install with `brew install {}`", other),
+		other => format!("Install `{}` with your package manager", other),
+	}
+}
+
+/// A `spatial` CLI that passed `check_spatial_cli`.
+#[derive(Clone, Debug)]
+pub struct SpatialCliInfo {
+	pub path: PathBuf,
+	pub version: String,
+}
+
+/// Confirms the `spatial` CLI at `path` runs and supports `make --format sbs`.
+/// Successful checks are cached for the rest of the process.
+pub fn check_spatial_cli(path: &Path) -> SpatialResult<SpatialCliInfo> {
+	static CHECKED: OnceLock<Mutex<HashMap<PathBuf, SpatialCliInfo>>> = OnceLock::new();
+	let cache = CHECKED.get_or_init(|| Mutex::new(HashMap::new()));
+	if let Some(info) = cache.lock().ok().and_then(|c| c.get(path).cloned()) {
+		return Ok(info);
+	}
+
+	let missing = || SpatialError::MissingTool {
+		tool: path.display().to_string(),
+		install_hint: install_hint("spatial"),
+	};
+
+	let version = Command::new(path)
+		.arg("--version")
+		.output()
+		.ok()
+		.filter(|output| output.status.success())
+		.map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+		.ok_or_else(missing)?;
+
+	let help = Command::new(path)
+		.args(["make", "--help"])
+		.output()
+		.map_err(|_| missing())?;
+	let help = format!(
+		"{}{}",
+		String::from_utf8_lossy(&help.stdout),
+		String::from_utf8_lossy(&help.stderr)
+	);
+	if !(help.contains("--format") && help.contains("sbs")) {
+		return Err(SpatialError::ConfigError(format!(
+			"`{}` ({}) does not support `make --format sbs`. Update it: {}",
+			path.display(),
+			if version.is_empty() { "unknown version" } else { &version },
+			install_hint("spatial")
+		)));
+	}
+
+	let info = SpatialCliInfo {
+		path: path.to_path_buf(),
+		version,
+	};
+	if let Ok(mut cache) = cache.lock() {
+		cache.insert(path.to_path_buf(), info.clone());
+	}
+	Ok(info)
+}
+
+/// What this build and environment can read and write. Formats with a native
+/// decoder or encoder compiled in don't need ffmpeg.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct Backends {
+	pub ffmpeg: bool,
+	pub ffprobe: bool,
+	/// The `spatial` CLI, needed for MV-HEVC (`.heic` spatial photos).
+	pub spatial_cli: bool,
+	pub avif_decode: bool,
+	pub jxl_decode: bool,
+	pub heic_decode: bool,
+	/// AVIF depth maps.
+	pub avif_encode: bool,
+	/// Video input and output, which always go through ffmpeg.
+	pub video: bool,
+}
+
+/// Probes for external tools and combines them with the compiled-in features.
+/// JPEG, PNG, TIFF, WebP, BMP and PPM photos never need external tools.
+pub fn backends_available() -> Backends {
+	let ffmpeg = is_tool_available("ffmpeg");
+	let ffprobe = is_tool_available("ffprobe");
+	Backends {
+		ffmpeg,
+		ffprobe,
+		spatial_cli: check_spatial_cli(Path::new("spatial")).is_ok(),
+		avif_decode: cfg!(feature = "avif") || ffmpeg,
+		jxl_decode: cfg!(feature = "jxl") || ffmpeg,
+		heic_decode: cfg!(feature = "heic") || ffmpeg,
+		avif_encode: cfg!(feature = "avif-encode") || ffmpeg,
+		video: ffmpeg && ffprobe,
+	}
+}
+
+pub fn has_ffmpeg_filter(name: &str) -> bool {
+	Command::new("ffmpeg")
+		.args(["-hide_banner", "-filters"])
+		.output()
+		.map(|output| {
+			String::from_utf8_lossy(&output.stdout)
+				.lines()
+				.any(|line| line.split_whitespace().nth(1) == Some(name))
+		})
+		.unwrap_or(false)
+}
+
+pub(crate) fn is_tool_available(name: &str) -> bool {
+	let version_arg = match name {
+		"ffmpeg" | "ffprobe" => "-version",
+		_ => "--version",
+	};
+	Command::new(name)
+		.arg(version_arg)
+		.output()
+		.map(|output| output.status.success())
+		.unwrap_or(false)
+}
@@ -1,15 +1,23 @@
 use crate::depth_filter::DepthProcessor;
-use crate::error::{SpatialError, SpatialResult};
-use crate::output::{needs_depth, needs_stereo, OutputType};
-use crate::stereo::generate_stereo_pair;
-use crate::{NormalizeMode, SpatialConfig};
-use image::{DynamicImage, ImageBuffer, RgbImage};
+use crate::error::{SpatialError, SpatialResult, Warning};
+use crate::output::{
+	depth_formats, needs_depth, needs_disparity_mask, needs_normals, needs_preview, needs_stereo_clip, needs_views, stereo_types, DepthFormat, OutputFormat, OutputType,
+};
+use crate::stereo::{apply_luminance_match, generate_stereo_pair_with, validate_max_disparity_with_warnings, StereoParams};
+use crate::tools::has_ffmpeg_filter;
+use crate::{GlobalCache, NormalizeMode, SpatialConfig};
+use image::{DynamicImage, RgbImage};
 use ndarray::Array2;
-use std::path::Path;
+use futures_util::StreamExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::process::Command;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
 
 #[derive(Clone, Debug)]
 pub struct VideoProgress {
@@ -35,32 +43,350 @@ impl VideoProgress {
 	}
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct ColorInfo {
+	pub primaries: Option<String>,
+	pub transfer: Option<String>,
+	pub space: Option<String>,
+}
+
+impl ColorInfo {
+	pub fn is_hdr(&self) -> bool {
+		matches!(self.transfer.as_deref(), Some("smpte2084" | "arib-std-b67"))
+			|| matches!(self.primaries.as_deref(), Some("bt2020"))
+	}
+}
+
 #[derive(Clone, Debug)]
 pub struct VideoMetadata {
+	/// Display width, i.e. after `rotation` is applied.
 	pub width: u32,
+	/// Display height, i.e. after `rotation` is applied.
 	pub height: u32,
+	/// Clockwise rotation (0, 90, 180 or 270) the container asks players to apply.
+	/// ffmpeg applies it while decoding, so frames, depth and disparity are all in
+	/// display space and the output carries no rotation.
+	pub rotation: u32,
+	/// Rate of the frames the pipeline decodes and processes: `source_fps`, or a
+	/// lower output rate when frames are dropped at extraction.
 	pub fps: f64,
+	/// The input's own frame rate.
+	pub source_fps: f64,
+	/// Rate the encoders write; above `fps` when frames are repeated to reach a
+	/// higher output rate.
+	pub output_fps: f64,
+	/// Frames to process, at `fps`.
 	pub total_frames: u32,
 	pub duration: f64,
 	pub has_audio: bool,
+	pub color: ColorInfo,
+}
+
+const BT709_OUTPUT_ARGS: [&str; 10] = [
+	"-vf", "scale=out_color_matrix=bt709:out_range=tv",
+	"-color_primaries", "bt709",
+	"-color_trc", "bt709",
+	"-colorspace", "bt709",
+	"-color_range", "tv",
+];
+
+type StereoPair = (DynamicImage, DynamicImage);
+
+/// Options `SpatialConfig::extra_extract_args` may not set: each would change
+/// the size, format or number of the raw RGB frames read from ffmpeg.
+const RESERVED_EXTRACT_ARGS: [&str; 14] = [
+	"-i", "-f", "-pix_fmt", "-s", "-vf", "-filter", "-filter_complex", "-lavfi",
+	"-r", "-vsync", "-fps_mode", "-c", "-codec", "-vcodec",
+];
+
+/// Options `SpatialConfig::extra_encoder_args` may not set: a second input or
+/// a complex filtergraph would displace the raw RGB pipe as the video source,
+/// and a video filter would replace the BT.709 conversion.
+const RESERVED_ENCODER_ARGS: [&str; 5] = ["-i", "-filter_complex", "-lavfi", "-vf", "-filter"];
+
+/// Rejects pass-through ffmpeg `args` that set one of the `reserved` options,
+/// with or without a stream specifier (`-filter:v` matches `-filter`).
+fn check_extra_ffmpeg_args(setting: &str, args: &[String], reserved: &[&str]) -> SpatialResult<()> {
+	let clash = args
+		.iter()
+		.find(|arg| reserved.contains(&arg.split(':').next().unwrap_or(arg)));
+	match clash {
+		Some(arg) => Err(SpatialError::ConfigError(format!(
+			"{} cannot include {}: it conflicts with the options set for the raw RGB frame pipe",
+			setting, arg
+		))),
+		None => Ok(()),
+	}
+}
+
+impl VideoMetadata {
+	/// Retimes the output to `target` fps. A lower rate drops frames during
+	/// extraction so fewer are inferred; a higher one only raises the encoder's
+	/// output rate. Either way the duration is unchanged, so audio stays in sync.
+	pub fn with_output_fps(mut self, target: f64) -> SpatialResult<Self> {
+		if !(target.is_finite() && target > 0.0 && target <= MAX_VIDEO_FPS) {
+			return Err(SpatialError::ConfigError(format!(
+				"Output frame rate must be between 0 and {}, got {}",
+				MAX_VIDEO_FPS, target
+			)));
+		}
+		if target < self.fps {
+			self.total_frames = ((self.total_frames as f64 * target / self.fps).round() as u32).max(1);
+			self.fps = target;
+		}
+		self.output_fps = target;
+		Ok(self)
+	}
+
+	/// Encoder arguments that resample from `fps` to `output_fps`, if they differ.
+	fn output_rate_args(&self) -> Vec<String> {
+		if self.output_fps == self.fps {
+			Vec::new()
+		} else {
+			vec!["-r".to_string(), format!("{}", self.output_fps)]
+		}
+	}
+}
+
+/// Raw depth from the Global scan pass, replayed in the second pass instead of
+/// running the model again. See `GlobalCache`.
+enum DepthCache {
+	Reinfer,
+	Memory(std::collections::VecDeque<Array2<f32>>),
+	Disk {
+		file: tempfile::NamedTempFile,
+		writer: Option<std::io::BufWriter<std::fs::File>>,
+		reader: Option<std::io::BufReader<std::fs::File>>,
+		dim: (usize, usize),
+	},
+}
+
+impl DepthCache {
+	fn new(strategy: GlobalCache, dim: (usize, usize)) -> SpatialResult<Self> {
+		Ok(match strategy {
+			GlobalCache::Reinfer => Self::Reinfer,
+			GlobalCache::Memory => Self::Memory(std::collections::VecDeque::new()),
+			GlobalCache::Disk => {
+				let file = crate::temp::temp_file(".depth")?;
+				let writer = std::io::BufWriter::new(file.reopen()?);
+				Self::Disk { file, writer: Some(writer), reader: None, dim }
+			}
+		})
+	}
+
+	fn push(&mut self, raw: &Array2<f32>) -> SpatialResult<()> {
+		use std::io::Write as _;
+		match self {
+			Self::Reinfer => {}
+			Self::Memory(frames) => frames.push_back(raw.clone()),
+			Self::Disk { writer: Some(writer), .. } => {
+				let bytes: Vec<u8> = raw.iter().flat_map(|v| v.to_le_bytes()).collect();
+				writer.write_all(&bytes)?;
+			}
+			Self::Disk { .. } => {}
+		}
+		Ok(())
+	}
+
+	/// Switches a disk cache from writing to reading.
+	fn rewind(&mut self) -> SpatialResult<()> {
+		use std::io::Write as _;
+		if let Self::Disk { file, writer, reader, .. } = self {
+			if let Some(mut w) = writer.take() {
+				w.flush()?;
+			}
+			*reader = Some(std::io::BufReader::new(file.reopen()?));
+		}
+		Ok(())
+	}
+
+	fn next(&mut self) -> Option<Array2<f32>> {
+		use std::io::Read as _;
+		match self {
+			Self::Reinfer => None,
+			Self::Memory(frames) => frames.pop_front(),
+			Self::Disk { reader: Some(reader), dim, .. } => {
+				let mut bytes = vec![0u8; dim.0 * dim.1 * 4];
+				reader.read_exact(&mut bytes).ok()?;
+				let data = bytes
+					.chunks_exact(4)
+					.map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+					.collect();
+				Array2::from_shape_vec(*dim, data).ok()
+			}
+			Self::Disk { .. } => None,
+		}
+	}
+}
+
+/// Codec for the stereo video. Which ones are allowed depends on the output
+/// container; see `select_video_codec`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum VideoCodec {
+	H264,
+	Hevc,
+	Vp9,
+	Av1,
+	ProRes,
+}
+
+impl VideoCodec {
+	fn encoder_args(&self) -> &'static [&'static str] {
+		match self {
+			Self::H264 => &["-c:v", "libx264", "-preset", "medium", "-crf", "23", "-pix_fmt", "yuv420p"],
+			Self::Hevc => &["-c:v", "libx265", "-preset", "medium", "-crf", "26", "-tag:v", "hvc1", "-pix_fmt", "yuv420p"],
+			Self::Vp9 => &["-c:v", "libvpx-vp9", "-crf", "32", "-b:v", "0", "-pix_fmt", "yuv420p"],
+			Self::Av1 => &["-c:v", "libsvtav1", "-crf", "30", "-pix_fmt", "yuv420p"],
+			Self::ProRes => &["-c:v", "prores_ks", "-profile:v", "2", "-pix_fmt", "yuv422p10le"],
+		}
+	}
+
+	/// Encoder args for an average bitrate instead of constant quality, for
+	/// `pass` 1 or 2 of a two-pass encode with its stats at `stats`. AV1 runs a
+	/// single VBR pass, so both passes get the same args. `None` for ProRes,
+	/// whose bitrate is fixed by its profile.
+	fn bitrate_args(&self, kbps: u32, pass: u8, stats: &str) -> Option<Vec<String>> {
+		let bitrate = format!("{}k", kbps);
+		let x265_params = format!("pass={}:stats={}.log", pass, stats);
+		let pass = pass.to_string();
+		let args: Vec<&str> = match self {
+			Self::H264 => vec![
+				"-c:v", "libx264", "-preset", "medium", "-b:v", &bitrate,
+				"-pass", &pass, "-passlogfile", stats, "-pix_fmt", "yuv420p",
+			],
+			Self::Hevc => vec![
+				"-c:v", "libx265", "-preset", "medium", "-b:v", &bitrate,
+				"-x265-params", &x265_params, "-tag:v", "hvc1", "-pix_fmt", "yuv420p",
+			],
+			Self::Vp9 => vec![
+				"-c:v", "libvpx-vp9", "-b:v", &bitrate,
+				"-pass", &pass, "-passlogfile", stats, "-pix_fmt", "yuv420p",
+			],
+			Self::Av1 => vec!["-c:v", "libsvtav1", "-b:v", &bitrate, "-pix_fmt", "yuv420p"],
+			Self::ProRes => return None,
+		};
+		Some(args.into_iter().map(String::from).collect())
+	}
+
+	fn is_two_pass(&self) -> bool {
+		matches!(self, Self::H264 | Self::Hevc | Self::Vp9)
+	}
+}
+
+impl std::fmt::Display for VideoCodec {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::H264 => write!(f, "h264"),
+			Self::Hevc => write!(f, "hevc"),
+			Self::Vp9 => write!(f, "vp9"),
+			Self::Av1 => write!(f, "av1"),
+			Self::ProRes => write!(f, "prores"),
+		}
+	}
+}
+
+impl std::str::FromStr for VideoCodec {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"h264" | "avc" => Ok(Self::H264),
+			"hevc" | "h265" => Ok(Self::Hevc),
+			"vp9" => Ok(Self::Vp9),
+			"av1" => Ok(Self::Av1),
+			"prores" => Ok(Self::ProRes),
+			_ => Err(format!("Unknown video codec: '{}'. Use: h264, hevc, vp9, av1, prores", s)),
+		}
+	}
+}
+
+/// Containers the stereo video can be written to, with the codecs each can hold.
+/// The first codec is used when none is requested.
+const CONTAINER_CODECS: &[(&str, &[VideoCodec])] = &[
+	("mov", &[VideoCodec::H264, VideoCodec::Hevc, VideoCodec::ProRes]),
+	("mp4", &[VideoCodec::H264, VideoCodec::Hevc]),
+	("m4v", &[VideoCodec::H264, VideoCodec::Hevc]),
+	("mkv", &[VideoCodec::H264, VideoCodec::Hevc, VideoCodec::Vp9, VideoCodec::Av1, VideoCodec::ProRes]),
+	("webm", &[VideoCodec::Vp9, VideoCodec::Av1]),
+];
+
+const DEFAULT_CONTAINER: &str = "mov";
+
+/// Container for the stereo video: the output path's extension if it is one we can
+/// write, otherwise `.mov`.
+fn output_container(output_path: &Path) -> &'static str {
+	let ext = output_path
+		.extension()
+		.and_then(|e| e.to_str())
+		.map(|e| e.to_lowercase())
+		.unwrap_or_default();
+	CONTAINER_CODECS
+		.iter()
+		.find(|(container, _)| *container == ext)
+		.map(|(container, _)| *container)
+		.unwrap_or(DEFAULT_CONTAINER)
+}
+
+/// The depth video written for `format` beside `output_path`: lossy AV1 in
+/// `<stem>-depth.mov` for AVIF, lossless FFV1 in Matroska for the PNG formats.
+fn depth_video_path(output_path: &Path, format: DepthFormat) -> PathBuf {
+	let stem = output_path.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+	let parent = output_path.parent().unwrap_or_else(|| Path::new("."));
+	let extension = if format == DepthFormat::Avif { "mov" } else { "mkv" };
+	parent.join(format!("{}-depth{}.{}", stem, format.suffix(), extension))
+}
+
+/// The stereo video written for `output_path`: `<stem>-spatial.<extension>` beside it.
+fn stereo_video_path(output_path: &Path, extension: &str) -> PathBuf {
+	let stem = output_path.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+	let parent = output_path.parent().unwrap_or_else(|| Path::new("."));
+	parent.join(format!("{}-spatial.{}", stem, extension))
+}
+
+/// Returns `requested` if `container` can hold it, or the container's default codec
+/// when nothing was requested.
+pub fn select_video_codec(container: &str, requested: Option<VideoCodec>) -> SpatialResult<VideoCodec> {
+	let container = container.trim_start_matches('.').to_lowercase();
+	let codecs = CONTAINER_CODECS
+		.iter()
+		.find(|(c, _)| *c == container)
+		.map(|(_, codecs)| *codecs)
+		.ok_or_else(|| {
+			let supported: Vec<&str> = CONTAINER_CODECS.iter().map(|(c, _)| *c).collect();
+			SpatialError::ConfigError(format!(
+				"Unsupported video container '.{}'. Use: {}",
+				container,
+				supported.join(", ")
+			))
+		})?;
+
+	match requested {
+		None => Ok(codecs[0]),
+		Some(codec) if codecs.contains(&codec) => Ok(codec),
+		Some(codec) => {
+			let allowed: Vec<String> = codecs.iter().map(|c| c.to_string()).collect();
+			Err(SpatialError::ConfigError(format!(
+				"{} video can't be written to .{}; use one of: {}",
+				codec,
+				container,
+				allowed.join(", ")
+			)))
+		}
+	}
 }
 
+const MAX_VIDEO_DIMENSION: u64 = 16384;
+const MAX_VIDEO_FPS: f64 = 1000.0;
+
 pub type ProgressCallback = Box<dyn Fn(VideoProgress) + Send + Sync>;
 
 pub async fn get_video_metadata(input_path: &Path) -> SpatialResult<VideoMetadata> {
+	crate::tools::require_tool("ffprobe")?;
+
 	let input_str = input_path
 		.to_str()
 		.ok_or_else(|| SpatialError::Other("Invalid input path encoding".to_string()))?;
 
-	let output = Command::new("ffprobe")
-		.args([
-			"-v", "error",
-			"-select_streams", "v:0",
-			"-show_entries", "stream=width,height,r_frame_rate,nb_frames,duration",
-			"-show_entries", "format=duration",
-			"-of", "json",
-			input_str,
-		])
+	let output = video_probe(input_str)
 		.output()
 		.await
 		.map_err(|e| {
@@ -75,7 +401,43 @@ pub async fn get_video_metadata(input_path: &Path) -> SpatialResult<VideoMetadat
 		return Err(SpatialError::Other(format!("ffprobe failed: {}", stderr)));
 	}
 
-	let stdout = String::from_utf8_lossy(&output.stdout);
+	let audio_output = Command::new("ffprobe")
+		.args([
+			"-v", "error",
+			"-select_streams", "a:0",
+			"-show_entries", "stream=codec_type",
+			"-of", "csv=p=0",
+			input_str,
+		])
+		.output()
+		.await
+		.map_err(|e| SpatialError::Other(format!("Failed to check audio: {}", e)))?;
+
+	let has_audio = String::from_utf8_lossy(&audio_output.stdout)
+		.trim()
+		.contains("audio");
+
+	parse_video_probe(&output.stdout, has_audio)
+}
+
+/// ffprobe reporting the first video stream of `input` as JSON, for
+/// `parse_video_probe`.
+fn video_probe(input: &str) -> Command {
+	let mut command = Command::new("ffprobe");
+	command.args([
+		"-v", "error",
+		"-select_streams", "v:0",
+		"-show_entries", "stream=width,height,r_frame_rate,nb_frames,duration,color_primaries,color_transfer,color_space\
+			:stream_tags=rotate:stream_side_data=rotation",
+		"-show_entries", "format=duration",
+		"-of", "json",
+		input,
+	]);
+	command
+}
+
+fn parse_video_probe(stdout: &[u8], has_audio: bool) -> SpatialResult<VideoMetadata> {
+	let stdout = String::from_utf8_lossy(stdout);
 	let json: serde_json::Value = serde_json::from_str(&stdout)
 		.map_err(|e| SpatialError::Other(format!("Failed to parse ffprobe JSON: {}", e)))?;
 
@@ -84,25 +446,30 @@ pub async fn get_video_metadata(input_path: &Path) -> SpatialResult<VideoMetadat
 		.and_then(|s| s.first())
 		.ok_or_else(|| SpatialError::Other("No video stream found".to_string()))?;
 
-	let width = stream["width"]
-		.as_u64()
-		.ok_or_else(|| SpatialError::Other("Failed to parse width".to_string()))? as u32;
-	let height = stream["height"]
-		.as_u64()
-		.ok_or_else(|| SpatialError::Other("Failed to parse height".to_string()))? as u32;
+	let dimension = |key: &str| {
+		let value = stream[key]
+			.as_u64()
+			.ok_or_else(|| SpatialError::Other(format!("Failed to parse {} from ffprobe", key)))?;
+		if !(2..=MAX_VIDEO_DIMENSION).contains(&value) {
+			return Err(SpatialError::Other(format!(
+				"Video stream has invalid {} {} (expected 2-{})",
+				key, value, MAX_VIDEO_DIMENSION
+			)));
+		}
+		Ok(value as u32)
+	};
+	let rotation = stream_rotation(stream);
+	let (width, height) = match rotation {
+		90 | 270 => (dimension("height")?, dimension("width")?),
+		_ => (dimension("width")?, dimension("height")?),
+	};
 
-	let fps = stream["r_frame_rate"]
-		.as_str()
-		.map(|s| {
-			if let Some((num, den)) = s.split_once('/') {
-				let n: f64 = num.parse().unwrap_or(30.0);
-				let d: f64 = den.parse().unwrap_or(1.0);
-				n / d
-			} else {
-				s.parse().unwrap_or(30.0)
-			}
-		})
-		.unwrap_or(30.0);
+	let frame_rate = stream["r_frame_rate"].as_str().unwrap_or("");
+	let fps = parse_frame_rate(frame_rate)
+		.filter(|fps| fps.is_finite() && *fps > 0.0 && *fps <= MAX_VIDEO_FPS)
+		.ok_or_else(|| {
+			SpatialError::Other(format!("Video stream has invalid frame rate '{}'", frame_rate))
+		})?;
 
 	let duration = stream["duration"]
 		.as_str()
@@ -119,374 +486,1776 @@ pub async fn get_video_metadata(input_path: &Path) -> SpatialResult<VideoMetadat
 		.and_then(|s| s.parse::<u32>().ok())
 		.unwrap_or_else(|| (duration * fps).round() as u32);
 
-	let audio_output = Command::new("ffprobe")
-		.args([
-			"-v", "error",
-			"-select_streams", "a:0",
-			"-show_entries", "stream=codec_type",
-			"-of", "csv=p=0",
-			input_str,
-		])
-		.output()
-		.await
-		.map_err(|e| SpatialError::Other(format!("Failed to check audio: {}", e)))?;
-
-	let has_audio = String::from_utf8_lossy(&audio_output.stdout)
-		.trim()
-		.contains("audio");
+	let color_field = |key: &str| {
+		stream[key]
+			.as_str()
+			.filter(|v| !v.is_empty() && *v != "unknown")
+			.map(|v| v.to_string())
+	};
+	let color = ColorInfo {
+		primaries: color_field("color_primaries"),
+		transfer: color_field("color_transfer"),
+		space: color_field("color_space"),
+	};
 
 	Ok(VideoMetadata {
 		width,
 		height,
 		fps,
+		source_fps: fps,
+		output_fps: fps,
 		total_frames,
 		duration,
 		has_audio,
+		rotation,
+		color,
 	})
 }
 
-async fn extract_frames(
-	input_path: &Path,
-	metadata: &VideoMetadata,
-) -> SpatialResult<mpsc::Receiver<Vec<u8>>> {
-	let (tx, rx) = mpsc::channel::<Vec<u8>>(10);
+/// Clockwise display rotation from the legacy `rotate` tag or the display matrix
+/// side data (which ffprobe reports counter-clockwise), snapped to a quarter turn.
+fn stream_rotation(stream: &serde_json::Value) -> u32 {
+	let from_tag = stream["tags"]["rotate"].as_str().and_then(|r| r.trim().parse::<f64>().ok());
+	let from_matrix = stream["side_data_list"]
+		.as_array()
+		.and_then(|list| list.iter().find_map(|data| data["rotation"].as_f64()))
+		.map(|r| -r);
 
-	let width = metadata.width;
-	let height = metadata.height;
-	let frame_size = (width * height * 3) as usize;
+	from_tag
+		.or(from_matrix)
+		.map(|degrees| ((degrees / 90.0).round() as i64 * 90).rem_euclid(360) as u32)
+		.unwrap_or(0)
+}
 
-	let input_path = input_path.to_path_buf();
+fn parse_frame_rate(s: &str) -> Option<f64> {
+	match s.split_once('/') {
+		Some((num, den)) => {
+			let num: f64 = num.trim().parse().ok()?;
+			let den: f64 = den.trim().parse().ok()?;
+			(den != 0.0).then(|| num / den)
+		}
+		None => s.trim().parse().ok(),
+	}
+}
 
-	let vf_scale = format!("scale={}:{}", width, height);
+const SEQUENCE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "tif", "tiff", "bmp", "webp", "exr", "dpx"];
+
+/// Numbered still frames read as one video, from a printf-style pattern
+/// (`frames/%04d.png`) or a directory whose images are numbered contiguously.
+/// ffmpeg's image2 demuxer decodes them, so the rest of the video pipeline
+/// (temporal filtering, running normalization, encoding) runs unchanged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageSequence {
+	dir: PathBuf,
+	prefix: String,
+	suffix: String,
+	/// Zero-padded digit count, or `None` for unpadded numbers (`%d`).
+	digits: Option<usize>,
+	start_number: u32,
+	frame_count: u32,
+}
 
-	tokio::spawn(async move {
-		let mut child = Command::new("ffmpeg")
-			.args([
-				"-i",
-				input_path.to_str().unwrap(),
-				"-vf",
-				&vf_scale,
-				"-f",
-				"rawvideo",
-				"-pix_fmt",
-				"rgb24",
-				"-vsync",
-				"0",
-				"-",
-			])
-			.stdout(Stdio::piped())
-			.stderr(Stdio::null())
-			.spawn()
-			.expect("Failed to spawn ffmpeg");
+impl ImageSequence {
+	/// Whether `path` names a sequence rather than a file: a directory, or a file
+	/// name containing `%d` / `%0Nd`. Doesn't touch the filesystem beyond `is_dir`.
+	pub fn is_sequence_path(path: &Path) -> bool {
+		path.is_dir() || path.file_name().and_then(|n| n.to_str()).and_then(parse_frame_pattern).is_some()
+	}
 
-		let stdout = child.stdout.take().expect("Failed to capture stdout");
-		let mut reader = tokio::io::BufReader::new(stdout);
-		let mut frame_buffer = vec![0u8; frame_size];
+	/// `Ok(None)` for ordinary files; an error if `path` looks like a sequence but
+	/// has no frames or a gap in its numbering.
+	pub fn detect(path: &Path) -> SpatialResult<Option<Self>> {
+		if path.is_dir() {
+			return Self::from_dir(path).map(Some);
+		}
+		let Some((prefix, digits, suffix)) = path.file_name().and_then(|n| n.to_str()).and_then(parse_frame_pattern) else {
+			return Ok(None);
+		};
+		let dir = match path.parent() {
+			Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+			_ => PathBuf::from("."),
+		};
+		let numbers: Vec<u32> = list_file_names(&dir)?
+			.iter()
+			.filter_map(|name| frame_number(name, &prefix, &suffix))
+			.filter(|(_, width, padded)| match digits {
+				Some(d) => *width == d || (*width > d && !padded),
+				None => !padded,
+			})
+			.map(|(number, _, _)| number)
+			.collect();
+		Self::from_numbers(dir, prefix, suffix, digits, numbers, path).map(Some)
+	}
 
-		loop {
-			match reader.read_exact(&mut frame_buffer).await {
-				Ok(_) => {
-					if tx.send(frame_buffer.clone()).await.is_err() {
-						break;
-					}
-				}
-				Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-				Err(_) => break,
+	fn from_dir(dir: &Path) -> SpatialResult<Self> {
+		let mut groups: Vec<FrameGroup> = Vec::new();
+		for name in list_file_names(dir)? {
+			let is_frame = Path::new(&name)
+				.extension()
+				.and_then(|e| e.to_str())
+				.is_some_and(|e| SEQUENCE_EXTENSIONS.contains(&e.to_lowercase().as_str()));
+			let Some((prefix, suffix)) = split_frame_number(&name).filter(|_| is_frame) else {
+				continue;
+			};
+			let key = (prefix.to_string(), suffix.to_string());
+			let Some(frame) = frame_number(&name, &key.0, &key.1) else {
+				continue;
+			};
+			match groups.iter_mut().find(|(k, _)| *k == key) {
+				Some((_, frames)) => frames.push(frame),
+				None => groups.push((key, vec![frame])),
 			}
 		}
 
-		let _ = child.wait().await;
-	});
-
-	Ok(rx)
-}
+		let ((prefix, suffix), frames) = match groups.len() {
+			1 => groups.remove(0),
+			0 => {
+				return Err(SpatialError::IoError(format!(
+					"No numbered images found in {:?}",
+					dir
+				)))
+			}
+			_ => {
+				return Err(SpatialError::ConfigError(format!(
+					"{:?} holds more than one numbered image sequence; pass a pattern such as {:?} instead",
+					dir,
+					dir.join(format!("{}%04d{}", groups[0].0 .0, groups[0].0 .1))
+				)))
+			}
+		};
 
-fn frame_to_image(data: &[u8], width: u32, height: u32) -> SpatialResult<DynamicImage> {
-	let rgb_image = RgbImage::from_raw(width, height, data.to_vec()).ok_or_else(|| {
-		SpatialError::ImageError(format!(
-			"Failed to create image from frame data ({}x{})",
-			width, height
-		))
-	})?;
-	Ok(DynamicImage::ImageRgb8(rgb_image))
-}
+		let digits = if frames.iter().any(|(_, _, padded)| *padded) {
+			let width = frames[0].1;
+			if frames.iter().any(|(_, w, _)| *w != width) {
+				return Err(SpatialError::ConfigError(format!(
+					"Frames in {:?} mix padded and unpadded numbers",
+					dir
+				)));
+			}
+			Some(width)
+		} else {
+			None
+		};
+		let numbers = frames.into_iter().map(|(number, _, _)| number).collect();
+		Self::from_numbers(dir.to_path_buf(), prefix, suffix, digits, numbers, dir)
+	}
 
-async fn encode_stereo_video(
-	output_path: std::path::PathBuf,
-	metadata: VideoMetadata,
-	mut rx: mpsc::Receiver<(DynamicImage, DynamicImage)>,
-) -> SpatialResult<()> {
-	let width = metadata.width;
-	let height = metadata.height;
-	let fps = metadata.fps;
+	fn from_numbers(
+		dir: PathBuf,
+		prefix: String,
+		suffix: String,
+		digits: Option<usize>,
+		mut numbers: Vec<u32>,
+		source: &Path,
+	) -> SpatialResult<Self> {
+		numbers.sort_unstable();
+		numbers.dedup();
+		let (Some(&start_number), Some(&last)) = (numbers.first(), numbers.last()) else {
+			return Err(SpatialError::IoError(format!("No frames match {:?}", source)));
+		};
+		if let Some(gap) = numbers.windows(2).find(|pair| pair[1] != pair[0] + 1) {
+			return Err(SpatialError::ConfigError(format!(
+				"Image sequence {:?} is missing frames {}-{}; numbering must be contiguous",
+				source,
+				gap[0] + 1,
+				gap[1] - 1
+			)));
+		}
+		Ok(Self {
+			dir,
+			prefix,
+			suffix,
+			digits,
+			start_number,
+			frame_count: last - start_number + 1,
+		})
+	}
 
-	let output_width = width * 2;
-	let output_height = height;
+	/// The printf-style pattern handed to ffmpeg, with any literal `%` escaped.
+	pub fn pattern(&self) -> PathBuf {
+		let number = match self.digits {
+			Some(d) => format!("%0{}d", d),
+			None => "%d".to_string(),
+		};
+		self.dir.join(format!(
+			"{}{}{}",
+			self.prefix.replace('%', "%%"),
+			number,
+			self.suffix.replace('%', "%%")
+		))
+	}
 
-	let mut child = Command::new("ffmpeg")
-		.args([
-			"-f",
-			"rawvideo",
-			"-pix_fmt",
-			"rgb24",
-			"-s",
-			&format!("{}x{}", output_width, output_height),
-			"-r",
-			&format!("{}", fps),
-			"-i",
-			"-",
-			"-c:v",
-			"libx264",
-			"-preset",
-			"medium",
-			"-crf",
-			"23",
-			"-pix_fmt",
-			"yuv420p",
-			"-y",
-			output_path.to_str().unwrap(),
-		])
-		.stdin(Stdio::piped())
-		.stdout(Stdio::null())
-		.stderr(Stdio::null())
-		.spawn()
-		.map_err(|e| SpatialError::Other(format!("Failed to spawn ffmpeg encoder: {}", e)))?;
+	pub fn start_number(&self) -> u32 {
+		self.start_number
+	}
 
-	let mut stdin = child.stdin.take().expect("Failed to capture stdin");
+	pub fn frame_count(&self) -> u32 {
+		self.frame_count
+	}
 
-	while let Some((left, right)) = rx.recv().await {
-		let mut sbs_image = ImageBuffer::new(output_width, output_height);
+	pub fn frame_path(&self, number: u32) -> PathBuf {
+		let width = self.digits.unwrap_or(0);
+		self.dir.join(format!("{}{:0width$}{}", self.prefix, number, self.suffix, width = width))
+	}
 
-		let left_rgb = left.to_rgb8();
-		for y in 0..height {
-			for x in 0..width {
-				let pixel = left_rgb.get_pixel(x, y);
-				sbs_image.put_pixel(x, y, *pixel);
-			}
+	/// Path the sequence stands in for when naming outputs: `renders/shot_%04d.png`
+	/// becomes `renders/shot`, and `frames/%04d.png` or the directory `frames`
+	/// becomes `frames`.
+	pub fn stem_path(&self) -> PathBuf {
+		let name = self.prefix.trim_end_matches(['_', '-', '.', ' ']);
+		if name.is_empty() {
+			self.dir.clone()
+		} else {
+			self.dir.join(name)
 		}
+	}
 
-		let right_rgb = right.to_rgb8();
-		for y in 0..height {
-			for x in 0..width {
-				let pixel = right_rgb.get_pixel(x, y);
-				sbs_image.put_pixel(width + x, y, *pixel);
-			}
+	/// Dimensions and color come from probing the first frame; stills carry no
+	/// frame rate, so `fps` sets it.
+	pub async fn metadata(&self, fps: f64) -> SpatialResult<VideoMetadata> {
+		if !(fps.is_finite() && fps > 0.0 && fps <= MAX_VIDEO_FPS) {
+			return Err(SpatialError::ConfigError(format!(
+				"Image sequence frame rate must be between 0 and {}, got {}",
+				MAX_VIDEO_FPS, fps
+			)));
 		}
+		let first = get_video_metadata(&self.frame_path(self.start_number)).await?;
+		Ok(VideoMetadata {
+			fps,
+			source_fps: fps,
+			output_fps: fps,
+			total_frames: self.frame_count,
+			duration: self.frame_count as f64 / fps,
+			has_audio: false,
+			..first
+		})
+	}
 
-		stdin
-			.write_all(&sbs_image.into_raw())
-			.await
-			.map_err(|e| SpatialError::IoError(format!("Failed to write frame: {}", e)))?;
+	fn input_args(&self, fps: f64, start_frame: u32) -> SpatialResult<Vec<String>> {
+		let pattern = self.pattern();
+		let pattern = pattern
+			.to_str()
+			.ok_or_else(|| SpatialError::Other("Invalid input path encoding".to_string()))?;
+		Ok(vec![
+			"-f".to_string(),
+			"image2".to_string(),
+			"-pattern_type".to_string(),
+			"sequence".to_string(),
+			"-framerate".to_string(),
+			format!("{}", fps),
+			"-start_number".to_string(),
+			(self.start_number + start_frame).to_string(),
+			"-i".to_string(),
+			pattern.to_string(),
+		])
 	}
+}
 
-	drop(stdin);
+/// Splits a file name around a printf `%d` / `%0Nd` conversion, returning the
+/// literal prefix and suffix (with `%%` unescaped) and the padding width.
+fn parse_frame_pattern(name: &str) -> Option<(String, Option<usize>, String)> {
+	let mut prefix = String::new();
+	let mut chars = name.char_indices().peekable();
+	while let Some((i, c)) = chars.next() {
+		if c != '%' {
+			prefix.push(c);
+			continue;
+		}
+		if chars.peek().map(|(_, c)| *c) == Some('%') {
+			chars.next();
+			prefix.push('%');
+			continue;
+		}
+		let rest = &name[i + 1..];
+		let spec_len = rest.find('d')?;
+		let spec = &rest[..spec_len];
+		let digits = match spec {
+			"" => None,
+			_ if spec.starts_with('0') && spec.len() > 1 => Some(spec[1..].parse::<usize>().ok()?),
+			_ => return None,
+		};
+		let suffix = &rest[spec_len + 1..];
+		if suffix.replace("%%", "").contains('%') {
+			return None;
+		}
+		return Some((prefix, digits, suffix.replace("%%", "%")));
+	}
+	None
+}
 
-	let status = child
-		.wait()
-		.await
-		.map_err(|e| SpatialError::Other(format!("ffmpeg encoding failed: {}", e)))?;
+/// Prefix and suffix around the last run of digits in a file name's stem.
+fn split_frame_number(name: &str) -> Option<(&str, &str)> {
+	let stem_end = name.rfind('.').unwrap_or(name.len());
+	let end = name[..stem_end].rfind(|c: char| c.is_ascii_digit())? + 1;
+	let start = name[..end].rfind(|c: char| !c.is_ascii_digit()).map_or(0, |i| i + 1);
+	Some((&name[..start], &name[end..]))
+}
 
-	if !status.success() {
-		return Err(SpatialError::Other(
-			"ffmpeg encoding exited with error".to_string(),
-		));
+/// A frame number with its digit count and whether it has leading zeros.
+type FrameNumber = (u32, usize, bool);
+
+/// Frames sharing one (prefix, suffix) around their number.
+type FrameGroup = ((String, String), Vec<FrameNumber>);
+
+/// The frame number in `name` between `prefix` and `suffix`.
+fn frame_number(name: &str, prefix: &str, suffix: &str) -> Option<FrameNumber> {
+	let digits = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+	if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
 	}
+	let padded = digits.len() > 1 && digits.starts_with('0');
+	Some((digits.parse().ok()?, digits.len(), padded))
+}
 
-	Ok(())
+fn list_file_names(dir: &Path) -> SpatialResult<Vec<String>> {
+	let entries = std::fs::read_dir(dir)
+		.map_err(|e| SpatialError::IoError(format!("Failed to read {:?}: {}", dir, e)))?;
+	Ok(entries
+		.filter_map(|entry| entry.ok())
+		.filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+		.filter_map(|entry| entry.file_name().into_string().ok())
+		.collect())
 }
 
-async fn encode_depth_video(
-	output_path: std::path::PathBuf,
-	metadata: VideoMetadata,
-	mut rx: mpsc::Receiver<Array2<f32>>,
-) -> SpatialResult<()> {
-	let width = metadata.width;
-	let height = metadata.height;
-	let fps = metadata.fps;
+/// Input path that makes `process_video` read the video from stdin.
+pub const STDIN_PATH: &str = "-";
+/// How much of a piped video is buffered for ffprobe before ffmpeg takes over.
+const STDIN_PROBE_BYTES: u64 = 16 * 1024 * 1024;
 
-	let mut child = Command::new("ffmpeg")
-		.args([
-			"-f", "rawvideo",
-			"-pix_fmt", "gray",
-			"-s", &format!("{}x{}", width, height),
-			"-r", &format!("{}", fps),
-			"-i", "-",
-			"-c:v", "libsvtav1",
-			"-crf", "23",
-			"-pix_fmt", "yuv420p",
-			"-y",
-			output_path.to_str().unwrap(),
-		])
-		.stdin(Stdio::piped())
-		.stdout(Stdio::null())
-		.stderr(Stdio::piped())
-		.spawn()
-		.map_err(|e| SpatialError::Other(format!("Failed to spawn ffmpeg depth encoder: {}", e)))?;
+/// True if `path` is `STDIN_PATH`.
+pub fn is_stdin(path: &Path) -> bool {
+	path.as_os_str() == STDIN_PATH
+}
 
-	let mut stdin = child.stdin.take().expect("Failed to capture stdin");
+/// A video piped to stdin. Stdin can only be read once, so ffprobe gets just the
+/// first `STDIN_PROBE_BYTES`, which are then replayed to ffmpeg ahead of the rest
+/// of the stream.
+struct StdinVideo {
+	prefix: Vec<u8>,
+}
 
-	while let Some(depth) = rx.recv().await {
-		let mut min_val = f32::INFINITY;
-		let mut max_val = f32::NEG_INFINITY;
-		for &v in depth.iter() {
-			if v < min_val { min_val = v; }
-			if v > max_val { max_val = v; }
+impl StdinVideo {
+	async fn open() -> SpatialResult<Self> {
+		let mut prefix = Vec::new();
+		tokio::io::stdin()
+			.take(STDIN_PROBE_BYTES)
+			.read_to_end(&mut prefix)
+			.await
+			.map_err(|e| SpatialError::IoError(format!("Failed to read video from stdin: {}", e)))?;
+		if prefix.is_empty() {
+			return Err(SpatialError::IoError("No video data on stdin".to_string()));
 		}
-		let range = max_val - min_val;
+		Ok(Self { prefix })
+	}
 
-		let pixels: Vec<u8> = depth.iter().map(|&v| {
-			if range > 1e-6 {
-				((v - min_val) / range * 255.0).round() as u8
-			} else {
-				128u8
-			}
-		}).collect();
+	/// Metadata from the buffered start of the stream. Frame count and duration
+	/// are often missing there, which leaves progress indeterminate, and audio is
+	/// never reported since it can't be read back from the pipe for muxing.
+	async fn metadata(&self) -> SpatialResult<VideoMetadata> {
+		let mut child = video_probe("pipe:0")
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.kill_on_drop(true)
+			.spawn()
+			.map_err(|e| {
+				SpatialError::Other(format!(
+					"Failed to run ffprobe (is ffmpeg installed?): {}",
+					e
+				))
+			})?;
+		if let Some(mut sink) = child.stdin.take() {
+			// ffprobe stops reading once it has what it needs.
+			let _ = sink.write_all(&self.prefix).await;
+		}
+		let output = child
+			.wait_with_output()
+			.await
+			.map_err(|e| SpatialError::Other(format!("ffprobe failed: {}", e)))?;
+		if !output.status.success() {
+			let stderr = String::from_utf8_lossy(&output.stderr);
+			return Err(SpatialError::Other(format!(
+				"ffprobe failed on stdin (piped video must be in a streamable container such as \
+				 MPEG-TS, Matroska or fragmented MP4): {}",
+				stderr
+			)));
+		}
+		parse_video_probe(&output.stdout, false)
+	}
+
+	/// Writes the buffered prefix and then the rest of stdin to `sink`.
+	fn feed(self, mut sink: tokio::process::ChildStdin) -> JoinHandle<()> {
+		tokio::spawn(async move {
+			if sink.write_all(&self.prefix).await.is_ok() {
+				let _ = tokio::io::copy(&mut tokio::io::stdin(), &mut sink).await;
+			}
+		})
+	}
+}
+
+/// ffmpeg arguments that decode `input_args` to raw RGB24 frames on stdout,
+/// scaled and retimed for `metadata`.
+fn extract_args(input_args: &[String], metadata: &VideoMetadata, extra_args: &[String]) -> Vec<String> {
+	let mut vf_scale = frame_filter(metadata);
+	if metadata.fps < metadata.source_fps {
+		vf_scale = format!("fps={},{}", metadata.fps, vf_scale);
+	}
+
+	let mut args: Vec<String> = ["-v", "error"].map(String::from).to_vec();
+	args.extend_from_slice(input_args);
+	args.extend(["-vf", &vf_scale, "-f", "rawvideo", "-pix_fmt", "rgb24", "-vsync", "0"].map(String::from));
+	args.extend_from_slice(extra_args);
+	args.push("-".to_string());
+	args
+}
+
+/// Decodes frames from `start_frame` on. Seeking uses the frame's timestamp, so
+/// it is exact for constant-frame-rate input; sequences start at the frame's number.
+/// If ffmpeg goes `frame_timeout` without producing a frame, or exits cleanly
+/// without any when the probe promised some, it is killed and an error is sent
+/// instead of leaving the receiver waiting.
+async fn extract_frames(
+	input_path: &Path,
+	sequence: Option<&ImageSequence>,
+	metadata: &VideoMetadata,
+	start_frame: u32,
+	frame_timeout: Option<std::time::Duration>,
+	stdin: Option<StdinVideo>,
+	extra_args: &[String],
+) -> SpatialResult<mpsc::Receiver<SpatialResult<Vec<u8>>>> {
+	let (tx, rx) = mpsc::channel::<SpatialResult<Vec<u8>>>(10);
+
+	let width = metadata.width;
+	let height = metadata.height;
+	let frame_size = (width * height * 3) as usize;
+
+	let input_args = match sequence {
+		Some(sequence) => {
+			// `start_frame` counts processed frames; the sequence numbers source frames.
+			let source_start = (start_frame as f64 * metadata.source_fps / metadata.fps).round() as u32;
+			sequence.input_args(metadata.source_fps, source_start)?
+		}
+		None => {
+			let input_str = match stdin {
+				Some(_) => "pipe:0",
+				None => input_path
+					.to_str()
+					.ok_or_else(|| SpatialError::Other("Invalid input path encoding".to_string()))?,
+			};
+			let mut args = Vec::new();
+			if start_frame > 0 {
+				args.push("-ss".to_string());
+				args.push(format!("{:.6}", start_frame as f64 / metadata.fps));
+			}
+			args.push("-i".to_string());
+			args.push(input_str.to_string());
+			args
+		}
+	};
+
+	let mut child = Command::new("ffmpeg")
+		.args(extract_args(&input_args, metadata, extra_args))
+		.stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::inherit() })
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.kill_on_drop(true)
+		.spawn()
+		.map_err(|e| SpatialError::Other(format!("Failed to spawn ffmpeg frame extractor: {}", e)))?;
+	let stderr = capture_stderr(&mut child);
+	if let (Some(stdin), Some(sink)) = (stdin, child.stdin.take()) {
+		stdin.feed(sink);
+	}
+
+	let stdout = child
+		.stdout
+		.take()
+		.ok_or_else(|| SpatialError::Other("Failed to capture ffmpeg stdout".to_string()))?;
+
+	let expected_frames = metadata.total_frames.saturating_sub(start_frame);
+
+	tokio::spawn(async move {
+		let mut reader = tokio::io::BufReader::new(stdout);
+		let mut frame_buffer = vec![0u8; frame_size];
+		let mut frames_read = 0u32;
+
+		loop {
+			let read = reader.read_exact(&mut frame_buffer);
+			let read = match frame_timeout {
+				Some(limit) => match tokio::time::timeout(limit, read).await {
+					Ok(read) => read,
+					Err(_) => {
+						let _ = child.kill().await;
+						let stderr = stderr.await.unwrap_or_default();
+						let _ = tx
+							.send(Err(SpatialError::Other(format!(
+								"ffmpeg frame extraction stalled: no frame within {:.0}s after {} frames; \
+								 the input may be malformed{}",
+								limit.as_secs_f64(),
+								frames_read,
+								if stderr.is_empty() { String::new() } else { format!(": {}", stderr) }
+							))))
+							.await;
+						return;
+					}
+				},
+				None => read.await,
+			};
+			match read {
+				Ok(_) => {
+					frames_read += 1;
+					if tx.send(Ok(frame_buffer.clone())).await.is_err() {
+						let _ = child.kill().await;
+						return;
+					}
+				}
+				Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+				Err(_) => break,
+			}
+		}
+
+		let failed = !child.wait().await.is_ok_and(|status| status.success());
+		if failed {
+			let stderr = stderr.await.unwrap_or_default();
+			let _ = tx
+				.send(Err(SpatialError::Other(format!(
+					"ffmpeg frame extraction exited with error: {}",
+					stderr
+				))))
+				.await;
+		} else if frames_read == 0 && expected_frames > 0 {
+			let _ = tx
+				.send(Err(SpatialError::Other(format!(
+					"ffmpeg decoded no frames, though the input reports {}; it may be empty or malformed",
+					expected_frames
+				))))
+				.await;
+		}
+	});
+
+	Ok(rx)
+}
+
+const STDERR_TAIL_BYTES: usize = 4096;
+
+/// Drains `child`'s stderr in the background and resolves to its last few KB for
+/// error messages. Reading as it's written keeps a chatty ffmpeg from stalling
+/// on a full pipe during long runs.
+fn capture_stderr(child: &mut tokio::process::Child) -> JoinHandle<String> {
+	let stderr = child.stderr.take();
+	tokio::spawn(async move {
+		let Some(mut stderr) = stderr else {
+			return String::new();
+		};
+		let mut tail = std::collections::VecDeque::with_capacity(STDERR_TAIL_BYTES);
+		let mut buf = [0u8; 4096];
+		while let Ok(n @ 1..) = stderr.read(&mut buf).await {
+			tail.extend(&buf[..n]);
+			let excess = tail.len().saturating_sub(STDERR_TAIL_BYTES);
+			tail.drain(..excess);
+		}
+		String::from_utf8_lossy(tail.make_contiguous()).trim().to_string()
+	})
+}
+
+fn frame_filter(metadata: &VideoMetadata) -> String {
+	if metadata.color.is_hdr() && has_ffmpeg_filter("zscale") {
+		format!(
+			"zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,tonemap=hable:desat=0,\
+			 zscale=t=bt709:m=bt709:r=tv,format=yuv420p,scale={}:{}",
+			metadata.width, metadata.height
+		)
+	} else {
+		format!("scale={}:{}", metadata.width, metadata.height)
+	}
+}
+
+/// Seeks to `timestamp` (anything ffmpeg's `-ss` accepts, e.g. `00:01:30` or `90.5`)
+/// and decodes a single frame.
+pub async fn extract_single_frame(input_path: &Path, timestamp: &str) -> SpatialResult<DynamicImage> {
+	if is_stdin(input_path) {
+		return Err(SpatialError::ConfigError(
+			"Can't seek to a timestamp in a video read from stdin".to_string(),
+		));
+	}
+	crate::tools::ensure_ffmpeg()?;
+
+	let mut metadata = get_video_metadata(input_path).await?;
+	metadata.width &= !1;
+	metadata.height &= !1;
+
+	let input_str = input_path
+		.to_str()
+		.ok_or_else(|| SpatialError::Other("Invalid input path encoding".to_string()))?;
+	let vf_scale = frame_filter(&metadata);
+
+	let output = Command::new("ffmpeg")
+		.args([
+			"-ss",
+			timestamp,
+			"-i",
+			input_str,
+			"-frames:v",
+			"1",
+			"-vf",
+			&vf_scale,
+			"-f",
+			"rawvideo",
+			"-pix_fmt",
+			"rgb24",
+			"-",
+		])
+		.stdin(Stdio::null())
+		.output()
+		.await
+		.map_err(|e| SpatialError::Other(format!("Failed to spawn ffmpeg frame extractor: {}", e)))?;
+
+	if !output.status.success() {
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		return Err(SpatialError::Other(format!(
+			"ffmpeg failed to extract frame at {}: {}",
+			timestamp,
+			stderr.lines().last().unwrap_or("")
+		)));
+	}
+
+	let frame_size = (metadata.width * metadata.height * 3) as usize;
+	if output.stdout.len() < frame_size {
+		return Err(SpatialError::Other(format!(
+			"No frame at {} (video is {:.1}s long)",
+			timestamp, metadata.duration
+		)));
+	}
+
+	frame_to_image(&output.stdout[..frame_size], metadata.width, metadata.height)
+}
+
+fn hash_frame(data: &[u8]) -> u64 {
+	use std::hash::{Hash, Hasher};
+	let mut hasher = std::collections::hash_map::DefaultHasher::new();
+	data.hash(&mut hasher);
+	hasher.finish()
+}
+
+fn frame_to_image(data: &[u8], width: u32, height: u32) -> SpatialResult<DynamicImage> {
+	let rgb_image = RgbImage::from_raw(width, height, data.to_vec()).ok_or_else(|| {
+		SpatialError::ImageError(format!(
+			"Failed to create image from frame data ({}x{})",
+			width, height
+		))
+	})?;
+	Ok(DynamicImage::ImageRgb8(rgb_image))
+}
+
+fn stereo_layout(output_types: &[OutputType]) -> OutputFormat {
+	if output_types.iter().any(|t| matches!(t, OutputType::TopAndBottom)) {
+		OutputFormat::TopAndBottom
+	} else {
+		OutputFormat::SideBySide
+	}
+}
+
+fn stereo_metadata_args(layout: OutputFormat, codec: VideoCodec) -> Vec<String> {
+	let (frame_packing, stereo_mode) = match layout {
+		OutputFormat::TopAndBottom => (4, "top_bottom"),
+		_ => (3, "left_right"),
+	};
+	let mut args = Vec::new();
+	if codec == VideoCodec::H264 {
+		args.push("-x264-params".to_string());
+		args.push(format!("frame-packing={}", frame_packing));
+	}
+	args.push("-metadata:s:v:0".to_string());
+	args.push(format!("stereo_mode={}", stereo_mode));
+	args
+}
+
+/// UUID of the Spherical Video V1 box players read 360° metadata from.
+const SPHERICAL_UUID: [u8; 16] = [
+	0xff, 0xcc, 0x82, 0x63, 0xf8, 0x55, 0x4a, 0x93, 0x88, 0x14, 0x58, 0x7a, 0x02, 0x52, 0x1f, 0xdd,
+];
+/// Boxes on the path from `moov` to the chunk offset tables.
+const SPHERICAL_PATH_BOXES: [&[u8; 4]; 5] = [b"moov", b"trak", b"mdia", b"minf", b"stbl"];
+
+/// Spherical Video V1 `uuid` box marking a video as a stitched 360°
+/// equirectangular panorama, with both eyes packed in `layout`.
+fn spherical_box(layout: OutputFormat) -> Vec<u8> {
+	let stereo_mode = match layout {
+		OutputFormat::TopAndBottom => "top-bottom",
+		_ => "left-right",
+	};
+	let xml = format!(
+		concat!(
+			r#"<?xml version="1.0"?><rdf:SphericalVideo"#,
+			r#" xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#""#,
+			r#" xmlns:GSpherical="http://ns.google.com/videos/1.0/spherical/">"#,
+			"<GSpherical:Spherical>true</GSpherical:Spherical>",
+			"<GSpherical:Stitched>true</GSpherical:Stitched>",
+			"<GSpherical:StitchingSoftware>spatial-maker</GSpherical:StitchingSoftware>",
+			"<GSpherical:ProjectionType>equirectangular</GSpherical:ProjectionType>",
+			"<GSpherical:StereoMode>{}</GSpherical:StereoMode>",
+			"</rdf:SphericalVideo>",
+		),
+		stereo_mode
+	);
+	let mut body = SPHERICAL_UUID.to_vec();
+	body.extend_from_slice(xml.as_bytes());
+	mp4_box(b"uuid", &body)
+}
+
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+	let mut out = Vec::with_capacity(body.len() + 16);
+	match u32::try_from(body.len() + 8) {
+		Ok(size) => out.extend_from_slice(&size.to_be_bytes()),
+		Err(_) => {
+			out.extend_from_slice(&1u32.to_be_bytes());
+			out.extend_from_slice(kind);
+			out.extend_from_slice(&(body.len() as u64 + 16).to_be_bytes());
+			out.extend_from_slice(body);
+			return out;
+		}
+	}
+	out.extend_from_slice(kind);
+	out.extend_from_slice(body);
+	out
+}
+
+/// An MP4 box as (type, header length, whole box).
+type Mp4Box<'a> = ([u8; 4], usize, &'a [u8]);
+
+/// Splits `data` into its boxes.
+fn mp4_boxes(data: &[u8]) -> SpatialResult<Vec<Mp4Box<'_>>> {
+	let invalid = || SpatialError::Other("Malformed MP4 box structure".to_string());
+	let mut boxes = Vec::new();
+	let mut rest = data;
+	while !rest.is_empty() {
+		let header = rest.get(..8).ok_or_else(invalid)?;
+		let kind: [u8; 4] = header[4..8].try_into().unwrap();
+		let (size, header_len) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+			0 => (rest.len() as u64, 8),
+			1 => (u64::from_be_bytes(rest.get(8..16).ok_or_else(invalid)?.try_into().unwrap()), 16),
+			size => (size as u64, 8),
+		};
+		let size = usize::try_from(size).ok().filter(|&n| n >= header_len && n <= rest.len()).ok_or_else(invalid)?;
+		boxes.push((kind, header_len, &rest[..size]));
+		rest = &rest[size..];
+	}
+	Ok(boxes)
+}
+
+/// Rebuilds a box under `moov`, appending `spherical` to the video track and
+/// adding `shift` to chunk offsets at or past `shift_from`.
+fn rewrite_moov_box(
+	kind: [u8; 4],
+	header_len: usize,
+	whole: &[u8],
+	spherical: &[u8],
+	shift: u64,
+	shift_from: u64,
+) -> SpatialResult<Vec<u8>> {
+	let body = &whole[header_len..];
+	match &kind {
+		b"stco" | b"co64" if shift > 0 => {
+			let wide = &kind == b"co64";
+			let width = if wide { 8 } else { 4 };
+			let mut body = body.to_vec();
+			let entries = body.get_mut(8..).unwrap_or_default();
+			for entry in entries.chunks_exact_mut(width) {
+				let offset = if wide {
+					u64::from_be_bytes(entry.try_into().unwrap())
+				} else {
+					u32::from_be_bytes(entry.try_into().unwrap()) as u64
+				};
+				if offset < shift_from {
+					continue;
+				}
+				if wide {
+					entry.copy_from_slice(&(offset + shift).to_be_bytes());
+				} else {
+					let shifted = u32::try_from(offset + shift).map_err(|_| {
+						SpatialError::Other("Chunk offset overflow while adding 360 metadata".to_string())
+					})?;
+					entry.copy_from_slice(&shifted.to_be_bytes());
+				}
+			}
+			Ok(mp4_box(&kind, &body))
+		}
+		k if SPHERICAL_PATH_BOXES.contains(&k) => {
+			let children = mp4_boxes(body)?;
+			let is_video_track = &kind == b"trak"
+				&& children.iter().any(|(kind, header_len, mdia)| {
+					kind == b"mdia"
+						&& mp4_boxes(&mdia[*header_len..]).is_ok_and(|boxes| {
+							boxes.iter().any(|(kind, header_len, hdlr)| {
+								kind == b"hdlr" && hdlr.get(header_len + 8..header_len + 12) == Some(b"vide")
+							})
+						})
+				});
+			let mut out = Vec::with_capacity(body.len() + spherical.len());
+			for (kind, header_len, child) in children {
+				out.extend(rewrite_moov_box(kind, header_len, child, spherical, shift, shift_from)?);
+			}
+			if is_video_track {
+				out.extend_from_slice(spherical);
+			}
+			Ok(mp4_box(&kind, &out))
+		}
+		_ => Ok(whole.to_vec()),
+	}
+}
+
+/// Marks the MP4/MOV at `path` as a 360° equirectangular video with both eyes
+/// packed in `layout`, by adding a Spherical Video V1 box to its video track.
+/// ffmpeg has no option to write this itself.
+fn inject_spherical_metadata(path: &Path, layout: OutputFormat) -> SpatialResult<()> {
+	use std::io::{Read as _, Seek as _, SeekFrom, Write as _};
+	let io_error = |e: std::io::Error| SpatialError::IoError(format!("Failed to add 360 metadata to {:?}: {}", path, e));
+
+	let mut file = std::fs::File::open(path).map_err(io_error)?;
+	let len = file.metadata().map_err(io_error)?.len();
+	let mut moov = None;
+	let mut offset = 0u64;
+	while offset + 8 <= len {
+		let mut header = [0u8; 16];
+		file.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+		file.read_exact(&mut header[..8]).map_err(io_error)?;
+		let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+			0 => len - offset,
+			1 => {
+				file.read_exact(&mut header[8..]).map_err(io_error)?;
+				u64::from_be_bytes(header[8..].try_into().unwrap())
+			}
+			size => size as u64,
+		};
+		if size < 8 {
+			break;
+		}
+		if &header[4..8] == b"moov" {
+			moov = Some((offset, size));
+		}
+		offset += size;
+	}
+	let (moov_offset, moov_size) =
+		moov.ok_or_else(|| SpatialError::Other(format!("No moov box in {:?}", path)))?;
+
+	let mut moov_data = vec![0u8; moov_size as usize];
+	file.seek(SeekFrom::Start(moov_offset)).map_err(io_error)?;
+	file.read_exact(&mut moov_data).map_err(io_error)?;
+	let (kind, header_len, whole) = mp4_boxes(&moov_data)?.remove(0);
+	let spherical = spherical_box(layout);
+	// Media after moov (faststart files) moves by the size of the added box.
+	let grown = rewrite_moov_box(kind, header_len, whole, &spherical, 0, 0)?.len() as u64 - moov_size;
+	let moov_end = moov_offset + moov_size;
+	let new_moov = rewrite_moov_box(kind, header_len, whole, &spherical, grown, moov_end)?;
+
+	let parent = path.parent().unwrap_or_else(|| Path::new("."));
+	let mut rewritten = crate::temp::temp_file_in(parent, ".mov")?;
+	file.seek(SeekFrom::Start(0)).map_err(io_error)?;
+	std::io::copy(&mut (&mut file).take(moov_offset), rewritten.as_file_mut()).map_err(io_error)?;
+	rewritten.write_all(&new_moov).map_err(io_error)?;
+	file.seek(SeekFrom::Start(moov_end)).map_err(io_error)?;
+	std::io::copy(&mut file, rewritten.as_file_mut()).map_err(io_error)?;
+	drop(file);
+	rewritten
+		.persist(path)
+		.map_err(|e| SpatialError::IoError(format!("Failed to replace {:?}: {}", path, e.error)))?;
+	Ok(())
+}
+
+/// Packs a stereo pair into one raw RGB24 frame in `layout`, each eye cropped
+/// to `width`x`height`. Rows are copied whole rather than pixel by pixel.
+fn pack_stereo_frame(
+	left: DynamicImage,
+	right: DynamicImage,
+	width: u32,
+	height: u32,
+	layout: OutputFormat,
+) -> Vec<u8> {
+	let (left, right) = (left.into_rgb8(), right.into_rgb8());
+	let row = width as usize * 3;
+	let mut frame = Vec::with_capacity(row * height as usize * 2);
+	fn eye_rows(eye: &RgbImage, row: usize, height: u32) -> impl Iterator<Item = &[u8]> {
+		let stride = eye.width() as usize * 3;
+		eye.as_raw().chunks(stride).take(height as usize).map(move |r| &r[..row])
+	}
+	let (left_rows, right_rows) = (eye_rows(&left, row, height), eye_rows(&right, row, height));
+	if layout == OutputFormat::TopAndBottom {
+		left_rows.chain(right_rows).for_each(|r| frame.extend_from_slice(r));
+	} else {
+		for (l, r) in left_rows.zip(right_rows) {
+			frame.extend_from_slice(l);
+			frame.extend_from_slice(r);
+		}
+	}
+	frame
+}
+
+/// ffmpeg arguments, up to the output path, that encode raw RGB24 stereo
+/// frames packed in `layout` from stdin.
+fn stereo_encoder_args(
+	metadata: &VideoMetadata,
+	layout: OutputFormat,
+	codec: VideoCodec,
+	extra_args: &[String],
+) -> Vec<String> {
+	let (output_width, output_height) = match layout {
+		OutputFormat::TopAndBottom => (metadata.width, metadata.height * 2),
+		_ => (metadata.width * 2, metadata.height),
+	};
+
+	let mut args: Vec<String> = ["-v", "error", "-f", "rawvideo", "-pix_fmt", "rgb24"].map(String::from).to_vec();
+	args.extend([
+		"-s".to_string(),
+		format!("{}x{}", output_width, output_height),
+		"-r".to_string(),
+		format!("{}", metadata.fps),
+		"-i".to_string(),
+		"-".to_string(),
+	]);
+	args.extend(codec.encoder_args().iter().map(|arg| arg.to_string()));
+	args.extend(BT709_OUTPUT_ARGS.map(String::from));
+	args.extend(stereo_metadata_args(layout, codec));
+	args.extend(metadata.output_rate_args());
+	args.push("-y".to_string());
+	args.extend_from_slice(extra_args);
+	args
+}
+
+/// Encodes raw RGB24 frames already packed by `pack_stereo_frame`.
+async fn encode_stereo_video(
+	output_path: std::path::PathBuf,
+	metadata: VideoMetadata,
+	layout: OutputFormat,
+	codec: VideoCodec,
+	extra_args: Vec<String>,
+	mut rx: mpsc::Receiver<Vec<u8>>,
+) -> SpatialResult<()> {
+	let mut child = Command::new("ffmpeg")
+		.args(stereo_encoder_args(&metadata, layout, codec, &extra_args))
+		.arg(&output_path)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::null())
+		.stderr(Stdio::piped())
+		.kill_on_drop(true)
+		.spawn()
+		.map_err(|e| SpatialError::Other(format!("Failed to spawn ffmpeg encoder: {}", e)))?;
+	let stderr = capture_stderr(&mut child);
+
+	let mut stdin = child
+		.stdin
+		.take()
+		.ok_or_else(|| SpatialError::Other("Failed to capture ffmpeg encoder stdin".to_string()))?;
+
+	while let Some(frame) = rx.recv().await {
+		if let Err(e) = stdin.write_all(&frame).await {
+			// A broken pipe means ffmpeg quit; its stderr says why.
+			let _ = child.wait().await;
+			let stderr = stderr.await.unwrap_or_default();
+			return Err(SpatialError::IoError(format!("Failed to write frame: {}: {}", e, stderr)));
+		}
+	}
+
+	drop(stdin);
+
+	let status = child
+		.wait()
+		.await
+		.map_err(|e| SpatialError::Other(format!("ffmpeg encoding failed: {}", e)))?;
+
+	if !status.success() {
+		let stderr = stderr.await.unwrap_or_default();
+		return Err(SpatialError::Other(format!(
+			"ffmpeg encoding exited with error: {}",
+			stderr
+		)));
+	}
+
+	Ok(())
+}
+
+/// Share of a size target left for the streams after container overhead.
+const TARGET_SIZE_OVERHEAD: f64 = 0.97;
+const MIN_TARGET_KBPS: u32 = 100;
+/// Audio bitrate budgeted for when the source's can't be probed (some
+/// containers don't record it per stream).
+const FALLBACK_AUDIO_KBPS: u32 = 192;
+
+/// Bitrate of the first audio stream of `path` in kbps, if ffprobe reports one.
+async fn probe_audio_kbps(path: &Path) -> Option<u32> {
+	let output = Command::new("ffprobe")
+		.args(["-v", "error", "-select_streams", "a:0", "-show_entries", "stream=bit_rate", "-of", "csv=p=0"])
+		.arg(path)
+		.output()
+		.await
+		.ok()?;
+	let bits: u64 = String::from_utf8_lossy(&output.stdout).trim().parse().ok()?;
+	Some((bits / 1000) as u32)
+}
+
+/// Re-encodes the finished stereo video at the average bitrate that fits
+/// `size_mb` over its duration, in two passes where the codec supports it. The
+/// source's audio, when it has any, is copied in unchanged and its bitrate comes
+/// out of the budget. Rate control is approximate, so the result typically lands
+/// within a few percent of the target rather than exactly on it.
+async fn encode_to_target_size(
+	input_path: &Path,
+	output_path: &Path,
+	source_path: &Path,
+	metadata: &VideoMetadata,
+	codec: VideoCodec,
+	layout: OutputFormat,
+	size_mb: f64,
+) -> SpatialResult<()> {
+	let duration = if metadata.duration > 0.0 {
+		metadata.duration
+	} else {
+		metadata.total_frames as f64 / metadata.fps
+	};
+	let audio_kbps = if metadata.has_audio {
+		probe_audio_kbps(source_path).await.unwrap_or(FALLBACK_AUDIO_KBPS)
+	} else {
+		0
+	};
+	let total_kbps = size_mb * 8.0 * 1000.0 * TARGET_SIZE_OVERHEAD / duration.max(1e-3);
+	let kbps = (total_kbps - audio_kbps as f64).max(0.0) as u32;
+	if kbps < MIN_TARGET_KBPS {
+		return Err(SpatialError::ConfigError(format!(
+			"Target size {} MB is too small for {:.0}s of video ({} kbps after {} kbps of audio; need at least {})",
+			size_mb, duration, kbps, audio_kbps, MIN_TARGET_KBPS
+		)));
+	}
+
+	let stats = crate::temp::temp_file(".passlog")?.into_temp_path();
+	let stats_str = stats
+		.to_str()
+		.ok_or_else(|| SpatialError::Other("Invalid temp path encoding".to_string()))?;
+	let passes: &[u8] = if codec.is_two_pass() { &[1, 2] } else { &[2] };
+
+	for &pass in passes {
+		let args = codec.bitrate_args(kbps, pass, stats_str).ok_or_else(|| {
+			SpatialError::ConfigError(format!("Target size isn't supported for {}", codec))
+		})?;
+		let mut command = Command::new("ffmpeg");
+		command.args(["-v", "error", "-y", "-i"]).arg(input_path);
+		if pass == 2 && metadata.has_audio {
+			command
+				.arg("-i")
+				.arg(source_path)
+				.args(["-map", "0:v:0", "-map", "1:a:0", "-c:a", "copy"]);
+		}
+		command.args(&args);
+		if pass == 1 {
+			command.args(["-an", "-f", "null", "-"]);
+		} else {
+			command
+				.args(BT709_OUTPUT_ARGS)
+				.args(stereo_metadata_args(layout, codec))
+				.arg(output_path);
+		}
+
+		let output = command
+			.stdin(Stdio::null())
+			.kill_on_drop(true)
+			.output()
+			.await
+			.map_err(|e| SpatialError::Other(format!("Failed to run ffmpeg: {}", e)))?;
+		if !output.status.success() {
+			let stderr = String::from_utf8_lossy(&output.stderr);
+			return Err(SpatialError::Other(format!(
+				"ffmpeg target-size pass {} exited with error: {}",
+				pass,
+				stderr.trim()
+			)));
+		}
+	}
+
+	Ok(())
+}
+
+async fn encode_depth_video(
+	output_path: std::path::PathBuf,
+	metadata: VideoMetadata,
+	format: DepthFormat,
+	mut rx: mpsc::Receiver<Array2<f32>>,
+) -> SpatialResult<()> {
+	let width = metadata.width;
+	let height = metadata.height;
+	let fps = metadata.fps;
+	let high_bit_depth = matches!(format, DepthFormat::Png16);
+	let input_pix_fmt = match format {
+		DepthFormat::Png16 => "gray16le",
+		DepthFormat::Colormap => "rgb24",
+		DepthFormat::Avif | DepthFormat::Png => "gray",
+	};
+
+	let mut command = Command::new("ffmpeg");
+	command.args([
+		"-v", "error",
+		"-f", "rawvideo",
+		"-pix_fmt", input_pix_fmt,
+		"-s", &format!("{}x{}", width, height),
+		"-r", &format!("{}", fps),
+		"-i", "-",
+	]);
+	if format != DepthFormat::Avif {
+		let output_pix_fmt = if format == DepthFormat::Colormap { "gbrp" } else { input_pix_fmt };
+		command.args(["-c:v", "ffv1", "-pix_fmt", output_pix_fmt]);
+	} else {
+		command
+			.args(["-c:v", "libsvtav1", "-crf", "23", "-pix_fmt", "yuv420p"])
+			.args(BT709_OUTPUT_ARGS);
+	}
+
+	let mut child = command
+		.args(metadata.output_rate_args())
+		.arg("-y")
+		.arg(&output_path)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::null())
+		.stderr(Stdio::piped())
+		.kill_on_drop(true)
+		.spawn()
+		.map_err(|e| SpatialError::Other(format!("Failed to spawn ffmpeg depth encoder: {}", e)))?;
+	let stderr = capture_stderr(&mut child);
+
+	let mut stdin = child
+		.stdin
+		.take()
+		.ok_or_else(|| SpatialError::Other("Failed to capture ffmpeg depth encoder stdin".to_string()))?;
+
+	while let Some(depth) = rx.recv().await {
+		let pixels: Vec<u8> = if format == DepthFormat::Colormap {
+			crate::output::colorize_depth(&depth).into_rgb8().into_raw()
+		} else {
+			let mut min_val = f32::INFINITY;
+			let mut max_val = f32::NEG_INFINITY;
+			for &v in depth.iter() {
+				if v < min_val { min_val = v; }
+				if v > max_val { max_val = v; }
+			}
+			let range = max_val - min_val;
+			let scaled = depth.iter().map(|&v| if range > 1e-6 { (v - min_val) / range } else { 0.5 });
+
+			if high_bit_depth {
+				scaled
+					.flat_map(|v| ((v * 65535.0).round() as u16).to_le_bytes())
+					.collect()
+			} else {
+				scaled.map(|v| (v * 255.0).round() as u8).collect()
+			}
+		};
+
+		if let Err(e) = stdin.write_all(&pixels).await {
+			let _ = child.wait().await;
+			let stderr = stderr.await.unwrap_or_default();
+			return Err(SpatialError::IoError(format!("Failed to write depth frame: {}: {}", e, stderr)));
+		}
+	}
+
+	drop(stdin);
+
+	let status = child
+		.wait()
+		.await
+		.map_err(|e| SpatialError::Other(format!("ffmpeg depth encoding failed: {}", e)))?;
+
+	if !status.success() {
+		let stderr = stderr.await.unwrap_or_default();
+		return Err(SpatialError::Other(format!(
+			"ffmpeg depth encoding exited with error: {}", stderr
+		)));
+	}
+
+	Ok(())
+}
+
+fn is_spatial_cli_available() -> bool {
+	crate::tools::check_spatial_cli(Path::new("spatial")).is_ok()
+}
+
+async fn encode_mvhevc_video(
+	sbs_path: &Path,
+	output_path: &Path,
+	input_path: &Path,
+	metadata: &VideoMetadata,
+	layout: OutputFormat,
+) -> SpatialResult<()> {
+	let sbs_str = sbs_path.to_str()
+		.ok_or_else(|| SpatialError::Other("Invalid SBS path".to_string()))?;
+	let output_str = output_path.to_str()
+		.ok_or_else(|| SpatialError::Other("Invalid output path".to_string()))?;
+
+	let mut args = vec![
+		"make",
+		"--input", sbs_str,
+		"--output", output_str,
+		"--format", if layout == OutputFormat::TopAndBottom { "hou" } else { "sbs" },
+		"--cdist", "65",
+		"--hfov", "90",
+		"--hadjust", "0",
+		"--projection", "rect",
+		"--overwrite",
+	];
+
+	if !metadata.has_audio {
+		args.push("--no-audio");
+	}
+
+	let output = Command::new("spatial")
+		.args(&args)
+		.kill_on_drop(true)
+		.output()
+		.await
+		.map_err(|e| SpatialError::Other(format!("Failed to run spatial CLI: {}", e)))?;
+
+	if !output.status.success() {
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		return Err(SpatialError::Other(format!("spatial make failed: {}", stderr)));
+	}
+
+	if metadata.has_audio {
+		let input_str = input_path.to_str()
+			.ok_or_else(|| SpatialError::Other("Invalid input path".to_string()))?;
 
-		stdin
-			.write_all(&pixels)
+		let with_audio_path = output_path.with_extension("tmp.mov");
+		let with_audio_str = with_audio_path.to_str()
+			.ok_or_else(|| SpatialError::Other("Invalid temp path".to_string()))?;
+
+		let mux_output = Command::new("ffmpeg")
+			.args([
+				"-i", output_str,
+				"-i", input_str,
+				"-c:v", "copy",
+				"-c:a", "aac",
+				"-map", "0:v:0",
+				"-map", "1:a:0",
+				"-y", with_audio_str,
+			])
+			.kill_on_drop(true)
+			.output()
 			.await
-			.map_err(|e| SpatialError::IoError(format!("Failed to write depth frame: {}", e)))?;
+			.map_err(|e| SpatialError::Other(format!("Failed to mux audio: {}", e)))?;
+
+		if mux_output.status.success() {
+			let _ = tokio::fs::remove_file(output_path).await;
+			tokio::fs::rename(&with_audio_path, output_path).await
+				.map_err(|e| SpatialError::IoError(format!("Failed to rename muxed file: {}", e)))?;
+		}
+	}
+
+	Ok(())
+}
+
+const CHUNK_CHECKPOINT_FILE: &str = "progress.json";
+const CHUNK_STATE_FILE: &str = "depth-state.bin";
+
+/// Progress of a chunked run (`SpatialConfig::chunk_frames`), rewritten after
+/// every finished chunk so `SpatialConfig::resume` can pick up from there.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ChunkCheckpoint {
+	input: PathBuf,
+	total_frames: u32,
+	chunk_frames: u32,
+	outputs: Vec<PathBuf>,
+	/// Settings the finished stereo chunks were warped with; absent for depth-only runs.
+	#[serde(default)]
+	stereo: Option<StereoParams>,
+	completed_chunks: u32,
+}
+
+impl ChunkCheckpoint {
+	fn load(dir: &Path) -> Option<Self> {
+		let data = std::fs::read(dir.join(CHUNK_CHECKPOINT_FILE)).ok()?;
+		serde_json::from_slice(&data).ok()
+	}
+
+	fn save(&self, dir: &Path) -> SpatialResult<()> {
+		let json = serde_json::to_vec_pretty(self)
+			.map_err(|e| SpatialError::Other(format!("Failed to serialize checkpoint: {}", e)))?;
+		std::fs::write(dir.join(CHUNK_CHECKPOINT_FILE), json)?;
+		Ok(())
+	}
+
+	/// Same input, chunking, outputs and stereo settings, so the finished chunks
+	/// can be reused.
+	fn can_resume(&self, other: &Self) -> bool {
+		self.input == other.input
+			&& self.total_frames == other.total_frames
+			&& self.chunk_frames == other.chunk_frames
+			&& self.outputs == other.outputs
+			&& self.stereo == other.stereo
+	}
+}
+
+/// Where chunk `index` of `output` is written before the chunks are joined.
+fn segment_path(chunk_dir: &Path, index: u32, output: &Path) -> PathBuf {
+	let name = output.file_name().and_then(|n| n.to_str()).unwrap_or("output");
+	chunk_dir.join(format!("{:05}-{}", index, name))
+}
+
+/// Joins `segments` into `output_path` without re-encoding.
+async fn concat_segments(segments: &[PathBuf], output_path: &Path) -> SpatialResult<()> {
+	let mut list = String::new();
+	for segment in segments {
+		let path = std::fs::canonicalize(segment)?;
+		list.push_str(&format!("file '{}'\n", path.to_string_lossy().replace('\'', "'\\''")));
+	}
+	let list_file = crate::temp::temp_file(".txt")?;
+	std::fs::write(list_file.path(), list)?;
+
+	let output = Command::new("ffmpeg")
+		.args(["-f", "concat", "-safe", "0", "-i"])
+		.arg(list_file.path())
+		.args(["-c", "copy", "-y"])
+		.arg(output_path)
+		.kill_on_drop(true)
+		.output()
+		.await
+		.map_err(|e| SpatialError::Other(format!("Failed to run ffmpeg concat: {}", e)))?;
+
+	if !output.status.success() {
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		return Err(SpatialError::Other(format!("Failed to join chunks: {}", stderr)));
+	}
+	Ok(())
+}
+
+/// The encoders for one run of frames: the whole video, or one chunk of it.
+struct ChunkEncoders {
+	stereo_tx: Option<(mpsc::Sender<Vec<u8>>, OutputFormat)>,
+	size: (u32, u32),
+	depth_txs: Vec<mpsc::Sender<Array2<f32>>>,
+	handles: Vec<TaskGuard<SpatialResult<()>>>,
+}
+
+impl ChunkEncoders {
+	fn spawn(
+		stereo: Option<(PathBuf, OutputFormat, VideoCodec)>,
+		depth: Vec<(PathBuf, DepthFormat)>,
+		metadata: &VideoMetadata,
+		encoder_args: &[String],
+	) -> Self {
+		let mut handles = Vec::new();
+
+		let stereo_tx = stereo.map(|(path, layout, codec)| {
+			let (tx, rx) = mpsc::channel::<Vec<u8>>(10);
+			handles.push(TaskGuard(tokio::spawn(encode_stereo_video(
+				path,
+				metadata.clone(),
+				layout,
+				codec,
+				encoder_args.to_vec(),
+				rx,
+			))));
+			(tx, layout)
+		});
+
+		let depth_txs = depth
+			.into_iter()
+			.map(|(path, fmt)| {
+				let (tx, rx) = mpsc::channel::<Array2<f32>>(10);
+				handles.push(TaskGuard(tokio::spawn(encode_depth_video(
+					path,
+					metadata.clone(),
+					fmt,
+					rx,
+				))));
+				tx
+			})
+			.collect();
+
+		Self {
+			stereo_tx,
+			size: (metadata.width, metadata.height),
+			depth_txs,
+			handles,
+		}
+	}
+
+	async fn send(&self, depth_map: &Array2<f32>, pair: Option<StereoPair>) -> SpatialResult<()> {
+		for depth_tx in &self.depth_txs {
+			if depth_tx.send(depth_map.clone()).await.is_err() {
+				return Err(SpatialError::Other(
+					"Depth encoder stopped unexpectedly".to_string(),
+				));
+			}
+		}
+
+		if let (Some((stereo_tx, layout)), Some((left, right))) = (&self.stereo_tx, pair) {
+			let (width, height) = self.size;
+			let frame = pack_stereo_frame(left, right, width, height, *layout);
+			if stereo_tx.send(frame).await.is_err() {
+				return Err(SpatialError::Other(
+					"Encoder stopped unexpectedly".to_string(),
+				));
+			}
+		}
+		Ok(())
+	}
+
+	/// Closes the inputs and waits for every encoder to finish writing.
+	async fn finish(self) -> SpatialResult<()> {
+		let Self { stereo_tx, depth_txs, handles, .. } = self;
+		drop(stereo_tx);
+		drop(depth_txs);
+
+		for mut handle in handles {
+			(&mut handle.0)
+				.await
+				.map_err(|e| SpatialError::Other(format!("Encoding task failed: {}", e)))??;
+		}
+		Ok(())
+	}
+}
+
+/// Output files a video job has started writing, removed if it is cancelled.
+type CreatedOutputs = Arc<Mutex<Vec<PathBuf>>>;
+
+/// Aborts the wrapped task when dropped, so dropping the pipeline also stops its
+/// encoders (whose ffmpeg children are then killed via `kill_on_drop`).
+struct TaskGuard<T>(JoinHandle<T>);
+
+impl<T> Drop for TaskGuard<T> {
+	fn drop(&mut self) {
+		self.0.abort();
+	}
+}
+
+/// Handle to a video conversion started with [`start_video_job`].
+///
+/// Yields [`VideoProgress`] updates as a `Stream` until the job finishes; call
+/// [`VideoJob::wait`] for the result. Dropping the handle cancels the job.
+pub struct VideoJob {
+	progress: mpsc::UnboundedReceiver<VideoProgress>,
+	cancel: watch::Sender<bool>,
+	handle: Option<JoinHandle<SpatialResult<ProcessVideoOutput>>>,
+}
+
+/// Result of a finished video conversion.
+#[derive(Debug, Default)]
+pub struct ProcessVideoOutput {
+	/// Non-fatal issues encountered during the conversion.
+	pub warnings: Vec<Warning>,
+}
+
+impl VideoJob {
+	/// Stops the job: kills its ffmpeg/spatial processes and removes the outputs it
+	/// had started writing. `wait` then returns `SpatialError::Cancelled`.
+	pub fn cancel(&self) {
+		let _ = self.cancel.send(true);
+	}
+
+	pub async fn wait(self) -> SpatialResult<()> {
+		self.wait_with_warnings().await.map(|_| ())
 	}
 
-	drop(stdin);
+	/// `wait`, also returning the job's non-fatal warnings.
+	pub async fn wait_with_warnings(mut self) -> SpatialResult<ProcessVideoOutput> {
+		match self.handle.take() {
+			Some(handle) => handle
+				.await
+				.map_err(|e| SpatialError::Other(format!("Video job failed: {}", e)))?,
+			None => Ok(ProcessVideoOutput::default()),
+		}
+	}
+}
 
-	let output = child
-		.wait_with_output()
-		.await
-		.map_err(|e| SpatialError::Other(format!("ffmpeg depth encoding failed: {}", e)))?;
+impl futures_util::Stream for VideoJob {
+	type Item = VideoProgress;
 
-	if !output.status.success() {
-		let stderr = String::from_utf8_lossy(&output.stderr);
-		return Err(SpatialError::Other(format!(
-			"ffmpeg depth encoding exited with error: {}", stderr
-		)));
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<VideoProgress>> {
+		self.progress.poll_recv(cx)
 	}
-
-	Ok(())
 }
 
-fn is_spatial_cli_available() -> bool {
-	std::process::Command::new("spatial")
-		.arg("--version")
-		.output()
-		.map(|o| o.status.success())
-		.unwrap_or(false)
+impl Drop for VideoJob {
+	fn drop(&mut self) {
+		let _ = self.cancel.send(true);
+	}
 }
 
-async fn encode_mvhevc_video(
-	sbs_path: &Path,
-	output_path: &Path,
+/// Starts converting a video on the current tokio runtime and returns a handle to
+/// follow or cancel it.
+pub fn start_video_job(
 	input_path: &Path,
-	metadata: &VideoMetadata,
-) -> SpatialResult<()> {
-	let sbs_str = sbs_path.to_str()
-		.ok_or_else(|| SpatialError::Other("Invalid SBS path".to_string()))?;
-	let output_str = output_path.to_str()
-		.ok_or_else(|| SpatialError::Other("Invalid output path".to_string()))?;
+	output_path: &Path,
+	config: SpatialConfig,
+	output_types: &[OutputType],
+	force: bool,
+) -> VideoJob {
+	let (progress_tx, progress) = mpsc::unbounded_channel();
+	let (cancel, mut cancel_rx) = watch::channel(false);
+	let input_path = input_path.to_path_buf();
+	let output_path = output_path.to_path_buf();
+	let output_types = output_types.to_vec();
+
+	let handle = tokio::spawn(async move {
+		let created = CreatedOutputs::default();
+		let progress_cb: ProgressCallback = Box::new(move |p| {
+			let _ = progress_tx.send(p);
+		});
+
+		tokio::select! {
+			result = run_video(&input_path, &output_path, config, &output_types, Some(progress_cb), force, &created) => {
+				return result;
+			}
+			_ = cancel_rx.wait_for(|cancelled| *cancelled) => {}
+		}
 
-	let mut args = vec![
-		"make",
-		"--input", sbs_str,
-		"--output", output_str,
-		"--format", "sbs",
-		"--cdist", "65",
-		"--hfov", "90",
-		"--hadjust", "0",
-		"--projection", "rect",
-		"--overwrite",
-	];
+		let paths = created.lock().map(|c| c.clone()).unwrap_or_default();
+		for path in paths {
+			let _ = tokio::fs::remove_file(&path).await;
+		}
+		Err(SpatialError::Cancelled)
+	});
 
-	if !metadata.has_audio {
-		args.push("--no-audio");
+	VideoJob {
+		progress,
+		cancel,
+		handle: Some(handle),
 	}
+}
 
-	let output = Command::new("spatial")
-		.args(&args)
-		.output()
+/// Converts the video, image sequence or, with `STDIN_PATH`, piped video at
+/// `input_path`. A piped video is read once, as it arrives: it must be in a
+/// streamable container (MPEG-TS, Matroska, fragmented MP4 and the like), can't
+/// use `NormalizeMode::Global` or chunked resume, keeps no audio, and reports
+/// progress without a total when the stream doesn't announce its length.
+pub async fn process_video(
+	input_path: &Path,
+	output_path: &Path,
+	config: SpatialConfig,
+	output_types: &[OutputType],
+	progress_cb: Option<ProgressCallback>,
+	force: bool,
+) -> SpatialResult<()> {
+	process_video_with_warnings(input_path, output_path, config, output_types, progress_cb, force)
 		.await
-		.map_err(|e| SpatialError::Other(format!("Failed to run spatial CLI: {}", e)))?;
+		.map(|_| ())
+}
 
-	if !output.status.success() {
-		let stderr = String::from_utf8_lossy(&output.stderr);
-		return Err(SpatialError::Other(format!("spatial make failed: {}", stderr)));
+/// `process_video`, also returning the conversion's non-fatal warnings.
+pub async fn process_video_with_warnings(
+	input_path: &Path,
+	output_path: &Path,
+	config: SpatialConfig,
+	output_types: &[OutputType],
+	progress_cb: Option<ProgressCallback>,
+	force: bool,
+) -> SpatialResult<ProcessVideoOutput> {
+	let mut job = start_video_job(input_path, output_path, config, output_types, force);
+	while let Some(progress) = job.next().await {
+		if let Some(ref cb) = progress_cb {
+			cb(progress);
+		}
 	}
+	job.wait_with_warnings().await
+}
 
-	if metadata.has_audio {
-		let input_str = input_path.to_str()
-			.ok_or_else(|| SpatialError::Other("Invalid input path".to_string()))?;
-
-		let with_audio_path = output_path.with_extension("tmp.mov");
-		let with_audio_str = with_audio_path.to_str()
-			.ok_or_else(|| SpatialError::Other("Invalid temp path".to_string()))?;
-
-		let mux_output = Command::new("ffmpeg")
-			.args([
-				"-i", output_str,
-				"-i", input_str,
-				"-c:v", "copy",
-				"-c:a", "aac",
-				"-map", "0:v:0",
-				"-map", "1:a:0",
-				"-y", with_audio_str,
-			])
-			.output()
-			.await
-			.map_err(|e| SpatialError::Other(format!("Failed to mux audio: {}", e)))?;
+/// Stands in for the depth estimator when no backend is compiled in. It can't be
+/// constructed, so a video fails once, before any frames are extracted.
+#[cfg(not(any(all(target_os = "macos", feature = "coreml"), feature = "onnx")))]
+enum NoDepthBackend {}
 
-		if mux_output.status.success() {
-			let _ = tokio::fs::remove_file(output_path).await;
-			tokio::fs::rename(&with_audio_path, output_path).await
-				.map_err(|e| SpatialError::IoError(format!("Failed to rename muxed file: {}", e)))?;
-		}
+#[cfg(not(any(all(target_os = "macos", feature = "coreml"), feature = "onnx")))]
+impl NoDepthBackend {
+	fn new() -> SpatialResult<Self> {
+		Err(SpatialError::ConfigError(
+			"No depth backend enabled. Enable 'coreml' or 'onnx' feature.".to_string(),
+		))
 	}
 
-	Ok(())
+	fn estimate_raw_into(&self, _image: &DynamicImage, _out: &mut Array2<f32>) -> SpatialResult<()> {
+		match *self {}
+	}
 }
 
-pub async fn process_video(
+async fn run_video(
 	input_path: &Path,
 	output_path: &Path,
 	config: SpatialConfig,
 	output_types: &[OutputType],
 	progress_cb: Option<ProgressCallback>,
 	force: bool,
-) -> SpatialResult<()> {
-	if !input_path.exists() {
+	created: &CreatedOutputs,
+) -> SpatialResult<ProcessVideoOutput> {
+	let mut result = ProcessVideoOutput::default();
+	let from_stdin = is_stdin(input_path);
+	let sequence = if from_stdin { None } else { ImageSequence::detect(input_path)? };
+	if sequence.is_none() && !from_stdin && !input_path.exists() {
 		return Err(SpatialError::IoError(format!(
 			"Input file not found: {:?}",
 			input_path
 		)));
 	}
 
+	crate::tools::ensure_ffmpeg()?;
+
+	if from_stdin && matches!(config.normalize_mode, NormalizeMode::Global) {
+		return Err(SpatialError::ConfigError(
+			"Global normalization reads the video twice, which isn't possible from stdin; use running normalization instead"
+				.to_string(),
+		));
+	}
+
 	let do_depth = needs_depth(output_types);
-	let do_stereo = needs_stereo(output_types);
+	let do_stereo = !stereo_types(output_types).is_empty();
+	let photo_only = [
+		(needs_stereo_clip(output_types), "stereo clip"),
+		(needs_disparity_mask(output_types), "disparity mask"),
+		(crate::output::needs_aux_depth(output_types), "auxiliary depth"),
+		(needs_normals(output_types), "normal map"),
+		(needs_views(output_types), "multi-view"),
+		(needs_preview(output_types), "preview sheet"),
+	];
+	for (_, output) in photo_only.iter().filter(|(requested, _)| *requested) {
+		result.warnings.push(Warning::OutputSkipped {
+			output: output.to_string(),
+			reason: "only supported for photos".to_string(),
+		});
+	}
+	if config.fusion {
+		result.warnings.push(Warning::OptionIgnored {
+			option: "multi-resolution depth fusion".to_string(),
+			reason: "only supported for photos".to_string(),
+		});
+	}
+	let layout = if config.equirect {
+		if output_types.contains(&OutputType::SideBySide) {
+			result.warnings.push(Warning::OptionIgnored {
+				option: "sbs layout".to_string(),
+				reason: "360 stereo is written over-under".to_string(),
+			});
+		}
+		OutputFormat::TopAndBottom
+	} else {
+		stereo_layout(output_types)
+	};
 
-	let mut metadata = get_video_metadata(input_path).await?;
+	check_extra_ffmpeg_args("extra_encoder_args", &config.extra_encoder_args, &RESERVED_ENCODER_ARGS)?;
+	check_extra_ffmpeg_args("extra_extract_args", &config.extra_extract_args, &RESERVED_EXTRACT_ARGS)?;
+
+	let mut stdin_video = if from_stdin { Some(StdinVideo::open().await?) } else { None };
+	let mut metadata = match (&sequence, &stdin_video) {
+		(Some(sequence), _) => sequence.metadata(config.sequence_fps).await?,
+		(None, Some(stdin)) => stdin.metadata().await?,
+		(None, None) => get_video_metadata(input_path).await?,
+	};
+	if let Some(fps) = config.output_fps {
+		metadata = metadata.with_output_fps(fps)?;
+	}
+	let frame_timeout = std::time::Duration::try_from_secs_f64(config.frame_timeout_secs)
+		.ok()
+		.filter(|limit| !limit.is_zero());
 	metadata.width = metadata.width & !1;
 	metadata.height = metadata.height & !1;
+	let max_disparity = if do_stereo {
+		let (max_disparity, warning) = validate_max_disparity_with_warnings(config.max_disparity, metadata.width)?;
+		result.warnings.extend(warning);
+		max_disparity
+	} else {
+		config.max_disparity
+	};
+	let stereo_params = StereoParams::from_config(&config, max_disparity);
+	if metadata.color.is_hdr() {
+		result.warnings.push(Warning::HdrToneMapped {
+			transfer: metadata.color.transfer.clone().unwrap_or_else(|| "bt2020".to_string()),
+			zscale: has_ffmpeg_filter("zscale"),
+		});
+	}
 	let use_spatial = do_stereo && is_spatial_cli_available();
 
-	let stereo_output = {
-		let stem = output_path.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
-		let parent = output_path.parent().unwrap_or_else(|| Path::new("."));
-		parent.join(format!("{}-spatial.mov", stem))
+	// The spatial CLI always writes MV-HEVC .mov; the SBS/TAB intermediate is ours.
+	let container = if use_spatial { DEFAULT_CONTAINER } else { output_container(output_path) };
+	let codec = select_video_codec(container, if use_spatial { None } else { config.video_codec })?;
+	if use_spatial && config.video_codec.is_some() {
+		result.warnings.push(Warning::OptionIgnored {
+			option: "--video-codec".to_string(),
+			reason: "MV-HEVC is packaged by the spatial CLI".to_string(),
+		});
+	}
+
+	// Only ISO BMFF containers carry the Spherical Video box.
+	let tag_360 = config.equirect && do_stereo && !use_spatial && matches!(container, "mov" | "mp4" | "m4v");
+	if config.equirect && do_stereo && !tag_360 {
+		result.warnings.push(Warning::OptionIgnored {
+			option: "360 video metadata".to_string(),
+			reason: if use_spatial {
+				"MV-HEVC is packaged by the spatial CLI".to_string()
+			} else {
+				format!("not supported in .{}", container)
+			},
+		});
+	}
+
+	let stereo_output = stereo_video_path(
+		output_path,
+		if use_spatial {
+			config.spatial_extension.as_deref().unwrap_or(crate::output::SPATIAL_VIDEO_EXTENSION)
+		} else {
+			container
+		},
+	);
+
+	let target_size = config.target_size_mb.filter(|_| do_stereo);
+	if target_size.is_some() && (use_spatial || codec == VideoCodec::ProRes) {
+		result.warnings.push(Warning::OptionIgnored {
+			option: "--target-size".to_string(),
+			reason: if use_spatial {
+				"MV-HEVC is packaged by the spatial CLI".to_string()
+			} else {
+				"ProRes bitrate is set by its profile".to_string()
+			},
+		});
+	}
+	let target_size = target_size.filter(|_| !use_spatial && codec != VideoCodec::ProRes);
+
+	// Both the spatial CLI and size targeting re-encode a finished intermediate.
+	let sbs_temp = if use_spatial || target_size.is_some() {
+		Some(crate::temp::temp_file(&format!(".{}", container))?.into_temp_path())
+	} else {
+		None
 	};
 
-	let sbs_path = if use_spatial {
-		let temp_dir = std::env::temp_dir();
-		temp_dir.join(format!(
-			"spatial_maker_sbs_{}.mov",
-			std::time::SystemTime::now()
-				.duration_since(std::time::UNIX_EPOCH)
-				.unwrap_or_default()
-				.as_millis()
-		))
+	let sbs_path = if let Some(ref temp) = sbs_temp {
+		temp.to_path_buf()
 	} else if do_stereo {
 		stereo_output.clone()
 	} else {
 		output_path.to_path_buf()
 	};
 
+	let depth_outputs: Vec<(std::path::PathBuf, DepthFormat)> = if do_depth {
+		let mut outputs: Vec<(std::path::PathBuf, DepthFormat)> = Vec::new();
+		for fmt in depth_formats(output_types) {
+			let path = depth_video_path(output_path, fmt);
+			if !outputs.iter().any(|(p, _)| *p == path) && (force || !path.exists()) {
+				outputs.push((path, fmt));
+			}
+		}
+		outputs
+	} else {
+		Vec::new()
+	};
+
+	if !do_stereo && depth_outputs.is_empty() {
+		tracing::info!("Depth video already exists, skipping (use --force to regenerate)");
+		if let Some(ref cb) = progress_cb {
+			cb(VideoProgress::new(metadata.total_frames, metadata.total_frames, "complete".to_string()));
+		}
+		return Ok(result);
+	}
+
+	if let Ok(mut created) = created.lock() {
+		if do_stereo {
+			created.push(stereo_output.clone());
+			created.push(stereo_output.with_extension("tmp.mov"));
+		}
+		created.extend(depth_outputs.iter().map(|(path, _)| path.clone()));
+	}
+
+	#[cfg(not(any(all(target_os = "macos", feature = "coreml"), feature = "onnx")))]
+	let estimator = NoDepthBackend::new()?;
+
 	crate::model::ensure_model_exists::<fn(u64, u64)>(&config.encoder_size, None).await?;
 
 	#[cfg(all(target_os = "macos", feature = "coreml"))]
@@ -495,40 +2264,102 @@ pub async fn process_video(
 		let model_str = model_path.to_str().ok_or_else(|| {
 			SpatialError::ModelError("Invalid model path encoding".to_string())
 		})?;
-		std::sync::Arc::new(crate::depth_coreml::CoreMLDepthEstimator::new(model_str)?)
+		std::sync::Arc::new(crate::depth_coreml::CoreMLDepthEstimator::from_config(model_str, &config)?)
 	};
 
-	let mut depth_processor = DepthProcessor::new(
-		config.temporal_alpha,
-		config.bilateral_sigma_space,
-		config.bilateral_sigma_color,
-		config.depth_blur_sigma,
-		config.normalize_mode.clone(),
-	);
+	#[cfg(all(feature = "onnx", not(all(target_os = "macos", feature = "coreml"))))]
+	let mut estimator = {
+		let model_path = crate::model::find_model(&config.encoder_size)?;
+		let model_str = model_path.to_str().ok_or_else(|| {
+			SpatialError::ModelError("Invalid model path encoding".to_string())
+		})?;
+		crate::depth::OnnxDepthEstimator::from_config(model_str, &config)?
+	};
+
+	let mut depth_processor = DepthProcessor::from_config(&config);
 
 	let total_frames = metadata.total_frames;
 
-	if matches!(config.normalize_mode, NormalizeMode::Global) {
+	let chunking = config.chunk_frames.filter(|&n| n > 0);
+	if chunking.is_some() && from_stdin {
+		result.warnings.push(Warning::OptionIgnored {
+			option: "--chunk-frames".to_string(),
+			reason: "chunks can't be resumed from stdin".to_string(),
+		});
+	}
+	let chunking = chunking.filter(|_| !from_stdin);
+	let chunk_dir = {
+		let stem = output_path.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+		let parent = output_path.parent().unwrap_or_else(|| Path::new("."));
+		parent.join(format!("{}.chunks", stem))
+	};
+	let mut checkpoint = ChunkCheckpoint {
+		input: input_path.to_path_buf(),
+		total_frames,
+		chunk_frames: chunking.unwrap_or(0),
+		outputs: do_stereo
+			.then(|| stereo_output.clone())
+			.into_iter()
+			.chain(depth_outputs.iter().map(|(path, _)| path.clone()))
+			.collect(),
+		stereo: do_stereo.then_some(stereo_params),
+		completed_chunks: 0,
+	};
+	if chunking.is_some() {
+		match ChunkCheckpoint::load(&chunk_dir) {
+			Some(saved) if config.resume && saved.can_resume(&checkpoint) => {
+				depth_processor.load_state(&chunk_dir.join(CHUNK_STATE_FILE))?;
+				checkpoint.completed_chunks = saved.completed_chunks;
+				tracing::info!(
+					"Resuming after {} finished chunks ({:?})",
+					saved.completed_chunks,
+					chunk_dir
+				);
+			}
+			_ => {
+				if chunk_dir.exists() {
+					std::fs::remove_dir_all(&chunk_dir)?;
+				}
+				std::fs::create_dir_all(&chunk_dir)?;
+			}
+		}
+	}
+	let start_frame = checkpoint.completed_chunks * checkpoint.chunk_frames;
+
+	let mut depth_cache = DepthCache::new(GlobalCache::Reinfer, (0, 0))?;
+	// A resumed run restores the global range along with the rest of the state.
+	if matches!(config.normalize_mode, NormalizeMode::Global) && start_frame == 0 {
 		if let Some(ref cb) = progress_cb {
 			cb(VideoProgress::new(0, total_frames, "scanning".to_string()));
 		}
+		depth_cache = DepthCache::new(
+			config.global_cache,
+			(metadata.height as usize, metadata.width as usize),
+		)?;
+		if config.global_cache == GlobalCache::Memory {
+			let bytes = total_frames as u64 * metadata.width as u64 * metadata.height as u64 * 4;
+			tracing::info!("Caching depth in memory (~{} MB)", bytes / (1024 * 1024));
+		}
 
-		let mut scan_rx = extract_frames(input_path, &metadata).await?;
+		let mut scan_rx = extract_frames(
+			input_path,
+			sequence.as_ref(),
+			&metadata,
+			0,
+			frame_timeout,
+			None,
+			&config.extra_extract_args,
+		)
+		.await?;
 		let mut scan_count = 0u32;
+		let mut raw: Array2<f32> = Array2::zeros((0, 0));
 		while let Some(frame_data) = scan_rx.recv().await {
-			let frame = frame_to_image(&frame_data, metadata.width, metadata.height)?;
+			let frame = frame_to_image(&frame_data?, metadata.width, metadata.height)?;
 			scan_count += 1;
 
-			#[cfg(all(target_os = "macos", feature = "coreml"))]
-			{
-				let raw = estimator.estimate_unnormalized(&frame)?;
-				depth_processor.update_global_range(&raw);
-			}
-
-			#[cfg(not(all(target_os = "macos", feature = "coreml")))]
-			{
-				let _ = frame;
-			}
+			estimator.estimate_raw_into(&frame, &mut raw)?;
+			depth_processor.update_global_range(&raw);
+			depth_cache.push(&raw)?;
 
 			if let Some(ref cb) = progress_cb {
 				if scan_count % 10 == 0 || scan_count == total_frames {
@@ -542,63 +2373,51 @@ pub async fn process_video(
 		}
 	}
 
-	let mut frame_rx = extract_frames(input_path, &metadata).await?;
-
-	let stereo_tx_opt;
-	let stereo_handle;
-
-	if do_stereo {
-		let (tx, rx) = mpsc::channel::<(DynamicImage, DynamicImage)>(10);
-		stereo_tx_opt = Some(tx);
-		stereo_handle = Some(tokio::spawn(encode_stereo_video(
-			sbs_path.clone(),
-			metadata.clone(),
-			rx,
-		)));
-	} else {
-		stereo_tx_opt = None;
-		stereo_handle = None;
-	}
-
-	let depth_tx_opt;
-	let depth_handle;
-
-	let _encode_depth = if do_depth {
-		let depth_path = {
-			let stem = output_path.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
-			let parent = output_path.parent().unwrap_or_else(|| Path::new("."));
-			parent.join(format!("{}-depth.mov", stem))
+	depth_cache.rewind()?;
+
+	let mut frame_rx = extract_frames(
+		input_path,
+		sequence.as_ref(),
+		&metadata,
+		start_frame,
+		frame_timeout,
+		stdin_video.take(),
+		&config.extra_extract_args,
+	)
+	.await?;
+
+	// Each chunk gets its own encoders writing to segment files; unchunked runs
+	// write the outputs directly.
+	let spawn_encoders = |chunk: u32| {
+		let target = |path: &Path, key: &Path| match chunking {
+			Some(_) => segment_path(&chunk_dir, chunk, key),
+			None => path.to_path_buf(),
 		};
-
-		if !force && depth_path.exists() {
-			depth_tx_opt = None;
-			depth_handle = None;
-			false
-		} else {
-			let (tx, rx) = mpsc::channel::<Array2<f32>>(10);
-			depth_tx_opt = Some(tx);
-			depth_handle = Some(tokio::spawn(encode_depth_video(
-				depth_path,
-				metadata.clone(),
-				rx,
-			)));
-			true
-		}
-	} else {
-		depth_tx_opt = None;
-		depth_handle = None;
-		false
+		ChunkEncoders::spawn(
+			do_stereo.then(|| (target(&sbs_path, &stereo_output), layout, codec)),
+			depth_outputs
+				.iter()
+				.map(|(path, fmt)| (target(path, path), *fmt))
+				.collect(),
+			&metadata,
+			&config.extra_encoder_args,
+		)
 	};
 
-	let mut frame_count = 0u32;
+	let mut frame_count = start_frame;
+	let mut encoders: Option<ChunkEncoders> = None;
 
 	if let Some(ref cb) = progress_cb {
-		cb(VideoProgress::new(0, total_frames, "extracting".to_string()));
+		cb(VideoProgress::new(start_frame, total_frames, "extracting".to_string()));
 	}
 
-	while let Some(frame_data) = frame_rx.recv().await {
-		let frame = frame_to_image(&frame_data, metadata.width, metadata.height)?;
+	let mut previous: Option<(u64, Array2<f32>, Option<StereoPair>)> = None;
+	let mut skipped_frames = 0u32;
+	// Each frame's depth map, once encoded, is the next frame's estimation buffer.
+	let mut depth_buffer: Array2<f32> = Array2::zeros((0, 0));
 
+	while let Some(frame_data) = frame_rx.recv().await {
+		let frame_data = frame_data?;
 		frame_count += 1;
 		if let Some(ref cb) = progress_cb {
 			if frame_count % 10 == 0 || frame_count == total_frames {
@@ -610,48 +2429,70 @@ pub async fn process_video(
 			}
 		}
 
-		#[cfg(all(target_os = "macos", feature = "coreml"))]
-		let depth_map = {
-			let raw = estimator.estimate_unnormalized(&frame)?;
-			depth_processor.process(raw)
-		};
-
-		#[cfg(not(all(target_os = "macos", feature = "coreml")))]
-		let depth_map = {
-			#[cfg(feature = "onnx")]
-			{
-				let model_path = crate::model::find_model(&config.encoder_size)?;
-				let est = crate::depth::OnnxDepthEstimator::new(model_path.to_str().unwrap())?;
-				est.estimate(&frame)?
+		let frame_hash = config.skip_duplicate_frames.then(|| hash_frame(&frame_data));
+		let (depth_map, pair) = match previous.as_ref().filter(|(hash, _, _)| Some(*hash) == frame_hash) {
+			Some((_, depth_map, pair)) => {
+				// Keep the cache aligned with the frame sequence.
+				depth_cache.next();
+				skipped_frames += 1;
+				(depth_map.clone(), pair.clone())
 			}
-			#[cfg(not(feature = "onnx"))]
-			{
-				return Err(SpatialError::ConfigError(
-					"No depth backend enabled. Enable 'coreml' or 'onnx' feature.".to_string(),
-				));
+			None => {
+				let frame = frame_to_image(&frame_data, metadata.width, metadata.height)?;
+
+				let raw = match depth_cache.next() {
+					Some(raw) => raw,
+					None => {
+						estimator.estimate_raw_into(&frame, &mut depth_buffer)?;
+						std::mem::take(&mut depth_buffer)
+					}
+				};
+				let depth_map = depth_processor.process_with_image(raw, &frame);
+
+				let pair = if do_stereo {
+					let mut params = StereoParams {
+						convergence: if config.auto_convergence {
+							depth_processor.convergence()
+						} else {
+							stereo_params.convergence
+						},
+						..stereo_params
+					};
+					if config.auto_disparity {
+						params = params.with_disparity_scale(depth_processor.disparity_scale(), metadata.width);
+					}
+					let pair = generate_stereo_pair_with(&frame, &depth_map, &params, None::<fn(f64)>)?;
+					Some(apply_luminance_match(pair, config.match_luminance))
+				} else {
+					None
+				};
+
+				if let Some(hash) = frame_hash {
+					previous = Some((hash, depth_map.clone(), pair.clone()));
+				}
+				(depth_map, pair)
 			}
 		};
 
-		if let Some(ref depth_tx) = depth_tx_opt {
-			if depth_tx.send(depth_map.clone()).await.is_err() {
-				return Err(SpatialError::Other(
-					"Depth encoder stopped unexpectedly".to_string(),
-				));
-			}
-		}
+		encoders
+			.get_or_insert_with(|| spawn_encoders(checkpoint.completed_chunks))
+			.send(&depth_map, pair)
+			.await?;
+		depth_buffer = depth_map;
 
-		if let Some(ref stereo_tx) = stereo_tx_opt {
-			let (left, right) = generate_stereo_pair(&frame, &depth_map, config.max_disparity)?;
-			if stereo_tx.send((left, right)).await.is_err() {
-				return Err(SpatialError::Other(
-					"Encoder stopped unexpectedly".to_string(),
-				));
+		if chunking.is_some_and(|n| frame_count % n == 0) {
+			if let Some(chunk) = encoders.take() {
+				chunk.finish().await?;
 			}
+			checkpoint.completed_chunks += 1;
+			depth_processor.save_state(&chunk_dir.join(CHUNK_STATE_FILE))?;
+			checkpoint.save(&chunk_dir)?;
 		}
 	}
 
-	drop(stereo_tx_opt);
-	drop(depth_tx_opt);
+	if skipped_frames > 0 {
+		tracing::info!("Reused depth for {} duplicate frames", skipped_frames);
+	}
 
 	if let Some(ref cb) = progress_cb {
 		cb(VideoProgress::new(
@@ -661,16 +2502,39 @@ pub async fn process_video(
 		));
 	}
 
-	if let Some(handle) = stereo_handle {
-		handle
-			.await
-			.map_err(|e| SpatialError::Other(format!("Stereo encoding task failed: {}", e)))??;
+	if let Some(chunk) = encoders.take() {
+		chunk.finish().await?;
+		if chunking.is_some() {
+			checkpoint.completed_chunks += 1;
+			depth_processor.save_state(&chunk_dir.join(CHUNK_STATE_FILE))?;
+			checkpoint.save(&chunk_dir)?;
+		}
 	}
 
-	if let Some(handle) = depth_handle {
-		handle
-			.await
-			.map_err(|e| SpatialError::Other(format!("Depth encoding task failed: {}", e)))??;
+	if chunking.is_some() {
+		let segments = |key: &Path| -> Vec<PathBuf> {
+			(0..checkpoint.completed_chunks)
+				.map(|i| segment_path(&chunk_dir, i, key))
+				.collect()
+		};
+		if do_stereo {
+			concat_segments(&segments(&stereo_output), &sbs_path).await?;
+		}
+		for (path, _) in &depth_outputs {
+			concat_segments(&segments(path), path).await?;
+		}
+	}
+
+	if let Some(size_mb) = target_size {
+		if let Some(ref cb) = progress_cb {
+			cb(VideoProgress::new(
+				total_frames,
+				total_frames,
+				"compressing".to_string(),
+			));
+		}
+
+		encode_to_target_size(&sbs_path, &stereo_output, input_path, &metadata, codec, layout, size_mb).await?;
 	}
 
 	if use_spatial {
@@ -682,10 +2546,18 @@ pub async fn process_video(
 			));
 		}
 
-		let result = encode_mvhevc_video(&sbs_path, &stereo_output, input_path, &metadata).await;
+		let result = encode_mvhevc_video(&sbs_path, &stereo_output, input_path, &metadata, layout).await;
 		result?;
 	}
 
+	if tag_360 {
+		inject_spherical_metadata(&stereo_output, layout)?;
+	}
+
+	if chunking.is_some() {
+		let _ = std::fs::remove_dir_all(&chunk_dir);
+	}
+
 	if let Some(ref cb) = progress_cb {
 		cb(VideoProgress::new(
 			total_frames,
@@ -694,5 +2566,199 @@ pub async fn process_video(
 		));
 	}
 
-	Ok(())
+	Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn metadata() -> VideoMetadata {
+		VideoMetadata {
+			width: 640,
+			height: 360,
+			rotation: 0,
+			fps: 30.0,
+			source_fps: 30.0,
+			output_fps: 30.0,
+			total_frames: 90,
+			duration: 3.0,
+			has_audio: false,
+			color: ColorInfo::default(),
+		}
+	}
+
+	fn strings(args: &[&str]) -> Vec<String> {
+		args.iter().map(|arg| arg.to_string()).collect()
+	}
+
+	#[test]
+	fn chunks_only_resume_with_the_same_stereo_settings() {
+		let checkpoint = |stereo| ChunkCheckpoint {
+			input: PathBuf::from("clip.mp4"),
+			total_frames: 300,
+			chunk_frames: 100,
+			outputs: vec![PathBuf::from("clip-sbs.mov")],
+			stereo,
+			completed_chunks: 1,
+		};
+		let params = StereoParams::new(30);
+		assert!(checkpoint(Some(params)).can_resume(&checkpoint(Some(params))));
+		assert!(!checkpoint(Some(params)).can_resume(&checkpoint(Some(StereoParams::new(40)))));
+		let fill = StereoParams { fill: crate::stereo::DisocclusionFill::Nearest, ..params };
+		assert!(!checkpoint(Some(params)).can_resume(&checkpoint(Some(fill))));
+		let mode = StereoParams { mode: crate::stereo::StereoMode::Symmetric, ..params };
+		assert!(!checkpoint(Some(params)).can_resume(&checkpoint(Some(mode))));
+		assert!(!checkpoint(None).can_resume(&checkpoint(Some(params))));
+
+		// Checkpoints written before the settings were recorded don't resume a stereo run.
+		let old: ChunkCheckpoint = serde_json::from_str(
+			r#"{"input":"clip.mp4","total_frames":300,"chunk_frames":100,"outputs":["clip-sbs.mov"],"completed_chunks":1}"#,
+		)
+		.unwrap();
+		assert!(!old.can_resume(&checkpoint(Some(params))));
+	}
+
+	#[test]
+	fn probe_output_parses_a_valid_stream() {
+		let probe = br#"{"streams":[{"width":1920,"height":1080,"r_frame_rate":"30000/1001","nb_frames":"120"}],"format":{"duration":"4.0"}}"#;
+		let metadata = parse_video_probe(probe, true).unwrap();
+		assert_eq!((metadata.width, metadata.height), (1920, 1080));
+		assert!((metadata.fps - 29.97).abs() < 0.01);
+		assert_eq!(metadata.total_frames, 120);
+		assert_eq!(metadata.duration, 4.0);
+		assert!(metadata.has_audio);
+	}
+
+	#[test]
+	fn malformed_probe_output_is_rejected() {
+		let cases: &[&[u8]] = &[
+			b"not json",
+			br#"{"streams":[]}"#,
+			br#"{"format":{}}"#,
+			br#"{"streams":[{"width":0,"height":1080,"r_frame_rate":"30/1"}]}"#,
+			br#"{"streams":[{"width":1,"height":1080,"r_frame_rate":"30/1"}]}"#,
+			br#"{"streams":[{"width":1920,"r_frame_rate":"30/1"}]}"#,
+			br#"{"streams":[{"width":1920,"height":"1080","r_frame_rate":"30/1"}]}"#,
+			br#"{"streams":[{"width":1920,"height":1080,"r_frame_rate":"0/0"}]}"#,
+			br#"{"streams":[{"width":1920,"height":1080,"r_frame_rate":"0/1"}]}"#,
+			br#"{"streams":[{"width":1920,"height":1080,"r_frame_rate":"abc"}]}"#,
+			br#"{"streams":[{"width":1920,"height":1080}]}"#,
+		];
+		for probe in cases {
+			assert!(
+				parse_video_probe(probe, false).is_err(),
+				"accepted {}",
+				String::from_utf8_lossy(probe)
+			);
+		}
+	}
+
+	#[test]
+	fn rotated_probe_output_swaps_dimensions() {
+		let probe = br#"{"streams":[{"width":1920,"height":1080,"r_frame_rate":"30/1","tags":{"rotate":"90"}}]}"#;
+		let metadata = parse_video_probe(probe, false).unwrap();
+		assert_eq!((metadata.width, metadata.height, metadata.rotation), (1080, 1920, 90));
+	}
+
+	#[test]
+	fn extra_extract_args_come_before_the_stdout_pipe() {
+		let input = strings(&["-i", "clip.mp4"]);
+		let args = extract_args(&input, &metadata(), &strings(&["-hwaccel", "auto"]));
+		assert_eq!(args[args.len() - 3..], strings(&["-hwaccel", "auto", "-"]));
+		assert!(args.windows(2).any(|w| w == ["-vf", "scale=640:360"]));
+	}
+
+	#[test]
+	fn extra_encoder_args_follow_the_encoder_defaults() {
+		let args = stereo_encoder_args(
+			&metadata(),
+			OutputFormat::TopAndBottom,
+			VideoCodec::H264,
+			&strings(&["-crf", "18"]),
+		);
+		assert!(args.windows(2).any(|w| w == ["-s", "640x720"]));
+		assert_eq!(args[args.len() - 3..], strings(&["-y", "-crf", "18"]));
+	}
+
+	#[test]
+	fn reserved_ffmpeg_args_are_rejected() {
+		let check = |args: &[&str], reserved: &[&str]| check_extra_ffmpeg_args("args", &strings(args), reserved);
+		assert!(check(&["-filter:v", "hflip"], &RESERVED_EXTRACT_ARGS).is_err());
+		assert!(check(&["-c:v", "h264_cuvid"], &RESERVED_EXTRACT_ARGS).is_err());
+		assert!(check(&["-i", "other.mp4"], &RESERVED_ENCODER_ARGS).is_err());
+		assert!(check(&["-vf", "eq=gamma=1.1"], &RESERVED_ENCODER_ARGS).is_err());
+		assert!(check(&["-filter:v", "hflip"], &RESERVED_ENCODER_ARGS).is_err());
+		assert!(check(&["-crf", "18", "-c:v", "libx264"], &RESERVED_ENCODER_ARGS).is_ok());
+		assert!(check(&["-threads", "4"], &RESERVED_EXTRACT_ARGS).is_ok());
+	}
+
+	#[test]
+	fn spatial_video_defaults_to_mov() {
+		let path = stereo_video_path(Path::new("out/clip.mp4"), crate::output::SPATIAL_VIDEO_EXTENSION);
+		assert_eq!(path, Path::new("out/clip-spatial.mov"));
+	}
+
+	#[test]
+	fn quarter_turn_rotations_are_read_clockwise() {
+		let matrix = serde_json::json!({ "side_data_list": [{ "rotation": -90 }] });
+		let tag = serde_json::json!({ "tags": { "rotate": "270" } });
+		assert_eq!(stream_rotation(&matrix), 90);
+		assert_eq!(stream_rotation(&tag), 270);
+		assert_eq!(stream_rotation(&serde_json::json!({})), 0);
+	}
+
+	#[test]
+	fn depth_videos_get_one_file_per_format() {
+		let output = Path::new("out/clip.mp4");
+		let paths: Vec<PathBuf> = DepthFormat::all().iter().map(|&fmt| depth_video_path(output, fmt)).collect();
+		assert_eq!(
+			paths,
+			[
+				Path::new("out/clip-depth.mov"),
+				Path::new("out/clip-depth.mkv"),
+				Path::new("out/clip-depth-16bit.mkv"),
+				Path::new("out/clip-depth-color.mkv"),
+			]
+		);
+	}
+
+	#[test]
+	fn spherical_metadata_lands_in_video_track_and_shifts_offsets() {
+		let hdlr = mp4_box(b"hdlr", &[0, 0, 0, 0, 0, 0, 0, 0, b'v', b'i', b'd', b'e']);
+		let mdat = mp4_box(b"mdat", b"frame");
+		let ftyp = mp4_box(b"ftyp", b"isom");
+		let media_offset = |moov_len: usize| (ftyp.len() + moov_len + 8) as u32;
+		let moov_with = |offset: u32| {
+			let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+			stco.extend_from_slice(&offset.to_be_bytes());
+			let stbl = mp4_box(b"stbl", &mp4_box(b"stco", &stco));
+			let minf = mp4_box(b"minf", &stbl);
+			let mdia = mp4_box(b"mdia", &[hdlr.clone(), minf].concat());
+			mp4_box(b"moov", &mp4_box(b"trak", &mdia))
+		};
+		let moov_len = moov_with(0).len();
+		let file = [ftyp.clone(), moov_with(media_offset(moov_len)), mdat].concat();
+
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("clip.mp4");
+		std::fs::write(&path, &file).unwrap();
+		inject_spherical_metadata(&path, OutputFormat::TopAndBottom).unwrap();
+		let tagged = std::fs::read(&path).unwrap();
+
+		let new_moov_len = moov_len + spherical_box(OutputFormat::TopAndBottom).len();
+		assert_eq!(tagged.len(), file.len() + new_moov_len - moov_len);
+		let offset = media_offset(new_moov_len) as usize;
+		assert_eq!(&tagged[offset..offset + 5], b"frame");
+		let stco_entry = tagged.windows(4).position(|w| w == b"stco").unwrap() + 12;
+		assert_eq!(&tagged[stco_entry..stco_entry + 4], &(offset as u32).to_be_bytes());
+		let text = String::from_utf8_lossy(&tagged);
+		assert!(text.contains("<GSpherical:ProjectionType>equirectangular</GSpherical:ProjectionType>"));
+		assert!(text.contains("<GSpherical:StereoMode>top-bottom</GSpherical:StereoMode>"));
+	}
+
+	#[test]
+	fn stereo_video_uses_given_extension() {
+		assert_eq!(stereo_video_path(Path::new("clip.mp4"), "mp4"), Path::new("clip-spatial.mp4"));
+	}
 }